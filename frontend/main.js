@@ -24,8 +24,23 @@ async function invoke(cmd, args) {
 
 const el = {
   pickZipsBtn: document.getElementById("pickZipsBtn"),
+  pickFoldersBtn: document.getElementById("pickFoldersBtn"),
   exportExcelBtn: document.getElementById("exportExcelBtn"),
   exportBundleBtn: document.getElementById("exportBundleBtn"),
+  groupBySelect: document.getElementById("groupBySelect"),
+  layoutSelect: document.getElementById("layoutSelect"),
+  fontPresetSelect: document.getElementById("fontPresetSelect"),
+  imageLayoutSelect: document.getElementById("imageLayoutSelect"),
+  imageCaptionsCheck: document.getElementById("imageCaptionsCheck"),
+  excludeExportedCheck: document.getElementById("excludeExportedCheck"),
+  imageQualitySelect: document.getElementById("imageQualitySelect"),
+  stripMetadataCheck: document.getElementById("stripMetadataCheck"),
+  ocrTextCheck: document.getElementById("ocrTextCheck"),
+  ocrBtn: document.getElementById("ocrBtn"),
+  validateBtn: document.getElementById("validateBtn"),
+  reportBtn: document.getElementById("reportBtn"),
+  retryBtn: document.getElementById("retryBtn"),
+  templateBtn: document.getElementById("templateBtn"),
   cleanupBtn: document.getElementById("cleanupBtn"),
   zipList: document.getElementById("zipList"),
   details: document.getElementById("details"),
@@ -34,6 +49,16 @@ const el = {
   progressBar: document.getElementById("progressBar"),
   progressFill: document.getElementById("progressFill"),
   progressText: document.getElementById("progressText"),
+  progressCancelBtn: document.getElementById("progressCancelBtn"),
+  progressPauseBtn: document.getElementById("progressPauseBtn"),
+  jobsBtn: document.getElementById("jobsBtn"),
+  logLevelSelect: document.getElementById("logLevelSelect"),
+  diagnosticsBtn: document.getElementById("diagnosticsBtn"),
+  jobsPanel: document.getElementById("jobsPanel"),
+  watchBtn: document.getElementById("watchBtn"),
+  watchPanel: document.getElementById("watchPanel"),
+  rulesBtn: document.getElementById("rulesBtn"),
+  rulesPanel: document.getElementById("rulesPanel"),
   imageModal: document.getElementById("imageModal"),
   imageModalBackdrop: document.getElementById("imageModalBackdrop"),
   imageModalCloseBtn: document.getElementById("imageModalCloseBtn"),
@@ -41,6 +66,8 @@ const el = {
   imageModalImg: document.getElementById("imageModalImg"),
   imageModalTitle: document.getElementById("imageModalTitle"),
   searchInput: document.getElementById("searchInput"),
+  fullSearchInput: document.getElementById("fullSearchInput"),
+  searchResults: document.getElementById("searchResults"),
   selectAllBtn: document.getElementById("selectAllBtn"),
   invertSelBtn: document.getElementById("invertSelBtn"),
   zipStats: document.getElementById("zipStats"),
@@ -57,8 +84,29 @@ let state = {
   modal: { path: null, title: "" },
   pdfRendering: { busy: false },
   filter: "",
+  // 用户手动调整过顺序后，导出时按列表顺序而不是下发时间排列
+  customOrder: false,
+  // 导出Word时使用的 .docx 模板路径
+  templatePath: null,
+  // 本机是否启用了图片文字识别
+  ocrAvailable: false,
+  // 全文检索结果，null 表示未检索
+  searchHits: null,
+  // 正在进行的导入任务
+  importJobId: null,
+  // 后台任务列表（list_jobs 结果加上 job_update 事件），按创建时间倒序
+  jobs: [],
+  // 文件夹监控设置（get_watch_settings / set_watch_settings）
+  watch: { enabled: false, folders: [], settle_secs: 10 },
+  // 正在编辑 Word 字段的 ZIP
+  editingFieldsZipId: null,
+  // 字段校验规则（get_validation_rules / set_validation_rules）
+  rules: null,
 };
 
+// 等待结束的后台任务：任务 ID -> 结束时的回调
+const jobWatchers = {};
+
 function setStatus(text) {
   el.status.textContent = text;
 }
@@ -70,7 +118,8 @@ const progressState = {
   current: 0,
   total: 0,
   stepName: '',
-  message: ''
+  message: '',
+  job_id: null
 };
 
 function showProgress() {
@@ -87,7 +136,10 @@ function hideProgress() {
   progressState.total = 0;
   progressState.stepName = '';
   progressState.message = '';
+  progressState.job_id = null;
   el.progressFill.style.width = '0%';
+  el.progressCancelBtn.classList.add('hidden');
+  el.progressPauseBtn.classList.add('hidden');
 }
 
 function updateProgress(progressData) {
@@ -109,6 +161,12 @@ function updateProgress(progressData) {
     : progressState.stepName;
   el.progressText.textContent = progressText;
 
+  // 后台任务可以暂停和取消
+  el.progressCancelBtn.classList.toggle('hidden', !progressData.job_id || progressData.is_complete);
+  el.progressPauseBtn.classList.toggle('hidden', !progressData.job_id || progressData.is_complete);
+  progressState.job_id = progressData.job_id ?? null;
+  updatePauseButton();
+
   // 如果完成，延迟隐藏进度条
   if (progressData.is_complete) {
     setTimeout(() => {
@@ -117,6 +175,389 @@ function updateProgress(progressData) {
   }
 }
 
+const JOB_STATE_LABELS = {
+  queued: "排队中",
+  running: "进行中",
+  paused: "已暂停",
+  completed: "已完成",
+  failed: "失败",
+  cancelled: "已取消",
+};
+
+function isJobFinished(job) {
+  return job.state === "completed" || job.state === "failed" || job.state === "cancelled";
+}
+
+// 任务结束时调用 onFinished(job)；任务可能在注册前就已结束
+function watchJob(jobId, onFinished) {
+  const job = state.jobs.find((j) => j.id === jobId);
+  if (job && isJobFinished(job)) {
+    runJobWatcher(onFinished, job);
+  } else {
+    jobWatchers[jobId] = onFinished;
+  }
+}
+
+function runJobWatcher(onFinished, job) {
+  onFinished(job).catch((e) => {
+    console.error("处理任务结果失败:", e);
+    setStatus(`错误：${e?.message ?? e}`);
+  });
+}
+
+function onJobUpdate(job) {
+  const idx = state.jobs.findIndex((j) => j.id === job.id);
+  if (idx >= 0) {
+    state.jobs[idx] = job;
+  } else {
+    state.jobs.unshift(job);
+  }
+  renderJobs();
+  updatePauseButton();
+
+  const onFinished = jobWatchers[job.id];
+  if (onFinished && isJobFinished(job)) {
+    delete jobWatchers[job.id];
+    runJobWatcher(onFinished, job);
+  }
+  // 文字识别也会在导入后自动开始，不论由谁发起都刷新当前批次
+  if (job.kind === "ocr" && isJobFinished(job)) {
+    runJobWatcher(onOcrFinished, job);
+  }
+}
+
+function updatePauseButton() {
+  const job = state.jobs.find((j) => j.id === progressState.job_id);
+  el.progressPauseBtn.textContent = job?.state === "paused" ? "继续" : "暂停";
+}
+
+async function toggleJobPause(jobId) {
+  const job = state.jobs.find((j) => j.id === jobId);
+  try {
+    onJobUpdate(await invoke(job?.state === "paused" ? "resume_job" : "pause_job", { jobId }));
+  } catch (e) {
+    console.error(e);
+    setStatus(`操作失败：${e?.message ?? e}`);
+  }
+}
+
+function renderJobs() {
+  el.jobsPanel.innerHTML = "";
+  if (state.jobs.length === 0) {
+    const empty = document.createElement("div");
+    empty.className = "search-empty";
+    empty.textContent = "暂无任务";
+    el.jobsPanel.appendChild(empty);
+    return;
+  }
+  for (const job of state.jobs) {
+    const row = document.createElement("div");
+    row.className = "job-row";
+
+    const title = document.createElement("div");
+    title.className = "job-title";
+    title.textContent = job.title;
+    title.title = job.error || job.result?.path || job.message || "";
+    row.appendChild(title);
+
+    const status = document.createElement("span");
+    status.className = `job-state ${job.state}`;
+    status.textContent = isJobFinished(job)
+      ? JOB_STATE_LABELS[job.state]
+      : `${JOB_STATE_LABELS[job.state]} ${Math.round(job.progress * 100)}%`;
+    row.appendChild(status);
+
+    const addButton = (text, onClick) => {
+      const btn = document.createElement("button");
+      btn.className = "sm-btn";
+      btn.textContent = text;
+      btn.onclick = onClick;
+      row.appendChild(btn);
+    };
+    if (!isJobFinished(job)) {
+      addButton(job.state === "paused" ? "继续" : "暂停", () => toggleJobPause(job.id));
+      addButton("取消", async () => {
+        try {
+          onJobUpdate(await invoke("cancel_job", { jobId: job.id }));
+        } catch (e) {
+          setStatus(`取消失败：${e?.message ?? e}`);
+        }
+      });
+    } else if (job.result?.path) {
+      addButton("打开", async () => {
+        try {
+          await invoke("open_path", { path: job.result.path });
+        } catch (e) {
+          setStatus(`打开失败：${e?.message ?? e}`);
+        }
+      });
+    }
+    el.jobsPanel.appendChild(row);
+  }
+}
+
+el.jobsBtn.onclick = () => {
+  el.jobsPanel.classList.toggle("hidden");
+  renderJobs();
+};
+
+async function saveWatchSettings(settings) {
+  try {
+    state.watch = await invoke("set_watch_settings", { settings });
+    el.watchBtn.classList.toggle("active", state.watch.enabled);
+    setStatus(state.watch.enabled ? `文件夹监控已开启（${state.watch.folders.length} 个文件夹）` : "文件夹监控已关闭");
+  } catch (e) {
+    console.error(e);
+    setStatus(`保存监控设置失败：${e?.message ?? e}`);
+  }
+  renderWatchPanel();
+}
+
+function renderWatchPanel() {
+  const settings = state.watch;
+  el.watchPanel.innerHTML = "";
+
+  const header = document.createElement("div");
+  header.className = "job-row";
+  const enabled = document.createElement("label");
+  enabled.className = "job-title";
+  const enabledCheck = document.createElement("input");
+  enabledCheck.type = "checkbox";
+  enabledCheck.checked = settings.enabled;
+  enabledCheck.onchange = () => saveWatchSettings({ ...settings, enabled: enabledCheck.checked });
+  enabled.append(enabledCheck, "启用监控，新ZIP自动导入到当天批次或指定批次");
+  header.appendChild(enabled);
+
+  const settle = document.createElement("input");
+  settle.type = "number";
+  settle.min = "1";
+  settle.max = "600";
+  settle.className = "watch-settle-input";
+  settle.value = settings.settle_secs;
+  settle.title = "文件大小保持不变多少秒后才导入";
+  settle.onchange = () => saveWatchSettings({ ...settings, settle_secs: Number(settle.value) || 10 });
+  header.append(settle, "秒");
+
+  const addBtn = document.createElement("button");
+  addBtn.className = "sm-btn";
+  addBtn.textContent = "添加文件夹";
+  addBtn.onclick = async () => {
+    const path = await invoke("pick_watch_folder");
+    if (!path) return;
+    saveWatchSettings({ ...settings, folders: [...settings.folders, { path, batch_id: null }] });
+  };
+  header.appendChild(addBtn);
+  el.watchPanel.appendChild(header);
+
+  settings.folders.forEach((folder, index) => {
+    const row = document.createElement("div");
+    row.className = "job-row";
+
+    const title = document.createElement("div");
+    title.className = "job-title";
+    title.textContent = folder.path;
+    title.title = folder.path;
+    row.appendChild(title);
+
+    // 目标批次：当天批次，或固定导入到某个批次（可选当前打开的批次）
+    const target = document.createElement("select");
+    target.className = "toolbar-select";
+    const targets = [["", "当天批次"]];
+    if (folder.batch_id) targets.push([folder.batch_id, `批次 ${folder.batch_id}`]);
+    if (state.batchId && state.batchId !== folder.batch_id) targets.push([state.batchId, `当前批次 ${state.batchId}`]);
+    for (const [value, label] of targets) {
+      const option = document.createElement("option");
+      option.value = value;
+      option.textContent = label;
+      target.appendChild(option);
+    }
+    target.value = folder.batch_id ?? "";
+    target.onchange = () => {
+      const folders = settings.folders.map((f, i) => (i === index ? { ...f, batch_id: target.value || null } : f));
+      saveWatchSettings({ ...settings, folders });
+    };
+    row.appendChild(target);
+
+    const removeBtn = document.createElement("button");
+    removeBtn.className = "sm-btn";
+    removeBtn.textContent = "移除";
+    removeBtn.onclick = () => saveWatchSettings({ ...settings, folders: settings.folders.filter((_, i) => i !== index) });
+    row.appendChild(removeBtn);
+    el.watchPanel.appendChild(row);
+  });
+}
+
+el.watchBtn.onclick = () => {
+  el.watchPanel.classList.toggle("hidden");
+  renderWatchPanel();
+};
+
+// 与后端 ValidationFlagKind 对应
+const FLAG_LABELS = {
+  missing_field: "缺少字段",
+  invalid_number: "编号格式",
+  mismatched_number: "编号不一致",
+  duplicate_number: "编号重复",
+  suspicious_date: "时间可疑",
+  ocr_keyword: "图片关键词",
+};
+
+// 重新校验后只更新各 ZIP 的校验结果，保留列表顺序和勾选
+function syncFlags(batch) {
+  const flags = new Map(batch.zips.map((z) => [z.id, z.flags ?? []]));
+  state.zips = state.zips.map((z) => (flags.has(z.id) ? { ...z, flags: flags.get(z.id) } : z));
+  renderList();
+  renderDetails();
+}
+
+async function saveValidationRules(rules) {
+  try {
+    state.rules = await invoke("set_validation_rules", { rules });
+    if (state.batchId) syncFlags(await invoke("revalidate_batch_fields", { batchId: state.batchId }));
+    setStatus("校验规则已保存");
+  } catch (e) {
+    console.error(e);
+    setStatus(`保存校验规则失败：${e?.message ?? e}`);
+  }
+  renderRulesPanel();
+}
+
+function renderRulesPanel() {
+  const rules = state.rules;
+  el.rulesPanel.innerHTML = "";
+  if (!rules) return;
+
+  const checkRow = (label, checked, onChange) => {
+    const row = document.createElement("label");
+    row.className = "job-row";
+    const check = document.createElement("input");
+    check.type = "checkbox";
+    check.checked = checked;
+    check.onchange = () => onChange(check.checked);
+    row.append(check, label);
+    el.rulesPanel.appendChild(row);
+  };
+
+  const patternRow = document.createElement("div");
+  patternRow.className = "job-row";
+  const patternLabel = document.createElement("span");
+  patternLabel.className = "job-title";
+  patternLabel.textContent = "指令编号格式（正则，留空不检查）";
+  const pattern = document.createElement("input");
+  pattern.className = "rules-pattern-input";
+  pattern.value = rules.instruction_no_pattern;
+  pattern.onchange = () => saveValidationRules({ ...rules, instruction_no_pattern: pattern.value });
+  patternRow.append(patternLabel, pattern);
+  el.rulesPanel.appendChild(patternRow);
+
+  const required = document.createElement("div");
+  required.className = "job-row";
+  required.append("必填：");
+  for (const [key, label] of EDITABLE_WORD_FIELDS) {
+    const item = document.createElement("label");
+    const check = document.createElement("input");
+    check.type = "checkbox";
+    check.checked = rules.required_fields.includes(key);
+    check.onchange = () => {
+      const fields = EDITABLE_WORD_FIELDS.map(([k]) => k).filter((k) => (k === key ? check.checked : rules.required_fields.includes(k)));
+      saveValidationRules({ ...rules, required_fields: fields });
+    };
+    item.append(check, label);
+    required.appendChild(item);
+  }
+  el.rulesPanel.appendChild(required);
+
+  checkRow("指令编号须与文件名中的编号一致", rules.match_filename, (v) => saveValidationRules({ ...rules, match_filename: v }));
+  checkRow("同一批次中指令编号不能重复", rules.unique_in_batch, (v) => saveValidationRules({ ...rules, unique_in_batch: v }));
+
+  const ageRow = document.createElement("div");
+  ageRow.className = "job-row";
+  const age = document.createElement("input");
+  age.type = "number";
+  age.min = "0";
+  age.className = "watch-settle-input";
+  age.value = rules.max_age_days;
+  age.onchange = () => saveValidationRules({ ...rules, max_age_days: Math.max(0, Number(age.value) || 0) });
+  ageRow.append("下发时间早于", age, "天前视为可疑（0 不检查），晚于当前时间总是可疑");
+  el.rulesPanel.appendChild(ageRow);
+
+  const keywordRow = document.createElement("div");
+  keywordRow.className = "job-row";
+  const keywordLabel = document.createElement("span");
+  keywordLabel.className = "job-title";
+  keywordLabel.textContent = "图片文字关键词（逗号分隔，识别文本中出现时提示）";
+  const keywords = document.createElement("input");
+  keywords.className = "rules-pattern-input";
+  keywords.value = (rules.ocr_keywords ?? []).join("，");
+  keywords.onchange = () =>
+    saveValidationRules({ ...rules, ocr_keywords: keywords.value.split(/[,，、\s]+/).filter(Boolean) });
+  keywordRow.append(keywordLabel, keywords);
+  el.rulesPanel.appendChild(keywordRow);
+
+  checkRow("导出Excel时增加“校验问题”列", rules.excel_column, (v) => saveValidationRules({ ...rules, excel_column: v }));
+}
+
+el.rulesBtn.onclick = () => {
+  el.rulesPanel.classList.toggle("hidden");
+  renderRulesPanel();
+};
+
+// 自动导入到当前打开的批次（或还没有打开批次）时刷新列表；同一任务的多个文件只刷新一次
+let watchReloadTimer = null;
+
+function onWatchUpdate(event) {
+  const name = basename(event.path);
+  switch (event.state) {
+    case "detected":
+      setStatus(`监控文件夹发现新文件：${name}，等待写入完成`);
+      break;
+    case "importing":
+      setStatus(`正在自动导入：${name}`);
+      break;
+    case "failed":
+      setStatus(`自动导入失败：${name}：${event.message}`);
+      break;
+    case "imported":
+      setStatus(`已自动导入：${name}（批次 ${event.batch_id}）`);
+      break;
+  }
+  const batchId = event.batch_id;
+  if (!batchId || event.state === "detected" || event.state === "importing") return;
+  if (state.batchId && state.batchId !== batchId) return;
+  clearTimeout(watchReloadTimer);
+  watchReloadTimer = setTimeout(async () => {
+    try {
+      await showBatch(await invoke("load_batch", { batchId }), state.selectedZipId);
+      autoGeneratePdfScreenshots();
+    } catch (e) {
+      console.error("刷新自动导入的批次失败:", e);
+    }
+  }, 500);
+}
+
+el.logLevelSelect.onchange = async () => {
+  try {
+    const level = await invoke("set_log_level", { level: el.logLevelSelect.value });
+    setStatus(`日志级别已调整为 ${el.logLevelSelect.selectedOptions[0]?.textContent ?? level}`);
+  } catch (e) {
+    console.error(e);
+    setStatus(`调整日志级别失败：${e?.message ?? e}`);
+  }
+};
+
+el.diagnosticsBtn.onclick = async () => {
+  try {
+    el.diagnosticsBtn.disabled = true;
+    const outPath = await invoke("collect_diagnostics", { batchId: state.batchId ?? null });
+    setStatus(`诊断信息已保存：${outPath}`);
+  } catch (e) {
+    console.error(e);
+    setStatus(`收集诊断信息失败：${e?.message ?? e}`);
+  } finally {
+    el.diagnosticsBtn.disabled = false;
+  }
+};
+
 function openImageModal({ title, path, src }) {
   state.modal = { title, path };
   el.imageModalTitle.textContent = title ?? "";
@@ -706,6 +1147,35 @@ function initSelectionsForBatch() {
   }
 }
 
+// 恢复上次导出该批次时的勾选和顺序（字段与后端 ExportBundleSelection 对应）
+function applySavedSelection(saved) {
+  if (!saved) return;
+  const toFlags = (flags, indices) => flags.map((_, i) => (indices ?? []).includes(i));
+  for (const zs of saved.zips ?? []) {
+    const sel = state.selection[zs.zip_id];
+    if (!sel) continue;
+    sel.include = zs.include;
+    sel.includeOriginalZip = zs.include_original_zip;
+    sel.videos = toFlags(sel.videos, zs.selected_video_indices);
+    sel.images = toFlags(sel.images, zs.selected_image_indices);
+    sel.pdfFiles = toFlags(sel.pdfFiles, zs.selected_pdf_indices);
+    sel.pdfScreens = toFlags(sel.pdfScreens, zs.selected_pdf_page_screenshot_indices);
+    sel.excels = toFlags(sel.excels, zs.selected_excel_indices);
+    sel.additionalDocx = sel.additionalDocx.map((docSel, idx) => {
+      const savedDoc = (zs.selected_additional_docx ?? []).find((d) => d.docx_index === idx);
+      return {
+        includeText: savedDoc?.include_text ?? false,
+        includeImages: toFlags(docSel.includeImages, savedDoc?.selected_image_indices),
+      };
+    });
+  }
+  if (saved.order?.length) {
+    const rank = new Map(saved.order.map((id, i) => [id, i]));
+    state.zips.sort((a, b) => (rank.get(a.id) ?? rank.size) - (rank.get(b.id) ?? rank.size));
+    state.customOrder = true;
+  }
+}
+
 function selectedIndices(flags) {
   const out = [];
   for (let i = 0; i < flags.length; i++) if (flags[i]) out.push(i);
@@ -718,9 +1188,140 @@ el.searchInput.oninput = (e) => {
   updateZipStats();
 };
 
+const SEARCH_FIELD_LABELS = {
+  filename: "文件名",
+  instruction_no: "指令编号",
+  title: "指令标题",
+  content: "指令内容",
+  additional_docx: "附加文档",
+  pdf: "PDF",
+  ocr: "图片文字",
+};
+
+el.fullSearchInput.onkeydown = async (e) => {
+  if (e.key === "Escape") {
+    el.fullSearchInput.value = "";
+    renderSearchResults(null);
+    return;
+  }
+  if (e.key !== "Enter") return;
+  const query = el.fullSearchInput.value.trim();
+  if (!query) {
+    renderSearchResults(null);
+    return;
+  }
+  try {
+    setStatus("正在检索…");
+    const hits = await invoke("search", { query, filters: null });
+    renderSearchResults(hits);
+    setStatus(`检索完成：${hits.length} 个ZIP命中“${query}”`);
+  } catch (err) {
+    console.error(err);
+    setStatus(`检索失败：${err?.message ?? err}`);
+  }
+};
+
+// 检索结果列表，hits 为 null 时隐藏
+function renderSearchResults(hits) {
+  state.searchHits = hits;
+  el.searchResults.innerHTML = "";
+  el.searchResults.classList.toggle("hidden", hits === null);
+  if (hits === null) return;
+  if (hits.length === 0) {
+    const empty = document.createElement("div");
+    empty.className = "search-empty";
+    empty.textContent = "没有找到匹配的内容";
+    el.searchResults.appendChild(empty);
+    return;
+  }
+
+  for (const hit of hits) {
+    const item = document.createElement("div");
+    item.className = "search-hit"
+      + (hit.batch_id === state.batchId && hit.zip_id === state.selectedZipId ? " active" : "")
+      + (hit.batch_available ? "" : " unavailable");
+    item.onclick = () => openSearchHit(hit);
+
+    const title = document.createElement("div");
+    title.className = "search-hit-title";
+    title.textContent = hit.title || hit.filename;
+    title.title = hit.filename;
+    item.appendChild(title);
+
+    const meta = document.createElement("div");
+    meta.className = "search-hit-meta";
+    const batchTime = new Date(hit.batch_created_at * 1000).toLocaleString();
+    const batchLabel = hit.batch_id === state.batchId
+      ? "当前批次"
+      : hit.batch_available ? `批次 ${batchTime}` : `批次 ${batchTime}（已清理）`;
+    meta.textContent = [hit.issued_at_iso?.slice(0, 10), batchLabel].filter(Boolean).join(" · ");
+    item.appendChild(meta);
+
+    for (const snippet of hit.snippets.slice(0, 3)) {
+      const line = document.createElement("div");
+      line.className = "search-hit-snippet";
+      const label = document.createElement("span");
+      label.className = "search-hit-field";
+      label.textContent = SEARCH_FIELD_LABELS[snippet.field] + (snippet.source ? `（${snippet.source}）` : "");
+      line.appendChild(label);
+      appendHighlighted(line, snippet.text, snippet.highlights);
+      item.appendChild(line);
+    }
+    el.searchResults.appendChild(item);
+  }
+}
+
+// highlights 为字符区间 [start, end)，用 <mark> 标出命中位置
+function appendHighlighted(parent, text, highlights) {
+  const chars = Array.from(text);
+  let pos = 0;
+  for (const [start, end] of highlights) {
+    if (start < pos) continue;
+    parent.appendChild(document.createTextNode(chars.slice(pos, start).join("")));
+    const mark = document.createElement("mark");
+    mark.textContent = chars.slice(start, end).join("");
+    parent.appendChild(mark);
+    pos = end;
+  }
+  parent.appendChild(document.createTextNode(chars.slice(pos).join("")));
+}
+
+async function openSearchHit(hit) {
+  // 已清理的批次只保留检索文本，没有文件可以打开
+  if (!hit.batch_available) {
+    setStatus(`该批次已清理，无法打开：${hit.filename}`);
+    return;
+  }
+  try {
+    if (hit.batch_id !== state.batchId) {
+      setStatus("正在打开批次…");
+      const batch = await invoke("load_batch", { batchId: hit.batch_id });
+      await showBatch(batch, hit.zip_id);
+    } else {
+      state.selectedZipId = hit.zip_id;
+    }
+    // 清空列表过滤，确保命中的ZIP可见
+    state.filter = "";
+    el.searchInput.value = "";
+    renderList();
+    await renderDetails();
+    el.zipList.querySelector(".list-item.active")?.scrollIntoView({ block: "nearest" });
+    renderSearchResults(state.searchHits);
+    setStatus(`已定位：${hit.filename}`);
+  } catch (err) {
+    console.error(err);
+    setStatus(`打开失败：${err?.message ?? err}`);
+  }
+}
+
 function getVisibleZips() {
   if (!state.filter) return state.zips;
-  return state.zips.filter(z => z.filename.toLowerCase().includes(state.filter));
+  return state.zips.filter(z => [
+    z.filename,
+    z.word?.title,
+    z.word?.content,
+    ...(z.ocr_texts ?? []).map(o => o.text),
+  ].some(text => (text ?? "").toLowerCase().includes(state.filter)));
 }
 
 // 更新ZIP统计信息
@@ -779,9 +1380,17 @@ function renderList() {
     };
 
     const item = document.createElement("button");
-    item.className = "list-item" + (z.id === state.selectedZipId ? " active" : "");
+    const flags = z.flags ?? [];
+    item.className = "list-item" + (z.id === state.selectedZipId ? " active" : "") +
+      (zipHasErrors(z) ? " has-errors" : flags.length > 0 ? " has-flags" : "");
     item.textContent = z.filename;
-    item.title = z.filename;
+    item.title = zipHasErrors(z)
+      ? `${z.filename}（导入有错误）`
+      : flags.length > 0 ? `${z.filename}\n${flags.map((f) => f.message).join("\n")}` : z.filename;
+    if (z.duplicate_of) {
+      item.classList.add("is-duplicate");
+      item.title += `\n${duplicateText(z.duplicate_of)}`;
+    }
 
     // Row click
     row.onclick = () => {
@@ -790,6 +1399,24 @@ function renderList() {
       renderDetails();
     };
 
+    // Move Buttons（调整导出顺序）
+    const moveBtn = (label, delta) => {
+      const btn = document.createElement("button");
+      btn.className = "item-move-btn";
+      btn.textContent = label;
+      btn.title = delta < 0 ? "上移" : "下移";
+      btn.onclick = (e) => {
+        e.stopPropagation();
+        const from = state.zips.findIndex((x) => x.id === z.id);
+        const to = from + delta;
+        if (from < 0 || to < 0 || to >= state.zips.length) return;
+        [state.zips[from], state.zips[to]] = [state.zips[to], state.zips[from]];
+        state.customOrder = true;
+        renderList();
+      };
+      return btn;
+    };
+
     // Delete Button
     const delBtn = document.createElement("button");
     delBtn.className = "item-del-btn";
@@ -815,12 +1442,18 @@ function renderList() {
 
     row.appendChild(checkbox);
     row.appendChild(item);
+    row.appendChild(moveBtn("▲", -1));
+    row.appendChild(moveBtn("▼", 1));
     row.appendChild(delBtn);
     el.zipList.appendChild(row);
   }
   const anyIncluded = state.zips.some((z) => state.selection?.[z.id]?.include);
   el.exportExcelBtn.disabled = !state.batchId || state.zips.length === 0 || !anyIncluded;
   el.exportBundleBtn.disabled = !state.batchId || state.zips.length === 0 || !anyIncluded;
+  el.ocrBtn.disabled = !state.ocrAvailable || !state.batchId || state.zips.length === 0;
+  el.validateBtn.disabled = !state.batchId;
+  el.reportBtn.disabled = !state.batchId;
+  el.retryBtn.disabled = !state.batchId || !state.zips.some(zipHasErrors);
 
   // 更新统计信息
   updateZipStats();
@@ -926,6 +1559,130 @@ function addKV(root, kvs) {
   root.appendChild(grid);
 }
 
+const ISSUED_AT_CONFIDENCE = {
+  exact: { text: "时间：精确解析", cls: "status-success" },
+  date_only: { text: "时间：仅日期", cls: "" },
+  year_inferred: { text: "时间：年份推断", cls: "warning" },
+  unparsed: { text: "时间：无法解析", cls: "warning" },
+};
+
+function issuedAtConfidenceBadge(confidence) {
+  const info = ISSUED_AT_CONFIDENCE[confidence] ?? ISSUED_AT_CONFIDENCE.unparsed;
+  const badge = document.createElement("span");
+  badge.className = `badge ${info.cls}`.trim();
+  badge.textContent = info.text;
+  badge.style.marginTop = "6px";
+  return badge;
+}
+
+const EDITABLE_WORD_FIELDS = [
+  ["instruction_no", "指令编号"],
+  ["title", "指令标题"],
+  ["issued_at", "下发时间"],
+  ["content", "指令内容"],
+];
+
+// 修正抽取错误的字段，保存后导出都使用修正后的值
+function wordFieldsEditor(z) {
+  const form = document.createElement("div");
+  form.className = "kv field-editor";
+  const inputs = {};
+  for (const [key, label] of EDITABLE_WORD_FIELDS) {
+    const k = document.createElement("div");
+    k.className = "k";
+    k.textContent = label;
+    const input = document.createElement(key === "content" ? "textarea" : "input");
+    if (key === "content") input.rows = 6;
+    input.value = z.word?.[key] ?? "";
+    inputs[key] = input;
+    form.appendChild(k);
+    form.appendChild(input);
+  }
+
+  const actions = document.createElement("div");
+  actions.className = "row";
+  const saveBtn = document.createElement("button");
+  saveBtn.textContent = "保存";
+  saveBtn.onclick = async () => {
+    const fields = { ...z.word };
+    for (const [key] of EDITABLE_WORD_FIELDS) fields[key] = inputs[key].value;
+    try {
+      saveBtn.disabled = true;
+      const updated = await invoke("update_zip_fields", { batchId: state.batchId, zipId: z.id, fields });
+      state.zips = state.zips.map((x) => (x.id === updated.id ? updated : x));
+      state.editingFieldsZipId = null;
+      // 编号改动可能影响其他 ZIP 的重复编号提示
+      syncFlags(await invoke("load_batch", { batchId: state.batchId }));
+      setStatus("已保存修改的字段");
+    } catch (e) {
+      saveBtn.disabled = false;
+      setStatus(`保存字段失败：${e?.message ?? e}`);
+    }
+  };
+  const cancelBtn = document.createElement("button");
+  cancelBtn.textContent = "取消";
+  cancelBtn.onclick = () => {
+    state.editingFieldsZipId = null;
+    renderDetails();
+  };
+  actions.appendChild(saveBtn);
+  actions.appendChild(cancelBtn);
+
+  const root = document.createElement("div");
+  root.appendChild(form);
+  root.appendChild(actions);
+  return root;
+}
+
+// ZIP 内有多个 docx 时显示评分，可改选主文档
+function mainDocxPicker(z, sel) {
+  const candidates = z.main_docx_decision?.candidates ?? [];
+  if (candidates.length < 2) return document.createDocumentFragment();
+
+  const row = document.createElement("div");
+  row.className = "row main-docx-row";
+
+  const label = document.createElement("span");
+  label.className = "small";
+  label.textContent = "主文档";
+  const select = document.createElement("select");
+  select.className = "toolbar-select";
+  for (const c of candidates) {
+    const opt = document.createElement("option");
+    opt.value = c.entry;
+    opt.textContent = `${c.entry}（${c.score}分）`;
+    opt.title = (c.reasons ?? []).join("，");
+    opt.selected = c.entry === z.main_docx_entry;
+    select.appendChild(opt);
+  }
+  select.title = candidates.find((c) => c.entry === z.main_docx_entry)?.reasons?.join("，") ?? "";
+  select.onchange = async () => {
+    try {
+      setStatus("正在切换主文档…");
+      const updated = await invoke("set_main_document", { batchId: state.batchId, zipId: z.id, entry: select.value });
+      state.zips = state.zips.map((x) => (x.id === updated.id ? updated : x));
+      sel.additionalDocx = (updated.additional_docx_files ?? []).map((doc) => ({
+        includeText: true,
+        includeImages: (doc.image_files ?? []).map(() => true),
+      }));
+      syncFlags(await invoke("load_batch", { batchId: state.batchId }));
+      setStatus(`已将 ${select.value} 设为主文档`);
+    } catch (e) {
+      select.value = z.main_docx_entry;
+      setStatus(`切换主文档失败：${e?.message ?? e}`);
+    }
+  };
+  row.appendChild(label);
+  row.appendChild(select);
+  if (z.main_docx_decision?.manual) {
+    const badge = document.createElement("span");
+    badge.className = "badge";
+    badge.textContent = "手动指定";
+    row.appendChild(badge);
+  }
+  return row;
+}
+
 async function renderDetails() {
   const z = state.zips.find((x) => x.id === state.selectedZipId);
   el.details.innerHTML = "";
@@ -945,8 +1702,8 @@ async function renderDetails() {
   header.appendChild(title);
 
   const statusBadge = document.createElement("span");
-  statusBadge.className = `badge status-${z.status === 'completed' ? 'success' : 'default'}`;
-  statusBadge.textContent = z.status;
+  statusBadge.className = `badge status-${z.status === 'completed' ? 'success' : z.status === 'failed' ? 'error' : 'default'}`;
+  statusBadge.textContent = ZIP_STATUS_LABELS[z.status] ?? z.status;
   header.appendChild(statusBadge);
 
   if (z.has_sample) {
@@ -957,6 +1714,23 @@ async function renderDetails() {
   }
   meta.appendChild(header);
 
+  if (z.duplicate_of) {
+    const dup = document.createElement("div");
+    dup.className = "duplicate-notice";
+    dup.textContent = `⚠️ ${duplicateText(z.duplicate_of)}`;
+    meta.appendChild(dup);
+  }
+
+  // 从邮件导入时显示来源邮件
+  if (z.mail) {
+    const mail = document.createElement("div");
+    mail.className = "mail-source";
+    const received = z.mail.received_at ? new Date(z.mail.received_at * 1000).toLocaleString() : "";
+    mail.textContent = `📧 ${z.mail.subject || "（无主题）"} · ${z.mail.sender || "未知发件人"}${received ? ` · ${received}` : ""}`;
+    mail.title = z.mail.path;
+    meta.appendChild(mail);
+  }
+
   // 2. Stats Grid
   const statsGrid = document.createElement("div");
   statsGrid.className = "stats-grid";
@@ -1038,13 +1812,82 @@ async function renderDetails() {
 
   el.details.appendChild(meta);
 
+  const diagnostics = z.diagnostics ?? [];
+  if (diagnostics.length > 0) {
+    const problems = section(`导入问题（${diagnostics.length}）`);
+    for (const d of diagnostics) {
+      const row = document.createElement("div");
+      row.className = "diagnostic-row";
+      const badge = document.createElement("span");
+      badge.className = `badge ${d.severity === "error" ? "status-error" : "warning"}`;
+      badge.textContent = d.severity === "error" ? "错误" : "警告";
+      const text = document.createElement("div");
+      text.textContent = `${DIAGNOSTIC_LABELS[d.code] ?? d.code}：${d.message}`;
+      if (d.entry) {
+        const entry = document.createElement("div");
+        entry.className = "diagnostic-entry";
+        entry.textContent = d.entry;
+        text.appendChild(entry);
+      }
+      row.appendChild(badge);
+      row.appendChild(text);
+      problems.appendChild(row);
+    }
+    el.details.appendChild(problems);
+  }
+
+  const flags = z.flags ?? [];
+  if (flags.length > 0) {
+    const flagSection = section(`校验问题（${flags.length}）`);
+    for (const f of flags) {
+      const row = document.createElement("div");
+      row.className = "diagnostic-row";
+      const badge = document.createElement("span");
+      badge.className = "badge warning";
+      badge.textContent = FLAG_LABELS[f.kind] ?? f.kind;
+      const text = document.createElement("div");
+      text.textContent = f.message;
+      row.appendChild(badge);
+      row.appendChild(text);
+      flagSection.appendChild(row);
+    }
+    el.details.appendChild(flagSection);
+  }
+
   const word = section("Word字段（固定模板抽取）");
-  addKV(word, [
-    ["指令编号", z.word?.instruction_no ?? ""],
-    ["指令标题", z.word?.title ?? ""],
-    ["下发时间", z.word?.issued_at ?? ""],
-    ["指令内容", z.word?.content ?? ""],
-  ]);
+  if (state.editingFieldsZipId === z.id) {
+    word.appendChild(wordFieldsEditor(z));
+  } else {
+    addKV(word, [
+      ["指令编号", z.word?.instruction_no ?? ""],
+      ["指令标题", z.word?.title ?? ""],
+      ["下发时间", z.word?.issued_at ?? ""],
+      ["规范时间", z.word?.issued_at_iso || "—"],
+      ["指令内容", z.word?.content ?? ""],
+    ]);
+    const editRow = document.createElement("div");
+    editRow.className = "row";
+    editRow.appendChild(issuedAtConfidenceBadge(z.word?.issued_at_confidence));
+    const lastEdit = z.field_edits?.at(-1);
+    if (lastEdit) {
+      const edited = document.createElement("span");
+      edited.className = lastEdit.superseded ? "badge" : "badge warning";
+      const when = `${lastEdit.edited_by} ${new Date(lastEdit.edited_at * 1000).toLocaleString()}`;
+      edited.textContent = lastEdit.superseded ? `人工修改已随更换主文档失效（${when}）` : `已人工修改：${when}`;
+      const o = z.original_word;
+      if (o) edited.title = `原始抽取值\n指令编号：${o.instruction_no}\n指令标题：${o.title}\n下发时间：${o.issued_at}`;
+      editRow.appendChild(edited);
+    }
+    const editBtn = document.createElement("button");
+    editBtn.textContent = "✏️ 修改字段";
+    editBtn.onclick = () => {
+      state.editingFieldsZipId = z.id;
+      renderDetails();
+    };
+    editRow.appendChild(editBtn);
+    word.appendChild(editRow);
+  }
+  word.appendChild(mainDocxPicker(z, sel));
   el.details.appendChild(word);
 
   const imageFiles = z.image_files ?? [];
@@ -1091,6 +1934,15 @@ async function renderDetails() {
       row.appendChild(name);
       card.appendChild(row);
 
+      const metaText = imageMetadataText((z.image_metadata ?? []).find((m) => m.file === imageFiles[i]));
+      if (metaText) {
+        const metaLine = document.createElement("div");
+        metaLine.className = "small image-meta";
+        metaLine.textContent = metaText;
+        metaLine.title = metaText;
+        card.appendChild(metaLine);
+      }
+
       const img = document.createElement("img");
       img.alt = basename(imageFiles[i]);
       img.src = "";
@@ -1552,29 +2404,119 @@ async function renderDetails() {
   }
 }
 
-el.pickZipsBtn.onclick = async () => {
+// 切换到某个批次（导入完成或从检索结果跳转）
+async function showBatch(batch, selectedZipId = null) {
+  state.batchId = batch.batch_id;
+  state.zips = batch.zips;
+  state.selectedZipId = selectedZipId ?? state.zips[0]?.id ?? null;
+  state.customOrder = false;
+  state.imageDataCache = {};
+  initSelectionsForBatch();
   try {
-    setStatus("正在选择ZIP…");
-    const paths = await invoke("pick_zip_files", {});
+    applySavedSelection(await invoke("get_saved_selection", { batchId: batch.batch_id }));
+  } catch (e) {
+    console.error("读取上次的导出选择失败:", e);
+  }
+  renderList();
+  await renderDetails();
+  updateZipStats();
+}
+
+// ZIP 和已解压的文件夹走同一个导入流程
+async function pickAndImport(command, label) {
+  try {
+    setStatus(`正在选择${label}…`);
+    const paths = await invoke(command, {});
     if (!paths?.length) {
       setStatus("已取消");
       return;
     }
-    setStatus(`已选择${paths.length}个ZIP，正在导入解析…`);
-    const result = await invoke("import_zips", { paths });
-    state.batchId = result.batch_id;
-    state.zips = result.zips;
-    state.selectedZipId = state.zips[0]?.id ?? null;
-    initSelectionsForBatch();
-    renderList();
-    await renderDetails();
-    updateZipStats();
-    setStatus(`导入完成：批次 ${state.batchId}，正在自动生成PDF页面截图…`);
-    autoGeneratePdfScreenshots();
+    setStatus(`已选择${paths.length}个${label}，正在导入解析…`);
+    el.pickZipsBtn.disabled = true;
+    el.pickFoldersBtn.disabled = true;
+    state.importJobId = await invoke("import_zips", { paths });
+    watchJob(state.importJobId, onImportFinished);
   } catch (e) {
     console.error(e);
+    el.pickZipsBtn.disabled = false;
+    el.pickFoldersBtn.disabled = false;
     setStatus(`错误：${e?.message ?? e}`);
   }
+}
+
+el.pickZipsBtn.onclick = () => pickAndImport("pick_zip_files", "ZIP/邮件");
+el.pickFoldersBtn.onclick = () => pickAndImport("pick_import_folders", "文件夹");
+
+// 导入在后台任务中进行，结束后按任务结果里的批次 ID 读取批次
+async function onImportFinished(job) {
+  if (job.id !== state.importJobId) return;
+  state.importJobId = null;
+  el.pickZipsBtn.disabled = false;
+  el.pickFoldersBtn.disabled = false;
+
+  if (job.state === "failed") {
+    setStatus(`错误：${job.error}`);
+    return;
+  }
+  if (!job.result?.batch_id) {
+    setStatus("导入已取消");
+    return;
+  }
+  await showBatch(await invoke("load_batch", { batchId: job.result.batch_id }));
+  const prefix = job.state === "cancelled"
+    ? `导入已取消：保留已完成的 ${job.result.zip_count} 个ZIP`
+    : `导入完成：批次 ${state.batchId}`;
+  setStatus(`${prefix}，正在自动生成PDF页面截图…`);
+  offerToSkipDuplicates();
+  autoGeneratePdfScreenshots();
+}
+
+// 图片的拍摄时间、设备和位置
+function imageMetadataText(meta) {
+  if (!meta) return "";
+  const parts = [];
+  if (meta.captured_at) parts.push(`📅 ${meta.captured_at}`);
+  if (meta.device) parts.push(`📷 ${meta.device}`);
+  if (meta.latitude != null && meta.longitude != null) {
+    parts.push(`📍 ${meta.latitude.toFixed(6)}, ${meta.longitude.toFixed(6)}`);
+  }
+  return parts.join(" · ");
+}
+
+function duplicateText(d) {
+  const imported = new Date(d.imported_at * 1000).toLocaleDateString();
+  const exported = d.exported_at ? `，${new Date(d.exported_at * 1000).toLocaleDateString()} 已导出` : "";
+  const by = d.matched_by === "content" ? "内容相同的指令" : "该指令编号";
+  return `${by}已在批次 ${d.batch_id}（${imported}）处理过${exported}`;
+}
+
+// 其他批次已处理过的指令默认仍然导入，由用户决定是否在导出时跳过
+function offerToSkipDuplicates() {
+  const duplicates = state.zips.filter((z) => z.duplicate_of);
+  if (duplicates.length === 0) return;
+  const lines = duplicates.slice(0, 10).map((z) => `${z.filename}：${duplicateText(z.duplicate_of)}`);
+  if (duplicates.length > 10) lines.push(`……共 ${duplicates.length} 个`);
+  if (!confirm(`有 ${duplicates.length} 个指令之前已处理过：\n\n${lines.join("\n")}\n\n是否跳过这些指令（取消勾选，不参与导出）？`)) return;
+  for (const z of duplicates) state.selection[z.id].include = false;
+  renderList();
+  setStatus(`已跳过 ${duplicates.length} 个之前处理过的指令`);
+}
+
+el.progressCancelBtn.onclick = async () => {
+  if (!progressState.job_id) return;
+  try {
+    el.progressCancelBtn.classList.add("hidden");
+    setStatus("正在取消…");
+    await invoke("cancel_job", { jobId: progressState.job_id });
+  } catch (e) {
+    console.error(e);
+    setStatus(`取消失败：${e?.message ?? e}`);
+  }
+};
+
+el.progressPauseBtn.onclick = async () => {
+  if (!progressState.job_id) return;
+  await toggleJobPause(progressState.job_id);
 };
 
 el.exportExcelBtn.onclick = async () => {
@@ -1584,17 +2526,50 @@ el.exportExcelBtn.onclick = async () => {
     const zipIds = state.zips
       .filter((z) => state.selection[z.id]?.include ?? true)
       .map((z) => z.id);
-    const outPath = await invoke("export_excel_with_selection", {
+    const jobId = await invoke("export_excel_with_selection", {
       batchId: state.batchId,
       zipIds,
+      excludeExported: el.excludeExportedCheck.checked,
+    });
+    watchJob(jobId, async (job) => {
+      setStatus(job.state === "completed"
+        ? `Excel已导出：${job.result.path}`
+        : `导出${JOB_STATE_LABELS[job.state]}${job.error ? `：${job.error}` : ""}`);
     });
-    setStatus(`Excel已导出：${outPath}`);
   } catch (e) {
     console.error(e);
     setStatus(`导出失败：${e?.message ?? e}`);
   }
 };
 
+// 汇总文档字体预设，字段与后端 SummaryDocStyle 对应
+const FONT_PRESETS = {
+  official: { title_font: "黑体", heading_font: "黑体", body_font: "仿宋_GB2312", body_size: 32 },
+  song: { title_font: "微软雅黑", heading_font: "微软雅黑", body_font: "宋体", body_size: 24 },
+};
+
+// 图片最大尺寸（像素）和照片的 JPEG 质量
+const IMAGE_QUALITY_PRESETS = {
+  high: { max_width: 1200, max_height: 1680, jpeg_quality: 95 },
+  standard: { max_width: 1000, max_height: 1400, jpeg_quality: 85 },
+  compact: { max_width: 800, max_height: 1120, jpeg_quality: 75 },
+};
+
+// 公文版式字体由标准规定，字体预设只对普通版式生效
+el.layoutSelect.onchange = () => {
+  el.fontPresetSelect.disabled = el.layoutSelect.value === "official";
+};
+
+el.templateBtn.onclick = async () => {
+  try {
+    state.templatePath = await invoke("pick_docx_template");
+    const name = state.templatePath ? state.templatePath.split(/[\\/]/).pop() : "无";
+    el.templateBtn.textContent = `Word模板：${name}`;
+  } catch (e) {
+    setStatus(`选择模板失败：${e}`);
+  }
+};
+
 el.exportBundleBtn.onclick = async () => {
   try {
     if (!state.batchId) return;
@@ -1618,21 +2593,181 @@ el.exportBundleBtn.onclick = async () => {
           selected_image_indices: selectedIndices(docxSel.includeImages ?? []),
         })).filter(docxSel => docxSel.include_text || docxSel.selected_image_indices.length > 0),
       })),
+      order: state.customOrder ? state.zips.map((z) => z.id) : [],
+      group_by: el.groupBySelect.value || null,
+      style: {
+        ...(FONT_PRESETS[el.fontPresetSelect.value] || FONT_PRESETS.official),
+        layout: el.layoutSelect.value || "plain",
+        image_layout: el.imageLayoutSelect.value || "single",
+        image_captions: el.imageCaptionsCheck.checked,
+        ocr_text: el.ocrTextCheck.checked,
+        image_settings: {
+          ...(IMAGE_QUALITY_PRESETS[el.imageQualitySelect.value] || IMAGE_QUALITY_PRESETS.high),
+          strip_metadata: el.stripMetadataCheck.checked,
+        },
+      },
+      template_path: state.templatePath,
     };
 
-    const outPath = await invoke("export_bundle_zip_with_selection", {
+    const jobId = await invoke("export_bundle_zip_with_selection", {
       batchId: state.batchId,
       selection,
       embedFiles: true,
     });
-
-    setStatus(`Word文档已导出：${outPath}`);
+    setStatus("正在后台导出Word文档…");
+    watchJob(jobId, async (job) => {
+      setStatus(job.state === "completed"
+        ? `Word文档已导出：${job.result.path}`
+        : `导出${JOB_STATE_LABELS[job.state]}${job.error ? `：${job.error}` : ""}`);
+    });
   } catch (e) {
     console.error(e);
     setStatus(`导出失败：${e?.message ?? e}`);
   }
 };
 
+const ZIP_STATUS_LABELS = {
+  processing: "处理中",
+  completed: "已导入",
+  failed: "失败",
+};
+
+// 与后端 DiagnosticCode 对应
+const DIAGNOSTIC_LABELS = {
+  import_failed: "导入失败",
+  copy_failed: "复制ZIP失败",
+  zip_unreadable: "无法读取ZIP",
+  main_docx_unreadable: "主文档解析失败",
+  extract_failed: "解压附件失败",
+  additional_docx_failed: "附加文档处理失败",
+  additional_docx_text: "附加文档文本无法提取",
+  additional_docx_images: "附加文档图片无法提取",
+  nested_zip_failed: "嵌套ZIP处理失败",
+  mail_unreadable: "无法读取邮件",
+  mail_no_packages: "邮件中没有ZIP或docx附件",
+};
+
+function zipHasErrors(z) {
+  return z.status === "failed" || (z.diagnostics ?? []).some((d) => d.severity === "error");
+}
+
+el.reportBtn.onclick = async () => {
+  if (!state.batchId) return;
+  try {
+    const outPath = await invoke("export_import_report", { batchId: state.batchId });
+    setStatus(`导入报告已导出：${outPath}`);
+  } catch (e) {
+    console.error(e);
+    setStatus(`导出报告失败：${e?.message ?? e}`);
+  }
+};
+
+el.retryBtn.onclick = async () => {
+  if (!state.batchId) return;
+  const failed = state.zips.filter(zipHasErrors);
+  if (!confirm(`重新导入 ${failed.length} 个有错误的ZIP？已生成的PDF截图和文字识别结果需要重新生成。`)) return;
+  try {
+    el.retryBtn.disabled = true;
+    const batchId = state.batchId;
+    const jobId = await invoke("retry_failed_zips", { batchId, zipIds: failed.map((z) => z.id) });
+    setStatus(`正在重试 ${failed.length} 个ZIP…`);
+    watchJob(jobId, async (job) => {
+      if (job.state === "failed") {
+        setStatus(`重试失败：${job.error}`);
+        renderList();
+        return;
+      }
+      if (state.batchId === batchId) {
+        await showBatch(await invoke("load_batch", { batchId }), state.selectedZipId);
+      }
+      const { retried = 0, fixed = 0 } = job.result ?? {};
+      setStatus(`重试完成：${retried} 个ZIP已重新导入，其中 ${fixed} 个没有错误`);
+      autoGeneratePdfScreenshots();
+    });
+  } catch (e) {
+    console.error(e);
+    el.retryBtn.disabled = false;
+    setStatus(`重试失败：${e?.message ?? e}`);
+  }
+};
+
+const MISSING_KIND_LABELS = {
+  zip: "ZIP副本",
+  video: "视频",
+  image: "图片",
+  pdf: "PDF",
+  pdf_screenshot: "PDF截图",
+  excel: "Excel",
+  additional_docx: "附加文档",
+  additional_docx_image: "附加文档图片",
+};
+
+el.validateBtn.onclick = async () => {
+  if (!state.batchId) return;
+  try {
+    setStatus("正在校验批次…");
+    const report = await invoke("validate_batch", { batchId: state.batchId, repair: false });
+    if (report.missing.length === 0) {
+      setStatus(`校验完成：${report.checked_files} 个文件全部存在`);
+      return;
+    }
+
+    const lines = report.missing
+      .slice(0, 10)
+      .map((m) => `${m.filename}：${MISSING_KIND_LABELS[m.kind] ?? m.kind} ${basename(m.path)}`);
+    if (report.missing.length > 10) lines.push(`……共 ${report.missing.length} 个`);
+    const confirmed = confirm(`发现 ${report.missing.length} 个文件已不在磁盘上：\n\n${lines.join("\n")}\n\n是否修复？修复会移除缺失文件的引用，并尝试从原始位置恢复ZIP副本。`);
+    if (!confirmed) {
+      setStatus(`校验完成：${report.missing.length} 个文件缺失，未修复`);
+      return;
+    }
+
+    const repaired = await invoke("validate_batch", { batchId: state.batchId, repair: true });
+    const batch = await invoke("load_batch", { batchId: state.batchId });
+    await showBatch(batch, state.selectedZipId);
+    const restored = repaired.restored.length ? `，恢复 ${repaired.restored.length} 个ZIP副本` : "";
+    const removed = repaired.missing.filter((m) => m.kind !== "zip" && m.kind !== "additional_docx").length;
+    setStatus(`修复完成：移除 ${removed} 个缺失文件的引用${restored}`);
+  } catch (e) {
+    console.error(e);
+    setStatus(`校验失败：${e?.message ?? e}`);
+  }
+};
+
+el.ocrBtn.onclick = async () => {
+  if (!state.batchId) return;
+  try {
+    await invoke("run_ocr", { batchId: state.batchId });
+    setStatus("已开始在后台识别图片文字");
+  } catch (e) {
+    console.error(e);
+    setStatus(`文字识别失败：${e?.message ?? e}`);
+  }
+};
+
+// 识别结果和关键词校验只合并到当前批次，保留列表顺序和勾选
+async function onOcrFinished(job) {
+  if (job.state === "failed") {
+    setStatus(`文字识别失败：${job.error}`);
+    return;
+  }
+  if (!job.result || job.result.batch_id !== state.batchId) return;
+  const batch = await invoke("load_batch", { batchId: state.batchId });
+  const byId = new Map(batch.zips.map((z) => [z.id, z]));
+  for (const z of state.zips) {
+    const loaded = byId.get(z.id);
+    if (!loaded) continue;
+    z.ocr_texts = loaded.ocr_texts ?? [];
+    z.flags = loaded.flags ?? [];
+  }
+
+  const count = state.zips.reduce((n, z) => n + (z.ocr_texts ?? []).filter((o) => o.text).length, 0);
+  renderList();
+  await renderDetails();
+  const prefix = job.state === "cancelled" ? "文字识别已取消" : "文字识别完成";
+  setStatus(`${prefix}：${count} 张图片识别到文字`);
+}
+
 el.cleanupBtn.onclick = async () => {
   try {
     // 显示确认对话框
@@ -1659,6 +2794,11 @@ el.cleanupBtn.onclick = async () => {
     renderDetails();
     el.exportExcelBtn.disabled = true;
     el.exportBundleBtn.disabled = true;
+    el.ocrBtn.disabled = true;
+    el.validateBtn.disabled = true;
+    el.reportBtn.disabled = true;
+    el.retryBtn.disabled = true;
+    renderSearchResults(null);
     
     setStatus(`清理完成：${result}`);
   } catch (e) {
@@ -1679,7 +2819,48 @@ if (window.__TAURI__) {
       console.error('处理进度事件失败:', error);
     }
   });
+  window.__TAURI__.event.listen('job_update', (event) => {
+    onJobUpdate(event.payload);
+  });
+  window.__TAURI__.event.listen('watch_update', (event) => {
+    onWatchUpdate(event.payload);
+  });
 }
 
+// 重新打开界面后恢复任务列表，之前的导出结果仍可查看
+invoke("list_jobs")
+  .then((jobs) => {
+    state.jobs = jobs;
+    renderJobs();
+  })
+  .catch((e) => console.error("读取任务列表失败:", e));
+
+invoke("get_watch_settings")
+  .then((settings) => {
+    state.watch = settings;
+    el.watchBtn.classList.toggle("active", settings.enabled);
+  })
+  .catch((e) => console.error("读取监控设置失败:", e));
+
+invoke("get_validation_rules")
+  .then((rules) => {
+    state.rules = rules;
+  })
+  .catch((e) => console.error("读取校验规则失败:", e));
+
+invoke("get_log_level")
+  .then((level) => {
+    el.logLevelSelect.value = level;
+  })
+  .catch((e) => console.error("读取日志级别失败:", e));
+
+invoke("ocr_available")
+  .then((available) => {
+    state.ocrAvailable = available;
+    el.ocrBtn.title = available ? el.ocrBtn.title : "当前版本未启用文字识别（需要 ocr 功能并安装 tesseract）";
+    renderList();
+  })
+  .catch((e) => console.error("查询文字识别功能失败:", e));
+
 renderList();
 renderDetails();
@@ -33,6 +33,9 @@ pub struct ProgressEvent {
     pub step_name: String,       // 当前步骤名称
     pub message: String,         // 详细消息
     pub is_complete: bool,       // 是否完成
+    /// 可取消的后台任务 ID，前端据此显示取消按钮
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
 }
 
 impl ProgressEvent {
@@ -44,6 +47,7 @@ impl ProgressEvent {
             step_name: step_name.to_string(),
             message: message.to_string(),
             is_complete: current >= total,
+            job_id: None,
         }
     }
 
@@ -55,8 +59,14 @@ impl ProgressEvent {
             step_name: "完成".to_string(),
             message: "操作已完成".to_string(),
             is_complete: true,
+            job_id: None,
         }
     }
+
+    pub fn with_job(mut self, job_id: &str) -> Self {
+        self.job_id = Some(job_id.to_string());
+        self
+    }
 }
 
 /// 发送进度事件到前端（用于AppHandle）
@@ -69,6 +79,15 @@ fn emit_progress_handle(app: &tauri::AppHandle, event: ProgressEvent) -> Result<
     Ok(())
 }
 
+fn emit_event_handle<S: Serialize + Clone>(app: &tauri::AppHandle, name: &str, payload: S) -> Result<()> {
+    if let Some(window) = app.get_webview_window("main") {
+        window
+            .emit(name, payload)
+            .with_context(|| format!("发送 {} 事件失败", name))?;
+    }
+    Ok(())
+}
+
 
 // 文件嵌入相关结构体和函数
 
@@ -139,45 +158,42 @@ impl Default for EmbeddingConfig {
 /// 增强的汇总文档构建，支持文��嵌入
 fn build_enhanced_summary_docx(
     batch: &BatchSummary,
+    groups: &[ExportGroup],
+    style: &SummaryDocStyle,
     embed_files: bool,
-    app: &tauri::AppHandle,
-) -> Result<(Docx, Vec<EmbeddedFile>)> {
-    let mut docx = Docx::new();
-    docx = docx.add_paragraph(
-        Paragraph::new().add_run(Run::new().add_text("汇总文档").bold()),
-    );
+    ctx: &JobContext,
+    step: usize,
+) -> Result<(Docx, Vec<EmbeddedFile>, Vec<RichBody>)> {
+    let mut docx = new_summary_docx(batch, style, !groups.is_empty());
+    let mut numbering = SectionNumbering::new(style, groups);
 
     let mut all_embedded_files = Vec::new();
+    let mut rich_bodies = Vec::new();
     let total_zips = batch.zips.len();
 
     for (zip_idx, z) in batch.zips.iter().enumerate() {
-        // 发送ZIP处理开始进度
-        let progress_event = ProgressEvent::new(
-            "export_word",
-            zip_idx,
-            total_zips * 4, // 假设每个ZIP有4个主要步骤
-            "处理ZIP内容",
-            &format!("正在处理: {}", z.word.instruction_no)
-        );
-        if let Err(e) = emit_progress_handle(app, progress_event) {
-            eprintln!("发送进度事件失败: {}", e);
-        }
+        ctx.checkpoint()?;
+        let span = ProgressSpan::item(step, zip_idx, total_zips);
+        ctx.report_span(span, 0.0, &format!("正在处理: {}", z.word.instruction_no));
+        docx = add_section_headings(docx, z, group_heading_at(groups, zip_idx), &mut numbering, style);
         docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
             "指令编号:  {}",
             z.word.instruction_no
         ))));
-        docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
-            "指令标题:  {}",
-            z.word.title
-        ))));
         docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
             "下发时间:  {}",
             z.word.issued_at
         ))));
 
-        // 处理指令内容（保持换行格式）
+        // 处理指令内容：能读取原始 docx 时保留原格式，否则按纯文本输出（保持换行格式）
+        let main_rich = if z.word.content.trim().is_empty() { None } else { main_docx_rich_body(z) };
         if !z.word.content.trim().is_empty() {
-            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text("指令内容:")));
+            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(numbering.item("指令内容"))));
+        }
+        if let Some(rich) = main_rich {
+            docx = docx.add_paragraph(hidden_marker_paragraph(&rich.marker));
+            rich_bodies.push(rich);
+        } else if !z.word.content.trim().is_empty() {
             for line in z.word.content.lines() {
                 let trimmed_line = line.trim();
                 if !trimmed_line.is_empty() {
@@ -190,7 +206,7 @@ fn build_enhanced_summary_docx(
 
         // 处理附加 docx 内容
         if !z.additional_docx_files.is_empty() {
-            for additional in &z.additional_docx_files {
+            for (docx_index, additional) in z.additional_docx_files.iter().enumerate() {
                 // 如果有结构化字段，优先展示
                 if !additional.fields.instruction_no.is_empty() ||
                    !additional.fields.title.is_empty() ||
@@ -226,8 +242,13 @@ fn build_enhanced_summary_docx(
                     }
                 }
 
-                // 展示完整文本内容
-                if !additional.full_text.trim().is_empty() {
+                // 展示完整文本内容，能保留原格式时图片随正文一起出现
+                let additional_rich = additional_docx_rich_body(&z.id, docx_index, additional);
+                let images_inline = additional_rich.is_some();
+                if let Some(rich) = additional_rich {
+                    docx = docx.add_paragraph(hidden_marker_paragraph(&rich.marker));
+                    rich_bodies.push(rich);
+                } else if !additional.full_text.trim().is_empty() {
                     for line in additional.full_text.lines() {
                         let trimmed_line = line.trim();
                         if !trimmed_line.is_empty() {
@@ -239,15 +260,14 @@ fn build_enhanced_summary_docx(
                 }
 
                 // 附加文档的图片（直接显示，不加标题）
-                if !additional.image_files.is_empty() {
+                if !images_inline && !additional.image_files.is_empty() {
+                    let mut images = Vec::new();
                     for img_path in &additional.image_files {
                         let bytes = fs::read(img_path)
                             .with_context(|| format!("读取附加docx图片失败: {}", img_path))?;
-                        // 缩放图片到 1200x1680，质量 95（高分辨率，文字非常清晰）
-                        let resized_bytes = resize_image_to_jpeg(&bytes, 1200, 1680, 95)?;
-                        let pic = Pic::new(&resized_bytes).size(5040000, 7056000);
-                        docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_image(pic)));
+                        images.push((img_path.clone(), prepare_image(&bytes, &style.image_settings)?));
                     }
+                    docx = add_images(docx, &images, &[], style);
                 }
 
                 // 分隔线
@@ -260,134 +280,140 @@ fn build_enhanced_summary_docx(
         all_images.extend_from_slice(&z.pdf_page_screenshot_files);
 
         if !all_images.is_empty() {
-            // 发送图片处理开始进度
-            let img_start_progress = ProgressEvent::new(
-                "export_word",
-                zip_idx * 4 + 1,
-                total_zips * 4,
-                "处理图片",
-                &format!("开始处理 {} 张图片", all_images.len())
-            );
-            if let Err(e) = emit_progress_handle(app, img_start_progress) {
-                eprintln!("发送图片开始进度事件失败: {}", e);
-            }
-
+            // 图片处理占该 ZIP 进度区间的大部分
+            let image_span = ProgressSpan { step, start: span.at(0.1), end: span.at(0.9) };
             let processed_images = process_images_parallel_with_progress(
                 &all_images,
-                1200,  // 高分辨率宽度
-                1680,  // 高分辨率高度
-                95,    // 高质量，确保文字非常清晰
-                app,
-                "export_word",
+                &style.image_settings,
+                ctx,
+                image_span,
             ).with_context(|| "并行处理图片失败")?;
 
-            // 发送图片处理完成进度
-            let img_complete_progress = ProgressEvent::new(
-                "export_word",
-                zip_idx * 4 + 2,
-                total_zips * 4,
-                "处理图片",
-                &format!("图片处理完成，共 {} 张", processed_images.len())
-            );
-            if let Err(e) = emit_progress_handle(app, img_complete_progress) {
-                eprintln!("发送图片完成进度事件失败: {}", e);
-            }
+            docx = add_images(docx, &processed_images, &z.ocr_texts, style);
+        }
 
-            for (_path, resized_bytes) in processed_images {
-                let pic = Pic::new(&resized_bytes).size(5040000, 7056000);
-                docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_image(pic)));
-            }
+        // 图片的拍摄信息；导出时去除元数据则不列出位置
+        for line in zip_image_metadata_lines(z, !style.image_settings.strip_metadata) {
+            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(line)));
         }
 
         // 添加章节标记段落（用于后续插入OLE对象）
         let marker = format!("EMBED_MARKER_{}", z.id);
-        docx = docx.add_paragraph(
-            Paragraph::new().add_run(Run::new().add_text(&marker).size(2).color("FFFFFF"))
-        );
+        docx = docx.add_paragraph(hidden_marker_paragraph(&marker));
 
         // 收集需要嵌入的文件（包括视频、PDF、Excel、ZIP，不包括图片）
         if embed_files {
-            // 发送文件嵌入开始进度
-            let embed_start_progress = ProgressEvent::new(
-                "export_word",
-                zip_idx * 4 + 3,
-                total_zips * 4,
-                "嵌入文件",
-                &format!("开始嵌入附件文件 (当前��嵌入 {} 个)", all_embedded_files.len())
-            );
-            if let Err(e) = emit_progress_handle(app, embed_start_progress) {
-                eprintln!("发送文件嵌入开始进度事件失败: {}", e);
-            }
-
             // 内存使用监控：检查当前嵌入文件的总大小
             let current_embed_size_mb: f64 = all_embedded_files.iter()
                 .map(|f: &EmbeddedFile| f.data.len() as f64 / 1024.0 / 1024.0)
                 .sum();
 
             if current_embed_size_mb > 100.0 { // 如果已嵌入超过100MB
-                println!("⚠️ 当前已嵌入文件大小: {:.1}MB，可能影响性能", current_embed_size_mb);
+                log::warn!("当前已嵌入文件大小: {:.1}MB，可能影响性能", current_embed_size_mb);
             }
 
-            // 嵌入视频文件（跳过过大的文件）
-            for video_path in &z.video_files {
-                if Path::new(video_path).exists() {
-                    match create_embedded_file(video_path, &z.id) {
-                        Ok(embed_file) => all_embedded_files.push(embed_file),
-                        Err(e) => {
-                            println!("⚠️ 视频嵌入失败: {}", e);
-                            // 继续处理其他文件，不中断流程
-                        }
-                    }
+            all_embedded_files.extend(collect_embedded_files(z, style.image_settings.strip_metadata));
+        }
+    }
+
+    // 返回文档、嵌入文件列表和待合并的正文，让调用者处理最终的构建
+    Ok((docx, all_embedded_files, rich_bodies))
+}
+
+/// 收集 ZIP 需要以 OLE 对象嵌入的附件（视频、PDF、Excel、原始ZIP），嵌入失败的文件跳过。
+/// strip_metadata 时去掉动图原文件和原始ZIP中图片的元数据
+fn collect_embedded_files(z: &ZipSummary, strip_metadata: bool) -> Vec<EmbeddedFile> {
+    let mut files = Vec::new();
+
+    // 嵌入视频文件（跳过过大的文件）
+    for video_path in &z.video_files {
+        if Path::new(video_path).exists() {
+            match create_embedded_file(video_path, &z.id) {
+                Ok(embed_file) => files.push(embed_file),
+                Err(e) => {
+                    log::warn!("视频嵌入失败: {}", e);
+                    // 继续处理其他文件，不中断流程
                 }
             }
+        }
+    }
 
-            // 嵌入PDF文件（跳过过大的文件）
-            for pdf_path in &z.pdf_files {
-                if Path::new(pdf_path).exists() {
-                    match create_embedded_file(pdf_path, &z.id) {
-                        Ok(embed_file) => all_embedded_files.push(embed_file),
-                        Err(e) => {
-                            println!("⚠️ PDF嵌入失败: {}", e);
-                            // 继续处理其他文件，不中断流程
-                        }
-                    }
+    // 嵌入PDF文件（跳过过大的文件）
+    for pdf_path in &z.pdf_files {
+        if Path::new(pdf_path).exists() {
+            match create_embedded_file(pdf_path, &z.id) {
+                Ok(embed_file) => files.push(embed_file),
+                Err(e) => {
+                    log::warn!("PDF嵌入失败: {}", e);
+                    // 继续处理其他文件，不中断流程
                 }
             }
+        }
+    }
 
-            // 嵌入Excel文件（跳过过大的文件）
-            for excel_path in &z.excel_files {
-                if Path::new(excel_path).exists() {
-                    match create_embedded_file(excel_path, &z.id) {
-                        Ok(embed_file) => all_embedded_files.push(embed_file),
-                        Err(e) => {
-                            println!("⚠️ Excel嵌入失败: {}", e);
-                            // 继续处理其他文件，不中断流程
-                        }
+    // 嵌入Excel文件（跳过过大的文件）
+    for excel_path in &z.excel_files {
+        if Path::new(excel_path).exists() {
+            match create_embedded_file(excel_path, &z.id) {
+                Ok(embed_file) => files.push(embed_file),
+                Err(e) => {
+                    log::warn!("Excel嵌入失败: {}", e);
+                    // 继续处理其他文件，不中断流程
+                }
+            }
+        }
+    }
+
+    // 嵌入动图原文件（汇总文档中只有抽帧联系表）
+    for image_path in &z.image_files {
+        if fs::read(image_path).is_ok_and(|bytes| is_animated_image(&bytes)) {
+            match create_embedded_file(image_path, &z.id) {
+                Ok(mut embed_file) => {
+                    if strip_metadata {
+                        embed_file.data = strip_image_metadata(&embed_file.data);
                     }
+                    files.push(embed_file);
+                }
+                Err(e) => {
+                    log::warn!("动图嵌入失败: {}", e);
+                    // 继续处理其他文件，不中断流程
                 }
             }
+        }
+    }
 
-            // 嵌入原始ZIP文件（如果启用）
-            if z.include_original_zip {
-                let zip_path = &z.stored_path;
-                if Path::new(zip_path).exists() {
-                    match create_embedded_file(zip_path, &z.id) {
-                        Ok(embed_file) => all_embedded_files.push(embed_file),
-                        Err(e) => {
-                            println!("⚠️ 原始ZIP嵌入失败: {}", e);
-                            // 继续处理，不中断流程
+    // 嵌入原始ZIP文件（如果启用）
+    if z.include_original_zip {
+        let zip_path = original_zip_path(z)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|e| {
+                log::warn!("打包原始文件夹失败: {:#}", e);
+                String::new()
+            });
+        if !zip_path.is_empty() && Path::new(&zip_path).exists() {
+            match create_embedded_file(&zip_path, &z.id) {
+                Ok(mut embed_file) => {
+                    if strip_metadata {
+                        match strip_zip_image_metadata(&embed_file.data) {
+                            Ok(stripped) => {
+                                embed_file.data = stripped;
+                                files.push(embed_file);
+                            }
+                            Err(e) => log::warn!("原始ZIP无法去除图片元数据，不嵌入: {:#}", e),
                         }
+                    } else {
+                        files.push(embed_file);
                     }
                 }
+                Err(e) => {
+                    log::warn!("原始ZIP嵌入失败: {}", e);
+                    // 继续处理，不中断流程
+                }
             }
         }
-
-        // 在不同章节之间添加空行，提高可读性
-        docx = docx.add_paragraph(Paragraph::new());
     }
 
-    // 返回文档和嵌入文件列表，让调用者处理最终的构建
-    Ok((docx, all_embedded_files))
+    files
 }
 
 fn create_embedded_file(path: &str, zip_id: &str) -> Result<EmbeddedFile> {
@@ -400,7 +426,7 @@ fn create_embedded_file(path: &str, zip_id: &str) -> Result<EmbeddedFile> {
     const MAX_FILE_SIZE: usize = 100 * 1024 * 1024;
     if file_size > MAX_FILE_SIZE {
         let file_size_mb = file_size as f64 / 1024.0 / 1024.0;
-        println!("⚠️ 跳过过大文件: {} ({:.1}MB > 100MB限制)",
+        log::warn!("跳过过大文件: {} ({:.1}MB > 100MB限制)",
                 safe_basename(path), file_size_mb);
         return Err(anyhow::anyhow!("文件过大，跳过嵌入: {} ({:.1}MB)",
                           safe_basename(path), file_size_mb));
@@ -418,7 +444,7 @@ fn create_embedded_file(path: &str, zip_id: &str) -> Result<EmbeddedFile> {
     let file_type = detect_file_type(&name);
     let content_type = get_content_type(&name);
 
-    println!("✓ 准备嵌入文件: {} ({:.1}MB)",
+    log::debug!("准备嵌入文件: {} ({:.1}MB)",
             safe_basename(path), data.len() as f64 / 1024.0 / 1024.0);
 
     Ok(EmbeddedFile {
@@ -493,6 +519,7 @@ fn get_content_type(filename: &str) -> String {
 /// 构建带嵌入文件的 DOCX（真正的 OLE 嵌入）
 fn build_docx_with_embeddings(
     base_docx: Docx,
+    rich_bodies: &[RichBody],
     embedded_files: &[EmbeddedFile]
 ) -> Result<Vec<u8>> {
     // 1. 首先生成基础的 DOCX
@@ -502,16 +529,28 @@ fn build_docx_with_embeddings(
         let mut cursor = Cursor::new(&mut base_bytes);
         xmldocx.pack(&mut cursor)?;
     }
+    let base_bytes = merge_rich_bodies(fix_docx_media_extensions(base_bytes)?, rich_bodies)?;
+
+    embed_files_into_docx_bytes(base_bytes, embedded_files)
+}
+
+/// 汇总文档：由 docx-rs 构建，或已由模板填充好的字节
+enum SummaryDocx {
+    Built(Box<Docx>, Vec<RichBody>),
+    Rendered(Vec<u8>),
+}
 
+/// 把附件以 OLE 对象嵌入已生成的 docx，嵌入失败时返回原文档
+fn embed_files_into_docx_bytes(base_bytes: Vec<u8>, embedded_files: &[EmbeddedFile]) -> Result<Vec<u8>> {
     // 2. 如果没有文件要嵌入，直接返回
     if embedded_files.is_empty() {
         return Ok(base_bytes);
     }
 
-    println!("=== OLE 文件嵌入模式 ===");
-    println!("找到 {} 个附件文件:", embedded_files.len());
+    log::debug!("OLE 文件嵌入模式");
+    log::debug!("找到 {} 个附件文件:", embedded_files.len());
     for (i, file) in embedded_files.iter().enumerate() {
-        println!("  {}. {} (大小: {:.1} MB, 类型: {})",
+        log::debug!("{}. {} (大小: {:.1} MB, 类型: {})",
             i + 1,
             file.name,
             file.data.len() as f64 / 1024.0 / 1024.0,
@@ -522,12 +561,11 @@ fn build_docx_with_embeddings(
     // 3. 执行真正的 OLE 嵌入
     match embed_ole_objects_into_docx(&base_bytes, embedded_files) {
         Ok(result) => {
-            println!("✓ OLE 对象嵌入成功！");
+            log::debug!("OLE 对象嵌入成功！");
             Ok(result)
         }
         Err(e) => {
-            println!("⚠ OLE 嵌入失败: {}", e);
-            println!("  返回基础文档以确保功能正常");
+            log::warn!("OLE 嵌入失败，返回基础文档: {}", e);
             Ok(base_bytes)
         }
     }
@@ -881,7 +919,7 @@ fn replace_filename_in_emf(mut emf_data: Vec<u8>, old_filename: &str, new_filena
     if let Some(pos) = emf_data.windows(old_utf16.len())
         .position(|window| window == old_utf16.as_slice()) {
 
-        println!("找到硬编码文件名 '{}' 在偏移 0x{:x}", old_filename, pos);
+        log::debug!("找到硬编码文件名 '{}' 在偏移 0x{:x}", old_filename, pos);
 
         // 可用空间 = 旧文件名的长度（不要向后查找null-null，那会覆盖EMF元数据！）
         let available_space = old_utf16.len();
@@ -893,12 +931,12 @@ fn replace_filename_in_emf(mut emf_data: Vec<u8>, old_filename: &str, new_filena
             .collect();
 
         let new_filename_bytes = new_filename.encode_utf16().count() * 2;
-        println!("可用空间: {} 字节, 原文件名需要: {} 字节",
+        log::debug!("可用空间: {} 字节, 原文件名需要: {} 字节",
                  available_space,
                  new_filename_bytes);
 
         if final_filename != new_filename {
-            println!("⚠ 文件名已截断: '{}' -> '{}'", new_filename, final_filename);
+            log::warn!("文件名已截断: '{}' -> '{}'", new_filename, final_filename);
         }
 
         // 替换文件名
@@ -911,9 +949,9 @@ fn replace_filename_in_emf(mut emf_data: Vec<u8>, old_filename: &str, new_filena
             emf_data[pos + i] = 0;
         }
 
-        println!("✓ 成功替换文件名为 '{}'", final_filename);
+        log::debug!("成功替换文件名为 '{}'", final_filename);
     } else {
-        println!("⚠ 未在EMF图标中找到硬编码文件名 '{}'", old_filename);
+        log::warn!("未在EMF图标中找到硬编码文件名 '{}'", old_filename);
     }
 
     emf_data
@@ -990,8 +1028,8 @@ fn add_ole_objects_to_document_xml(
 ) -> Result<String> {
     let mut result = document_xml.to_string();
 
-    println!("=== 添加 OLE 对象到 document.xml ===");
-    println!("嵌入文件数量: {}", embedded_files.len());
+    log::debug!("添加 OLE 对象到 document.xml");
+    log::debug!("嵌入文件数量: {}", embedded_files.len());
 
     // 按 zip_id 分组嵌入文件，但保持原始索引
     use std::collections::HashMap;
@@ -1002,12 +1040,12 @@ fn add_ole_objects_to_document_xml(
             .push((index, file));
     }
 
-    println!("按章节分组后的数量: {}", files_by_zip.len());
+    log::debug!("按章节分组后的数量: {}", files_by_zip.len());
 
     // 为每个章节生成OLE对象XML并插入
     for (zip_id, files) in files_by_zip.iter() {
         let marker = format!("EMBED_MARKER_{}", zip_id);
-        println!("处理章节: {}, 文件数: {}, 标记: {}", zip_id, files.len(), marker);
+        log::debug!("处理章节: {}, 文件数: {}, 标记: {}", zip_id, files.len(), marker);
 
         let mut objects_xml = String::new();
 
@@ -1017,7 +1055,7 @@ fn add_ole_objects_to_document_xml(
             let shape_id = format!("_x0000_i{}", 1025 + index);
             let object_id = format!("_146807572{}", index);
 
-            println!("  - 文件 {}: {} (rid={}, img_rid={})", index, file.name, ole_rid, img_rid);
+            log::debug!("文件 {}: {} (rid={}, img_rid={})", index, file.name, ole_rid, img_rid);
 
             objects_xml.push_str(&format!(r###"
 <w:p w14:paraId="{paraId}"><w:pPr><w:rPr><w:rFonts w:hint="default"/><w:lang w:val="en-US"/></w:rPr></w:pPr><w:r><w:rPr><w:rFonts w:hint="default"/><w:lang w:val="en-US"/></w:rPr><w:object><v:shape id="{shape_id}" o:spt="75" type="#_x0000_t75" style="height:65.25pt;width:72.4pt;" o:ole="t" filled="f" o:preferrelative="t" stroked="f" coordsize="21600,21600"><v:fill on="f" focussize="0,0"/><v:stroke on="f"/><v:imagedata r:id="{img_rid}" o:title=""/><o:lock v:ext="edit" aspectratio="t"/><w10:wrap type="none"/><w10:anchorlock/></v:shape><o:OLEObject Type="Embed" ProgID="Package" ShapeID="{shape_id}" DrawAspect="Icon" ObjectID="{object_id}" r:id="{ole_rid}"><o:LockedField>false</o:LockedField></o:OLEObject></w:object></w:r></w:p>
@@ -1033,17 +1071,17 @@ fn add_ole_objects_to_document_xml(
         // 在标记段落之后插入OLE对象
         // 直接搜索标记文本，不管XML标签格式
         if let Some(pos) = result.find(&marker) {
-            println!("  ✓ 找到标记文本位置: {}", pos);
+            log::debug!("找到标记文本位置: {}", pos);
             // 从标记位置向后查找段落结束标签
             if let Some(end_pos) = result[pos..].find("</w:p>") {
                 let insert_pos = pos + end_pos + "</w:p>".len();
-                println!("  ✓ 插入位置: {}", insert_pos);
+                log::debug!("插入位置: {}", insert_pos);
                 result.insert_str(insert_pos, &objects_xml);
             } else {
-                println!("  ✗ 未找到段落结束标签");
+                log::warn!("未找到段落结束标签");
             }
         } else {
-            println!("  ✗ 未找到标记文本: {}", marker);
+            log::warn!("未找到标记文本: {}", marker);
         }
     }
 
@@ -1117,12 +1155,20 @@ struct WordFields {
     title: String,
     issued_at: String,
     content: String,
+    /// 规范化后的下发时间（ISO 8601），解析失败时为空
+    #[serde(default)]
+    issued_at_iso: String,
+    #[serde(default)]
+    issued_at_confidence: IssuedAtConfidence,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct AdditionalDocx {
     id: String,
     name: String,
+    /// 在原始 ZIP 中的条目名；嵌套 ZIP 中的文档和旧批次为空
+    #[serde(default)]
+    entry: String,
     file_path: String,
     fields: WordFields,
     full_text: String,
@@ -1140,6 +1186,24 @@ struct ZipSummary {
     include_original_zip: bool,
     status: String,
     word: WordFields,
+    /// 主 docx 在原始 ZIP 中的条目名，用于导出时保留正文格式
+    #[serde(default)]
+    main_docx_entry: String,
+    /// 主 docx 的评分和是否手动指定；旧批次没有
+    #[serde(default)]
+    main_docx_decision: Option<MainDocxDecision>,
+    /// 人工修改字段前的抽取结果；没有修改过时为空
+    #[serde(default)]
+    original_word: Option<WordFields>,
+    /// 人工修改字段的记录
+    #[serde(default)]
+    field_edits: Vec<FieldEdit>,
+    /// 按校验规则发现的问题
+    #[serde(default)]
+    flags: Vec<ValidationFlag>,
+    /// 其他批次中已处理过的相同指令
+    #[serde(default)]
+    duplicate_of: Option<PreviousInstruction>,
     #[serde(default)]
     additional_docx_files: Vec<AdditionalDocx>,
     has_video: bool,
@@ -1150,10 +1214,136 @@ struct ZipSummary {
     pdf_files: Vec<String>,
     pdf_page_screenshot_files: Vec<String>,
     excel_files: Vec<String>,
+    /// 图片的 EXIF 信息（只包含有元数据的图片）
+    #[serde(default)]
+    image_metadata: Vec<ImageMetadata>,
+    /// 图片和 PDF 截图的 OCR 识别文本
+    #[serde(default)]
+    ocr_texts: Vec<OcrText>,
+    /// 导入过程中发现的问题；有错误级别的问题时可用 retry_failed_zips 重试
+    #[serde(default)]
+    diagnostics: Vec<ZipDiagnostic>,
+    /// 从邮件附件导入时的来源邮件
+    #[serde(default)]
+    mail: Option<MailSource>,
+}
+
+impl ZipSummary {
+    /// 刚开始导入、尚未解析的记录
+    fn pending(id: String, filename: String, source_path: String) -> Self {
+        ZipSummary {
+            id,
+            filename,
+            source_path,
+            stored_path: String::new(),
+            extracted_dir: String::new(),
+            include_original_zip: false,
+            status: "processing".to_string(),
+            word: WordFields::default(),
+            main_docx_entry: String::new(),
+            main_docx_decision: None,
+            original_word: None,
+            field_edits: vec![],
+            flags: vec![],
+            duplicate_of: None,
+            additional_docx_files: vec![],
+            has_video: false,
+            has_sample: false,
+            video_entries: vec![],
+            video_files: vec![],
+            image_files: vec![],
+            pdf_files: vec![],
+            pdf_page_screenshot_files: vec![],
+            excel_files: vec![],
+            image_metadata: vec![],
+            ocr_texts: vec![],
+            diagnostics: vec![],
+            mail: None,
+        }
+    }
+
+    /// 整个 ZIP 导入失败：记录错误并把状态设为 failed
+    fn fail(&mut self, code: DiagnosticCode, err: &anyhow::Error) {
+        self.diagnostics.push(ZipDiagnostic::new(code, "", format!("{err:#}")));
+        self.status = "failed".to_string();
+    }
+
+    fn has_errors(&self) -> bool {
+        self.status == "failed" || self.diagnostics.iter().any(|d| d.severity == DiagnosticSeverity::Error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DiagnosticSeverity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DiagnosticCode {
+    /// 旧版本只保存了 "failed: 原因" 文本
+    ImportFailed,
+    CopyFailed,
+    ZipUnreadable,
+    MainDocxUnreadable,
+    ExtractFailed,
+    AdditionalDocxFailed,
+    AdditionalDocxText,
+    AdditionalDocxImages,
+    NestedZipFailed,
+    MailUnreadable,
+    MailNoPackages,
+}
+
+impl DiagnosticCode {
+    fn severity(self) -> DiagnosticSeverity {
+        match self {
+            DiagnosticCode::AdditionalDocxText | DiagnosticCode::AdditionalDocxImages => DiagnosticSeverity::Warning,
+            _ => DiagnosticSeverity::Error,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            DiagnosticCode::ImportFailed => "导入失败",
+            DiagnosticCode::CopyFailed => "复制ZIP失败",
+            DiagnosticCode::ZipUnreadable => "无法读取ZIP",
+            DiagnosticCode::MainDocxUnreadable => "主文档解析失败",
+            DiagnosticCode::ExtractFailed => "解压附件失败",
+            DiagnosticCode::AdditionalDocxFailed => "附加文档处理失败",
+            DiagnosticCode::AdditionalDocxText => "附加文档文本无法提取",
+            DiagnosticCode::AdditionalDocxImages => "附加文档图片无法提取",
+            DiagnosticCode::NestedZipFailed => "嵌套ZIP处理失败",
+            DiagnosticCode::MailUnreadable => "无法读取邮件",
+            DiagnosticCode::MailNoPackages => "邮件中没有ZIP或docx附件",
+        }
+    }
+}
+
+/// ZIP 导入时的一条问题记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ZipDiagnostic {
+    code: DiagnosticCode,
+    severity: DiagnosticSeverity,
+    /// 出问题的 ZIP 内条目；整个 ZIP 的问题为空
+    #[serde(default)]
+    entry: String,
+    message: String,
+}
+
+impl ZipDiagnostic {
+    fn new(code: DiagnosticCode, entry: &str, message: String) -> Self {
+        Self { code, severity: code.severity(), entry: entry.to_string(), message }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct BatchSummary {
+    /// 批次数据版本，见 BATCH_MIGRATIONS；旧数据没有该字段，视为 0
+    #[serde(default)]
+    schema_version: u32,
     batch_id: String,
     created_at: i64,
     zips: Vec<ZipSummary>,
@@ -1162,6 +1352,10 @@ struct BatchSummary {
 #[derive(Default)]
 struct AppState {
     last_batch_id: std::sync::Mutex<Option<String>>,
+    /// 后台任务（导入、导出）的队列和状态
+    jobs: JobManager,
+    /// 文件夹监控设置
+    watch: FolderWatch,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -1185,9 +1379,39 @@ struct ExportZipSelection {
     selected_additional_docx: Vec<AdditionalDocxSelection>,
 }
 
+/// 汇总文档的分组方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ExportGroupBy {
+    /// 按下发日期
+    IssuedDate,
+    /// 按任务分类规则（执行类/签收类）
+    CategoryRule,
+    /// 按原始ZIP所在文件夹
+    SourceFolder,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct ExportBundleSelection {
     zips: Vec<ExportZipSelection>,
+    /// 用户排列的 ZIP 顺序（zip_id 列表），为空时按下发时间排序
+    #[serde(default)]
+    order: Vec<String>,
+    #[serde(default)]
+    group_by: Option<ExportGroupBy>,
+    /// 汇总文档字体
+    #[serde(default)]
+    style: SummaryDocStyle,
+    /// 用户提供的 .docx 模板路径，设置后按模板生成汇总文档
+    #[serde(default)]
+    template_path: Option<String>,
+}
+
+/// 导出时的一个分组，first_index 为该组第一个 ZIP 在 batch.zips 中的下标
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ExportGroup {
+    heading: String,
+    first_index: usize,
 }
 
 fn app_data_dir(app: &tauri::AppHandle) -> Result<PathBuf> {
@@ -1252,10 +1476,22 @@ fn default_export_bundle_name(now: OffsetDateTime) -> String {
     )
 }
 
+/// 选择已解压的指令包文件夹，与 ZIP 一样用 import_zips 导入
+#[tauri::command]
+fn pick_import_folders() -> Result<Vec<String>, String> {
+    let folders = rfd::FileDialog::new().pick_folders().unwrap_or_default();
+    Ok(folders
+        .into_iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect())
+}
+
 #[tauri::command]
 fn pick_zip_files() -> Result<Vec<String>, String> {
     let files = rfd::FileDialog::new()
+        .add_filter("ZIP或邮件", &["zip", "eml", "msg"])
         .add_filter("ZIP", &["zip"])
+        .add_filter("邮件", &["eml", "msg"])
         .pick_files()
         .unwrap_or_default();
     Ok(files
@@ -1264,2529 +1500,10614 @@ fn pick_zip_files() -> Result<Vec<String>, String> {
         .collect())
 }
 
-#[tauri::command]
-fn import_zips(app: tauri::AppHandle, state: State<'_, AppState>, paths: Vec<String>) -> Result<BatchSummary, String> {
-    let total_zips = paths.len();
+// ==================== 日志 ====================
+
+/// 日志目录（AppData/ArchiveBox/logs）和日志文件名
+const LOG_DIR: &str = "logs";
+const LOG_FILE_NAME: &str = "archivebox";
+/// 单个日志文件上限，超过后轮转；最多保留的历史日志文件数
+const LOG_MAX_FILE_SIZE: u128 = 5 * 1024 * 1024;
+const LOG_FILES_KEPT: usize = 5;
+const LOG_LEVELS: &[&str] = &["error", "warn", "info", "debug", "trace"];
+
+thread_local! {
+    /// 当前线程正在执行的任务 ID，写入每条日志，便于按任务筛选
+    static LOG_JOB_ID: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+/// 在作用域内把日志关联到某个任务，离开作用域时恢复原来的任务 ID
+struct LogJobScope {
+    previous: Option<String>,
+}
 
-    // 发送开始进度事件
-    let start_event = ProgressEvent::new("import", 0, total_zips, "开始导入", "正在准备导入ZIP文件");
-    if let Err(e) = emit_progress_handle(&app, start_event) {
-        eprintln!("发送进度事件失败: {}", e);
+impl LogJobScope {
+    fn enter(job_id: Option<&str>) -> Self {
+        let previous = LOG_JOB_ID.with(|id| id.replace(job_id.map(str::to_string)));
+        LogJobScope { previous }
     }
+}
 
-    // 清理旧的批次文件（保留最近的3个批次）
-    if let Err(e) = cleanup_old_batches_internal(&app, 3) {
-        eprintln!("清理旧批次失败: {}", e);
+impl Drop for LogJobScope {
+    fn drop(&mut self) {
+        LOG_JOB_ID.with(|id| *id.borrow_mut() = self.previous.take());
     }
+}
 
-    let now = OffsetDateTime::now_utc();
-    let batch_id = format!("batch_{}", now.unix_timestamp());
-    let batch_dir = batch_dir(&app, &batch_id).map_err(err_to_string)?;
+fn current_log_job_id() -> Option<String> {
+    LOG_JOB_ID.with(|id| id.borrow().clone())
+}
 
-    let mut zips = Vec::new();
-    for (index, p) in paths.into_iter().enumerate() {
-        // 发送当前ZIP处理进度
-        let progress_event = ProgressEvent::new(
-            "import",
-            index + 1,
-            total_zips,
-            "处理ZIP文件",
-            &format!("正在处理: {}", safe_basename(&p))
-        );
-        if let Err(e) = emit_progress_handle(&app, progress_event) {
-            eprintln!("发送进度事件失败: {}", e);
-        }
+/// 一行日志：[时间][级别][job=任务ID] 内容，不在任务中时省略任务段
+fn format_log_line(now: OffsetDateTime, level: log::Level, job_id: Option<&str>, message: &std::fmt::Arguments) -> String {
+    let job = job_id.map(|id| format!("[job={id}]")).unwrap_or_default();
+    format!(
+        "[{}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}][{}]{} {}",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+        now.millisecond(),
+        level,
+        job,
+        message
+    )
+}
 
-        let source_path = PathBuf::from(&p);
-        let filename = source_path
-            .file_name()
-            .and_then(|s| s.to_str())
-            .unwrap_or("UNKNOWN.zip")
-            .to_string();
-        let zip_id = Uuid::new_v4().to_string();
+fn log_dir(app: &tauri::AppHandle) -> Result<PathBuf> {
+    Ok(app_data_dir(app)?.join(LOG_DIR))
+}
 
-        let stored_zip_path = {
-            let dir = batch_dir.join("zips").join(&zip_id);
-            fs::create_dir_all(&dir).map_err(err_to_string)?;
-            let dst = dir.join(&filename);
-            fs::copy(&source_path, &dst).map_err(err_to_string)?;
-            dst
-        };
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LogSettings {
+    level: String,
+}
 
-        let mut summary = ZipSummary {
-            id: zip_id.clone(),
-            filename: filename.clone(),
-            source_path: p.clone(),
-            stored_path: stored_zip_path.to_string_lossy().to_string(),
-            extracted_dir: String::new(),
-            include_original_zip: false,
-            status: "processing".to_string(),
-            word: WordFields::default(),
-            additional_docx_files: vec![],
-            has_video: false,
-            has_sample: false,
-            video_entries: vec![],
-            video_files: vec![],
-            image_files: vec![],
-            pdf_files: vec![],
-            pdf_page_screenshot_files: vec![],
-            excel_files: vec![],
-        };
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings { level: "info".to_string() }
+    }
+}
 
-        let zip_scan = match scan_zip(&stored_zip_path) {
-            Ok(v) => v,
-            Err(e) => {
-                summary.status = format!("failed: {e:#}");
-                zips.push(summary);
-                continue;
-            }
-        };
+fn log_settings_path(app: &tauri::AppHandle) -> Result<PathBuf> {
+    Ok(app_data_dir(app)?.join("log_settings.json"))
+}
 
-        let (word, video_entries) = match extract_word_and_videos(&stored_zip_path, &zip_scan) {
-            Ok(v) => v,
-            Err(e) => {
-                summary.status = format!("failed: {e:#}");
-                zips.push(summary);
-                continue;
-            }
-        };
+/// 读取保存的日志级别；文件不存在或内容无效时使用默认级别
+fn load_log_settings(app: &tauri::AppHandle) -> LogSettings {
+    log_settings_path(app)
+        .and_then(|path| Ok(fs::read(path)?))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<LogSettings>(&bytes).ok())
+        .filter(|s| parse_log_level(&s.level).is_ok())
+        .unwrap_or_default()
+}
+
+fn parse_log_level(level: &str) -> Result<log::LevelFilter> {
+    if !LOG_LEVELS.contains(&level) {
+        return Err(anyhow!("无效的日志级别: {}", level));
+    }
+    level.parse().map_err(|_| anyhow!("无效的日志级别: {}", level))
+}
 
-        summary.word = word;
-        summary.has_sample = zip_scan.has_sample;
-        summary.video_entries = video_entries;
-        summary.has_video = !summary.video_entries.is_empty();
+/// 日志插件：同时输出到控制台和 AppData 下按大小轮转的日志文件。
+/// 插件本身放行全部级别，实际级别由 log::set_max_level 控制，运行中可调整；第三方库只记录警告以上
+fn log_plugin(app: &tauri::AppHandle) -> Result<tauri::plugin::TauriPlugin<tauri::Wry>> {
+    use tauri_plugin_log::{RotationStrategy, Target, TargetKind, TimezoneStrategy};
+
+    let dir = log_dir(app)?;
+    fs::create_dir_all(&dir).context("创建日志目录失败")?;
+    Ok(tauri_plugin_log::Builder::new()
+        .clear_targets()
+        .targets([
+            Target::new(TargetKind::Stdout),
+            Target::new(TargetKind::Folder { path: dir, file_name: Some(LOG_FILE_NAME.to_string()) }),
+        ])
+        .rotation_strategy(RotationStrategy::KeepSome(LOG_FILES_KEPT))
+        .max_file_size(LOG_MAX_FILE_SIZE)
+        .timezone_strategy(TimezoneStrategy::UseLocal)
+        .level(log::LevelFilter::Trace)
+        .filter(|metadata| metadata.target().starts_with("app_lib") || metadata.level() <= log::Level::Warn)
+        .format(|out, message, record| {
+            let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+            let job_id = current_log_job_id();
+            out.finish(format_args!("{}", format_log_line(now, record.level(), job_id.as_deref(), message)))
+        })
+        .build())
+}
 
-        // 解压用于预览（视频/图片/PDF）
-        if let Err(e) = extract_preview_files(&batch_dir, &zip_id, &stored_zip_path, &zip_scan, &mut summary) {
-            summary.status = format!("failed: {e:#}");
-            zips.push(summary);
-            continue;
-        }
+#[tauri::command]
+fn get_log_level(app: tauri::AppHandle) -> String {
+    load_log_settings(&app).level
+}
 
-        // 处理附加 docx
-        if !zip_scan.additional_docx_entries.is_empty() {
-            match process_additional_docx(&batch_dir, &zip_id, &stored_zip_path, &zip_scan.additional_docx_entries) {
-                Ok(additional_docx) => {
-                    summary.additional_docx_files = additional_docx;
-                }
-                Err(e) => {
-                    println!("警告：处理附加docx失败: {}", e);
-                    summary.additional_docx_files = vec![];
-                }
-            }
-        }
+/// 调整日志级别（error/warn/info/debug/trace），立即生效并保存到下次启动
+#[tauri::command]
+fn set_log_level(app: tauri::AppHandle, level: String) -> Result<String, String> {
+    let level = level.trim().to_lowercase();
+    let filter = parse_log_level(&level).map_err(err_to_string)?;
+    let settings = LogSettings { level: level.clone() };
+    let path = log_settings_path(&app).map_err(err_to_string)?;
+    fs::write(path, serde_json::to_vec_pretty(&settings).map_err(err_to_string)?).map_err(err_to_string)?;
+    log::set_max_level(filter);
+    log::info!("日志级别已调整为 {}", level);
+    Ok(level)
+}
 
-        // 处理嵌套 ZIP
-        if !zip_scan.nested_zip_entries.is_empty() {
-            match process_nested_zip(&batch_dir, &zip_id, &stored_zip_path, &zip_scan.nested_zip_entries, &mut summary) {
-                Ok(_) => {
-                    println!("成功处理 {} 个嵌套ZIP", zip_scan.nested_zip_entries.len());
-                }
-                Err(e) => {
-                    println!("警告：处理嵌套ZIP失败: {}", e);
-                }
+fn default_diagnostics_name(now: OffsetDateTime) -> String {
+    format!(
+        "诊断包_{}{:02}{:02}_{:02}{:02}{:02}.zip",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    )
+}
+
+/// 诊断包内容：logs/ 下的全部日志文件，以及可选的 batch.json、jobs.json 和 environment.json
+fn write_diagnostics_zip(
+    out: &Path,
+    logs: &Path,
+    batch: Option<&BatchSummary>,
+    jobs: &[JobInfo],
+    environment: &serde_json::Value,
+) -> Result<()> {
+    let file = fs::File::create(out).with_context(|| format!("创建诊断包失败: {}", out.display()))?;
+    let mut writer = ZipWriter::new(file);
+    let options = FileOptions::default();
+
+    let mut log_files: Vec<PathBuf> = match fs::read_dir(logs) {
+        Ok(entries) => entries.flatten().map(|e| e.path()).filter(|p| p.is_file()).collect(),
+        Err(_) => Vec::new(),
+    };
+    log_files.sort();
+    for path in log_files {
+        // 日志文件可能正在写入，读取失败时跳过，不影响其他内容
+        match fs::read(&path) {
+            Ok(bytes) => {
+                writer.start_file(format!("logs/{}", safe_basename(&path.to_string_lossy())), options)?;
+                writer.write_all(&bytes)?;
             }
+            Err(e) => log::warn!("读取日志文件失败 {}: {}", path.display(), e),
         }
+    }
 
-        summary.status = "completed".to_string();
-
-        zips.push(summary);
+    if let Some(batch) = batch {
+        writer.start_file("batch.json", options)?;
+        writer.write_all(&serde_json::to_vec_pretty(batch)?)?;
     }
+    writer.start_file("jobs.json", options)?;
+    writer.write_all(&serde_json::to_vec_pretty(jobs)?)?;
+    writer.start_file("environment.json", options)?;
+    writer.write_all(&serde_json::to_vec_pretty(environment)?)?;
+    writer.finish()?;
+    Ok(())
+}
 
-    let batch = BatchSummary {
-        batch_id: batch_id.clone(),
-        created_at: now.unix_timestamp(),
-        zips,
-    };
+fn diagnostics_environment(app: &tauri::AppHandle, now: OffsetDateTime) -> serde_json::Value {
+    serde_json::json!({
+        "app_version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "ocr_feature": cfg!(feature = "ocr"),
+        "batch_schema_version": BATCH_SCHEMA_VERSION,
+        "catalog_schema_version": CATALOG_MIGRATIONS.len(),
+        "log_level": log::max_level().to_string().to_lowercase(),
+        "app_data_dir": app_data_dir(app).map(|p| p.display().to_string()).unwrap_or_default(),
+        "generated_at": now.unix_timestamp(),
+    })
+}
+
+/// 收集诊断信息：把日志、指定批次的 batch.json、最近任务和运行环境打包成 ZIP，返回保存路径
+#[tauri::command]
+fn collect_diagnostics(app: tauri::AppHandle, batch_id: Option<String>) -> Result<String, String> {
+    let now = OffsetDateTime::now_utc();
+    let out = prompt_save_path(default_diagnostics_name(now), "zip", "ZIP")?;
 
-    let meta_path = batch_dir.join("batch.json");
-    fs::write(&meta_path, serde_json::to_vec_pretty(&batch).map_err(err_to_string)?)
+    // 批次可能已被清理，读取失败时仍然打包其余内容
+    let batch = batch_id.as_deref().and_then(|id| match read_batch(&app, id) {
+        Ok(batch) => Some(batch),
+        Err(e) => {
+            log::warn!("诊断包未包含批次 {}: {:#}", id, e);
+            None
+        }
+    });
+    let jobs = list_jobs(app.clone(), app.state::<AppState>()).unwrap_or_default();
+    let logs = log_dir(&app).map_err(err_to_string)?;
+    write_diagnostics_zip(&out, &logs, batch.as_ref(), &jobs, &diagnostics_environment(&app, now))
         .map_err(err_to_string)?;
+    log::info!("诊断包已保存: {}", out.display());
+    Ok(out.to_string_lossy().to_string())
+}
 
-    *state.last_batch_id.lock().unwrap() = Some(batch_id);
+// ==================== 日志结束 ====================
+
+// ==================== 后台任务 ====================
 
-    // 发送完成进度事件
-    let complete_event = ProgressEvent::complete("import");
-    if let Err(e) = emit_progress_handle(&app, complete_event) {
-        eprintln!("发送进度事件失败: {}", e);
+/// 同时运行的任务数，其余任务排队等待
+const MAX_RUNNING_JOBS: usize = 2;
+/// 批次目录中保留的已结束任务数
+const JOB_HISTORY_LIMIT: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JobKind {
+    Import,
+    ExportExcel,
+    ExportWord,
+    Ocr,
+}
+
+impl JobKind {
+    /// progress_update 事件中的 operation_type，与原有进度条一致
+    fn operation(self) -> &'static str {
+        match self {
+            JobKind::Import => "import",
+            JobKind::ExportExcel => "export_excel",
+            JobKind::ExportWord => "export_word",
+            JobKind::Ocr => "ocr",
+        }
     }
+}
 
-    Ok(batch)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
 }
 
-#[tauri::command]
-fn export_excel(app: tauri::AppHandle, batch_id: String) -> Result<String, String> {
-    let batch_dir = batch_dir(&app, &batch_id).map_err(err_to_string)?;
-    let mut batch: BatchSummary = read_batch(&batch_dir).map_err(err_to_string)?;
-    // 按下发时间排序
-    sort_zips_by_issued_at(&mut batch.zips);
-    export_excel_impl(&app, &batch)
+impl JobState {
+    fn is_finished(self) -> bool {
+        matches!(self, JobState::Completed | JobState::Failed | JobState::Cancelled)
+    }
 }
 
-#[tauri::command]
-fn export_excel_with_selection(
-    app: tauri::AppHandle,
-    batch_id: String,
-    zip_ids: Vec<String>,
-) -> Result<String, String> {
-    let batch_dir = batch_dir(&app, &batch_id).map_err(err_to_string)?;
-    let mut batch: BatchSummary = read_batch(&batch_dir).map_err(err_to_string)?;
-    if !zip_ids.is_empty() {
-        batch.zips.retain(|z| zip_ids.contains(&z.id));
+/// 任务的一个步骤；总进度按权重折算，耗时长的步骤权重大
+#[derive(Debug, Clone, Copy)]
+struct JobStep {
+    name: &'static str,
+    weight: f64,
+}
+
+const IMPORT_JOB_STEPS: &[JobStep] = &[
+    JobStep { name: "准备导入", weight: 1.0 },
+    JobStep { name: "处理ZIP文件", weight: 18.0 },
+    JobStep { name: "保存批次", weight: 1.0 },
+];
+
+const EXCEL_JOB_STEPS: &[JobStep] = &[
+    JobStep { name: "导出数据行", weight: 9.0 },
+    JobStep { name: "保存文件", weight: 1.0 },
+];
+
+/// Word 导出：生成文档（含图片处理）最耗时，其次是把附件嵌入为 OLE 对象
+const WORD_JOB_STEPS: &[JobStep] = &[
+    JobStep { name: "生成文档", weight: 15.0 },
+    JobStep { name: "嵌入文件", weight: 4.0 },
+    JobStep { name: "保存文档", weight: 1.0 },
+];
+
+const OCR_JOB_STEPS: &[JobStep] = &[
+    JobStep { name: "识别图片文字", weight: 19.0 },
+    JobStep { name: "更新检索索引", weight: 1.0 },
+];
+
+/// 任务状态快照：通过 job_update 事件发送给前端，结束后保存到批次目录供重新打开时查询
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JobInfo {
+    id: String,
+    kind: JobKind,
+    title: String,
+    state: JobState,
+    /// 0..=1，按步骤权重折算
+    progress: f64,
+    step: String,
+    message: String,
+    created_at: i64,
+    started_at: Option<i64>,
+    finished_at: Option<i64>,
+    /// 任务结果，例如导出的文件路径、导入的批次 ID
+    result: Option<serde_json::Value>,
+    error: Option<String>,
+}
+
+/// 取消和暂停标记，任务在 checkpoint 处响应
+#[derive(Default)]
+struct JobControl {
+    cancel: std::sync::atomic::AtomicBool,
+    paused: std::sync::Mutex<bool>,
+    resumed: std::sync::Condvar,
+}
+
+impl JobControl {
+    fn is_cancelled(&self) -> bool {
+        self.cancel.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
     }
-    // 按下发时间排序
-    sort_zips_by_issued_at(&mut batch.zips);
-    export_excel_impl(&app, &batch)
 }
 
-fn export_excel_impl(app: &tauri::AppHandle, batch: &BatchSummary) -> Result<String, String> {
-    let total_rows = batch.zips.len();
+/// 任务被取消时 checkpoint 返回的错误
+#[derive(Debug)]
+struct JobCancelled;
 
-    // 发送开始进度事件
-    let start_event = ProgressEvent::new("export_excel", 0, total_rows, "开始导出Excel", "正在准备数据");
-    if let Err(e) = emit_progress_handle(app, start_event) {
-        eprintln!("发送进度事件失败: {}", e);
+impl std::fmt::Display for JobCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("任务已取消")
     }
+}
 
-    let now = OffsetDateTime::now_utc();
-    let out = prompt_save_path(default_export_excel_name(now), "xlsx", "Excel")?;
+impl std::error::Error for JobCancelled {}
 
-    let mut workbook = Workbook::new();
-    let worksheet = workbook.add_worksheet();
+/// 步骤内的一段进度区间，例如 10 个 ZIP 中的第 3 个占“生成文档”步骤的 [0.2, 0.3)
+#[derive(Debug, Clone, Copy)]
+struct ProgressSpan {
+    step: usize,
+    start: f64,
+    end: f64,
+}
 
-    let header_format = Format::new().set_bold().set_align(FormatAlign::Center);
-    let headers = [
-        "序号",
-        "日期",
-        "编码",
-        "标题",
-        "类型",
-        "样本（视频OR图文）",
-        "是否有样本",
-        "是否多批次任务",
-        "下发时间",
-        "任务执行",
-        "备注",
-        "原始ZIP",
-    ];
-    for (i, h) in headers.iter().enumerate() {
-        worksheet
-            .write_string_with_format(0, i as u16, *h, &header_format)
-            .map_err(err_to_string)?;
+impl ProgressSpan {
+    fn item(step: usize, index: usize, count: usize) -> Self {
+        let count = count.max(1) as f64;
+        Self { step, start: index as f64 / count, end: (index + 1) as f64 / count }
     }
 
-    for (idx, z) in batch.zips.iter().enumerate() {
-        // 发送行处理进度
-        let progress_event = ProgressEvent::new(
-            "export_excel",
-            idx + 1,
-            total_rows,
-            "导出数据行",
-            &format!("正在处理: {}", z.word.instruction_no)
-        );
-        if let Err(e) = emit_progress_handle(app, progress_event) {
-            eprintln!("发送进度事件失败: {}", e);
-        }
+    fn at(&self, fraction: f64) -> f64 {
+        self.start + (self.end - self.start) * fraction.clamp(0.0, 1.0)
+    }
+}
 
-        let row = (idx + 1) as u32;
-        let date = format!(
-            "{:04}{:02}{:02}",
-            now.year(),
-            now.month() as u8,
-            now.day()
-        );
+/// 传给任务函数的上下文：汇报进度、响应暂停和取消。
+/// 未登记的上下文（detached）只发送 progress_update，供仍同步执行的旧命令复用同一套构建函数
+struct JobContext {
+    app: Option<tauri::AppHandle>,
+    id: Option<String>,
+    kind: JobKind,
+    steps: &'static [JobStep],
+    control: std::sync::Arc<JobControl>,
+}
 
-        // 判断是否有图文类内容（PDF/图片/附加docx/Excel）
-        let has_image_text = !z.image_files.is_empty()
-            || !z.pdf_files.is_empty()
-            || !z.pdf_page_screenshot_files.is_empty()
-            || !z.excel_files.is_empty()
-            || !z.additional_docx_files.is_empty();
+impl JobContext {
+    fn detached(app: Option<&tauri::AppHandle>, kind: JobKind, steps: &'static [JobStep]) -> Self {
+        Self { app: app.cloned(), id: None, kind, steps, control: Default::default() }
+    }
 
-        // 判断是否有视频
-        let has_video = !z.video_files.is_empty() || !z.video_entries.is_empty();
+    /// 第 step 步完成 fraction 时的总进度（0..=1）
+    fn overall_progress(&self, step: usize, fraction: f64) -> f64 {
+        let total: f64 = self.steps.iter().map(|s| s.weight).sum();
+        if total <= 0.0 {
+            return 0.0;
+        }
+        let step = step.min(self.steps.len() - 1);
+        let before: f64 = self.steps[..step].iter().map(|s| s.weight).sum();
+        ((before + self.steps[step].weight * fraction.clamp(0.0, 1.0)) / total).clamp(0.0, 1.0)
+    }
 
-        // 根据内容类型组合判断
-        let sample_kind = match (has_image_text, has_video) {
-            (true, true) => "图文+视频",
-            (true, false) => "图文",
-            (false, true) => "视频",
-            (false, false) => "否",  // 没有任何附件时显示"否"
+    fn report(&self, step: usize, fraction: f64, message: &str) {
+        let Some(app) = &self.app else {
+            return;
         };
+        let progress = self.overall_progress(step, fraction);
+        let step_name = self.steps[step.min(self.steps.len() - 1)].name;
+
+        if let Some(id) = &self.id {
+            let info = app.state::<AppState>().jobs.update(id, |j| {
+                j.progress = progress;
+                j.step = step_name.to_string();
+                j.message = message.to_string();
+            });
+            if let Some(info) = info {
+                emit_job_update(app, &info);
+            }
+        }
 
-        // "是否有样本" 列始终根据 sample_kind 内容判断
-        let has_sample = if sample_kind == "否" { "否" } else { "是" };
-
-        worksheet
-            .write_number(row, 0, (idx + 1) as f64)
-            .map_err(err_to_string)?;
-        worksheet
-            .write_string(row, 1, &date)
-            .map_err(err_to_string)?;
-        worksheet
-            .write_string(row, 2, z.word.instruction_no.trim())
-            .map_err(err_to_string)?;
-        worksheet
-            .write_string(row, 3, z.word.title.trim())
-            .map_err(err_to_string)?;
-        worksheet
-            .write_string(row, 4, z.word.title.trim())
-            .map_err(err_to_string)?;
-        worksheet
-            .write_string(row, 5, sample_kind)
-            .map_err(err_to_string)?;
-        worksheet
-            .write_string(row, 6, has_sample)
-            .map_err(err_to_string)?;
-        worksheet
-            .write_string(row, 7, "否")
-            .map_err(err_to_string)?;
-        worksheet
-            .write_string(row, 8, z.word.issued_at.trim())
-            .map_err(err_to_string)?;
+        // 旧进度条按 current/total 显示，这里用千分比；完成事件由任务结束时单独发送
+        let mut event = ProgressEvent::new(
+            self.kind.operation(),
+            ((progress * 1000.0) as usize).min(999),
+            1000,
+            step_name,
+            message,
+        );
+        if let Some(id) = &self.id {
+            event = event.with_job(id);
+        }
+        if let Err(e) = emit_progress_handle(app, event) {
+            log::warn!("发送进度事件失败: {}", e);
+        }
+    }
 
-        // 根据标题内容智能判断任务执行状态
-        let title = z.word.title.trim().to_lowercase();
-        let task_status = {
-            // 条件a：执行类关键词（优先级高）
-            let execution_keywords = ["人工审核", "删除", "禁言", "样本查删", "拦截", "反馈", "溯源", "加私", "专项", "清理", "限流", "屏蔽"];
-            let is_execution = execution_keywords.iter().any(|&keyword| title.contains(keyword));
-
-            if is_execution {
-                "已执行"
-            } else {
-                // 条件b：接收类关键词（优先级低）
-                let receive_keywords = ["工作", "指令", "通知", "提示", "压后台"];
-                let is_receive = receive_keywords.iter().any(|&keyword| title.contains(keyword));
+    fn report_items(&self, step: usize, done: usize, total: usize, message: &str) {
+        let fraction = if total == 0 { 1.0 } else { done as f64 / total as f64 };
+        self.report(step, fraction, message);
+    }
 
-                if is_receive {
-                    "已签收"
-                } else {
-                    ""  // 无匹配关键词时保持字段为空
-                }
-            }
-        };
+    fn report_span(&self, span: ProgressSpan, fraction: f64, message: &str) {
+        self.report(span.step, span.at(fraction), message);
+    }
 
-        worksheet
-            .write_string(row, 9, task_status)
-            .map_err(err_to_string)?;
-        worksheet.write_string(row, 10, "").map_err(err_to_string)?;
+    fn is_cancelled(&self) -> bool {
+        self.control.is_cancelled()
+    }
 
-        // 添加原始ZIP文件路径（直接显示为文本，用户可以复制路径手动打开）
-        // Windows 平台：尝试创建超链接；macOS 平台：直接显示路径文本
-        if cfg!(target_os = "windows") {
-            // Windows: 尝试使用 file:// 超链接
-            let file_url = format!("file:///{}", z.source_path.replace("\\", "/"));
-            worksheet
-                .write_url_with_text(row, 11, Url::new(&file_url), &z.source_path)
-                .map_err(err_to_string)?;
-        } else {
-            // macOS: 直接显示文件路径作为文本（Excel for Mac 对 file:// 支持不好）
-            worksheet
-                .write_string(row, 11, &z.source_path)
-                .map_err(err_to_string)?;
+    /// 暂停时在此等待，取消时返回 JobCancelled
+    fn checkpoint(&self) -> Result<()> {
+        let mut paused = self.control.paused.lock().unwrap();
+        while *paused && !self.control.is_cancelled() {
+            paused = self.control.resumed.wait(paused).unwrap();
         }
+        if self.control.is_cancelled() {
+            return Err(JobCancelled.into());
+        }
+        Ok(())
     }
 
-    workbook
-        .save(out.to_string_lossy().as_ref())
-        .map_err(err_to_string)?;
-
-    // 发送完成进度事件
-    let complete_event = ProgressEvent::complete("export_excel");
-    if let Err(e) = emit_progress_handle(app, complete_event) {
-        eprintln!("发送进度事件失败: {}", e);
+    /// 把当前线程的日志关联到本任务；并行处理时每个工作线程各自进入
+    fn log_scope(&self) -> LogJobScope {
+        LogJobScope::enter(self.id.as_deref())
     }
+}
 
-    Ok(out.to_string_lossy().to_string())
+/// 任务列表和运行名额。结束的任务留在内存里，重启后从批次目录读取
+#[derive(Default)]
+struct JobManager {
+    jobs: std::sync::Mutex<Vec<(JobInfo, std::sync::Arc<JobControl>)>>,
+    running: std::sync::Mutex<usize>,
+    slot_freed: std::sync::Condvar,
 }
 
-#[tauri::command]
-fn export_bundle_zip(app: tauri::AppHandle, batch_id: String) -> Result<String, String> {
-    let batch_dir = batch_dir(&app, &batch_id).map_err(err_to_string)?;
-    let mut batch: BatchSummary = read_batch(&batch_dir).map_err(err_to_string)?;
+impl JobManager {
+    fn insert(&self, info: JobInfo) -> std::sync::Arc<JobControl> {
+        let control = std::sync::Arc::new(JobControl::default());
+        self.jobs.lock().unwrap().push((info, control.clone()));
+        control
+    }
 
-    // 按下发时间排序
-    sort_zips_by_issued_at(&mut batch.zips);
+    fn get(&self, job_id: &str) -> Option<JobInfo> {
+        self.jobs.lock().unwrap().iter().find(|(j, _)| j.id == job_id).map(|(j, _)| j.clone())
+    }
 
-    let now = OffsetDateTime::now_utc();
-    let out = prompt_save_path(default_export_bundle_name(now), "zip", "ZIP")?;
+    /// 按创建时间倒序
+    fn list(&self) -> Vec<JobInfo> {
+        self.jobs.lock().unwrap().iter().rev().map(|(j, _)| j.clone()).collect()
+    }
 
-    let docx_bytes = build_summary_docx(&batch).map_err(err_to_string)?;
-    let bundle_bytes = build_bundle_zip_bytes(&batch, &docx_bytes).map_err(err_to_string)?;
+    fn update(&self, job_id: &str, f: impl FnOnce(&mut JobInfo)) -> Option<JobInfo> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let (info, _) = jobs.iter_mut().find(|(j, _)| j.id == job_id)?;
+        f(info);
+        Some(info.clone())
+    }
 
-    fs::write(&out, bundle_bytes).map_err(err_to_string)?;
-    Ok(out.to_string_lossy().to_string())
-}
+    fn control(&self, job_id: &str) -> Result<std::sync::Arc<JobControl>> {
+        let jobs = self.jobs.lock().unwrap();
+        match jobs.iter().find(|(j, _)| j.id == job_id) {
+            Some((j, _)) if j.state.is_finished() => Err(anyhow!("任务已结束")),
+            Some((_, control)) => Ok(control.clone()),
+            None => Err(anyhow!("任务不存在")),
+        }
+    }
 
-#[tauri::command]
-async fn export_bundle_zip_with_selection(
-    app: tauri::AppHandle,
-    batch_id: String,
-    selection: ExportBundleSelection,
-    _embed_files: Option<bool>,
-) -> Result<String, String> {
-    let batch_dir = batch_dir(&app, &batch_id).map_err(err_to_string)?;
-    let batch: BatchSummary = read_batch(&batch_dir).map_err(err_to_string)?;
-    let batch = apply_bundle_selection(&batch, selection).map_err(err_to_string)?;
+    /// 等待运行名额；排队期间暂停的任务不占名额，取消时返回 false
+    fn acquire_slot(&self, control: &JobControl) -> bool {
+        let mut running = self.running.lock().unwrap();
+        loop {
+            if control.is_cancelled() {
+                return false;
+            }
+            if *running < MAX_RUNNING_JOBS && !control.is_paused() {
+                *running += 1;
+                return true;
+            }
+            running = self.slot_freed.wait(running).unwrap();
+        }
+    }
 
-    if batch.zips.is_empty() {
-        return Err("未选择任何ZIP用于导出".to_string());
+    fn release_slot(&self) {
+        let mut running = self.running.lock().unwrap();
+        *running = running.saturating_sub(1);
+        self.slot_freed.notify_all();
     }
 
-    let total_steps = 4; // 准备 -> 收集文件 -> 生成文档 -> 保存
-    let current_zip_count = batch.zips.len();
+    /// 唤醒等待名额和暂停中的任务，让它们重新检查标记
+    fn wake(&self, control: &JobControl) {
+        drop(control.paused.lock().unwrap());
+        control.resumed.notify_all();
+        drop(self.running.lock().unwrap());
+        self.slot_freed.notify_all();
+    }
 
-    // 始终使用文件嵌入功能
-    println!("=== 开始文件嵌入导出 ===");
+    fn cancel(&self, job_id: &str) -> Result<JobInfo> {
+        let control = self.control(job_id)?;
+        control.cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        self.wake(&control);
+        self.update(job_id, |j| j.message = "正在取消…".to_string())
+            .ok_or_else(|| anyhow!("任务不存在"))
+    }
 
-    // 立即询问保存位置，让用户能够快速响应
-    // 使用 spawn_blocking 避免阻塞异步运行时
-    let now = OffsetDateTime::now_utc();
-    let default_name = default_export_bundle_name(now);
-    let out = tokio::task::spawn_blocking(move || {
-        prompt_save_path(default_name, "docx", "Word文档")
-    })
-    .await
-    .map_err(|e| format!("文件对话框错误: {}", e))??;
+    fn pause(&self, job_id: &str) -> Result<JobInfo> {
+        let control = self.control(job_id)?;
+        *control.paused.lock().unwrap() = true;
+        self.update(job_id, |j| j.state = JobState::Paused)
+            .ok_or_else(|| anyhow!("任务不存在"))
+    }
 
-    // 发送开始进度事件（在文件对话框完成后）
-    let start_event = ProgressEvent::new(
-        "export_word",
-        0,
-        total_steps * current_zip_count.max(1),
-        "开始导出Word",
-        "正在准备导出Word文档"
-    );
-    if let Err(e) = emit_progress_handle(&app, start_event) {
-        eprintln!("发送进度事件失败: {}", e);
+    fn resume(&self, job_id: &str) -> Result<JobInfo> {
+        let control = self.control(job_id)?;
+        *control.paused.lock().unwrap() = false;
+        self.wake(&control);
+        self.update(job_id, |j| {
+            j.state = if j.started_at.is_some() { JobState::Running } else { JobState::Queued };
+        })
+        .ok_or_else(|| anyhow!("任务不存在"))
     }
+}
 
-    // 步骤1: 收集需要嵌入的文件
-    for (idx, z) in batch.zips.iter().enumerate() {
-        let progress_event = ProgressEvent::new(
-            "export_word",
-            idx + 1,
-            total_steps * current_zip_count,
-            "收集文件",
-            &format!("正在处理: {}", z.word.instruction_no)
-        );
-        if let Err(e) = emit_progress_handle(&app, progress_event) {
-            eprintln!("发送进度事件失败: {}", e);
-        }
+fn emit_job_update(app: &tauri::AppHandle, info: &JobInfo) {
+    if let Err(e) = emit_event_handle(app, "job_update", info) {
+        log::warn!("发送任务状态失败: {}", e);
     }
+}
 
-    let (docx, embedded_files) = build_enhanced_summary_docx(&batch, true, &app).map_err(err_to_string)?;
+/// 登记并排队一个后台任务，立即返回任务 ID；状态通过 job_update 事件通知前端
+fn spawn_job<F>(app: &tauri::AppHandle, kind: JobKind, title: String, steps: &'static [JobStep], run: F) -> String
+where
+    F: FnOnce(&JobContext) -> Result<serde_json::Value> + Send + 'static,
+{
+    let info = JobInfo {
+        id: Uuid::new_v4().to_string(),
+        kind,
+        title,
+        state: JobState::Queued,
+        progress: 0.0,
+        step: "排队中".to_string(),
+        message: String::new(),
+        created_at: OffsetDateTime::now_utc().unix_timestamp(),
+        started_at: None,
+        finished_at: None,
+        result: None,
+        error: None,
+    };
+    let job_id = info.id.clone();
+    let control = app.state::<AppState>().jobs.insert(info.clone());
+    emit_job_update(app, &info);
+
+    let ctx = JobContext { app: Some(app.clone()), id: Some(job_id.clone()), kind, steps, control };
+    let app = app.clone();
+    std::thread::spawn(move || run_job(&app, ctx, run));
+    job_id
+}
 
-    // 步骤2: 生成基础Word文档
-    let progress_event = ProgressEvent::new(
-        "export_word",
-        current_zip_count + 1,
-        total_steps * current_zip_count,
-        "生成基础文档",
-        "正在生成基础Word文档"
-    );
-    if let Err(e) = emit_progress_handle(&app, progress_event) {
-        eprintln!("发送进度事件失败: {}", e);
-    }
+fn run_job<F>(app: &tauri::AppHandle, ctx: JobContext, run: F)
+where
+    F: FnOnce(&JobContext) -> Result<serde_json::Value>,
+{
+    let state = app.state::<AppState>();
+    let manager = &state.jobs;
+    let job_id = ctx.id.clone().unwrap_or_default();
+    let _log_scope = ctx.log_scope();
+    log::info!("任务排队: {:?}", ctx.kind);
+
+    let result = if manager.acquire_slot(&ctx.control) {
+        log::info!("任务开始");
+        let started = manager.update(&job_id, |j| {
+            j.started_at = Some(OffsetDateTime::now_utc().unix_timestamp());
+            j.state = if ctx.control.is_paused() { JobState::Paused } else { JobState::Running };
+        });
+        if let Some(info) = started {
+            emit_job_update(app, &info);
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| run(&ctx)))
+            .unwrap_or_else(|_| Err(anyhow!("任务异常终止")));
+        manager.release_slot();
+        result
+    } else {
+        Err(JobCancelled.into())
+    };
 
-    // 步骤3: 嵌入文件到Word文档
-    let progress_event = ProgressEvent::new(
-        "export_word",
-        current_zip_count + 2,
-        total_steps * current_zip_count,
-        "嵌入文件",
-        &format!("正在嵌入 {} 个文件", embedded_files.len())
-    );
-    if let Err(e) = emit_progress_handle(&app, progress_event) {
-        eprintln!("发送进度事件失败: {}", e);
+    let cancelled = ctx.is_cancelled();
+    let finished = manager.update(&job_id, |j| {
+        j.finished_at = Some(OffsetDateTime::now_utc().unix_timestamp());
+        match result {
+            // 导入取消时仍返回已完成部分的结果
+            Ok(value) => {
+                j.state = if cancelled { JobState::Cancelled } else { JobState::Completed };
+                if !cancelled {
+                    j.progress = 1.0;
+                }
+                j.result = Some(value).filter(|v| !v.is_null());
+            }
+            Err(e) if cancelled || e.is::<JobCancelled>() => j.state = JobState::Cancelled,
+            Err(e) => {
+                j.state = JobState::Failed;
+                j.error = Some(format!("{e:#}"));
+            }
+        }
+        j.message = match j.state {
+            JobState::Completed => "已完成".to_string(),
+            JobState::Cancelled => "已取消".to_string(),
+            _ => "失败".to_string(),
+        };
+    });
+    let Some(info) = finished else {
+        return;
+    };
+    match (&info.state, &info.error) {
+        (JobState::Failed, Some(error)) => log::warn!("任务失败: {}", error),
+        (state, _) => log::info!("任务结束: {:?}", state),
     }
 
-    let docx_bytes = build_docx_with_embeddings(docx, &embedded_files).map_err(err_to_string)?;
+    if let Err(e) = Catalog::open(app).and_then(|catalog| catalog.save_job(&info)) {
+        log::warn!("保存任务记录失败: {:#}", e);
+    }
+    emit_job_update(app, &info);
+    if let Err(e) = emit_progress_handle(app, ProgressEvent::complete(ctx.kind.operation()).with_job(&job_id)) {
+        log::warn!("发送进度事件失败: {}", e);
+    }
+}
 
-    // 步骤4: 保存文档
-    let progress_event = ProgressEvent::new(
-        "export_word",
-        current_zip_count + 3,
-        total_steps * current_zip_count,
-        "保存文档",
-        "正在保存Word文档"
-    );
-    if let Err(e) = emit_progress_handle(&app, progress_event) {
-        eprintln!("发送进度事件失败: {}", e);
+/// 任务列表：本次运行的任务加上批次目录中保存的历史任务，按创建时间倒序
+#[tauri::command]
+fn list_jobs(app: tauri::AppHandle, state: State<'_, AppState>) -> Result<Vec<JobInfo>, String> {
+    let mut jobs = state.jobs.list();
+    let saved = Catalog::open(&app)
+        .and_then(|catalog| catalog.load_jobs(JOB_HISTORY_LIMIT))
+        .map_err(err_to_string)?;
+    for job in saved {
+        if !jobs.iter().any(|j| j.id == job.id) {
+            jobs.push(job);
+        }
     }
+    jobs.sort_by_key(|j| std::cmp::Reverse(j.created_at));
+    jobs.truncate(JOB_HISTORY_LIMIT);
+    Ok(jobs)
+}
 
-    // 直接保存docx文件，不再创建zip包
-    fs::write(&out, docx_bytes).map_err(err_to_string)?;
-
-    // 发送完成进度事件
-    let complete_event = ProgressEvent::new(
-        "export_word",
-        total_steps * current_zip_count,
-        total_steps * current_zip_count,
-        "完成",
-        &format!("Word文档导出完成，包含 {} 个嵌入文件", embedded_files.len())
-    );
-    if let Err(e) = emit_progress_handle(&app, complete_event) {
-        eprintln!("发送进度事件失败: {}", e);
+#[tauri::command]
+fn get_job(app: tauri::AppHandle, state: State<'_, AppState>, job_id: String) -> Result<JobInfo, String> {
+    if let Some(job) = state.jobs.get(&job_id) {
+        return Ok(job);
     }
+    Catalog::open(&app)
+        .and_then(|catalog| catalog.load_job(&job_id))
+        .map_err(err_to_string)?
+        .ok_or_else(|| "任务不存在".to_string())
+}
 
-    println!("✓ Word文档导出完成！");
-    println!("生成的Word文档包含 {} 个嵌入文件", embedded_files.len());
-    println!("提示: 所有附件文件已完整嵌入到Word文档中");
+/// 取消排队中或正在运行的任务
+#[tauri::command]
+fn cancel_job(app: tauri::AppHandle, state: State<'_, AppState>, job_id: String) -> Result<JobInfo, String> {
+    let info = state.jobs.cancel(&job_id).map_err(err_to_string)?;
+    emit_job_update(&app, &info);
+    Ok(info)
+}
 
-    Ok(out.to_string_lossy().to_string())
+/// 暂停任务：正在运行的任务在下一个检查点停下，排队中的任务暂不开始
+#[tauri::command]
+fn pause_job(app: tauri::AppHandle, state: State<'_, AppState>, job_id: String) -> Result<JobInfo, String> {
+    let info = state.jobs.pause(&job_id).map_err(err_to_string)?;
+    emit_job_update(&app, &info);
+    Ok(info)
 }
 
-fn read_batch(batch_dir: &Path) -> Result<BatchSummary> {
-    let path = batch_dir.join("batch.json");
-    let data = fs::read(&path).with_context(|| format!("读取批次信息失败: {}", path.display()))?;
-    let batch: BatchSummary = serde_json::from_slice(&data)?;
-    Ok(batch)
+#[tauri::command]
+fn resume_job(app: tauri::AppHandle, state: State<'_, AppState>, job_id: String) -> Result<JobInfo, String> {
+    let info = state.jobs.resume(&job_id).map_err(err_to_string)?;
+    emit_job_update(&app, &info);
+    Ok(info)
 }
 
-fn apply_bundle_selection(batch: &BatchSummary, selection: ExportBundleSelection) -> Result<BatchSummary> {
-    let mut out = Vec::new();
+// ==================== 后台任务结束 ====================
 
-    for z in &batch.zips {
-        let Some(sel) = selection.zips.iter().find(|s| s.zip_id == z.id && s.include) else {
-            continue;
+/// 同时处理的 ZIP 数（解压和图片处理较占磁盘和内存，不宜过多）
+const IMPORT_CONCURRENCY: usize = 4;
+
+/// 在后台导入 ZIP（或已解压的文件夹），立即返回任务 ID；完成后任务结果为 { batch_id, zip_count }，可用 cancel_job 取消
+#[tauri::command]
+fn import_zips(app: tauri::AppHandle, paths: Vec<String>) -> Result<String, String> {
+    if paths.is_empty() {
+        return Err("没有选择ZIP文件".to_string());
+    }
+
+    let title = format!("导入 {} 个ZIP", paths.len());
+    let handle = app.clone();
+    Ok(spawn_job(&app, JobKind::Import, title, IMPORT_JOB_STEPS, move |ctx| {
+        // 取消时只保留已处理完的 ZIP；一个都没完成时没有结果
+        let Some(imported) = import_zips_job(&handle, ctx, &paths, None)? else {
+            return Ok(serde_json::Value::Null);
         };
+        *handle.state::<AppState>().last_batch_id.lock().unwrap() = Some(imported.batch_id.clone());
+        Ok(serde_json::json!({ "batch_id": imported.batch_id, "zip_count": imported.zips.len() }))
+    }))
+}
 
-        let mut z2 = z.clone();
-        z2.include_original_zip = sel.include_original_zip;
-        let mut selected_videos = Vec::new();
-        for &idx in &sel.selected_video_indices {
-            if let Some(p) = z.video_files.get(idx) {
-                selected_videos.push(p.clone());
-            }
-        }
-        z2.video_files = selected_videos;
+/// 新批次 ID：创建时间加随机后缀，同一秒内开始的多个导入（并行任务、自动导入）不会落到同一批次
+fn new_batch_id(now: OffsetDateTime) -> String {
+    format!("batch_{}_{}", now.unix_timestamp(), &Uuid::new_v4().simple().to_string()[..8])
+}
 
-        let mut selected_images = Vec::new();
-        for &idx in &sel.selected_image_indices {
-            if let Some(p) = z.image_files.get(idx) {
-                selected_images.push(p.clone());
-            }
-        }
-        z2.image_files = selected_images;
+/// 批次 ID 中的创建时间，兼容没有随机后缀的旧批次
+fn batch_id_timestamp(batch_id: &str) -> Option<i64> {
+    let rest = batch_id.strip_prefix("batch_")?;
+    rest.split('_').next()?.parse().ok()
+}
 
-        let mut selected_pdfs = Vec::new();
-        for &idx in &sel.selected_pdf_indices {
-            if let Some(p) = z.pdf_files.get(idx) {
-                selected_pdfs.push(p.clone());
-            }
-        }
-        z2.pdf_files = selected_pdfs;
+/// 一次导入的结果：所在批次和本次导入的 ZIP
+struct ImportedZips {
+    batch_id: String,
+    zips: Vec<ZipSummary>,
+}
 
-        let mut selected_pdf_screens = Vec::new();
-        for &idx in &sel.selected_pdf_page_screenshot_indices {
-            if let Some(p) = z.pdf_page_screenshot_files.get(idx) {
-                selected_pdf_screens.push(p.clone());
-            }
-        }
-        z2.pdf_page_screenshot_files = selected_pdf_screens;
+/// 并行导入所有 ZIP，放入新批次，或追加到 target 指定的已有批次；
+/// 取消后未开始的 ZIP 跳过，处理到一半的 ZIP 删除已解压的文件
+fn import_zips_job(
+    app: &tauri::AppHandle,
+    ctx: &JobContext,
+    paths: &[String],
+    target: Option<&str>,
+) -> Result<Option<ImportedZips>> {
+    use std::sync::atomic::Ordering;
 
-        let mut selected_excels = Vec::new();
-        for &idx in &sel.selected_excel_indices {
-            if let Some(p) = z.excel_files.get(idx) {
-                selected_excels.push(p.clone());
+    let total_zips = paths.len();
+    ctx.report(0, 0.0, "正在准备导入ZIP文件");
+
+    let now = OffsetDateTime::now_utc();
+    let batch_id = match target {
+        Some(batch_id) => batch_id.to_string(),
+        None => {
+            // 清理旧的批次文件（保留最近的3个批次）
+            if let Err(e) = cleanup_old_batches_internal(app, 3) {
+                log::warn!("清理旧批次失败: {}", e);
             }
+            new_batch_id(now)
         }
-        z2.excel_files = selected_excels;
+    };
+    let batch_dir = batch_dir(app, &batch_id)?;
 
-        // 过滤附加 docx（根据细粒度选择）
-        let mut selected_additional_docx = Vec::new();
-        for docx_sel in &sel.selected_additional_docx {
-            if let Some(doc) = z.additional_docx_files.get(docx_sel.docx_index) {
-                let mut filtered_doc = doc.clone();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(IMPORT_CONCURRENCY.min(total_zips).max(1))
+        .build()
+        .context("创建导入线程池失败")?;
+    let done = std::sync::atomic::AtomicUsize::new(0);
+    ctx.report(0, 1.0, &format!("共 {} 个ZIP", total_zips));
 
-                // 如果不包含文本，清空 full_text 和 fields
-                if !docx_sel.include_text {
-                    filtered_doc.full_text = String::new();
-                    filtered_doc.fields = WordFields::default();
+    let zips: Vec<Option<Vec<ZipSummary>>> = pool.install(|| {
+        paths
+            .par_iter()
+            .map(|p| {
+                let _log_scope = ctx.log_scope();
+                // 暂停时在这里等待，已开始的 ZIP 继续处理完
+                if ctx.checkpoint().is_err() {
+                    return None;
                 }
+                let summaries = import_source(&batch_dir, p, &ctx.control.cancel);
 
-                // 过滤图片
-                let mut selected_images = Vec::new();
-                for &img_idx in &docx_sel.selected_image_indices {
-                    if let Some(img_path) = doc.image_files.get(img_idx) {
-                        selected_images.push(img_path.clone());
+                let current = done.fetch_add(1, Ordering::Relaxed) + 1;
+                let message = match &summaries {
+                    Some(zips) if zips.iter().any(|z| z.status.starts_with("failed")) => {
+                        format!("处理失败: {}", safe_basename(p))
                     }
-                }
-                filtered_doc.image_files = selected_images;
+                    Some(_) => format!("已完成: {}", safe_basename(p)),
+                    None => format!("已取消: {}", safe_basename(p)),
+                };
+                ctx.report_items(1, current, total_zips, &message);
+                summaries
+            })
+            .collect()
+    });
+    // 保持用户选择的顺序，去掉取消的 ZIP；一封邮件可能带来多个 ZIP
+    let mut zips: Vec<ZipSummary> = zips.into_iter().flatten().flatten().collect();
+    let zip_ids: Vec<String> = zips.iter().map(|z| z.id.clone()).collect();
 
-                selected_additional_docx.push(filtered_doc);
-            }
+    if zips.is_empty() && ctx.is_cancelled() {
+        if target.is_none() {
+            let _ = fs::remove_dir_all(&batch_dir);
         }
-        z2.additional_docx_files = selected_additional_docx;
-
-        out.push(z2);
+        return Ok(None);
     }
 
-    // 按下发时间排序
-    sort_zips_by_issued_at(&mut out);
+    ctx.report(2, 0.0, "正在保存批次");
+    if let Err(e) = Catalog::open(app).and_then(|catalog| mark_previous_instructions(&catalog, &batch_id, &mut zips)) {
+        log::warn!("查询已处理过的指令失败: {:#}", e);
+    }
+    if target.is_some() {
+        Catalog::open(app)?.append_zips(&batch_id, &zips).context("保存批次信息失败")?;
+    } else {
+        write_batch(
+            app,
+            &BatchSummary {
+                schema_version: BATCH_SCHEMA_VERSION,
+                batch_id: batch_id.clone(),
+                created_at: now.unix_timestamp(),
+                zips,
+            },
+        )?;
+    }
+    // 追加到已有批次时重复编号要和原有的 ZIP 一起检查，所以保存后整批校验
+    let batch = refresh_validation_flags(app, &batch_id)?;
+    update_search_index(app, &batch);
+    record_seen_instructions(app, &batch_id, &batch.zips);
+    start_ocr_after_import(app, &batch_id);
+    let imported: std::collections::HashSet<&str> = zip_ids.iter().map(String::as_str).collect();
+    let zips = batch.zips.iter().filter(|z| imported.contains(z.id.as_str())).cloned().collect();
+    Ok(Some(ImportedZips { batch_id, zips }))
+}
 
-    Ok(BatchSummary {
-        batch_id: batch.batch_id.clone(),
-        created_at: batch.created_at,
-        zips: out,
-    })
+/// 复制整个文件夹（跳过系统生成的文件），每个文件都可取消
+fn copy_dir_cancellable(src: &Path, dst: &Path, cancel: &std::sync::atomic::AtomicBool) -> Result<()> {
+    fs::create_dir_all(dst).with_context(|| format!("无法创建: {}", dst.display()))?;
+    for entry in fs::read_dir(src).with_context(|| format!("无法读取文件夹: {}", src.display()))? {
+        let path = entry?.path();
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        if is_folder_artifact(&name.to_string_lossy()) {
+            continue;
+        }
+        if path.is_dir() {
+            copy_dir_cancellable(&path, &dst.join(name), cancel)?;
+        } else {
+            copy_file_cancellable(&path, &dst.join(name), cancel)?;
+        }
+    }
+    Ok(())
 }
 
-#[derive(Debug, Clone)]
-struct ZipScan {
-    docx_entry: String,
-    additional_docx_entries: Vec<usize>,  // 附加docx的ZIP索引列表
-    video_entries: Vec<usize>,  // 存储ZIP中的索引
-    image_entries: Vec<usize>,
-    pdf_entries: Vec<usize>,
-    excel_entries: Vec<usize>,
-    nested_zip_entries: Vec<usize>,  // 嵌套ZIP的索引列表
-    has_sample: bool,
+/// 分块复制，复制大文件时也能及时响应取消
+fn copy_file_cancellable(src: &Path, dst: &Path, cancel: &std::sync::atomic::AtomicBool) -> Result<()> {
+    let mut reader = fs::File::open(src).with_context(|| format!("无法打开: {}", src.display()))?;
+    let mut writer = fs::File::create(dst).with_context(|| format!("无法创建: {}", dst.display()))?;
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        if cancel.load(std::sync::atomic::Ordering::Relaxed) {
+            return Err(anyhow!("已取消"));
+        }
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n])?;
+    }
+    Ok(())
 }
 
-/// 识别主 docx：优先匹配与 ZIP 文件名相同的 docx
-fn identify_main_docx(zip_filename: &str, all_docx_names: &[String]) -> Option<String> {
-    if all_docx_names.is_empty() {
-        return None;
+/// 按文件类型导入：邮件拆出其中的 ZIP 附件，其余按 ZIP 或已解压的文件夹导入
+fn import_source(batch_dir: &Path, p: &str, cancel: &std::sync::atomic::AtomicBool) -> Option<Vec<ZipSummary>> {
+    if is_mail_file(Path::new(p)) {
+        import_mail(batch_dir, p, cancel, None)
+    } else {
+        import_one_zip(batch_dir, p, cancel).map(|z| vec![z])
     }
+}
 
-    // 提取 ZIP 文件名（不含扩展名和路径）
-    let zip_stem = Path::new(zip_filename)
-        .file_stem()
+/// 导入单个 ZIP；失败时返回带 failed 状态的记录，取消时删除该 ZIP 的全部文件并返回 None
+fn import_one_zip(batch_dir: &Path, p: &str, cancel: &std::sync::atomic::AtomicBool) -> Option<ZipSummary> {
+    import_zip_with_id(batch_dir, Uuid::new_v4().to_string(), p, cancel)
+}
+
+/// 按指定 ID 导入，重试失败的 ZIP 时沿用原来的 ID，导出选择等仍然有效
+fn import_zip_with_id(
+    batch_dir: &Path,
+    zip_id: String,
+    p: &str,
+    cancel: &std::sync::atomic::AtomicBool,
+) -> Option<ZipSummary> {
+    let source_path = PathBuf::from(p);
+    let filename = source_path
+        .file_name()
         .and_then(|s| s.to_str())
-        .unwrap_or("")
-        .to_lowercase();
+        .unwrap_or("UNKNOWN.zip")
+        .to_string();
+    let zip_dir = batch_dir.join("zips").join(&zip_id);
+    let cancelled = || {
+        let cancelled = cancel.load(std::sync::atomic::Ordering::Relaxed);
+        if cancelled {
+            let _ = fs::remove_dir_all(&zip_dir);
+        }
+        cancelled
+    };
 
-    // 尝试精确匹配（忽略大小写）
-    for docx_name in all_docx_names {
-        let docx_stem = Path::new(docx_name)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_lowercase();
+    let mut summary = ZipSummary::pending(zip_id.clone(), filename.clone(), p.to_string());
 
-        if docx_stem == zip_stem {
-            return Some(docx_name.clone());
+    // 已解压的文件夹整个复制，导出原始 ZIP 时再打包
+    let stored_zip_path = zip_dir.join(&filename);
+    let copied = fs::create_dir_all(&zip_dir).map_err(anyhow::Error::from).and_then(|_| {
+        if source_path.is_dir() {
+            copy_dir_cancellable(&source_path, &stored_zip_path, cancel)
+        } else {
+            copy_file_cancellable(&source_path, &stored_zip_path, cancel)
         }
+    });
+    if cancelled() {
+        return None;
+    }
+    if let Err(e) = copied {
+        summary.fail(DiagnosticCode::CopyFailed, &e);
+        return Some(summary);
     }
+    summary.stored_path = stored_zip_path.to_string_lossy().to_string();
 
-    // 尝试部分匹配：ZIP 名包含 docx 名或 docx 名包含 ZIP 名
-    for docx_name in all_docx_names {
-        let docx_stem = Path::new(docx_name)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_lowercase();
+    let zip_scan = match scan_zip(&stored_zip_path) {
+        Ok(v) => v,
+        Err(e) => {
+            summary.fail(DiagnosticCode::ZipUnreadable, &e);
+            return Some(summary);
+        }
+    };
+    summary.main_docx_entry = zip_scan.docx_entry.clone();
+    summary.main_docx_decision = Some(zip_scan.main_docx_decision.clone());
 
-        if zip_stem.contains(&docx_stem) || docx_stem.contains(&zip_stem) {
-            return Some(docx_name.clone());
+    let (word, video_entries) = match extract_word_and_videos(&stored_zip_path, &zip_scan) {
+        Ok(v) => v,
+        Err(e) => {
+            summary.diagnostics.push(ZipDiagnostic::new(
+                DiagnosticCode::MainDocxUnreadable,
+                &zip_scan.docx_entry,
+                format!("{e:#}"),
+            ));
+            summary.status = "failed".to_string();
+            return Some(summary);
         }
+    };
+
+    summary.word = word;
+    summary.has_sample = zip_scan.has_sample;
+    summary.video_entries = video_entries;
+    summary.has_video = !summary.video_entries.is_empty();
+    if cancelled() {
+        return None;
     }
 
-    // 如果都没匹配，返回第一个 docx
-    Some(all_docx_names[0].clone())
-}
+    // 解压用于预览（视频/图片/PDF）
+    if let Err(e) = extract_preview_files(batch_dir, &zip_id, &stored_zip_path, &zip_scan, &mut summary) {
+        summary.fail(DiagnosticCode::ExtractFailed, &e);
+        return Some(summary);
+    }
+    if cancelled() {
+        return None;
+    }
 
-/// 解码ZIP文件名（处理中文乱码）
-/// Windows创建的ZIP文件通常使用GBK编码，需要正确解码
-fn decode_zip_filename(name_bytes: &[u8]) -> String {
-    // 首先尝试UTF-8解码
-    if let Ok(utf8_name) = std::str::from_utf8(name_bytes) {
-        // 检查是否包含乱码字符（如□、�等）
-        if !utf8_name.chars().any(|c| c == '\u{FFFD}' || c == '□') {
-            return utf8_name.to_string();
+    // 处理附加 docx（单个文档失败只记录问题，不影响整个 ZIP）
+    if !zip_scan.additional_docx_entries.is_empty() {
+        match process_additional_docx(
+            batch_dir,
+            &zip_id,
+            &stored_zip_path,
+            &zip_scan.additional_docx_entries,
+            &mut summary.diagnostics,
+        ) {
+            Ok(additional_docx) => summary.additional_docx_files = additional_docx,
+            Err(e) => summary
+                .diagnostics
+                .push(ZipDiagnostic::new(DiagnosticCode::AdditionalDocxFailed, "", format!("{e:#}"))),
         }
     }
+    if cancelled() {
+        return None;
+    }
 
-    // UTF-8失败或有乱码，尝试GBK解码
-    let (decoded, _encoding, had_errors) = GBK.decode(name_bytes);
-    if !had_errors {
-        return decoded.to_string();
+    // 处理嵌套 ZIP
+    if !zip_scan.nested_zip_entries.is_empty() {
+        if let Err(e) = process_nested_zip(batch_dir, &zip_id, &stored_zip_path, &zip_scan.nested_zip_entries, &mut summary) {
+            summary
+                .diagnostics
+                .push(ZipDiagnostic::new(DiagnosticCode::NestedZipFailed, "", format!("{e:#}")));
+        }
+    }
+    if cancelled() {
+        return None;
     }
 
-    // 都失败了，使用UTF-8并替换无效字符
-    String::from_utf8_lossy(name_bytes).to_string()
+    summary.image_metadata = collect_image_metadata(&summary.image_files);
+    summary.status = "completed".to_string();
+    Some(summary)
 }
 
-/// 缩放图片并转换为 JPEG 格式以减小文件体积（优化版本）
-/// max_width: 最大宽度（像素）
-/// max_height: 最大高度（像素）
-/// quality: JPEG 质量（1-100）
-fn resize_image_to_jpeg(image_bytes: &[u8], max_width: u32, max_height: u32, quality: u8) -> Result<Vec<u8>> {
-    // 加载图片
-    let img = image::load_from_memory(image_bytes)
-        .context("无法加载图片")?;
+// ==================== 邮件导入 ====================
+
+/// 从邮件（.eml / .msg）导入的 ZIP 记录的来源信息
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct MailSource {
+    /// 邮件文件路径
+    path: String,
+    sender: String,
+    subject: String,
+    /// 收件时间（Unix 秒）；邮件中没有时间时为空
+    received_at: Option<i64>,
+}
 
-    let (orig_width, orig_height) = img.dimensions();
+struct MailAttachment {
+    name: String,
+    bytes: Vec<u8>,
+}
 
-    // 计算缩放后的尺寸（保持纵横比）
-    let (new_width, new_height) = if orig_width <= max_width && orig_height <= max_height {
-        // 图片已经足够小，不需要缩放
-        (orig_width, orig_height)
-    } else {
-        let width_ratio = max_width as f32 / orig_width as f32;
-        let height_ratio = max_height as f32 / orig_height as f32;
-        let ratio = width_ratio.min(height_ratio);
+struct ParsedMail {
+    sender: String,
+    subject: String,
+    received_at: Option<i64>,
+    attachments: Vec<MailAttachment>,
+}
 
-        ((orig_width as f32 * ratio) as u32, (orig_height as f32 * ratio) as u32)
-    };
+fn is_mail_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("eml") || e.eq_ignore_ascii_case("msg"))
+}
 
-    // 使用Lanczos3滤波器进行缩放（对文字友好，减少锯齿）
-    let resized = if new_width != orig_width || new_height != orig_height {
-        img.resize(new_width, new_height, image::imageops::FilterType::Lanczos3)
+fn parse_mail_file(path: &Path) -> Result<ParsedMail> {
+    let bytes = fs::read(path).with_context(|| format!("读取邮件失败: {}", path.display()))?;
+    let is_msg = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("msg"));
+    if is_msg {
+        parse_msg(&bytes)
     } else {
-        img
-    };
-
-    // 转换为 JPEG 格式，使用指定的质量参数
-    let mut jpeg_bytes = Vec::new();
-    {
-        let mut encoder = JpegEncoder::new_with_quality(&mut jpeg_bytes, quality);
-        encoder.encode(
-            resized.as_bytes(),
-            resized.width(),
-            resized.height(),
-            resized.color().into()
-        ).context("无法将图片转换为JPEG")?;
+        parse_eml(&bytes)
     }
+}
 
-    Ok(jpeg_bytes)
+/// MIME 邮件（.eml）：发件人、主题、Date 头和全部附件
+fn parse_eml(bytes: &[u8]) -> Result<ParsedMail> {
+    use mail_parser::MimeHeaders;
+
+    let message = mail_parser::MessageParser::default()
+        .parse(bytes)
+        .ok_or_else(|| anyhow!("不是有效的邮件文件"))?;
+    let sender = message
+        .from()
+        .and_then(|from| from.first())
+        .map(|addr| match (addr.name(), addr.address()) {
+            (Some(name), Some(address)) => format!("{} <{}>", name, address),
+            (name, address) => name.or(address).unwrap_or_default().to_string(),
+        })
+        .unwrap_or_default();
+    let attachments = message
+        .attachments()
+        .enumerate()
+        .map(|(i, part)| MailAttachment {
+            name: part.attachment_name().map(str::to_string).unwrap_or_else(|| format!("附件{}", i + 1)),
+            bytes: part.contents().to_vec(),
+        })
+        .collect();
+    Ok(ParsedMail {
+        sender,
+        subject: message.subject().unwrap_or_default().to_string(),
+        received_at: message.date().map(|d| d.to_timestamp()),
+        attachments,
+    })
 }
 
-/// 并行处理多个图片文件，支持进度报告和分批处理
-fn process_images_parallel_with_progress(
-    image_paths: &[String],
-    max_width: u32,
-    max_height: u32,
-    quality: u8,
-    app: &tauri::AppHandle,
-    operation_name: &str,
-) -> Result<Vec<(String, Vec<u8>)>> {
-    let paths: Vec<String> = image_paths.to_vec();
-    let count = paths.len();
+/// Outlook 邮件（.msg，复合文档）：属性按 __substg1.0_<属性号><类型> 分别存成流
+fn parse_msg(bytes: &[u8]) -> Result<ParsedMail> {
+    let mut comp = cfb::CompoundFile::open(Cursor::new(bytes)).context("不是有效的Outlook邮件")?;
+    let root = Path::new("/");
 
-    if count == 0 {
-        return Ok(Vec::new());
+    // PR_SENDER_NAME / PR_SENDER_SMTP_ADDRESS（旧版本只有 PR_SENDER_EMAIL_ADDRESS）
+    let sender_name = msg_string(&mut comp, root, 0x0C1A).unwrap_or_default();
+    let sender_address = msg_string(&mut comp, root, 0x5D01)
+        .or_else(|| msg_string(&mut comp, root, 0x0C1F))
+        .unwrap_or_default();
+    let sender = match (sender_name.is_empty(), sender_address.is_empty()) {
+        (false, false) if sender_name != sender_address => format!("{} <{}>", sender_name, sender_address),
+        (false, _) => sender_name,
+        _ => sender_address,
+    };
+    let subject = msg_string(&mut comp, root, 0x0037).unwrap_or_default();
+    // PR_MESSAGE_DELIVERY_TIME，没有时用 PR_CLIENT_SUBMIT_TIME
+    let received_at = msg_time(&mut comp, 0x0E06).or_else(|| msg_time(&mut comp, 0x0039));
+
+    let attach_dirs: Vec<PathBuf> = comp
+        .read_storage(root)?
+        .filter(|e| e.is_storage() && e.name().starts_with("__attach_version1.0_"))
+        .map(|e| e.path().to_path_buf())
+        .collect();
+    let mut attachments = Vec::new();
+    for (i, dir) in attach_dirs.iter().enumerate() {
+        // 只取以二进制保存的附件（PR_ATTACH_DATA_BIN），嵌入的邮件和 OLE 对象跳过
+        let Some(bytes) = msg_stream(&mut comp, &dir.join("__substg1.0_37010102")) else {
+            continue;
+        };
+        let name = msg_string(&mut comp, dir, 0x3707)
+            .or_else(|| msg_string(&mut comp, dir, 0x3704))
+            .unwrap_or_else(|| format!("附件{}", i + 1));
+        attachments.push(MailAttachment { name, bytes });
     }
 
-    // 分批处理，避免内存爆炸
-    let batch_size = std::cmp::min(5, count); // 每批最多处理5张图片
-    let mut all_results = Vec::new();
-
-    println!("开始分批处理 {} 张图片，每批 {} 张...", count, batch_size);
+    Ok(ParsedMail { sender, subject, received_at, attachments })
+}
 
-    for (batch_idx, chunk) in paths.chunks(batch_size).enumerate() {
-        // 发送批次开始进度
-        let batch_progress = ProgressEvent::new(
-            operation_name,
-            batch_idx * batch_size,
-            count,
-            "处理图片",
-            &format!("处理第 {}/{} 批，每批 {} 张", batch_idx + 1, (count + batch_size - 1) / batch_size, batch_size)
-        );
-        if let Err(e) = emit_progress_handle(app, batch_progress) {
-            eprintln!("发送批次进度事件失败: {}", e);
-        }
+fn msg_stream<F: Read + std::io::Seek>(comp: &mut cfb::CompoundFile<F>, path: &Path) -> Option<Vec<u8>> {
+    let mut stream = comp.open_stream(path).ok()?;
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).ok()?;
+    Some(buf)
+}
 
-        let batch_results: Result<Vec<_>> = chunk
-            .par_iter()
-            .enumerate()
-            .map(|(index_in_batch, path)| {
-                let global_index = batch_idx * batch_size + index_in_batch;
-
-                // 每处理完一张图片发送一次进度
-                let img_progress = ProgressEvent::new(
-                    operation_name,
-                    global_index,
-                    count,
-                    "处理图片",
-                    &format!("处理图片 {}/{}: {}", global_index + 1, count, safe_basename(path))
-                );
-                if let Err(e) = emit_progress_handle(app, img_progress) {
-                    eprintln!("发送图片进度事件失败: {}", e);
-                }
+/// 字符串属性：001F 为 UTF-16LE，001E 为本地代码页（中文 Outlook 多为 GBK）
+fn msg_string<F: Read + std::io::Seek>(comp: &mut cfb::CompoundFile<F>, dir: &Path, prop: u16) -> Option<String> {
+    let text = if let Some(bytes) = msg_stream(comp, &dir.join(format!("__substg1.0_{prop:04X}001F"))) {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        decode_zip_filename(&msg_stream(comp, &dir.join(format!("__substg1.0_{prop:04X}001E")))?)
+    };
+    let text = text.trim_end_matches('\0').trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
 
-                let bytes = fs::read(path)
-                    .with_context(|| format!("读取图片失败: {}", path))?;
-                let resized_bytes = resize_image_to_jpeg(&bytes, max_width, max_height, quality)
-                    .with_context(|| format!("调整图片大小失败: {}", path))?;
+/// 时间属性保存在 __properties_version1.0 中：32 字节头之后每 16 字节一项（标签、标志、8 字节值）
+fn msg_time<F: Read + std::io::Seek>(comp: &mut cfb::CompoundFile<F>, prop: u16) -> Option<i64> {
+    const PT_SYSTIME: u32 = 0x0040;
+    /// 1601-01-01 到 1970-01-01 的秒数
+    const FILETIME_UNIX_OFFSET: i64 = 11_644_473_600;
+
+    let props = msg_stream(comp, Path::new("/__properties_version1.0"))?;
+    let tag = ((prop as u32) << 16) | PT_SYSTIME;
+    props.get(32..)?.chunks_exact(16).find_map(|entry| {
+        if u32::from_le_bytes(entry[0..4].try_into().ok()?) != tag {
+            return None;
+        }
+        let filetime = u64::from_le_bytes(entry[8..16].try_into().ok()?);
+        Some((filetime / 10_000_000) as i64 - FILETIME_UNIX_OFFSET)
+    })
+}
 
-                Ok((path.clone(), resized_bytes))
-            })
-            .collect();
+/// 导入一封邮件：每个 ZIP 附件各自导入；其余附件中有 docx 时合在一起按文件夹导入。
+/// 附件先保存到批次的 mail 目录，重试时从那里重新导入；first_id 用于重试时沿用原来的 ID
+fn import_mail(
+    batch_dir: &Path,
+    p: &str,
+    cancel: &std::sync::atomic::AtomicBool,
+    first_id: Option<String>,
+) -> Option<Vec<ZipSummary>> {
+    let mail_path = Path::new(p);
+    let mail_name = safe_basename(p);
+    let mut ids = first_id.into_iter();
+    let mut next_id = move || ids.next().unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let parsed = match parse_mail_file(mail_path) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let mut z = ZipSummary::pending(next_id(), mail_name, p.to_string());
+            z.fail(DiagnosticCode::MailUnreadable, &e);
+            return Some(vec![z]);
+        }
+    };
+    let source = MailSource {
+        path: p.to_string(),
+        sender: parsed.sender,
+        subject: parsed.subject,
+        received_at: parsed.received_at,
+    };
 
-        match batch_results {
-            Ok(mut results) => {
-                all_results.append(&mut results);
-                println!("✓ 批次 {}/{} 完成，已处理 {} 张图片", batch_idx + 1, (count + batch_size - 1) / batch_size, all_results.len());
+    // ZIP 附件逐个保存；其余附件放进以邮件命名的文件夹
+    let mail_dir = batch_dir.join("mail").join(Uuid::new_v4().to_string());
+    let stem = mail_path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "邮件".to_string());
+    let loose_dir = mail_dir.join(&stem);
+    let mut packages = Vec::new();
+    let mut has_loose_docx = false;
+    let staged = (|| -> Result<()> {
+        for attachment in &parsed.attachments {
+            let name = safe_basename(&attachment.name);
+            if name.is_empty() || is_folder_artifact(&name) {
+                continue;
             }
-            Err(e) => {
-                return Err(e);
+            let lower = name.to_lowercase();
+            let dir = if lower.ends_with(".zip") { &mail_dir } else { &loose_dir };
+            fs::create_dir_all(dir)?;
+            let out = unique_path(dir, &name);
+            fs::write(&out, &attachment.bytes).with_context(|| format!("保存附件失败: {}", name))?;
+            if lower.ends_with(".zip") {
+                packages.push(out);
+            } else if lower.ends_with(".docx") {
+                has_loose_docx = true;
             }
         }
-
-        // 强制释放当前批次的内存
-        let _ = chunk;
+        Ok(())
+    })();
+    if let Err(e) = staged {
+        let _ = fs::remove_dir_all(&mail_dir);
+        let mut z = ZipSummary::pending(next_id(), mail_name, p.to_string());
+        z.mail = Some(source);
+        z.fail(DiagnosticCode::MailUnreadable, &e);
+        return Some(vec![z]);
+    }
+    if has_loose_docx {
+        packages.push(loose_dir);
+    }
+    if packages.is_empty() {
+        let _ = fs::remove_dir_all(&mail_dir);
+        let mut z = ZipSummary::pending(next_id(), mail_name, p.to_string());
+        z.mail = Some(source);
+        z.fail(DiagnosticCode::MailNoPackages, &anyhow!("邮件中没有ZIP或docx附件"));
+        return Some(vec![z]);
     }
 
-    println!("✓ 所有图片处理完成，共 {} 张", all_results.len());
-    Ok(all_results)
+    let mut zips = Vec::new();
+    for package in packages {
+        let Some(mut z) = import_zip_with_id(batch_dir, next_id(), &package.to_string_lossy(), cancel) else {
+            break;
+        };
+        z.mail = Some(source.clone());
+        zips.push(z);
+    }
+    if zips.is_empty() {
+        let _ = fs::remove_dir_all(&mail_dir);
+        return None;
+    }
+    Some(zips)
 }
 
-/// 并行处理多个图片文件（保留原函数用于其他地方）
-fn process_images_parallel(
-    image_paths: &[String],
-    max_width: u32,
-    max_height: u32,
-    quality: u8,
-    progress_callback: impl Fn(usize, usize, &str) + Send + Sync,
-) -> Result<Vec<(String, Vec<u8>)>> {
-    let paths: Vec<String> = image_paths.to_vec();
-    let count = paths.len();
+/// 导出和模板中使用的邮件来源字段：发件人、邮件主题、收件时间（本地时间）
+fn mail_field(z: &ZipSummary, name: &str) -> String {
+    let Some(mail) = &z.mail else {
+        return String::new();
+    };
+    match name {
+        "发件人" => mail.sender.clone(),
+        "邮件主题" => mail.subject.clone(),
+        "收件时间" => mail
+            .received_at
+            .and_then(|t| OffsetDateTime::from_unix_timestamp(t).ok())
+            .map(|t| {
+                let t = time::UtcOffset::current_local_offset().map_or(t, |offset| t.to_offset(offset));
+                format!(
+                    "{}-{:02}-{:02} {:02}:{:02}",
+                    t.year(),
+                    t.month() as u8,
+                    t.day(),
+                    t.hour(),
+                    t.minute()
+                )
+            })
+            .unwrap_or_default(),
+        _ => String::new(),
+    }
+}
 
-    let results: Result<Vec<_>> = paths
-        .into_par_iter()
-        .enumerate()
-        .map(|(index, path)| {
-            progress_callback(index, count, &safe_basename(&path));
+const MAIL_FIELDS: [&str; 3] = ["发件人", "邮件主题", "收件时间"];
 
-            let bytes = fs::read(&path)
-                .with_context(|| format!("读取图片失败: {}", path))?;
-            let resized_bytes = resize_image_to_jpeg(&bytes, max_width, max_height, quality)?;
+// ==================== 邮件导入结束 ====================
 
-            Ok((path, resized_bytes))
-        })
-        .collect();
+// ==================== 导入问题报告与重试 ====================
 
-    results
+/// 导入问题报告中的一个 ZIP（只包含有问题的 ZIP）
+#[derive(Debug, Clone, Serialize)]
+struct ImportReportZip {
+    zip_id: String,
+    filename: String,
+    instruction_no: String,
+    status: String,
+    diagnostics: Vec<ZipDiagnostic>,
 }
 
-fn scan_zip(zip_path: &Path) -> Result<ZipScan> {
-    let f = fs::File::open(zip_path)?;
-    let mut zip = ZipArchive::new(f)?;
+#[derive(Debug, Clone, Serialize)]
+struct ImportReport {
+    batch_id: String,
+    generated_at: String,
+    total_zips: usize,
+    failed_zips: usize,
+    error_count: usize,
+    warning_count: usize,
+    zips: Vec<ImportReportZip>,
+}
 
-    let mut all_docx_entries = Vec::new();  // 收集所有 docx 的索引和名称
-    let mut has_sample = false;
-    let mut video_entries = Vec::new();
-    let mut image_entries = Vec::new();
-    let mut pdf_entries = Vec::new();
-    let mut excel_entries = Vec::new();
-    let mut nested_zip_entries = Vec::new();  // 收集嵌套 ZIP 的索引
+fn build_import_report(batch: &BatchSummary, now: OffsetDateTime) -> ImportReport {
+    let zips: Vec<ImportReportZip> = batch
+        .zips
+        .iter()
+        .filter(|z| z.status == "failed" || !z.diagnostics.is_empty())
+        .map(|z| ImportReportZip {
+            zip_id: z.id.clone(),
+            filename: z.filename.clone(),
+            instruction_no: z.word.instruction_no.clone(),
+            status: z.status.clone(),
+            diagnostics: z.diagnostics.clone(),
+        })
+        .collect();
+    let count = |severity: DiagnosticSeverity| {
+        zips.iter()
+            .flat_map(|z| &z.diagnostics)
+            .filter(|d| d.severity == severity)
+            .count()
+    };
+    ImportReport {
+        batch_id: batch.batch_id.clone(),
+        generated_at: format_cover_time(now),
+        total_zips: batch.zips.len(),
+        failed_zips: batch.zips.iter().filter(|z| z.status == "failed").count(),
+        error_count: count(DiagnosticSeverity::Error),
+        warning_count: count(DiagnosticSeverity::Warning),
+        zips,
+    }
+}
 
-    for i in 0..zip.len() {
-        let file = zip.by_index(i)?;
-        // 保存原始文件名（用于后续从ZIP中读取）
-        let name = file.name().to_string();
-        let lower = name.to_ascii_lowercase();
+/// Excel 报告每条问题一行
+fn write_import_report_xlsx(report: &ImportReport, out: &Path) -> Result<()> {
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    let header_format = Format::new().set_bold().set_align(FormatAlign::Center);
+    let headers = ["序号", "ZIP文件", "指令编号", "状态", "级别", "问题类型", "条目", "说明"];
+    for (i, h) in headers.iter().enumerate() {
+        worksheet.write_string_with_format(0, i as u16, *h, &header_format)?;
+    }
 
-        if lower.ends_with(".docx") {
-            all_docx_entries.push((i, name));  // 收集所有 docx
-            continue;
+    let mut row = 0u32;
+    for z in &report.zips {
+        for d in &z.diagnostics {
+            row += 1;
+            let status = if z.status == "failed" { "失败" } else { "已导入" };
+            let severity = match d.severity {
+                DiagnosticSeverity::Error => "错误",
+                DiagnosticSeverity::Warning => "警告",
+            };
+            worksheet.write_number(row, 0, row as f64)?;
+            worksheet.write_string(row, 1, &z.filename)?;
+            worksheet.write_string(row, 2, z.instruction_no.trim())?;
+            worksheet.write_string(row, 3, status)?;
+            worksheet.write_string(row, 4, severity)?;
+            worksheet.write_string(row, 5, d.code.label())?;
+            worksheet.write_string(row, 6, &d.entry)?;
+            worksheet.write_string(row, 7, &d.message)?;
         }
+    }
 
-        if lower.ends_with("/") || lower.ends_with(".ds_store") {
-            continue;
-        }
+    workbook
+        .save(out)
+        .with_context(|| format!("保存报告失败: {}", out.display()))?;
+    Ok(())
+}
 
-        // Word之外都算样本
-        has_sample = true;
+/// 导出批次的导入问题报告；保存为 .json 时输出 JSON，否则输出 Excel
+#[tauri::command]
+fn export_import_report(app: tauri::AppHandle, batch_id: String) -> Result<String, String> {
+    let batch = read_batch(&app, &batch_id).map_err(err_to_string)?;
+    let now = OffsetDateTime::now_utc();
+    let report = build_import_report(&batch, now);
 
-        if lower.ends_with(".mp4") {
-            video_entries.push(i);  // 保存索引
-        } else if lower.ends_with(".pdf") {
-            pdf_entries.push(i);
-        } else if lower.ends_with(".png")
-            || lower.ends_with(".jpg")
-            || lower.ends_with(".jpeg")
-            || lower.ends_with(".gif")
-        {
-            image_entries.push(i);
-        } else if lower.ends_with(".xlsx") || lower.ends_with(".xls") {
-            excel_entries.push(i);
-        } else if lower.ends_with(".zip") {
-            nested_zip_entries.push(i);  // 收集嵌套 ZIP
-        }
-    }
+    let default_name = format!(
+        "导入报告_{}{:02}{:02}_{:02}{:02}{:02}.xlsx",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    );
+    let chosen = rfd::FileDialog::new()
+        .add_filter("Excel", &["xlsx"])
+        .add_filter("JSON", &["json"])
+        .set_file_name(&default_name)
+        .save_file();
+    let Some(path) = chosen else {
+        return Err("已取消".to_string());
+    };
 
-    if all_docx_entries.is_empty() {
-        return Err(anyhow!("ZIP内未找到docx"));
-    }
+    let is_json = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let out = if is_json {
+        let json = serde_json::to_string_pretty(&report).map_err(err_to_string)?;
+        fs::write(&path, json).map_err(err_to_string)?;
+        path
+    } else {
+        let path = ensure_extension(path, "xlsx");
+        write_import_report_xlsx(&report, &path).map_err(err_to_string)?;
+        path
+    };
+    Ok(out.to_string_lossy().to_string())
+}
 
-    // 识别主 docx
-    let zip_filename = zip_path
-        .file_name()
-        .and_then(|s| s.to_str())
-        .unwrap_or("unknown.zip");
+/// 在后台重新导入有错误的 ZIP，沿用原 ID；zip_ids 为空时重试批次中所有失败项。
+/// 任务结果为 { batch_id, retried, fixed }
+#[tauri::command]
+fn retry_failed_zips(app: tauri::AppHandle, batch_id: String, zip_ids: Option<Vec<String>>) -> Result<String, String> {
+    let batch = read_batch(&app, &batch_id).map_err(err_to_string)?;
+    let targets: Vec<ZipSummary> = batch
+        .zips
+        .into_iter()
+        .filter(|z| z.has_errors())
+        .filter(|z| zip_ids.as_ref().map_or(true, |ids| ids.is_empty() || ids.contains(&z.id)))
+        .collect();
+    if targets.is_empty() {
+        return Err("没有需要重试的ZIP".to_string());
+    }
 
-    let all_docx_names: Vec<String> = all_docx_entries.iter().map(|(_, name)| name.clone()).collect();
-    let main_docx_name = identify_main_docx(zip_filename, &all_docx_names)
-        .ok_or_else(|| anyhow!("无法识别主docx"))?;
+    let title = format!("重试 {} 个失败的ZIP", targets.len());
+    let handle = app.clone();
+    Ok(spawn_job(&app, JobKind::Import, title, IMPORT_JOB_STEPS, move |ctx| {
+        retry_zips_job(&handle, ctx, &batch_id, targets)
+    }))
+}
 
-    // 分离主 docx 和附加 docx
-    let mut main_docx_entry = None;
-    let mut additional_docx_entries = Vec::new();
+fn retry_zips_job(
+    app: &tauri::AppHandle,
+    ctx: &JobContext,
+    batch_id: &str,
+    targets: Vec<ZipSummary>,
+) -> Result<serde_json::Value> {
+    let batch_dir = batch_dir(app, batch_id)?;
+    let total = targets.len();
+    // 旧文件删除后必须导入完，取消只在 ZIP 之间生效
+    let never_cancel = std::sync::atomic::AtomicBool::new(false);
+    let mut retried = 0;
+    let mut fixed = 0;
+    ctx.report(0, 1.0, &format!("共 {} 个ZIP", total));
+
+    for (i, old) in targets.into_iter().enumerate() {
+        if ctx.checkpoint().is_err() {
+            break;
+        }
+        ctx.report_items(1, i, total, &format!("正在重试: {}", old.filename));
 
-    for (idx, name) in all_docx_entries {
-        if name == main_docx_name {
-            main_docx_entry = Some(name);
+        // 原文件不在时用导入时保存的副本；副本在 ZIP 目录里，先移出来
+        let zip_dir = batch_dir.join("zips").join(&old.id);
+        let staged = if Path::new(&old.stored_path).is_dir() {
+            batch_dir.join(format!("retry_{}", old.id))
         } else {
-            additional_docx_entries.push(idx);
+            batch_dir.join(format!("retry_{}.zip", old.id))
+        };
+        let source = if Path::new(&old.source_path).exists() {
+            PathBuf::from(&old.source_path)
+        } else if Path::new(&old.stored_path).exists() && fs::rename(&old.stored_path, &staged).is_ok() {
+            staged.clone()
+        } else {
+            PathBuf::from(&old.source_path)
+        };
+        // 用户保存的 PDF 截图不是导入产生的，重新导入后放回原处
+        let screens_dir = zip_dir.join("extracted").join("pdf_screens");
+        let staged_screens = batch_dir.join(format!("retry_{}_pdf_screens", old.id));
+        let screens_moved = screens_dir.is_dir() && fs::rename(&screens_dir, &staged_screens).is_ok();
+        let _ = fs::remove_dir_all(&zip_dir);
+        let restore_screens = || {
+            if screens_moved {
+                let _ = fs::create_dir_all(zip_dir.join("extracted"));
+                let _ = fs::rename(&staged_screens, &screens_dir);
+            }
+        };
+
+        // 整封邮件导入失败时重新读取邮件，邮件里多出来的 ZIP 追加到批次
+        if is_mail_file(&source) {
+            let zips = import_mail(&batch_dir, &old.source_path, &never_cancel, Some(old.id.clone()));
+            restore_screens();
+            let Some(mut zips) = zips else {
+                continue;
+            };
+            let mut summary = zips.remove(0);
+            summary.include_original_zip = old.include_original_zip;
+            retried += 1;
+            if !summary.has_errors() {
+                fixed += 1;
+            }
+            update_zip(app, batch_id, &old.id, |z| {
+                carry_over_user_data(z, &mut summary, OffsetDateTime::now_utc().date());
+                *z = summary;
+                Ok(())
+            })?;
+            if !zips.is_empty() {
+                Catalog::open(app)?.append_zips(batch_id, &zips).context("保存批次信息失败")?;
+            }
+            ctx.report_items(1, i + 1, total, &format!("已完成: {}", old.filename));
+            continue;
+        }
+
+        let summary = import_zip_with_id(&batch_dir, old.id.clone(), &source.to_string_lossy(), &never_cancel);
+        let _ = if staged.is_dir() { fs::remove_dir_all(&staged) } else { fs::remove_file(&staged) };
+        restore_screens();
+        let Some(mut summary) = summary else {
+            continue;
+        };
+        summary.filename = old.filename.clone();
+        summary.source_path = old.source_path.clone();
+        summary.include_original_zip = old.include_original_zip;
+        summary.mail = old.mail.clone();
+
+        retried += 1;
+        if !summary.has_errors() {
+            fixed += 1;
         }
+        update_zip(app, batch_id, &old.id, |z| {
+            carry_over_user_data(z, &mut summary, OffsetDateTime::now_utc().date());
+            *z = summary;
+            Ok(())
+        })?;
+        ctx.report_items(1, i + 1, total, &format!("已完成: {}", old.filename));
     }
 
-    Ok(ZipScan {
-        docx_entry: main_docx_entry.ok_or_else(|| anyhow!("主docx丢失"))?,
-        additional_docx_entries,
-        video_entries,
-        image_entries,
-        pdf_entries,
-        excel_entries,
-        nested_zip_entries,
-        has_sample,
-    })
+    ctx.report(2, 0.0, "正在更新检索索引");
+    let batch = refresh_validation_flags(app, batch_id)?;
+    update_search_index(app, &batch);
+    record_seen_instructions(app, batch_id, &batch.zips);
+    start_ocr_after_import(app, batch_id);
+    Ok(serde_json::json!({ "batch_id": batch_id, "retried": retried, "fixed": fixed }))
 }
 
-fn extract_word_and_videos(zip_path: &Path, scan: &ZipScan) -> Result<(WordFields, Vec<String>)> {
-    let f = fs::File::open(zip_path)?;
-    let mut zip = ZipArchive::new(f)?;
-
-    let docx_bytes = {
-        let mut file = zip.by_name(&scan.docx_entry)?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        buf
-    };
-    let fields = extract_fields_from_docx(&docx_bytes)?;
+// ==================== 导入问题报告与重试结束 ====================
 
-    // 返回空的 video_entries，因为现在使用索引而不是文件名
-    // 实际的文件信息在 extract_preview_files 中处理
-    Ok((fields, vec![]))
-}
+// ==================== 文件夹监控自动导入 ====================
 
-fn extract_preview_files(
-    batch_dir: &Path,
-    zip_id: &str,
-    zip_path: &Path,
-    scan: &ZipScan,
-    summary: &mut ZipSummary,
-) -> Result<()> {
-    let root = batch_dir.join("zips").join(zip_id).join("extracted");
-    summary.extracted_dir = root.to_string_lossy().to_string();
-    let videos_dir = root.join("videos");
-    let images_dir = root.join("images");
-    let pdf_dir = root.join("pdf");
-    let excel_dir = root.join("excel");
-    fs::create_dir_all(&videos_dir)?;
-    fs::create_dir_all(&images_dir)?;
-    fs::create_dir_all(&pdf_dir)?;
-    fs::create_dir_all(&excel_dir)?;
+/// 扫描监控文件夹的间隔
+const WATCH_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3);
 
-    let f = fs::File::open(zip_path)?;
-    let mut zip = ZipArchive::new(f)?;
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct WatchFolder {
+    path: String,
+    /// 导入到指定批次；为空时导入到当天的自动导入批次
+    #[serde(default)]
+    batch_id: Option<String>,
+}
 
-    for &index in &scan.video_entries {
-        let mut file = zip.by_index(index)?;
-        let name = decode_zip_filename(file.name_raw());  // 正确解码文件名
-        let basename = safe_basename(&name);
-        let out = unique_path(&videos_dir, &basename);
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        fs::write(&out, buf)?;
-        summary.video_files.push(out.to_string_lossy().to_string());
-    }
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct WatchSettings {
+    enabled: bool,
+    folders: Vec<WatchFolder>,
+    /// 文件大小和修改时间保持不变多少秒后才导入，避免导入还在复制中的文件
+    settle_secs: u64,
+}
 
-    for &index in &scan.image_entries {
-        let mut file = zip.by_index(index)?;
-        let name = decode_zip_filename(file.name_raw());
-        let basename = safe_basename(&name);
-        let out = unique_path(&images_dir, &basename);
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        fs::write(&out, buf)?;
-        summary.image_files.push(out.to_string_lossy().to_string());
+impl Default for WatchSettings {
+    fn default() -> Self {
+        WatchSettings { enabled: false, folders: Vec::new(), settle_secs: 10 }
     }
+}
 
-    for &index in &scan.pdf_entries {
-        let mut file = zip.by_index(index)?;
-        let name = decode_zip_filename(file.name_raw());
-        let basename = safe_basename(&name);
-        let out = unique_path(&pdf_dir, &basename);
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        fs::write(&out, buf)?;
-        summary.pdf_files.push(out.to_string_lossy().to_string());
+/// 监控设置和自动导入的串行锁
+#[derive(Default)]
+struct FolderWatch {
+    settings: std::sync::Mutex<WatchSettings>,
+    changed: std::sync::Condvar,
+    /// 自动导入逐个执行，避免同时创建两个当天批次
+    import_lock: std::sync::Mutex<()>,
+}
+
+impl FolderWatch {
+    /// 等待下一次扫描（设置修改后立即返回），返回当前设置
+    fn wait(&self, timeout: std::time::Duration) -> WatchSettings {
+        let settings = self.settings.lock().unwrap();
+        let (settings, _) = self.changed.wait_timeout(settings, timeout).unwrap();
+        settings.clone()
     }
 
-    for &index in &scan.excel_entries {
-        let mut file = zip.by_index(index)?;
-        let name = decode_zip_filename(file.name_raw());
-        let basename = safe_basename(&name);
-        let out = unique_path(&excel_dir, &basename);
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        fs::write(&out, buf)?;
-        summary.excel_files.push(out.to_string_lossy().to_string());
+    fn replace(&self, settings: WatchSettings) {
+        *self.settings.lock().unwrap() = settings;
+        self.changed.notify_all();
     }
+}
 
-    Ok(())
+/// 监控文件夹中的一个 ZIP；同一路径的文件被替换后大小或修改时间不同，视为新文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WatchedFile {
+    path: String,
+    size: u64,
+    /// 修改时间（Unix 毫秒）
+    modified: i64,
 }
 
-/// 从 docx 中提取图片
-fn extract_images_from_docx(docx_bytes: &[u8], output_dir: &Path) -> Result<Vec<String>> {
-    let cursor = Cursor::new(docx_bytes);
-    let mut zip = ZipArchive::new(cursor)?;
-    let mut image_paths = Vec::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatchObservation {
+    /// 第一次看到，或导入后又被替换
+    Detected,
+    /// 仍在写入，或还没稳定够 settle_secs
+    Waiting,
+    /// 已稳定，可以导入（每个版本只返回一次）
+    Ready,
+    /// 已交给导入任务
+    Handled,
+}
 
-    fs::create_dir_all(output_dir)?;
+struct TrackedFile {
+    size: u64,
+    modified: i64,
+    stable_since: std::time::Instant,
+    handled: bool,
+}
 
-    for i in 0..zip.len() {
-        let mut file = zip.by_index(i)?;
-        let name = file.name();
+/// 记录每次扫描看到的文件，判断文件何时停止增长
+#[derive(Default)]
+struct WatchTracker {
+    files: std::collections::HashMap<String, TrackedFile>,
+    /// 无法读取的文件夹只记录一次警告
+    failing_folders: std::collections::HashSet<String>,
+}
 
-        // 只提取 word/media/ 下的图片
-        if name.starts_with("word/media/") {
-            let lower = name.to_ascii_lowercase();
-            if lower.ends_with(".png") || lower.ends_with(".jpg") ||
-               lower.ends_with(".jpeg") || lower.ends_with(".gif") {
-                let basename = Path::new(name)
-                    .file_name()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("image.png");
-
-                let out_path = unique_path(output_dir, basename);
-                let mut buf = Vec::new();
-                file.read_to_end(&mut buf)?;
-                fs::write(&out_path, buf)?;
-                image_paths.push(out_path.to_string_lossy().to_string());
-            }
+impl WatchTracker {
+    fn observe(&mut self, file: &WatchedFile, now: std::time::Instant, settle: std::time::Duration) -> WatchObservation {
+        let fresh = TrackedFile { size: file.size, modified: file.modified, stable_since: now, handled: false };
+        let Some(tracked) = self.files.get_mut(&file.path) else {
+            self.files.insert(file.path.clone(), fresh);
+            return WatchObservation::Detected;
+        };
+        if tracked.size != file.size || tracked.modified != file.modified {
+            let replaced = tracked.handled;
+            *tracked = fresh;
+            return if replaced { WatchObservation::Detected } else { WatchObservation::Waiting };
+        }
+        if tracked.handled {
+            return WatchObservation::Handled;
         }
+        if now.duration_since(tracked.stable_since) < settle {
+            return WatchObservation::Waiting;
+        }
+        tracked.handled = true;
+        WatchObservation::Ready
     }
 
-    Ok(image_paths)
+    /// 忘记已经不在文件夹中的文件（被移走或删除）
+    fn retain(&mut self, seen: &std::collections::HashSet<String>) {
+        self.files.retain(|path, _| seen.contains(path));
+    }
 }
 
-/// 提取 docx 的完整文本内容（所有段落）
-fn extract_full_text_from_docx(docx_bytes: &[u8]) -> Result<String> {
-    let cursor = Cursor::new(docx_bytes);
-    let mut zip = ZipArchive::new(cursor)?;
-    let mut document_xml = zip
-        .by_name("word/document.xml")
-        .context("docx缺少word/document.xml")?;
-    let mut xml = String::new();
-    document_xml.read_to_string(&mut xml)?;
-
-    // 复用现有的段落文本提取逻辑
-    let text = extract_paragraph_texts(&xml)?;
-
-    Ok(text)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum WatchEventState {
+    Detected,
+    Importing,
+    Imported,
+    Failed,
 }
 
-/// 处理附加 docx 文件
-fn process_additional_docx(
-    batch_dir: &Path,
-    zip_id: &str,
-    zip_path: &Path,
-    additional_indices: &[usize],
-) -> Result<Vec<AdditionalDocx>> {
-    let f = fs::File::open(zip_path)?;
-    let mut zip = ZipArchive::new(f)?;
-    let mut results = Vec::new();
-
-    for &index in additional_indices {
-        let mut file = zip.by_index(index)?;
-        let name = decode_zip_filename(file.name_raw());
-
-        // 读取 docx 内容
-        let mut docx_bytes = Vec::new();
-        file.read_to_end(&mut docx_bytes)?;
-
-        // 解析结构化字段（可能失败，不影响整体流程）
-        let fields = extract_fields_from_docx(&docx_bytes)
-            .unwrap_or_else(|_| WordFields::default());
-
-        // 提取完整文本内容
-        let full_text = extract_full_text_from_docx(&docx_bytes)
-            .unwrap_or_else(|_| String::from("无法提取文本内容"));
-
-        // 提取图片
-        let docx_id = Uuid::new_v4().to_string();
-        let images_dir = batch_dir
-            .join("zips")
-            .join(zip_id)
-            .join("extracted")
-            .join("additional_docx")
-            .join(&docx_id);
-
-        let image_files = extract_images_from_docx(&docx_bytes, &images_dir)
-            .unwrap_or_else(|_| vec![]);
-
-        // 保存 docx 文件本身
-        let docx_dir = batch_dir
-            .join("zips")
-            .join(zip_id)
-            .join("extracted")
-            .join("additional_docx_files");
-        fs::create_dir_all(&docx_dir)?;
-        let docx_path = unique_path(&docx_dir, &name);
-        fs::write(&docx_path, &docx_bytes)?;
+/// watch_update 事件：监控文件夹中某个文件的处理进展
+#[derive(Debug, Clone, Serialize)]
+struct WatchEvent {
+    path: String,
+    state: WatchEventState,
+    batch_id: Option<String>,
+    job_id: Option<String>,
+    message: String,
+}
 
-        results.push(AdditionalDocx {
-            id: docx_id,
-            name: safe_basename(&name),
-            file_path: docx_path.to_string_lossy().to_string(),
-            fields,
-            full_text,
-            image_files,
-        });
+impl WatchEvent {
+    fn new(path: &str, state: WatchEventState, message: impl Into<String>) -> Self {
+        WatchEvent { path: path.to_string(), state, batch_id: None, job_id: None, message: message.into() }
     }
-
-    Ok(results)
 }
 
-/// 处理嵌套 ZIP 文件
-fn process_nested_zip(
-    batch_dir: &Path,
-    parent_zip_id: &str,
-    parent_zip_path: &Path,
-    nested_zip_indices: &[usize],
-    summary: &mut ZipSummary,
-) -> Result<()> {
-    let f = fs::File::open(parent_zip_path)?;
-    let mut parent_zip = ZipArchive::new(f)?;
+fn emit_watch_event(app: &tauri::AppHandle, event: WatchEvent) {
+    if let Err(e) = app.emit("watch_update", event) {
+        log::warn!("发送监控事件失败: {}", e);
+    }
+}
 
-    for &index in nested_zip_indices {
-        let mut file = parent_zip.by_index(index)?;
-        let nested_zip_name = decode_zip_filename(file.name_raw());
-        let nested_zip_basename = safe_basename(&nested_zip_name);
+fn watch_settings_path(app: &tauri::AppHandle) -> Result<PathBuf> {
+    Ok(app_data_dir(app)?.join("watch_settings.json"))
+}
 
-        // 读取嵌套 ZIP 内容
-        let mut nested_zip_bytes = Vec::new();
-        file.read_to_end(&mut nested_zip_bytes)?;
+fn load_watch_settings(app: &tauri::AppHandle) -> WatchSettings {
+    watch_settings_path(app)
+        .and_then(|path| Ok(fs::read(path)?))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
 
-        // 解析嵌套 ZIP
-        let cursor = Cursor::new(&nested_zip_bytes);
-        let mut nested_zip = ZipArchive::new(cursor)?;
+/// 整理用户提交的设置：去掉空路径和重复文件夹，检查文件夹存在
+fn normalize_watch_settings(mut settings: WatchSettings) -> Result<WatchSettings> {
+    let mut folders: Vec<WatchFolder> = Vec::new();
+    for mut folder in settings.folders {
+        folder.path = folder.path.trim().to_string();
+        folder.batch_id = folder.batch_id.map(|id| id.trim().to_string()).filter(|id| !id.is_empty());
+        if folder.path.is_empty() || folders.iter().any(|f| f.path == folder.path) {
+            continue;
+        }
+        if !Path::new(&folder.path).is_dir() {
+            return Err(anyhow!("文件夹不存在: {}", folder.path));
+        }
+        folders.push(folder);
+    }
+    settings.folders = folders;
+    settings.settle_secs = settings.settle_secs.clamp(1, 600);
+    Ok(settings)
+}
 
-        // 提取嵌套 ZIP 中的文件
-        for i in 0..nested_zip.len() {
-            let mut nested_file = nested_zip.by_index(i)?;
-            let nested_file_name = decode_zip_filename(nested_file.name_raw());
-            let lower = nested_file_name.to_ascii_lowercase();
+/// 文件夹中（不含子文件夹）的 ZIP，跳过隐藏文件和 Office 临时文件
+fn list_watch_zips(folder: &Path) -> Result<Vec<WatchedFile>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(folder).with_context(|| format!("无法读取文件夹: {}", folder.display()))? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let is_zip = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("zip"));
+        if !is_zip || name.starts_with('.') || name.starts_with("~$") {
+            continue;
+        }
+        // 文件可能刚好被移走，读不到元数据时下次再看
+        let Ok(meta) = fs::metadata(&path) else {
+            continue;
+        };
+        if !meta.is_file() {
+            continue;
+        }
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_millis() as i64);
+        files.push(WatchedFile { path: path.to_string_lossy().to_string(), size: meta.len(), modified });
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
 
-            if lower.ends_with("/") || lower.ends_with(".ds_store") {
-                continue;
-            }
+/// 当地时间当天零点的 Unix 时间戳
+fn local_day_start(now: OffsetDateTime) -> i64 {
+    now.replace_time(time::Time::MIDNIGHT).unix_timestamp()
+}
 
-            // 为文件名添加前缀（标识来源）
-            let prefixed_name = format!("[{}]/{}", nested_zip_basename, safe_basename(&nested_file_name));
-
-            // 根据文件类型分类处理
-            if lower.ends_with(".docx") {
-                // 处理为附加 docx
-                let mut docx_bytes = Vec::new();
-                nested_file.read_to_end(&mut docx_bytes)?;
-
-                let fields = extract_fields_from_docx(&docx_bytes)
-                    .unwrap_or_else(|_| WordFields::default());
-                let full_text = extract_full_text_from_docx(&docx_bytes)
-                    .unwrap_or_else(|_| String::from("无法提取文本内容"));
-
-                let docx_id = Uuid::new_v4().to_string();
-                let images_dir = batch_dir
-                    .join("zips")
-                    .join(parent_zip_id)
-                    .join("extracted")
-                    .join("nested_zip_docx")
-                    .join(&docx_id);
-
-                let image_files = extract_images_from_docx(&docx_bytes, &images_dir)
-                    .unwrap_or_else(|_| vec![]);
-
-                let docx_dir = batch_dir
-                    .join("zips")
-                    .join(parent_zip_id)
-                    .join("extracted")
-                    .join("nested_zip_docx_files");
-                fs::create_dir_all(&docx_dir)?;
-                let docx_path = unique_path(&docx_dir, &prefixed_name);
-                fs::write(&docx_path, &docx_bytes)?;
-
-                summary.additional_docx_files.push(AdditionalDocx {
-                    id: docx_id,
-                    name: prefixed_name,
-                    file_path: docx_path.to_string_lossy().to_string(),
-                    fields,
-                    full_text,
-                    image_files,
-                });
-            } else if lower.ends_with(".pdf") {
-                // 处理 PDF
-                let pdf_dir = batch_dir
-                    .join("zips")
-                    .join(parent_zip_id)
-                    .join("extracted")
-                    .join("nested_zip_pdfs");
-                fs::create_dir_all(&pdf_dir)?;
-                let pdf_path = unique_path(&pdf_dir, &prefixed_name);
-
-                let mut pdf_bytes = Vec::new();
-                nested_file.read_to_end(&mut pdf_bytes)?;
-                fs::write(&pdf_path, pdf_bytes)?;
-                summary.pdf_files.push(pdf_path.to_string_lossy().to_string());
-            } else if lower.ends_with(".mp4") {
-                // 处理视频
-                let video_dir = batch_dir
-                    .join("zips")
-                    .join(parent_zip_id)
-                    .join("extracted")
-                    .join("nested_zip_videos");
-                fs::create_dir_all(&video_dir)?;
-                let video_path = unique_path(&video_dir, &prefixed_name);
-
-                let mut video_bytes = Vec::new();
-                nested_file.read_to_end(&mut video_bytes)?;
-                fs::write(&video_path, video_bytes)?;
-                summary.video_files.push(video_path.to_string_lossy().to_string());
-            } else if lower.ends_with(".png") || lower.ends_with(".jpg") ||
-                      lower.ends_with(".jpeg") || lower.ends_with(".gif") {
-                // 处理图片
-                let image_dir = batch_dir
-                    .join("zips")
-                    .join(parent_zip_id)
-                    .join("extracted")
-                    .join("nested_zip_images");
-                fs::create_dir_all(&image_dir)?;
-                let image_path = unique_path(&image_dir, &prefixed_name);
-
-                let mut image_bytes = Vec::new();
-                nested_file.read_to_end(&mut image_bytes)?;
-                fs::write(&image_path, image_bytes)?;
-                summary.image_files.push(image_path.to_string_lossy().to_string());
-            } else if lower.ends_with(".xlsx") || lower.ends_with(".xls") {
-                // 处理 Excel
-                let excel_dir = batch_dir
-                    .join("zips")
-                    .join(parent_zip_id)
-                    .join("extracted")
-                    .join("nested_zip_excels");
-                fs::create_dir_all(&excel_dir)?;
-                let excel_path = unique_path(&excel_dir, &prefixed_name);
-
-                let mut excel_bytes = Vec::new();
-                nested_file.read_to_end(&mut excel_bytes)?;
-                fs::write(&excel_path, excel_bytes)?;
-                summary.excel_files.push(excel_path.to_string_lossy().to_string());
-            }
+/// 监控线程：按间隔扫描文件夹，稳定的 ZIP 交给导入任务
+fn run_folder_watcher(app: tauri::AppHandle) {
+    let mut tracker = WatchTracker::default();
+    loop {
+        let settings = app.state::<AppState>().watch.wait(WATCH_POLL_INTERVAL);
+        if !settings.enabled || settings.folders.is_empty() {
+            tracker = WatchTracker::default();
+            continue;
         }
+        scan_watch_folders(&app, &settings, &mut tracker);
     }
-
-    Ok(())
 }
 
-fn unique_path(dir: &Path, file_name: &str) -> PathBuf {
-    let base = Path::new(file_name)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("file");
-    let ext = Path::new(file_name).extension().and_then(|s| s.to_str());
-
-    let mut n = 0usize;
-    loop {
-        let candidate = if n == 0 {
-            match ext {
-                Some(ext) => dir.join(format!("{base}.{ext}")),
-                None => dir.join(base),
+fn scan_watch_folders(app: &tauri::AppHandle, settings: &WatchSettings, tracker: &mut WatchTracker) {
+    let now = std::time::Instant::now();
+    let settle = std::time::Duration::from_secs(settings.settle_secs);
+    let mut seen = std::collections::HashSet::new();
+    let mut ready: Vec<(Option<String>, WatchedFile)> = Vec::new();
+
+    for folder in &settings.folders {
+        let files = match list_watch_zips(Path::new(&folder.path)) {
+            Ok(files) => {
+                tracker.failing_folders.remove(&folder.path);
+                files
             }
-        } else {
-            match ext {
-                Some(ext) => dir.join(format!("{base}_{n}.{ext}")),
-                None => dir.join(format!("{base}_{n}")),
+            Err(e) => {
+                if tracker.failing_folders.insert(folder.path.clone()) {
+                    log::warn!("监控文件夹不可用: {:#}", e);
+                }
+                continue;
             }
         };
-        if !candidate.exists() {
-            return candidate;
+        for file in files {
+            if !seen.insert(file.path.clone()) {
+                continue;
+            }
+            match tracker.observe(&file, now, settle) {
+                WatchObservation::Detected => {
+                    emit_watch_event(app, WatchEvent::new(&file.path, WatchEventState::Detected, "等待文件写入完成"));
+                }
+                WatchObservation::Ready => ready.push((folder.batch_id.clone(), file)),
+                WatchObservation::Waiting | WatchObservation::Handled => {}
+            }
         }
-        n += 1;
     }
-}
-
-fn extract_fields_from_docx(docx_bytes: &[u8]) -> Result<WordFields> {
-    let cursor = Cursor::new(docx_bytes);
-    let mut zip = ZipArchive::new(cursor)?;
-    let mut document_xml = zip
-        .by_name("word/document.xml")
-        .context("docx缺少word/document.xml")?;
-    let mut xml = String::new();
-    document_xml.read_to_string(&mut xml)?;
-
-    let text = extract_paragraph_texts(&xml)?;
-
-    // 处理字段提取，特别处理指令内容的多行情况
-    let fields = extract_all_fields(&text)?;
+    tracker.retain(&seen);
+    if ready.is_empty() {
+        return;
+    }
 
-    fn first_nonempty(values: Option<&Vec<String>>) -> String {
-        let Some(values) = values else { return String::new() };
-        for v in values {
-            let t = v.trim();
-            if !t.is_empty() {
-                return t.to_string();
-            }
+    // 重启后再次看到的文件按目录中的记录跳过
+    let catalog = match Catalog::open(app) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            log::warn!("打开批次目录失败，暂不自动导入: {:#}", e);
+            return;
+        }
+    };
+    let mut groups: std::collections::BTreeMap<Option<String>, Vec<WatchedFile>> = Default::default();
+    for (target, file) in ready {
+        match catalog.is_watched_file_imported(&file) {
+            Ok(true) => log::debug!("已自动导入过，跳过: {}", file.path),
+            Ok(false) => groups.entry(target).or_default().push(file),
+            Err(e) => log::warn!("查询自动导入记录失败 {}: {:#}", file.path, e),
         }
-        String::new()
     }
+    for (target, files) in groups {
+        start_watch_import(app, target, files);
+    }
+}
 
-    // 对于指令内容，直接使用提取的完整内容，不再选择"最佳"
-    fn get_instruction_content(values: Option<&Vec<String>>) -> String {
-        let Some(values) = values else { return String::new() };
-        for v in values {
-            let t = v.trim();
-            if !t.is_empty() && t != "序号" {
-                return t.to_string();
+fn start_watch_import(app: &tauri::AppHandle, target: Option<String>, files: Vec<WatchedFile>) {
+    let title = format!("自动导入 {} 个ZIP", files.len());
+    let handle = app.clone();
+    spawn_job(app, JobKind::Import, title, IMPORT_JOB_STEPS, move |ctx| {
+        let result = watch_import_job(&handle, ctx, target.as_deref(), &files);
+        if let Err(e) = &result {
+            if !ctx.is_cancelled() {
+                for file in &files {
+                    emit_watch_event(&handle, WatchEvent::new(&file.path, WatchEventState::Failed, format!("{e:#}")));
+                }
             }
         }
-        String::new()
-    }
-
-    Ok(WordFields {
-        instruction_no: first_nonempty(fields.get("指令编号")),
-        title: first_nonempty(fields.get("指令标题")),
-        issued_at: first_nonempty(fields.get("下发时间")),
-        content: get_instruction_content(fields.get("指令内容")),
-    })
+        result
+    });
 }
 
-// 检查是否是表格表头或表格内容
-fn is_table_header_or_content(line: &str) -> bool {
-    let trimmed = line.trim();
-
-    // 常见的表格表头模式
-    let table_headers = [
-        "标题", "链接", "网站", "属地", "处置方式", "序号", "时间", "内容", "类型",
-        "编号", "名称", "地址", "来源", "状态", "备注", "操作", "详情",
-        "链接地址", "网站名称", "处理方式", "处理结果", "处理时间"
-    ];
-
-    // 检查是否包含表格表头关键词
-    for header in &table_headers {
-        if trimmed.contains(header) && trimmed.len() <= 20 {
-            return true;
+/// 导入到指定批次或当天批次，并记录已导入的文件；取消后未导入的文件等文件变化或下次启动时再导入
+fn watch_import_job(
+    app: &tauri::AppHandle,
+    ctx: &JobContext,
+    target: Option<&str>,
+    files: &[WatchedFile],
+) -> Result<serde_json::Value> {
+    let state = app.state::<AppState>();
+    let _serial = state.watch.import_lock.lock().unwrap();
+
+    let catalog = Catalog::open(app)?;
+    let batch_id = match target {
+        Some(batch_id) if catalog.batch_exists(batch_id)? => Some(batch_id.to_string()),
+        _ => {
+            if let Some(batch_id) = target {
+                log::warn!("监控文件夹指定的批次 {} 不存在，改为导入到当天批次", batch_id);
+            }
+            let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+            catalog.latest_daily_watch_batch(local_day_start(now))?
         }
-    }
+    };
+    let daily = target.is_none() || batch_id.as_deref() != target;
 
-    // 检查是否是纯数字或编号（表格中的常见内容）
-    if trimmed.chars().all(|c| c.is_ascii_digit() || c == '.' || c == '、') {
-        return true;
+    for file in files {
+        let mut event = WatchEvent::new(&file.path, WatchEventState::Importing, "正在导入");
+        event.batch_id = batch_id.clone();
+        event.job_id = ctx.id.clone();
+        emit_watch_event(app, event);
     }
 
-    // 检查是否是URL链接（表格中常见）
-    if trimmed.starts_with("http://") || trimmed.starts_with("https://") || trimmed.starts_with("www.") {
-        return true;
-    }
+    let paths: Vec<String> = files.iter().map(|f| f.path.clone()).collect();
+    let Some(imported) = import_zips_job(app, ctx, &paths, batch_id.as_deref())? else {
+        return Ok(serde_json::Value::Null);
+    };
 
-    // 检查是否包含大量分隔符（表格特征）
-    let tab_count = trimmed.matches('\t').count();
-    let space_count = trimmed.matches("  ").count();
-    if tab_count >= 2 || space_count >= 3 {
-        return true;
+    let imported_at = OffsetDateTime::now_utc().unix_timestamp();
+    for z in &imported.zips {
+        let Some(file) = files.iter().find(|f| f.path == z.source_path) else {
+            continue;
+        };
+        // 导入失败的 ZIP 同样记录，问题在导入报告中查看，用“重试失败项”重新导入
+        catalog.mark_watched_file(file, &imported.batch_id, &z.id, imported_at, daily)?;
+        let (state, message) = if z.status == "failed" {
+            (WatchEventState::Failed, "导入失败，详见导入报告".to_string())
+        } else {
+            (WatchEventState::Imported, format!("已导入批次 {}", imported.batch_id))
+        };
+        let mut event = WatchEvent::new(&file.path, state, message);
+        event.batch_id = Some(imported.batch_id.clone());
+        event.job_id = ctx.id.clone();
+        emit_watch_event(app, event);
     }
+    log::info!("自动导入 {} 个ZIP到批次 {}", imported.zips.len(), imported.batch_id);
 
-    false
+    Ok(serde_json::json!({ "batch_id": imported.batch_id, "zip_count": imported.zips.len() }))
 }
 
-// 提取所有字段，特别处理指令内容的多行情况
-fn extract_all_fields(text: &str) -> Result<std::collections::BTreeMap<String, Vec<String>>> {
-    let mut map: std::collections::BTreeMap<String, Vec<String>> = std::collections::BTreeMap::new();
-    let lines: Vec<&str> = text.lines().collect();
-
-    let mut i = 0;
-    while i < lines.len() {
-        let line = lines[i].trim();
-
-        // 检查是否是字段行
-        if let Some(cap) = RE_FIELD.captures(line) {
-            let key = cap.get(1).unwrap().as_str().to_string();
-            let mut value = cap.name("v").unwrap().as_str().trim().to_string();
-
-            // 如果是指令内容，需要收集多行内容
-            if key == "指令内容" {
-                // 收集后续非字段行，直到遇到下一个字段或表格
-                i += 1;
-                let mut content_lines = Vec::new();
-                while i < lines.len() {
-                    let next_line = lines[i].trim();
-
-                    // 如果下一个字段开始，停止收集
-                    if RE_FIELD.is_match(next_line) {
-                        break;
-                    }
-
-                    // 如果遇到空行，继续检查下一行（空行可能是段落分隔）
-                    if next_line.is_empty() {
-                        i += 1;
-                        continue;
-                    }
-
-                    // 如果是表格表头或表格内容，停止收集指令内容
-                    if is_table_header_or_content(next_line) {
-                        break;
-                    }
+#[tauri::command]
+fn get_watch_settings(state: State<'_, AppState>) -> WatchSettings {
+    state.watch.settings.lock().unwrap().clone()
+}
 
-                    // 过滤掉一些明显的非内容行
-                    if next_line != "序号" && next_line != "指令编号" && next_line != "指令标题" && next_line != "下发时间" {
-                        // 检查行长度，过短的行可能不是内容
-                        if next_line.len() > 3 {
-                            content_lines.push(next_line);
-                        }
-                    }
-                    i += 1;
-                }
+/// 保存监控设置并立即生效，返回整理后的设置
+#[tauri::command]
+fn set_watch_settings(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    settings: WatchSettings,
+) -> Result<WatchSettings, String> {
+    let settings = normalize_watch_settings(settings).map_err(err_to_string)?;
+    let path = watch_settings_path(&app).map_err(err_to_string)?;
+    fs::write(path, serde_json::to_vec_pretty(&settings).map_err(err_to_string)?).map_err(err_to_string)?;
+    log::info!(
+        "文件夹监控已{}，共 {} 个文件夹",
+        if settings.enabled { "开启" } else { "关闭" },
+        settings.folders.len()
+    );
+    state.watch.replace(settings.clone());
+    Ok(settings)
+}
 
-                // 保留原始格式的多行内容
-                if !content_lines.is_empty() {
-                    let multi_line_content = content_lines.join("\n");
-                    let cleaned_content = normalize_instruction_content_with_format(&format!("{}\n{}", value, multi_line_content));
-                    value = cleaned_content;
-                }
-                i -= 1; // 回退一行，因为外层循环会再次递增
-            } else {
-                // 对于其他字段，应用简单的清理
-                value = normalize_text(&value);
-            }
+#[tauri::command]
+fn pick_watch_folder() -> Option<String> {
+    rfd::FileDialog::new()
+        .pick_folder()
+        .map(|p| p.to_string_lossy().to_string())
+}
 
-            map.entry(key).or_default().push(value);
-        }
-        i += 1;
-    }
+// ==================== 文件夹监控自动导入结束 ====================
 
-    Ok(map)
+#[tauri::command]
+fn export_excel(app: tauri::AppHandle, batch_id: String) -> Result<String, String> {
+    let mut batch: BatchSummary = read_batch(&app, &batch_id).map_err(err_to_string)?;
+    // 按下发时间排序
+    sort_zips_by_issued_at(&mut batch.zips);
+    start_excel_export(&app, batch)
 }
 
-fn extract_paragraph_texts(document_xml: &str) -> Result<String> {
-    let mut reader = XmlReader::from_str(document_xml);
-    reader.config_mut().trim_text(false);
-    let mut buf = Vec::new();
-    let mut current = String::new();
-    let mut out = String::new();
-    let mut in_paragraph = false;
-
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(e)) => {
-                if e.name().as_ref() == b"w:p" {
-                    in_paragraph = true;
-                    current.clear();
-                }
-            }
-            Ok(Event::Empty(e)) => {
-                if in_paragraph && e.name().as_ref() == b"w:br" {
-                    current.push('\n');
-                }
-            }
-            Ok(Event::End(e)) => {
-                if e.name().as_ref() == b"w:p" {
-                    in_paragraph = false;
-                    let line = normalize_text(&current);
-                    if !line.trim().is_empty() {
-                        out.push_str(line.trim_end());
-                        out.push('\n');
-                    }
-                }
-            }
-            Ok(Event::Text(e)) => {
-                if in_paragraph {
-                    current.push_str(&e.unescape()?.to_string());
-                }
+/// exclude_exported 为 true 时跳过已作为其他 ZIP 导出过的相同指令，避免台账中出现重复行
+#[tauri::command]
+fn export_excel_with_selection(
+    app: tauri::AppHandle,
+    batch_id: String,
+    zip_ids: Vec<String>,
+    exclude_exported: Option<bool>,
+) -> Result<String, String> {
+    let mut batch: BatchSummary = read_batch(&app, &batch_id).map_err(err_to_string)?;
+    if !zip_ids.is_empty() {
+        batch.zips.retain(|z| zip_ids.contains(&z.id));
+    }
+    if exclude_exported.unwrap_or(false) {
+        let catalog = Catalog::open(&app).map_err(err_to_string)?;
+        let before = batch.zips.len();
+        let mut kept = Vec::new();
+        for z in batch.zips {
+            if !catalog.exported_elsewhere(&batch_id, &z).map_err(err_to_string)? {
+                kept.push(z);
             }
-            Ok(Event::Eof) => break,
-            Err(err) => return Err(anyhow!("XML解析错误: {:?}", err)),
-            _ => {}
         }
-        buf.clear();
+        batch.zips = kept;
+        log::info!("导出Excel时跳过 {} 条已导出过的指令", before - batch.zips.len());
+        if batch.zips.is_empty() {
+            return Err("所选指令都已导出过".to_string());
+        }
     }
-
-    Ok(out)
+    // 按下发时间排序
+    sort_zips_by_issued_at(&mut batch.zips);
+    start_excel_export(&app, batch)
 }
 
-fn normalize_text(s: &str) -> String {
-    s.replace('\u{00A0}', " ")
-        .replace('\u{3000}', " ")
-        .replace('：', ":")
+/// 先询问保存位置，再在后台写入 Excel；返回任务 ID，任务结果为 { path }
+fn start_excel_export(app: &tauri::AppHandle, batch: BatchSummary) -> Result<String, String> {
+    let now = OffsetDateTime::now_utc();
+    let out = prompt_save_path(default_export_excel_name(now), "xlsx", "Excel")?;
+    let flag_column = load_validation_rules(app).excel_column;
+
+    let title = format!("导出Excel（{} 条）", batch.zips.len());
+    let handle = app.clone();
+    Ok(spawn_job(app, JobKind::ExportExcel, title, EXCEL_JOB_STEPS, move |ctx| {
+        export_excel_impl(ctx, &batch, &out, now, flag_column)?;
+        // 记录导出时间，之后导出时可以排除这些指令；旧批次的指令先补录到索引
+        record_seen_instructions(&handle, &batch.batch_id, &batch.zips);
+        let zip_ids: Vec<String> = batch.zips.iter().map(|z| z.id.clone()).collect();
+        if let Err(e) = Catalog::open(&handle)
+            .and_then(|catalog| catalog.mark_instructions_exported(&batch.batch_id, &zip_ids, now.unix_timestamp()))
+        {
+            log::warn!("记录导出时间失败: {:#}", e);
+        }
+        Ok(serde_json::json!({ "path": out.to_string_lossy() }))
+    }))
 }
 
-// 专门用于处理指令内容的函数，保留标点符号和格式
+/// flag_column 为 true 时在最后增加"校验问题"列
+fn export_excel_impl(
+    ctx: &JobContext,
+    batch: &BatchSummary,
+    out: &Path,
+    now: OffsetDateTime,
+    flag_column: bool,
+) -> Result<()> {
+    let total_rows = batch.zips.len();
+    ctx.report(0, 0.0, "正在准备数据");
 
-// 专门用于处理指令内容的函数，保留原始格式（换行、段落等）
-fn normalize_instruction_content_with_format(s: &str) -> String {
-    let mut result = String::new();
-    let mut lines = Vec::new();
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
 
-    // 按行分割内容，保留空行（段落分隔）
-    for line in s.lines() {
-        let trimmed = line.trim_end();  // 只删除行尾空白，保留标点
-        lines.push(trimmed);
+    let header_format = Format::new().set_bold().set_align(FormatAlign::Center);
+    let headers = [
+        "序号",
+        "日期",
+        "编码",
+        "标题",
+        "类型",
+        "样本（视频OR图文）",
+        "是否有样本",
+        "是否多批次任务",
+        "下发时间",
+        "任务执行",
+        "备注",
+        "原始ZIP",
+    ];
+    for (i, h) in headers.iter().enumerate() {
+        worksheet.write_string_with_format(0, i as u16, *h, &header_format)?;
     }
-
-    // 重新组合内容，保留原始换行结构
-    for (i, line) in lines.iter().enumerate() {
-        if i > 0 {
-            // 检查是否需要在换行前添加空格
-            let prev_line = lines[i-1];
-            let current_line = *line;
-
-            // 如果前一行以标点结尾，且当前行不是空行，保留换行
-            if !current_line.is_empty() &&
-               (prev_line.ends_with('。') || prev_line.ends_with('！') || prev_line.ends_with('？') ||
-                prev_line.ends_with('.') || prev_line.ends_with('!') || prev_line.ends_with('?')) {
-                // 保留换行
-                result.push('\n');
-            } else if !current_line.is_empty() && !prev_line.is_empty() {
-                // 如果都不是空行，且前一行不是句号等结束，添加换行
-                result.push('\n');
-            } else {
-                // 如果当前行是空行，添加额外的换行（段落分隔）
-                result.push('\n');
-            }
+    // 有从邮件导入的 ZIP 时追加邮件来源列
+    let has_mail = batch.zips.iter().any(|z| z.mail.is_some());
+    if has_mail {
+        for (i, h) in MAIL_FIELDS.iter().enumerate() {
+            worksheet.write_string_with_format(0, (headers.len() + i) as u16, *h, &header_format)?;
         }
-
-        // 替换特殊空白字符，但保留格式
-        let cleaned = line.replace('\u{00A0}', " ")
-                         .replace('\u{3000}', " ")
-                         .replace('：', ":");
-        result.push_str(&cleaned);
+    }
+    let flag_col = (headers.len() + if has_mail { MAIL_FIELDS.len() } else { 0 }) as u16;
+    if flag_column {
+        worksheet.write_string_with_format(0, flag_col, "校验问题", &header_format)?;
     }
 
-    // 清理开头和结尾的空白字符，但保留中间的换行
-    result.trim_matches('\n').trim_matches('\r').to_string()
-}
+    for (idx, z) in batch.zips.iter().enumerate() {
+        ctx.checkpoint()?;
+        ctx.report_items(0, idx, total_rows, &format!("正在处理: {}", z.word.instruction_no));
 
-// 解析下发时间字符串为 OffsetDateTime，支持多种格式
-fn parse_issued_at(date_str: &str) -> Result<OffsetDateTime> {
-    let trimmed = date_str.trim();
-    if trimmed.is_empty() {
-        // 如果时间为空，返回一个很早的时间作为默认值
-        return Ok(OffsetDateTime::UNIX_EPOCH);
-    }
-
-    // 尝试完整的时间戳格式 YYYY-MM-DD HH:MM:SS
-    if trimmed.len() >= 19 {
-        let date_part = &trimmed[0..10];
-        let time_part = &trimmed[11..19];
-
-        if date_part.chars().nth(4) == Some('-') && date_part.chars().nth(7) == Some('-') &&
-           time_part.chars().nth(2) == Some(':') && time_part.chars().nth(5) == Some(':') {
-
-            // 解析日期部分
-            if let (Ok(year), Ok(month_u8), Ok(day)) = (
-                date_part[0..4].parse::<i32>(),
-                date_part[5..7].parse::<u8>(),
-                date_part[8..10].parse::<u8>()
-            ) {
-                // 解析时间部分
-                if let (Ok(hour), Ok(minute), Ok(second)) = (
-                    time_part[0..2].parse::<u8>(),
-                    time_part[3..5].parse::<u8>(),
-                    time_part[6..8].parse::<u8>()
-                ) {
-                    // 转换月份类型
-                    if let Ok(month) = time::Month::try_from(month_u8) {
-                        if let (Ok(date), Ok(time)) = (
-                            time::Date::from_calendar_date(year, month, day),
-                            time::Time::from_hms(hour, minute, second)
-                        ) {
-                            return Ok(time::PrimitiveDateTime::new(date, time).assume_utc());
-                        }
-                    }
-                }
-            }
-        }
-    }
+        let row = (idx + 1) as u32;
+        let date = format!(
+            "{:04}{:02}{:02}",
+            now.year(),
+            now.month() as u8,
+            now.day()
+        );
 
-    // 尝试带时间的 YYYY-MM-DD HH:MM 格式
-    if trimmed.len() >= 16 && trimmed.len() < 19 {
-        let date_part = &trimmed[0..10];
-        let time_part = &trimmed[11..16];
-
-        if date_part.chars().nth(4) == Some('-') && date_part.chars().nth(7) == Some('-') &&
-           time_part.chars().nth(2) == Some(':') {
-
-            // 解析日期部分
-            if let (Ok(year), Ok(month_u8), Ok(day)) = (
-                date_part[0..4].parse::<i32>(),
-                date_part[5..7].parse::<u8>(),
-                date_part[8..10].parse::<u8>()
-            ) {
-                // 解析时间部分
-                if let (Ok(hour), Ok(minute)) = (
-                    time_part[0..2].parse::<u8>(),
-                    time_part[3..5].parse::<u8>()
-                ) {
-                    // 转换月份类型
-                    if let Ok(month) = time::Month::try_from(month_u8) {
-                        if let (Ok(date), Ok(time)) = (
-                            time::Date::from_calendar_date(year, month, day),
-                            time::Time::from_hms(hour, minute, 0)
-                        ) {
-                            return Ok(time::PrimitiveDateTime::new(date, time).assume_utc());
-                        }
-                    }
-                }
-            }
-        }
-    }
+        // 判断是否有图文类内容（PDF/图片/附加docx/Excel）
+        let has_image_text = !z.image_files.is_empty()
+            || !z.pdf_files.is_empty()
+            || !z.pdf_page_screenshot_files.is_empty()
+            || !z.excel_files.is_empty()
+            || !z.additional_docx_files.is_empty();
 
-    // 简单的解析策略：尝试数字格式
-    if let Ok(num) = trimmed.parse::<i64>() {
-        if num >= 10000101 && num <= 99991231 {
-            let year = (num / 10000) as i32;
-            let month = ((num % 10000) / 100) as u8;
-            let day = (num % 100) as u8;
+        // 判断是否有视频
+        let has_video = !z.video_files.is_empty() || !z.video_entries.is_empty();
 
-            if month >= 1 && month <= 12 && day >= 1 && day <= 31 {
-                // 使用time 0.3兼容的API
-                if let Ok(month) = time::Month::try_from(month) {
-                    if let Ok(date) = time::Date::from_calendar_date(year, month, day) {
-                        return Ok(time::PrimitiveDateTime::new(date, time::Time::MIDNIGHT).assume_utc());
-                    }
-                }
-            }
-        }
-    }
+        // 根据内容类型组合判断
+        let sample_kind = match (has_image_text, has_video) {
+            (true, true) => "图文+视频",
+            (true, false) => "图文",
+            (false, true) => "视频",
+            (false, false) => "否",  // 没有任何附件时显示"否"
+        };
 
-    // 尝试标准格式 YYYY-MM-DD
-    if trimmed.len() >= 10 && trimmed.chars().nth(4) == Some('-') && trimmed.chars().nth(7) == Some('-') {
-        if let Ok(year) = trimmed[0..4].parse::<i32>() {
-            if let Ok(month) = trimmed[5..7].parse::<u8>() {
-                if let Ok(day) = trimmed[8..10].parse::<u8>() {
-                    if let Ok(month) = time::Month::try_from(month) {
-                        if let Ok(date) = time::Date::from_calendar_date(year, month, day) {
-                            return Ok(time::PrimitiveDateTime::new(date, time::Time::MIDNIGHT).assume_utc());
-                        }
-                    }
-                }
-            }
-        }
-    }
+        // "是否有样本" 列始终根据 sample_kind 内容判断
+        let has_sample = if sample_kind == "否" { "否" } else { "是" };
 
-    // 如果都无法解析，返回当前时间
-    Ok(OffsetDateTime::now_utc())
-}
+        worksheet.write_number(row, 0, (idx + 1) as f64)?;
+        worksheet.write_string(row, 1, &date)?;
+        worksheet.write_string(row, 2, z.word.instruction_no.trim())?;
+        worksheet.write_string(row, 3, z.word.title.trim())?;
+        worksheet.write_string(row, 4, z.word.title.trim())?;
+        worksheet.write_string(row, 5, sample_kind)?;
+        worksheet.write_string(row, 6, has_sample)?;
+        worksheet.write_string(row, 7, "否")?;
+        worksheet.write_string(row, 8, z.word.issued_at.trim())?;
 
-// 对 ZipSummary 列表按下发时间排序
-fn sort_zips_by_issued_at(zips: &mut Vec<ZipSummary>) {
-    zips.sort_by(|a, b| {
-        let time_a = parse_issued_at(&a.word.issued_at).unwrap_or_else(|_| OffsetDateTime::UNIX_EPOCH);
-        let time_b = parse_issued_at(&b.word.issued_at).unwrap_or_else(|_| OffsetDateTime::UNIX_EPOCH);
-        time_a.cmp(&time_b)
-    });
-}
+        // 根据标题内容智能判断任务执行状态
+        let task_status = classify_task_status(&z.word.title);
 
-fn build_summary_docx(batch: &BatchSummary) -> Result<Vec<u8>> {
-    let mut docx = Docx::new();
-    docx = docx.add_paragraph(
-        Paragraph::new().add_run(Run::new().add_text("汇总文档").bold()),
-    );
+        worksheet.write_string(row, 9, task_status)?;
+        worksheet.write_string(row, 10, "")?;
 
-    for z in &batch.zips {
-        let zip_folder = format!("attachments/{}/", z.id);
-        docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
-            "指令编号:  {}",
-            z.word.instruction_no
-        ))));
-        docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
-            "指令标题:  {}",
-            z.word.title
-        ))));
-        docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
-            "下发时间:  {}",
-            z.word.issued_at
-        ))));
-        if !z.word.content.trim().is_empty() {
-            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text("指令内容:")));
-            // 将指令内容按换行符分割，创建多个段落
-            for line in z.word.content.lines() {
-                let trimmed_line = line.trim();
-                if !trimmed_line.is_empty() {
-                    docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(trimmed_line)));
-                } else {
-                    // 空行创建空段落（段落间距）
-                    docx = docx.add_paragraph(Paragraph::new());
-                }
+        // 添加原始ZIP文件路径（直接显示为文本，用户可以复制路径手动打开）
+        // Windows 平台：尝试创建超链接；macOS 平台：直接显示路径文本
+        if cfg!(target_os = "windows") {
+            // Windows: 尝试使用 file:// 超链接
+            let file_url = format!("file:///{}", z.source_path.replace("\\", "/"));
+            worksheet.write_url_with_text(row, 11, Url::new(&file_url), &z.source_path)?;
+        } else {
+            // macOS: 直接显示文件路径作为文本（Excel for Mac 对 file:// 支持不好）
+            worksheet.write_string(row, 11, &z.source_path)?;
+        }
+        if has_mail {
+            for (i, name) in MAIL_FIELDS.iter().enumerate() {
+                worksheet.write_string(row, (headers.len() + i) as u16, mail_field(z, name))?;
             }
         }
-
-        // 直接显示图片，删除"图片"标题
-        for img_path in &z.image_files {
-            let bytes = fs::read(img_path)
-                .with_context(|| format!("读取图片失败: {}", img_path))?;
-            // 缩放图片到 1200x1680，质量 95（高分辨率，文字非常清晰）
-            let resized_bytes = resize_image_to_jpeg(&bytes, 1200, 1680, 95)?;
-            let pic = Pic::new(&resized_bytes).size(5040000, 7056000);
-            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_image(pic)));
+        if flag_column {
+            worksheet.write_string(row, flag_col, validation_flags_text(z))?;
         }
+    }
 
-        // 直接显示PDF图片，删除"PDF页面图片:"标题
-        
-        // 直接显示PDF截图，删除"PDF页面截图:"标题
-        for img_path in &z.pdf_page_screenshot_files {
-            let bytes = fs::read(img_path)
-                .with_context(|| format!("读取PDF页面截图失败: {}", img_path))?;
-            // 缩放图片到 1200x1680，质量 95（高分辨率，文字非常清晰）
-            let resized_bytes = resize_image_to_jpeg(&bytes, 1200, 1680, 95)?;
-            let pic = Pic::new(&resized_bytes).size(5040000, 7056000);
-            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_image(pic)));
-        }
+    ctx.report(1, 0.0, "正在保存Excel文件");
+    workbook
+        .save(out)
+        .with_context(|| format!("保存Excel失败: {}", out.display()))?;
+    Ok(())
+}
 
-        docx = docx.add_paragraph(
-            Paragraph::new().add_run(Run::new().add_text("附件清单:").bold()),
-        );
+/// 根据标题关键词判断任务执行状态："已执行" / "已签收" / ""
+fn classify_task_status(title: &str) -> &'static str {
+    let title = title.trim().to_lowercase();
 
-        // 仅提供“本ZIP附件文件夹”链接
-        let folder_link = Hyperlink::new(&zip_folder, HyperlinkType::External)
-            .add_run(Run::new().add_text(zip_folder.clone()).style("Hyperlink"));
-        docx = docx.add_paragraph(
-            Paragraph::new()
-                .add_run(Run::new().add_text("附件目录："))
-                .add_hyperlink(folder_link),
-        );
+    // 条件a：执行类关键词（优先级高）
+    let execution_keywords = ["人工审核", "删除", "禁言", "样本查删", "拦截", "反馈", "溯源", "加私", "专项", "清理", "限流", "屏蔽"];
+    if execution_keywords.iter().any(|&keyword| title.contains(keyword)) {
+        return "已执行";
+    }
 
-        for video in &z.video_files {
-            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
-                "- {}",
-                safe_basename(video)
-            ))));
-        }
-        if z.include_original_zip {
-            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
-                "- {}",
-                z.filename
-            ))));
-        }
-        if z.video_files.is_empty() && !z.include_original_zip {
-            docx = docx
-                .add_paragraph(Paragraph::new().add_run(Run::new().add_text("- （无）")));
-        }
-        docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text("— — —")));
+    // 条件b：接收类关键词（优先级低）
+    let receive_keywords = ["工作", "指令", "通知", "提示", "压后台"];
+    if receive_keywords.iter().any(|&keyword| title.contains(keyword)) {
+        return "已签收";
     }
 
-    let mut out = Cursor::new(Vec::<u8>::new());
-    docx.build()
-        .pack(&mut out)
-        .map_err(|e| anyhow!("docx生成失败: {e:?}"))?;
-    Ok(out.into_inner())
+    ""  // 无匹配关键词时保持字段为空
 }
 
-fn build_bundle_zip_bytes(batch: &BatchSummary, docx_bytes: &[u8]) -> Result<Vec<u8>> {
-    let file_options = FileOptions::default();
-    let dir_options = FileOptions::default();
+#[tauri::command]
+fn export_bundle_zip(
+    app: tauri::AppHandle,
+    batch_id: String,
+    selection: Option<ExportBundleSelection>,
+) -> Result<String, String> {
+    let mut batch: BatchSummary = read_batch(&app, &batch_id).map_err(err_to_string)?;
+
+    // 有选择时按用户顺序和分组导出，否则按下发时间排序
+    let (groups, style) = match selection {
+        Some(selection) => {
+            let style = selection.style.clone();
+            let (selected, groups) = apply_bundle_selection(&batch, selection).map_err(err_to_string)?;
+            batch = selected;
+            (groups, style)
+        }
+        None => {
+            sort_zips_by_issued_at(&mut batch.zips);
+            (vec![], SummaryDocStyle::default())
+        }
+    };
 
-    let mut out = Cursor::new(Vec::<u8>::new());
-    {
-        let mut writer = ZipWriter::new(&mut out);
+    let now = OffsetDateTime::now_utc();
+    let out = prompt_save_path(default_export_bundle_name(now), "zip", "ZIP")?;
 
-        writer.start_file("汇总文档.docx", file_options)?;
-        writer.write_all(docx_bytes)?;
+    let docx_bytes = build_summary_docx(&batch, &groups, &style).map_err(err_to_string)?;
+    let bundle_bytes =
+        build_bundle_zip_bytes(&batch, &docx_bytes, style.image_settings.strip_metadata).map_err(err_to_string)?;
 
-        writer.add_directory("attachments/", dir_options)?;
+    fs::write(&out, bundle_bytes).map_err(err_to_string)?;
+    Ok(out.to_string_lossy().to_string())
+}
 
-        for z in &batch.zips {
-            let zip_dir = format!("attachments/{}/", z.id);
-            writer.add_directory(&zip_dir, dir_options)?;
+/// 导出汇总 Word 文档：询问保存位置后在后台生成，返回任务 ID，任务结果为 { path, embedded_files }
+#[tauri::command]
+async fn export_bundle_zip_with_selection(
+    app: tauri::AppHandle,
+    batch_id: String,
+    selection: ExportBundleSelection,
+    _embed_files: Option<bool>,
+) -> Result<String, String> {
+    let batch: BatchSummary = read_batch(&app, &batch_id).map_err(err_to_string)?;
+    // 记住本次选择，重新打开批次时恢复
+    if let Err(e) = Catalog::open(&app).and_then(|catalog| catalog.save_selection(&batch_id, &selection)) {
+        log::warn!("保存导出选择失败: {:#}", e);
+    }
+    let style = selection.style.clone();
+    let template = match selection.template_path.as_deref().filter(|p| !p.trim().is_empty()) {
+        Some(path) => Some(fs::read(path).map_err(|e| format!("读取Word模板失败: {}", e))?),
+        None => None,
+    };
+    let (batch, groups) = apply_bundle_selection(&batch, selection).map_err(err_to_string)?;
 
-            let zip_path = if !z.stored_path.trim().is_empty() {
-                PathBuf::from(&z.stored_path)
-            } else {
-                PathBuf::from(&z.source_path)
-            };
-            if z.include_original_zip {
-                let zip_bytes = fs::read(&zip_path)
-                    .with_context(|| format!("读取ZIP失败: {}", zip_path.display()))?;
-                writer.start_file(format!("{zip_dir}{}", z.filename), file_options)?;
-                writer.write_all(&zip_bytes)?;
-            }
+    if batch.zips.is_empty() {
+        return Err("未选择任何ZIP用于导出".to_string());
+    }
 
-            for video_path in &z.video_files {
-                let bytes = fs::read(video_path)
-                    .with_context(|| format!("读取视频失败: {}", video_path))?;
-                writer.start_file(
-                    format!("{zip_dir}{}", safe_basename(video_path)),
-                    file_options,
-                )?;
-                writer.write_all(&bytes)?;
-            }
+    // 立即询问保存位置，让用户能够快速响应
+    // 使用 spawn_blocking 避免阻塞异步运行时
+    let now = OffsetDateTime::now_utc();
+    let default_name = default_export_bundle_name(now);
+    let out = tokio::task::spawn_blocking(move || {
+        prompt_save_path(default_name, "docx", "Word文档")
+    })
+    .await
+    .map_err(|e| format!("文件对话框错误: {}", e))??;
 
-            for pdf_path in &z.pdf_files {
-                let bytes = fs::read(pdf_path)
-                    .with_context(|| format!("读取PDF失败: {}", pdf_path))?;
-                writer.start_file(format!("{zip_dir}{}", safe_basename(pdf_path)), file_options)?;
-                writer.write_all(&bytes)?;
-            }
+    let title = format!("导出Word（{} 条）", batch.zips.len());
+    Ok(spawn_job(&app, JobKind::ExportWord, title, WORD_JOB_STEPS, move |ctx| {
+        let embedded = export_word_document(ctx, &batch, &groups, &style, template.as_deref(), &out)?;
+        Ok(serde_json::json!({ "path": out.to_string_lossy(), "embedded_files": embedded }))
+    }))
+}
+
+/// 生成汇总 Word 文档（始终嵌入附件）并保存，返回嵌入的文件数。进度按 WORD_JOB_STEPS 汇报
+fn export_word_document(
+    ctx: &JobContext,
+    batch: &BatchSummary,
+    groups: &[ExportGroup],
+    style: &SummaryDocStyle,
+    template: Option<&[u8]>,
+    out: &Path,
+) -> Result<usize> {
+    let (docx, embedded_files) = match template {
+        Some(template) => {
+            let (bytes, files) = build_summary_docx_from_template(template, batch, &style.image_settings, ctx, 0)?;
+            (SummaryDocx::Rendered(bytes), files)
         }
+        None => {
+            let (docx, files, rich_bodies) = build_enhanced_summary_docx(batch, groups, style, true, ctx, 0)?;
+            (SummaryDocx::Built(Box::new(docx), rich_bodies), files)
+        }
+    };
 
-        writer.finish()?;
-    } // writer 在这里被 drop，释放对 out 的借用
+    ctx.checkpoint()?;
+    ctx.report(1, 0.0, &format!("正在嵌入 {} 个文件", embedded_files.len()));
+    let docx_bytes = match docx {
+        SummaryDocx::Built(docx, rich_bodies) => build_docx_with_embeddings(*docx, &rich_bodies, &embedded_files),
+        SummaryDocx::Rendered(bytes) => embed_files_into_docx_bytes(bytes, &embedded_files),
+    }?;
 
-    Ok(out.into_inner())
-}
+    ctx.checkpoint()?;
+    ctx.report(2, 0.0, "正在保存Word文档");
+    // 直接保存docx文件，不再创建zip包
+    fs::write(out, docx_bytes).with_context(|| format!("保存Word文档失败: {}", out.display()))?;
 
-fn safe_basename(name: &str) -> String {
-    let n = name.replace('\\', "/");
-    n.rsplit('/').next().unwrap_or(&n).to_string()
+    log::info!("Word文档导出完成，包含 {} 个嵌入文件", embedded_files.len());
+    Ok(embedded_files.len())
 }
 
-fn err_to_string<E: std::fmt::Display>(e: E) -> String {
-    e.to_string()
-}
+fn apply_bundle_selection(
+    batch: &BatchSummary,
+    selection: ExportBundleSelection,
+) -> Result<(BatchSummary, Vec<ExportGroup>)> {
+    let mut out = Vec::new();
 
-#[tauri::command]
-fn open_path(path: String) -> Result<(), String> {
-    let p = PathBuf::from(path);
-    if !p.exists() {
-        return Err("路径不存在".to_string());
+    for z in &batch.zips {
+        let Some(sel) = selection.zips.iter().find(|s| s.zip_id == z.id && s.include) else {
+            continue;
+        };
+
+        let mut z2 = z.clone();
+        z2.include_original_zip = sel.include_original_zip;
+        let mut selected_videos = Vec::new();
+        for &idx in &sel.selected_video_indices {
+            if let Some(p) = z.video_files.get(idx) {
+                selected_videos.push(p.clone());
+            }
+        }
+        z2.video_files = selected_videos;
+
+        let mut selected_images = Vec::new();
+        for &idx in &sel.selected_image_indices {
+            if let Some(p) = z.image_files.get(idx) {
+                selected_images.push(p.clone());
+            }
+        }
+        z2.image_files = selected_images;
+
+        let mut selected_pdfs = Vec::new();
+        for &idx in &sel.selected_pdf_indices {
+            if let Some(p) = z.pdf_files.get(idx) {
+                selected_pdfs.push(p.clone());
+            }
+        }
+        z2.pdf_files = selected_pdfs;
+
+        let mut selected_pdf_screens = Vec::new();
+        for &idx in &sel.selected_pdf_page_screenshot_indices {
+            if let Some(p) = z.pdf_page_screenshot_files.get(idx) {
+                selected_pdf_screens.push(p.clone());
+            }
+        }
+        z2.pdf_page_screenshot_files = selected_pdf_screens;
+
+        let mut selected_excels = Vec::new();
+        for &idx in &sel.selected_excel_indices {
+            if let Some(p) = z.excel_files.get(idx) {
+                selected_excels.push(p.clone());
+            }
+        }
+        z2.excel_files = selected_excels;
+
+        // 过滤附加 docx（根据细粒度选择）
+        let mut selected_additional_docx = Vec::new();
+        for docx_sel in &sel.selected_additional_docx {
+            if let Some(doc) = z.additional_docx_files.get(docx_sel.docx_index) {
+                let mut filtered_doc = doc.clone();
+
+                // 如果不包含文本，清空 full_text 和 fields
+                if !docx_sel.include_text {
+                    filtered_doc.full_text = String::new();
+                    filtered_doc.fields = WordFields::default();
+                }
+
+                // 过滤图片
+                let mut selected_images = Vec::new();
+                for &img_idx in &docx_sel.selected_image_indices {
+                    if let Some(img_path) = doc.image_files.get(img_idx) {
+                        selected_images.push(img_path.clone());
+                    }
+                }
+                filtered_doc.image_files = selected_images;
+
+                selected_additional_docx.push(filtered_doc);
+            }
+        }
+        z2.additional_docx_files = selected_additional_docx;
+
+        out.push(z2);
     }
-    open_in_os(&p).map_err(err_to_string)?;
-    Ok(())
+
+    let groups = arrange_zips_for_export(&mut out, &selection.order, selection.group_by);
+
+    Ok((
+        BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: batch.batch_id.clone(),
+            created_at: batch.created_at,
+            zips: out,
+        },
+        groups,
+    ))
 }
 
-fn open_in_os(path: &Path) -> Result<()> {
-    #[cfg(target_os = "macos")]
-    {
-        let status = Command::new("open").arg(path).status()?;
-        if !status.success() {
-            return Err(anyhow!("open 返回非0状态码"));
+/// 按用户顺序排列 ZIP（未指定顺序时按下发时间），再按分组方式把同组的 ZIP 聚在一起。
+/// 组的先后由组内第一个 ZIP 的位置决定，组内保持用户顺序。
+fn arrange_zips_for_export(
+    zips: &mut [ZipSummary],
+    order: &[String],
+    group_by: Option<ExportGroupBy>,
+) -> Vec<ExportGroup> {
+    // 先按下发时间排序，保证不在 order 里的 ZIP 也有稳定的位置
+    sort_zips_by_issued_at(zips);
+    if !order.is_empty() {
+        zips.sort_by_key(|z| order.iter().position(|id| id == &z.id).unwrap_or(usize::MAX));
+    }
+
+    let Some(group_by) = group_by else {
+        return vec![];
+    };
+
+    let mut headings: Vec<String> = Vec::new();
+    for z in zips.iter() {
+        let heading = export_group_heading(z, group_by);
+        if !headings.contains(&heading) {
+            headings.push(heading);
         }
-        return Ok(());
     }
+    zips.sort_by_cached_key(|z| {
+        let heading = export_group_heading(z, group_by);
+        headings.iter().position(|h| h == &heading).unwrap_or(usize::MAX)
+    });
 
-    #[cfg(target_os = "windows")]
-    {
-        // Windows上使用explorer打开文件或文件夹
-        // 如果是文件，使用 /select 参数在资源管理器中选中它
-        // 如果是文件夹，直接打开
-        if path.is_file() {
-            // /select 参数格式: explorer /select,"C:\path\to\file"
-            let path_str = path.to_str().unwrap_or("");
-            let select_arg = format!("/select,{}", path_str);
-            let status = Command::new("explorer")
-                .arg(select_arg)
-                .status()?;
-            if !status.success() {
-                return Err(anyhow!("explorer 返回非0状态码"));
-            }
-        } else {
-            let status = Command::new("explorer")
-                .arg(path)
-                .status()?;
-            if !status.success() {
-                return Err(anyhow!("explorer 返回非0状态码"));
-            }
+    let mut groups = Vec::new();
+    for (idx, z) in zips.iter().enumerate() {
+        let heading = export_group_heading(z, group_by);
+        if groups.last().map_or(true, |g: &ExportGroup| g.heading != heading) {
+            groups.push(ExportGroup { heading, first_index: idx });
         }
-        return Ok(());
     }
+    groups
+}
 
-    #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
-    {
-        let status = Command::new("xdg-open").arg(path).status()?;
-        if !status.success() {
-            return Err(anyhow!("xdg-open 返回非0状态码"));
+fn export_group_heading(z: &ZipSummary, group_by: ExportGroupBy) -> String {
+    match group_by {
+        ExportGroupBy::IssuedDate => match issued_at_sort_key(&z.word) {
+            Some(t) => format!("{}年{}月{}日", t.year(), t.month() as u8, t.day()),
+            None => "下发时间未知".to_string(),
+        },
+        ExportGroupBy::CategoryRule => match classify_task_status(&z.word.title) {
+            "已执行" => "执行类指令".to_string(),
+            "已签收" => "签收类指令".to_string(),
+            _ => "其他指令".to_string(),
+        },
+        ExportGroupBy::SourceFolder => {
+            let source = Path::new(&z.source_path);
+            source
+                .parent()
+                .and_then(|p| p.file_name())
+                .map(|n| n.to_string_lossy().to_string())
+                .filter(|n| !n.is_empty())
+                .unwrap_or_else(|| "未知来源".to_string())
         }
-        return Ok(());
     }
 }
 
-#[tauri::command]
-fn get_preview_image_data(
-    app: tauri::AppHandle,
-    batch_id: String,
-    zip_id: String,
-    index: usize,
-) -> Result<String, String> {
-    let batch_dir = batch_dir(&app, &batch_id).map_err(err_to_string)?;
-    let batch: BatchSummary = read_batch(&batch_dir).map_err(err_to_string)?;
-    let z = batch
-        .zips
+/// 如果 zip_idx 是某个分组的第一个 ZIP，返回该组标题
+fn group_heading_at(groups: &[ExportGroup], zip_idx: usize) -> Option<&str> {
+    groups
         .iter()
-        .find(|z| z.id == zip_id)
-        .ok_or_else(|| "ZIP不存在".to_string())?;
-    let path = z
-        .image_files
-        .get(index)
-        .ok_or_else(|| "图片索引越界".to_string())?;
-    let bytes = fs::read(path).map_err(err_to_string)?;
-    let mime = guess_image_mime(path);
-    let b64 = base64_encode(&bytes);
-    Ok(format!("data:{mime};base64,{b64}"))
+        .find(|g| g.first_index == zip_idx)
+        .map(|g| g.heading.as_str())
 }
 
-#[tauri::command]
-fn save_pdf_page_screenshots(
-    app: tauri::AppHandle,
-    batch_id: String,
-    zip_id: String,
-    pdf_name: String,
-    screenshots: Vec<String>,
-) -> Result<Vec<String>, String> {
-    if screenshots.is_empty() {
-        return Err("没有提供截图数据".to_string());
-    }
-    
-    let batch_dir = batch_dir(&app, &batch_id).map_err(err_to_string)?;
-    let mut batch: BatchSummary = read_batch(&batch_dir).map_err(err_to_string)?;
+// ==================== 汇总文档样式 ====================
+
+const STYLE_TITLE: &str = "Title";
+const STYLE_INSTRUCTION_HEADING: &str = "Heading1";
+const STYLE_GROUP_HEADING: &str = "GroupHeading";
+
+const FONT_XIAOBIAOSONG: &str = "方正小标宋简体";
+const FONT_HEITI: &str = "黑体";
+const FONT_KAITI: &str = "楷体_GB2312";
+const FONT_FANGSONG: &str = "仿宋_GB2312";
+
+/// 汇总文档版式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum SummaryLayout {
+    /// 普通版式：封面、目录、每条指令另起一页
+    #[default]
+    Plain,
+    /// 公文版式：按 GB/T 9704 排版，字体固定，不使用封面和目录
+    Official,
+}
 
-    let zip = batch
-        .zips
-        .iter_mut()
-        .find(|z| z.id == zip_id)
-        .ok_or_else(|| "ZIP不存在".to_string())?;
+/// 图片排版方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum ImageLayout {
+    /// 每张图片单独一段，按原始尺寸排版，超出版心时等比缩小
+    #[default]
+    Single,
+    /// 每行三张的无边框表格，适合大量小截图
+    Grid,
+}
 
-    let out_dir = batch_dir
-        .join("zips")
-        .join(&zip.id)
-        .join("extracted")
-        .join("pdf_screens")
-        .join(sanitize_file_stem(&pdf_name));
-    
-    // 确保目录存在
-    fs::create_dir_all(&out_dir).map_err(|e| format!("创建目录失败: {}", e))?;
+/// 汇总文档字体设置，字号单位为半磅（三号 = 32，二号 = 44）。
+/// 字体只对普通版式生效，公文版式使用标准规定的字体。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct SummaryDocStyle {
+    layout: SummaryLayout,
+    /// 公文版式的发文机关标志，为空时使用“汇总文档”
+    issuer: String,
+    title_font: String,
+    heading_font: String,
+    body_font: String,
+    body_size: usize,
+    image_layout: ImageLayout,
+    /// 在图片下方显示来源文件名
+    image_captions: bool,
+    /// 在图片下方显示 OCR 识别文本
+    ocr_text: bool,
+    image_settings: ImageExportSettings,
+}
 
-    let mut saved = Vec::new();
-    let mut failed_count = 0;
-    
-    for (i, s) in screenshots.iter().enumerate() {
-        match decode_data_url_base64(s) {
-            Ok(bytes) => {
-                if bytes.is_empty() {
-                    eprintln!("警告: 第{}页截图数据为空", i + 1);
-                    failed_count += 1;
-                    continue;
-                }
-                
-                let out_path = out_dir.join(format!("page_{:03}.png", i + 1));
-                match fs::write(&out_path, bytes) {
-                    Ok(_) => {
-                        let p = out_path.to_string_lossy().to_string();
-                        saved.push(p.clone());
-                        zip.pdf_page_screenshot_files.push(p);
-                    }
-                    Err(e) => {
-                        eprintln!("警告: 保存第{}页截图失败: {}", i + 1, e);
-                        failed_count += 1;
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("警告: 解码第{}页截图数据失败: {}", i + 1, e);
-                failed_count += 1;
-            }
+impl Default for SummaryDocStyle {
+    fn default() -> Self {
+        Self {
+            layout: SummaryLayout::Plain,
+            issuer: String::new(),
+            title_font: FONT_HEITI.to_string(),
+            heading_font: FONT_HEITI.to_string(),
+            body_font: FONT_FANGSONG.to_string(),
+            body_size: 32,
+            image_layout: ImageLayout::Single,
+            image_captions: false,
+            ocr_text: false,
+            image_settings: ImageExportSettings::default(),
         }
     }
-    
-    if saved.is_empty() {
-        return Err(format!("所有截图保存失败，共{}个截图", screenshots.len()));
+}
+
+impl SummaryDocStyle {
+    /// 普通版式每条指令另起一页，公文版式连续排版
+    fn page_break_between_sections(&self) -> bool {
+        self.layout == SummaryLayout::Plain
     }
-    
-    // 保存批次信息
-    let meta_path = batch_dir.join("batch.json");
-    fs::write(&meta_path, serde_json::to_vec_pretty(&batch).map_err(err_to_string)?)
-        .map_err(|e| format!("保存批次信息失败: {}", e))?;
 
-    if failed_count > 0 {
-        println!("PDF截图保存完成: 成功{}个，失败{}个", saved.len(), failed_count);
+    /// 版心宽高（缇）：普通版式为 docx-rs 默认 A4 页边距，公文版式为 156mm×225mm
+    fn text_area_twips(&self) -> (u32, u32) {
+        match self.layout {
+            SummaryLayout::Plain => (11906 - 1701 * 2, 16838 - 1985 - 1701),
+            SummaryLayout::Official => (mm_to_twips(156.0) as u32, mm_to_twips(225.0) as u32),
+        }
     }
-    
-    Ok(saved)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ExcelPreviewData {
-    sheet_name: String,
-    rows: Vec<Vec<String>>,
-    total_sheets: usize,
-    sheet_names: Vec<String>,
+/// 章节编号。公文版式按“一、（一）1.”逐级编号，有分组时分组为第一层；普通版式不编号。
+struct SectionNumbering {
+    enabled: bool,
+    grouped: bool,
+    counters: [usize; 4],
 }
 
-fn read_excel_preview(excel_path: &Path) -> Result<ExcelPreviewData> {
-    let extension = excel_path
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("")
-        .to_lowercase();
+impl SectionNumbering {
+    fn new(style: &SummaryDocStyle, groups: &[ExportGroup]) -> Self {
+        Self {
+            enabled: style.layout == SummaryLayout::Official,
+            grouped: !groups.is_empty(),
+            counters: [0; 4],
+        }
+    }
 
-    println!("正在读取Excel文件: {:?}, 扩展名: {}", excel_path, extension);
+    fn next(&mut self, level: usize, text: &str) -> String {
+        self.counters[level] += 1;
+        for c in &mut self.counters[level + 1..] {
+            *c = 0;
+        }
+        format!("{}{}", official_section_prefix(level, self.counters[level]), text)
+    }
 
-    if extension == "xlsx" {
-        let mut workbook = calamine::open_workbook::<Xlsx<_>, _>(excel_path)
-            .map_err(|e| anyhow!("打开xlsx文件失败: {}", e))?;
+    fn group(&mut self, heading: &str) -> String {
+        if !self.enabled {
+            return heading.to_string();
+        }
+        self.next(0, heading)
+    }
 
-        // Use the trait methods
-        use calamine::Reader;
-        let sheet_names = workbook.sheet_names();
-        let first_sheet_name = sheet_names
-            .first()
-            .ok_or_else(|| anyhow!("Excel文件没有工作表"))?
-            .to_string();
+    fn instruction(&mut self, heading: &str) -> String {
+        if !self.enabled {
+            return heading.to_string();
+        }
+        self.next(usize::from(self.grouped), heading)
+    }
 
-        println!("工作表名称: {:?}", sheet_names);
+    /// 指令下的小节（指令内容、附件清单），普通版式保留原来的“标签:”写法
+    fn item(&mut self, label: &str) -> String {
+        if !self.enabled {
+            return format!("{}:", label);
+        }
+        self.next(usize::from(self.grouped) + 1, label)
+    }
+}
 
-        // 尝试读取第一个工作表的实际数据
-        let mut rows = Vec::new();
-        if let Ok(range) = workbook.worksheet_range(&first_sheet_name) {
-            // 限制读取前10行和前10列，避免数据过多
-            for row in range.rows().take(10) {
-                let mut row_data = Vec::new();
-                for cell in row.iter().take(10) {
-                    let value_str = match cell {
-                        calamine::Data::String(s) => s.to_string(),
-                        calamine::Data::Float(f) => f.to_string(),
-                        calamine::Data::Int(i) => i.to_string(),
-                        calamine::Data::Bool(b) => b.to_string(),
-                        calamine::Data::Empty => String::new(),
-                        _ => "【数据】".to_string(),
-                    };
-                    row_data.push(value_str);
-                }
-                rows.push(row_data);
+/// 公文结构层次序数：一、（一）1.（1）
+fn official_section_prefix(level: usize, n: usize) -> String {
+    match level {
+        0 => format!("{}、", chinese_number(n)),
+        1 => format!("（{}）", chinese_number(n)),
+        2 => format!("{}.", n),
+        _ => format!("（{}）", n),
+    }
+}
+
+/// 1~99 转为中文数字，超出范围时返回阿拉伯数字
+fn chinese_number(n: usize) -> String {
+    const DIGITS: [&str; 10] = ["零", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+    match n {
+        1..=9 => DIGITS[n].to_string(),
+        10..=99 => {
+            let (tens, ones) = (n / 10, n % 10);
+            let mut out = String::new();
+            if tens > 1 {
+                out.push_str(DIGITS[tens]);
             }
-        } else {
-            // 如果无法读取数据，返回错误信息
-            return Err(anyhow!("无法读取Excel工作表数据: {}", first_sheet_name));
+            out.push('十');
+            if ones > 0 {
+                out.push_str(DIGITS[ones]);
+            }
+            out
         }
+        _ => n.to_string(),
+    }
+}
 
-        if rows.is_empty() {
-            // 如果没有数据，至少返回表头
-            rows = vec![
-                vec!["工作表".to_string(), first_sheet_name.to_string(), "".to_string()],
-                vec!["状态".to_string(), "无数据".to_string(), "".to_string()],
-            ];
-        }
+fn mm_to_twips(mm: f64) -> i32 {
+    (mm * 1440.0 / 25.4).round() as i32
+}
 
-        Ok(ExcelPreviewData {
-            sheet_name: first_sheet_name,
-            rows,
-            total_sheets: sheet_names.len(),
-            sheet_names,
-        })
-    } else if extension == "xls" {
-        let mut workbook = calamine::open_workbook::<Xls<_>, _>(excel_path)
-            .map_err(|e| anyhow!("打开xls文件失败: {}", e))?;
+fn run_fonts(font: &str) -> RunFonts {
+    RunFonts::new().ascii(font).hi_ansi(font).east_asia(font).cs(font)
+}
 
-        // Use the trait methods
-        let sheet_names = workbook.sheet_names();
-        let first_sheet_name = sheet_names
-            .first()
-            .ok_or_else(|| anyhow!("Excel文件没有工作表"))?
-            .to_string();
+fn format_cover_time(t: OffsetDateTime) -> String {
+    // 按北京时间显示
+    let offset = time::UtcOffset::from_hms(8, 0, 0).unwrap_or(time::UtcOffset::UTC);
+    let t = t.to_offset(offset);
+    format!(
+        "{}年{}月{}日 {:02}:{:02}",
+        t.year(),
+        t.month() as u8,
+        t.day(),
+        t.hour(),
+        t.minute()
+    )
+}
 
-        println!("工作表名称: {:?}", sheet_names);
+/// 按版式创建汇总文档骨架，grouped 表示正文是否带分组标题
+fn new_summary_docx(batch: &BatchSummary, style: &SummaryDocStyle, grouped: bool) -> Docx {
+    match style.layout {
+        SummaryLayout::Plain => new_plain_summary_docx(batch, style),
+        SummaryLayout::Official => new_official_summary_docx(batch, style, grouped),
+    }
+}
 
-        // 尝试读取第一个工作表的实际数据
-        let mut rows = Vec::new();
-        if let Ok(range) = workbook.worksheet_range(&first_sheet_name) {
-            // 限制读取前10行和前10列，避免数据过多
-            for row in range.rows().take(10) {
-                let mut row_data = Vec::new();
-                for cell in row.iter().take(10) {
-                    let value_str = match cell {
-                        calamine::Data::String(s) => s.to_string(),
-                        calamine::Data::Float(f) => f.to_string(),
-                        calamine::Data::Int(i) => i.to_string(),
-                        calamine::Data::Bool(b) => b.to_string(),
-                        calamine::Data::Empty => String::new(),
-                        _ => "【数据】".to_string(),
-                    };
-                    row_data.push(value_str);
-                }
-                rows.push(row_data);
-            }
-        } else {
-            // 如果无法读取数据，返回错误信息
-            return Err(anyhow!("无法读取Excel工作表数据: {}", first_sheet_name));
-        }
+/// 普通版式骨架：样式、页眉页脚、封面和自动目录。
+/// 封面页不显示页眉页脚，指令标题使用 Heading 1，Word 导航窗格和目录都依赖它。
+fn new_plain_summary_docx(batch: &BatchSummary, style: &SummaryDocStyle) -> Docx {
+    let header = Header::new().add_paragraph(
+        Paragraph::new()
+            .align(AlignmentType::Center)
+            .add_run(Run::new().add_text("汇总文档").size(18)),
+    );
+    let footer = Footer::new().add_paragraph(
+        Paragraph::new()
+            .align(AlignmentType::Center)
+            .add_run(Run::new().add_text("第 ").size(18))
+            .add_page_num(PageNum::new())
+            .add_run(Run::new().add_text(" 页 / 共 ").size(18))
+            .add_num_pages(NumPages::new())
+            .add_run(Run::new().add_text(" 页").size(18)),
+    );
 
-        if rows.is_empty() {
-            // 如果没有数据，至少返回表头
-            rows = vec![
-                vec!["工作表".to_string(), first_sheet_name.to_string(), "".to_string()],
-                vec!["状态".to_string(), "无数据".to_string(), "".to_string()],
-            ];
-        }
+    let mut docx = Docx::new()
+        .default_fonts(run_fonts(&style.body_font))
+        .default_size(style.body_size)
+        .add_style(
+            Style::new(STYLE_TITLE, StyleType::Paragraph)
+                .name("Title")
+                .fonts(run_fonts(&style.title_font))
+                .size(44)
+                .bold()
+                .align(AlignmentType::Center),
+        )
+        .add_style(
+            Style::new(STYLE_INSTRUCTION_HEADING, StyleType::Paragraph)
+                .name("heading 1")
+                .fonts(run_fonts(&style.heading_font))
+                .size(style.body_size)
+                .bold()
+                .outline_lvl(0)
+                .line_spacing(LineSpacing::new().before(240).after(120)),
+        )
+        .add_style(
+            Style::new(STYLE_GROUP_HEADING, StyleType::Paragraph)
+                .name("分组标题")
+                .fonts(run_fonts(&style.heading_font))
+                .size(36)
+                .bold()
+                .align(AlignmentType::Center)
+                .outline_lvl(0),
+        )
+        .header(header)
+        .footer(footer)
+        .first_header(Header::new())
+        .first_footer(Footer::new());
+
+    // 封面
+    let issued: Vec<OffsetDateTime> = batch.zips.iter().filter_map(|z| issued_at_sort_key(&z.word)).collect();
+    docx = docx
+        .add_paragraph(Paragraph::new().line_spacing(LineSpacing::new().before(2400)))
+        .add_paragraph(Paragraph::new().style(STYLE_TITLE).add_run(Run::new().add_text("汇总文档")))
+        .add_paragraph(Paragraph::new().line_spacing(LineSpacing::new().after(1200)));
+    let mut cover_lines = vec![
+        format!("批次编号：{}", batch.batch_id),
+        format!("指令数量：{}", batch.zips.len()),
+    ];
+    if let (Some(first), Some(last)) = (issued.iter().min(), issued.iter().max()) {
+        cover_lines.push(format!(
+            "下发时间：{} 至 {}",
+            format_cover_time(*first),
+            format_cover_time(*last)
+        ));
+    }
+    if let Ok(created) = OffsetDateTime::from_unix_timestamp(batch.created_at) {
+        cover_lines.push(format!("导入时间：{}", format_cover_time(created)));
+    }
+    cover_lines.push(format!("导出时间：{}", format_cover_time(OffsetDateTime::now_utc())));
+    for line in cover_lines {
+        docx = docx.add_paragraph(
+            Paragraph::new()
+                .align(AlignmentType::Center)
+                .add_run(Run::new().add_text(line)),
+        );
+    }
+    docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_break(BreakType::Page)));
+
+    // 目录：由 Heading 1 与分组标题生成，打开文档时由 Word 更新页码
+    docx.add_table_of_contents(
+        TableOfContents::new()
+            .heading_styles_range(1, 1)
+            .add_style_with_level(StyleWithLevel::new(STYLE_GROUP_HEADING, 1))
+            .alias("目录")
+            .add_before_paragraph(
+                Paragraph::new()
+                    .align(AlignmentType::Center)
+                    .add_run(Run::new().add_text("目  录").fonts(run_fonts(&style.heading_font)).size(36).bold()),
+            )
+            .auto()
+            .dirty(),
+    )
+}
 
-        Ok(ExcelPreviewData {
-            sheet_name: first_sheet_name,
-            rows,
-            total_sheets: sheet_names.len(),
-            sheet_names,
-        })
-    } else {
-        Err(anyhow!("不支持的Excel格式: {}", extension))
+/// 公文版式骨架（GB/T 9704-2012）：A4 纸，天头 37mm、订口 28mm，版心 156mm×225mm，
+/// 每面 22 行、每行 28 字；红色发文机关标志和红色分隔线，小标宋标题，三号仿宋正文，
+/// 页码为四号宋体“— 1 —”，单页居右、双页居左。
+fn new_official_summary_docx(batch: &BatchSummary, style: &SummaryDocStyle, grouped: bool) -> Docx {
+    // 版心 225mm / 22 行 ≈ 579 缇；156mm / 28 字 ≈ 15.8 磅，相对三号字（16 磅）每字收缩约 844/4096 磅
+    const LINE_PITCH: usize = 579;
+    const CHAR_SPACE: isize = -844;
+
+    // 有分组时分组为一级标题（黑体），指令为二级标题（楷体）
+    let heading_font = if grouped { FONT_KAITI } else { FONT_HEITI };
+
+    let mut docx = Docx::new()
+        .page_size(11906, 16838)
+        .page_margin(
+            PageMargin::new()
+                .top(mm_to_twips(37.0))
+                .bottom(mm_to_twips(35.0))
+                .left(mm_to_twips(28.0))
+                .right(mm_to_twips(26.0))
+                .header(mm_to_twips(15.0))
+                .footer(mm_to_twips(22.0)),
+        )
+        .default_fonts(run_fonts(FONT_FANGSONG))
+        .default_size(32)
+        .add_style(
+            Style::new(STYLE_TITLE, StyleType::Paragraph)
+                .name("Title")
+                .fonts(run_fonts(FONT_XIAOBIAOSONG))
+                .size(44)
+                .align(AlignmentType::Center),
+        )
+        .add_style(
+            Style::new(STYLE_INSTRUCTION_HEADING, StyleType::Paragraph)
+                .name("heading 1")
+                .fonts(run_fonts(heading_font))
+                .size(32)
+                .outline_lvl(0),
+        )
+        .add_style(
+            Style::new(STYLE_GROUP_HEADING, StyleType::Paragraph)
+                .name("分组标题")
+                .fonts(run_fonts(FONT_HEITI))
+                .size(32)
+                .outline_lvl(0),
+        )
+        .footer(Footer::new().add_paragraph(official_page_number_paragraph(AlignmentType::Right)))
+        .even_footer(Footer::new().add_paragraph(official_page_number_paragraph(AlignmentType::Left)));
+    docx.document = docx.document.doc_grid(
+        DocGrid::new()
+            .grid_type(DocGridType::LinesAndChars)
+            .line_pitch(LINE_PITCH)
+            .char_space(CHAR_SPACE),
+    );
+
+    // 版头：红色发文机关标志，批次编号下方为红色分隔线
+    let issuer = match style.issuer.trim() {
+        "" => "汇总文档",
+        issuer => issuer,
+    };
+    let mut batch_line = Paragraph::new()
+        .align(AlignmentType::Center)
+        .add_run(Run::new().add_text(format!("批次 {}", batch.batch_id)));
+    batch_line.property = batch_line.property.set_border(
+        ParagraphBorder::new(ParagraphBorderPosition::Bottom)
+            .val(BorderType::Single)
+            .size(12)
+            .space(11)
+            .color("FF0000"),
+    );
+    docx = docx
+        .add_paragraph(
+            Paragraph::new().align(AlignmentType::Center).add_run(
+                Run::new()
+                    .add_text(issuer)
+                    .fonts(run_fonts(FONT_XIAOBIAOSONG))
+                    .size(72)
+                    .color("FF0000"),
+            ),
+        )
+        .add_paragraph(Paragraph::new())
+        .add_paragraph(batch_line)
+        .add_paragraph(Paragraph::new())
+        .add_paragraph(Paragraph::new().style(STYLE_TITLE).add_run(Run::new().add_text("指令汇总")))
+        .add_paragraph(Paragraph::new());
+
+    let issued: Vec<OffsetDateTime> = batch.zips.iter().filter_map(|z| issued_at_sort_key(&z.word)).collect();
+    let mut intro = format!("本批次共收录指令{}条", batch.zips.len());
+    if let (Some(first), Some(last)) = (issued.iter().min(), issued.iter().max()) {
+        intro.push_str(&format!(
+            "，下发时间自{}至{}",
+            format_cover_time(*first),
+            format_cover_time(*last)
+        ));
     }
+    intro.push('。');
+    docx.add_paragraph(
+        Paragraph::new()
+            .first_line_chars(200)
+            .add_run(Run::new().add_text(intro)),
+    )
 }
 
+fn official_page_number_paragraph(align: AlignmentType) -> Paragraph {
+    // 页码距版心一字（四号 14 磅 = 280 缇）
+    let paragraph = match align {
+        AlignmentType::Left => Paragraph::new().indent(Some(280), None, None, None),
+        _ => Paragraph::new().indent(None, None, Some(280), None),
+    };
+    paragraph
+        .align(align)
+        .add_run(Run::new().add_text("— ").fonts(run_fonts("宋体")).size(28))
+        .add_page_num(PageNum::new())
+        .add_run(Run::new().add_text(" —").fonts(run_fonts("宋体")).size(28))
+}
 
-#[tauri::command]
-fn get_excel_preview_data(
-    app: tauri::AppHandle,
-    batch_id: String,
-    zip_id: String,
-    index: usize,
-) -> Result<ExcelPreviewData, String> {
-    let batch_dir = batch_dir(&app, &batch_id).map_err(err_to_string)?;
-    let batch: BatchSummary = read_batch(&batch_dir).map_err(err_to_string)?;
-    let z = batch
-        .zips
-        .iter()
-        .find(|z| z.id == zip_id)
-        .ok_or_else(|| "ZIP不存在".to_string())?;
-    let path = z
-        .excel_files
-        .get(index)
-        .ok_or_else(|| "Excel文件索引越界".to_string())?;
+fn group_heading_paragraph(heading: &str, page_break: bool) -> Paragraph {
+    Paragraph::new()
+        .style(STYLE_GROUP_HEADING)
+        .page_break_before(page_break)
+        .add_run(Run::new().add_text(heading))
+}
 
-    read_excel_preview(Path::new(path)).map_err(err_to_string)
+/// 指令标题文本：优先指令标题，其次编号和文件名
+fn instruction_heading_text(z: &ZipSummary) -> String {
+    [&z.word.title, &z.word.instruction_no, &z.filename]
+        .into_iter()
+        .find(|s| !s.trim().is_empty())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|| z.id.clone())
 }
 
-fn sanitize_file_stem(name: &str) -> String {
-    let base = Path::new(name)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("pdf");
-    base.chars()
-        .map(|c| {
-            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
-                c
-            } else {
-                '_'
-            }
-        })
-        .collect::<String>()
+/// 每条指令一个 Heading 1
+fn instruction_heading_paragraph(heading: &str, page_break: bool) -> Paragraph {
+    Paragraph::new()
+        .style(STYLE_INSTRUCTION_HEADING)
+        .page_break_before(page_break)
+        .add_run(Run::new().add_text(heading))
 }
 
-fn decode_data_url_base64(data_url: &str) -> Result<Vec<u8>> {
-    let (meta, b64) = data_url
-        .split_once(',')
-        .ok_or_else(|| anyhow!("data url格式不正确"))?;
-    if !meta.contains(";base64") {
-        return Err(anyhow!("data url不是base64格式"));
+/// 写入分组标题（如有）和指令标题。普通版式除紧跟分组标题外，每条指令另起一页。
+fn add_section_headings(
+    docx: Docx,
+    z: &ZipSummary,
+    group_heading: Option<&str>,
+    numbering: &mut SectionNumbering,
+    style: &SummaryDocStyle,
+) -> Docx {
+    let page_break = style.page_break_between_sections();
+    let mut docx = docx;
+    if let Some(heading) = group_heading {
+        docx = docx.add_paragraph(group_heading_paragraph(&numbering.group(heading), page_break));
     }
-    base64_decode(b64)
+    let heading = numbering.instruction(&instruction_heading_text(z));
+    docx.add_paragraph(instruction_heading_paragraph(&heading, page_break && group_heading.is_none()))
 }
 
-fn base64_decode(s: &str) -> Result<Vec<u8>> {
-    fn val(c: u8) -> Option<u8> {
-        match c {
-            b'A'..=b'Z' => Some(c - b'A'),
-            b'a'..=b'z' => Some(c - b'a' + 26),
-            b'0'..=b'9' => Some(c - b'0' + 52),
-            b'+' => Some(62),
-            b'/' => Some(63),
-            _ => None,
-        }
+const EMU_PER_TWIP: u64 = 635;
+/// 按 96 dpi 换算像素
+const EMU_PER_PIXEL: u64 = 9525;
+const IMAGE_GRID_COLUMNS: usize = 3;
+/// 网格单元格左右内边距之和（缇）
+const IMAGE_GRID_CELL_PADDING: u32 = 216;
+
+/// 按原始像素尺寸排版，超出 max_cx×max_cy（EMU）时等比缩小，小图不放大
+fn fit_image_extent(width: u32, height: u32, max_cx: u64, max_cy: u64) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (max_cx as u32, max_cy as u32);
     }
+    let (cx, cy) = (width as u64 * EMU_PER_PIXEL, height as u64 * EMU_PER_PIXEL);
+    let scale = (max_cx as f64 / cx as f64).min(max_cy as f64 / cy as f64).min(1.0);
+    ((cx as f64 * scale) as u32, (cy as f64 * scale) as u32)
+}
 
-    let bytes = s.as_bytes();
-    let mut out = Vec::with_capacity((bytes.len() / 4) * 3);
-    let mut buf = [0u8; 4];
-    let mut n = 0usize;
+/// 图注：来源文件名；动图总是说明帧数和时长，提示原文件在附件中
+fn image_caption_paragraph(path: &str, image: &ProcessedImage, show_name: bool) -> Option<Paragraph> {
+    let text = match image.animation {
+        Some(info) => format!(
+            "{}（动图，共 {} 帧，约 {:.1} 秒，以上为 {} 帧抽样，原文件见附件）",
+            safe_basename(path),
+            info.frame_count,
+            info.duration_ms as f64 / 1000.0,
+            info.sampled_frames
+        ),
+        None if show_name => safe_basename(path),
+        None => return None,
+    };
+    Some(
+        Paragraph::new()
+            .align(AlignmentType::Center)
+            .add_run(Run::new().add_text(text).size(21)),
+    )
+}
 
-    for &b in bytes {
-        if b == b'=' {
-            buf[n] = 64;
-            n += 1;
-        } else if let Some(v) = val(b) {
-            buf[n] = v;
-            n += 1;
-        } else if b == b'\n' || b == b'\r' || b == b' ' || b == b'\t' {
-            continue;
-        } else {
-            return Err(anyhow!("base64包含非法字符"));
-        }
+fn image_paragraph(image: &ProcessedImage, max_cx: u64, max_cy: u64) -> Paragraph {
+    let (cx, cy) = fit_image_extent(image.width, image.height, max_cx, max_cy);
+    let pic = Pic::new_with_dimensions(image.bytes.clone(), image.width, image.height).size(cx, cy);
+    Paragraph::new().align(AlignmentType::Center).add_run(Run::new().add_image(pic))
+}
 
-        if n == 4 {
-            out.push((buf[0] << 2) | (buf[1] >> 4));
-            if buf[2] != 64 {
-                out.push((buf[1] << 4) | (buf[2] >> 2));
+/// 图片下方的“识别文本”块，未开启或没有识别结果时为空
+fn ocr_text_paragraphs(path: &str, ocr_texts: &[OcrText], style: &SummaryDocStyle) -> Vec<Paragraph> {
+    let Some(ocr) = ocr_texts.iter().find(|o| o.file == path && !o.text.trim().is_empty()) else {
+        return Vec::new();
+    };
+    if !style.ocr_text {
+        return Vec::new();
+    }
+    std::iter::once(Paragraph::new().add_run(Run::new().add_text("识别文本：").bold().size(21)))
+        .chain(
+            ocr.text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| Paragraph::new().add_run(Run::new().add_text(line.trim()).size(21))),
+        )
+        .collect()
+}
+
+/// 按版式把图片加入汇总文档，图片尺寸由像素尺寸和版心大小决定
+fn add_images(
+    docx: Docx,
+    images: &[(String, ProcessedImage)],
+    ocr_texts: &[OcrText],
+    style: &SummaryDocStyle,
+) -> Docx {
+    let (text_width, text_height) = style.text_area_twips();
+    let mut docx = docx;
+    match style.image_layout {
+        ImageLayout::Single => {
+            // 高度留出一成给图注和段距，避免图片独占一页后再被挤到下一页
+            let max_cx = text_width as u64 * EMU_PER_TWIP;
+            let max_cy = text_height as u64 * EMU_PER_TWIP * 9 / 10;
+            for (path, image) in images {
+                docx = docx.add_paragraph(image_paragraph(image, max_cx, max_cy));
+                if let Some(caption) = image_caption_paragraph(path, image, style.image_captions) {
+                    docx = docx.add_paragraph(caption);
+                }
+                for paragraph in ocr_text_paragraphs(path, ocr_texts, style) {
+                    docx = docx.add_paragraph(paragraph);
+                }
             }
-            if buf[3] != 64 {
-                out.push((buf[2] << 6) | buf[3]);
+        }
+        ImageLayout::Grid => {
+            if images.is_empty() {
+                return docx;
             }
-            n = 0;
+            let cell_width = text_width / IMAGE_GRID_COLUMNS as u32;
+            let max_cx = (cell_width - IMAGE_GRID_CELL_PADDING) as u64 * EMU_PER_TWIP;
+            let max_cy = text_height as u64 * EMU_PER_TWIP / 3;
+            let rows = images
+                .chunks(IMAGE_GRID_COLUMNS)
+                .map(|row| {
+                    let mut cells: Vec<TableCell> = row
+                        .iter()
+                        .map(|(path, image)| {
+                            let cell = TableCell::new()
+                                .width(cell_width as usize, WidthType::Dxa)
+                                .vertical_align(VAlignType::Bottom)
+                                .add_paragraph(image_paragraph(image, max_cx, max_cy));
+                            let cell = match image_caption_paragraph(path, image, style.image_captions) {
+                                Some(caption) => cell.add_paragraph(caption),
+                                None => cell,
+                            };
+                            ocr_text_paragraphs(path, ocr_texts, style)
+                                .into_iter()
+                                .fold(cell, |cell, p| cell.add_paragraph(p))
+                        })
+                        .collect();
+                    // 最后一行补齐空单元格
+                    while cells.len() < IMAGE_GRID_COLUMNS {
+                        cells.push(TableCell::new().width(cell_width as usize, WidthType::Dxa).add_paragraph(Paragraph::new()));
+                    }
+                    TableRow::new(cells).cant_split()
+                })
+                .collect();
+            docx = docx.add_table(
+                Table::without_borders(rows)
+                    .set_grid(vec![cell_width as usize; IMAGE_GRID_COLUMNS])
+                    .width(text_width as usize, WidthType::Dxa)
+                    .layout(TableLayoutType::Fixed)
+                    .align(TableAlignmentType::Center),
+            );
         }
     }
+    docx
+}
 
-    Ok(out)
+// ==================== 汇总文档样式结束 ====================
+
+// ==================== Word 模板导出 ====================
+//
+// 模板是用户提供的 .docx，正文中可以使用以下占位符：
+//   {{批次编号}} {{指令数量}} {{导出时间}}                          —— 全文可用
+//   {{序号}} {{指令编号}} {{指令标题}} {{下发时间}} {{指令内容}}
+//   {{文件名}} {{任务状态}}                                          —— 仅在 {{#each zips}} 块内
+//   {{images}} {{attachments}}                                      —— 独占一段，仅在块内
+// {{#each zips}} 与 {{/each}} 各占一段，两者之间的内容对每个 ZIP 重复一次。
+// 模板自身的样式、页眉页脚和图片保持不变，页眉页脚中也可以使用全文占位符。
+
+const TEMPLATE_EACH_START: &str = "{{#each zips}}";
+const TEMPLATE_EACH_END: &str = "{{/each}}";
+const TEMPLATE_IMAGES: &str = "{{images}}";
+const TEMPLATE_ATTACHMENTS: &str = "{{attachments}}";
+
+/// 段落开始标签不能是自闭合的（Word 常写出 `<w:p w:rsidR="00AB"/>` 这样的空段落），否则会一直匹配到下一个段落的结尾
+static RE_TEMPLATE_PARAGRAPH: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?s)<w:p(?:\s[^>]*[^/>])?\s*>.*?</w:p>").expect("valid regex")
+});
+
+static RE_TEMPLATE_TEXT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?s)<w:t(?:\s[^>]*)?>(.*?)</w:t>|<w:t(?:\s[^>]*)?/>").expect("valid regex")
+});
+
+static RE_TEMPLATE_PLACEHOLDER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\{\{\s*([^{}#/]+?)\s*\}\}").expect("valid regex")
+});
+
+/// 模板填充过程中新增的图片
+struct TemplateImage {
+    rel_id: String,
+    target: String,
+    bytes: Vec<u8>,
 }
 
-fn guess_image_mime(path: &str) -> &'static str {
-    let lower = path.to_ascii_lowercase();
-    if lower.ends_with(".png") {
-        "image/png"
-    } else if lower.ends_with(".jpg") || lower.ends_with(".jpeg") {
-        "image/jpeg"
-    } else if lower.ends_with(".gif") {
-        "image/gif"
-    } else {
-        "application/octet-stream"
-    }
+struct TemplateContext<'a> {
+    batch: &'a BatchSummary,
+    exported_at: String,
+    images: Vec<TemplateImage>,
 }
 
-fn base64_encode(bytes: &[u8]) -> String {
-    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-    let mut out = String::with_capacity(((bytes.len() + 2) / 3) * 4);
-    let mut i = 0usize;
-    while i + 3 <= bytes.len() {
-        let b0 = bytes[i];
-        let b1 = bytes[i + 1];
-        let b2 = bytes[i + 2];
-        out.push(TABLE[(b0 >> 2) as usize] as char);
-        out.push(TABLE[(((b0 & 0b0000_0011) << 4) | (b1 >> 4)) as usize] as char);
-        out.push(TABLE[(((b1 & 0b0000_1111) << 2) | (b2 >> 6)) as usize] as char);
-        out.push(TABLE[(b2 & 0b0011_1111) as usize] as char);
-        i += 3;
-    }
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
 
-    let rem = bytes.len() - i;
-    if rem == 1 {
-        let b0 = bytes[i];
-        out.push(TABLE[(b0 >> 2) as usize] as char);
-        out.push(TABLE[((b0 & 0b0000_0011) << 4) as usize] as char);
-        out.push('=');
-        out.push('=');
-    } else if rem == 2 {
-        let b0 = bytes[i];
-        let b1 = bytes[i + 1];
-        out.push(TABLE[(b0 >> 2) as usize] as char);
-        out.push(TABLE[(((b0 & 0b0000_0011) << 4) | (b1 >> 4)) as usize] as char);
-        out.push(TABLE[((b1 & 0b0000_1111) << 2) as usize] as char);
-        out.push('=');
-    }
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
 
-    out
+/// 段落中所有 w:t 的文本
+fn template_paragraph_text(paragraph: &str) -> String {
+    RE_TEMPLATE_TEXT
+        .captures_iter(paragraph)
+        .filter_map(|c| c.get(1))
+        .map(|m| xml_unescape(m.as_str()))
+        .collect()
 }
 
-// 新增：带文件嵌入的Word导出命令
-#[tauri::command]
-fn export_bundle_zip_with_embeddings(
-    app: tauri::AppHandle,
-    batch_id: String,
-    embed_files: bool,
-    max_file_size_mb: Option<u64>,
-    allowed_types: Option<Vec<String>>,
-) -> Result<String, String> {
-    let batch_dir = batch_dir(&app, &batch_id).map_err(err_to_string)?;
-    let mut batch: BatchSummary = read_batch(&batch_dir).map_err(err_to_string)?;
+/// Word 经常把一个占位符拆进多个 run（拼写检查、输入法、格式变化都会导致）。
+/// 对含占位符的段落，把全部文本合并进第一个 w:t，其余 w:t 置空，保留第一个 run 的格式。
+fn merge_template_runs(xml: &str) -> String {
+    RE_TEMPLATE_PARAGRAPH
+        .replace_all(xml, |caps: &regex::Captures| {
+            let paragraph = &caps[0];
+            let text = template_paragraph_text(paragraph);
+            if !text.contains("{{") {
+                return paragraph.to_string();
+            }
+            let mut first = true;
+            RE_TEMPLATE_TEXT
+                .replace_all(paragraph, |_: &regex::Captures| {
+                    if first {
+                        first = false;
+                        format!(r#"<w:t xml:space="preserve">{}</w:t>"#, xml_escape(&text))
+                    } else {
+                        "<w:t></w:t>".to_string()
+                    }
+                })
+                .into_owned()
+        })
+        .into_owned()
+}
 
-    // 按下发时间排序
-    sort_zips_by_issued_at(&mut batch.zips);
+fn template_value(ctx: &TemplateContext, name: &str, zip: Option<(usize, &ZipSummary)>) -> Option<String> {
+    let value = match (name, zip) {
+        ("批次编号", _) => ctx.batch.batch_id.clone(),
+        ("指令数量", _) => ctx.batch.zips.len().to_string(),
+        ("导出时间", _) => ctx.exported_at.clone(),
+        ("序号", Some((idx, _))) => (idx + 1).to_string(),
+        ("指令编号", Some((_, z))) => z.word.instruction_no.clone(),
+        ("指令标题", Some((_, z))) => z.word.title.clone(),
+        ("下发时间", Some((_, z))) => z.word.issued_at.clone(),
+        ("指令内容", Some((_, z))) => z.word.content.clone(),
+        ("文件名", Some((_, z))) => z.filename.clone(),
+        ("任务状态", Some((_, z))) => classify_task_status(&z.word.title).to_string(),
+        (name, Some((_, z))) if MAIL_FIELDS.contains(&name) => mail_field(z, name),
+        _ => return None,
+    };
+    Some(value)
+}
 
-    let now = OffsetDateTime::now_utc();
-    let out = prompt_save_path(default_export_bundle_name(now), "zip", "ZIP")?;
+/// 替换段落内的文本占位符，多行值按换行拆成 w:br。未知占位符原样保留，方便用户发现拼写错误。
+fn fill_template_paragraph(paragraph: &str, ctx: &TemplateContext, zip: Option<(usize, &ZipSummary)>) -> String {
+    RE_TEMPLATE_PLACEHOLDER
+        .replace_all(paragraph, |caps: &regex::Captures| {
+            match template_value(ctx, caps[1].trim(), zip) {
+                Some(value) => value
+                    .lines()
+                    .map(xml_escape)
+                    .collect::<Vec<_>>()
+                    .join(r#"</w:t><w:br/><w:t xml:space="preserve">"#),
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
 
-    // 创建嵌入配置
-    let mut config = EmbeddingConfig::default();
-    if embed_files {
-        config.enabled = true;
-        if let Some(size_mb) = max_file_size_mb {
-            config.max_file_size = (size_mb * 1024 * 1024) as usize;
-        }
-        if let Some(types) = allowed_types {
-            config.allowed_types = types;
+/// 模板的版心未知，按 14cm×19.6cm 的最大尺寸等比缩放
+fn template_image_paragraph(ctx: &mut TemplateContext, image: &ProcessedImage) -> String {
+    const MAX_CX: u64 = 5040000;
+    const MAX_CY: u64 = 7056000;
+    let n = ctx.images.len() + 1;
+    let rel_id = format!("rIdArchiveBoxImg{}", n);
+    let pic_id = 20000 + n;
+    let (cx, cy) = fit_image_extent(image.width, image.height, MAX_CX, MAX_CY);
+    let bytes = image.bytes.clone();
+    let ext = image_extension(image.format);
+    let xml = format!(
+        r#"<w:p><w:r><w:drawing><wp:inline distT="0" distB="0" distL="0" distR="0"><wp:extent cx="{cx}" cy="{cy}"/><wp:docPr id="{pic_id}" name="Picture {pic_id}"/><a:graphic xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main"><a:graphicData uri="http://schemas.openxmlformats.org/drawingml/2006/picture"><pic:pic xmlns:pic="http://schemas.openxmlformats.org/drawingml/2006/picture"><pic:nvPicPr><pic:cNvPr id="{pic_id}" name="archivebox_{n}.{ext}"/><pic:cNvPicPr/></pic:nvPicPr><pic:blipFill><a:blip r:embed="{rel_id}"/><a:stretch><a:fillRect/></a:stretch></pic:blipFill><pic:spPr><a:xfrm><a:off x="0" y="0"/><a:ext cx="{cx}" cy="{cy}"/></a:xfrm><a:prstGeom prst="rect"><a:avLst/></a:prstGeom></pic:spPr></pic:pic></a:graphicData></a:graphic></wp:inline></w:drawing></w:r></w:p>"#
+    );
+    ctx.images.push(TemplateImage {
+        rel_id,
+        target: format!("media/archivebox_{}.{}", n, ext),
+        bytes,
+    });
+    xml
+}
+
+/// 填充一段模板 XML。zip 为 None 时只替换全文占位符。
+fn fill_template_scope(
+    xml: &str,
+    ctx: &mut TemplateContext,
+    zip: Option<(usize, &ZipSummary)>,
+    images: &[ProcessedImage],
+) -> String {
+    let mut out = String::with_capacity(xml.len());
+    let mut last = 0;
+    for m in RE_TEMPLATE_PARAGRAPH.find_iter(xml) {
+        out.push_str(&xml[last..m.start()]);
+        last = m.end();
+        let paragraph = m.as_str();
+        let text = template_paragraph_text(paragraph);
+        match (text.trim(), zip) {
+            (TEMPLATE_IMAGES, Some(_)) => {
+                for image in images {
+                    let image_paragraph = template_image_paragraph(ctx, image);
+                    out.push_str(&image_paragraph);
+                }
+            }
+            (TEMPLATE_ATTACHMENTS, Some((_, z))) => {
+                // 与 build_enhanced_summary_docx 相同的标记段落，OLE 对象插在其后
+                out.push_str(&format!(
+                    r#"<w:p><w:r><w:rPr><w:color w:val="FFFFFF"/><w:sz w:val="2"/><w:szCs w:val="2"/></w:rPr><w:t>EMBED_MARKER_{}</w:t></w:r></w:p>"#,
+                    z.id
+                ));
+            }
+            _ => out.push_str(&fill_template_paragraph(paragraph, ctx, zip)),
         }
-    } else {
-        config.enabled = false;
     }
+    out.push_str(&xml[last..]);
+    out
+}
 
-    // 使用增强的导出功能
-    let (docx, embedded_files) = build_enhanced_summary_docx(&batch, embed_files, &app).map_err(err_to_string)?;
-    let docx_bytes = build_docx_with_embeddings(docx, &embedded_files).map_err(err_to_string)?;
-    let bundle_bytes = build_bundle_zip_bytes(&batch, &docx_bytes).map_err(err_to_string)?;
+/// 找到文本恰好为 marker 的段落，返回其字节范围
+fn find_template_marker(xml: &str, marker: &str) -> Option<(usize, usize)> {
+    RE_TEMPLATE_PARAGRAPH
+        .find_iter(xml)
+        .find(|m| template_paragraph_text(m.as_str()).trim() == marker)
+        .map(|m| (m.start(), m.end()))
+}
 
-    fs::write(&out, bundle_bytes).map_err(err_to_string)?;
-    Ok(out.to_string_lossy().to_string())
+/// 按模板生成汇总文档，返回 docx 字节和需要 OLE 嵌入的附件
+fn build_summary_docx_from_template(
+    template: &[u8],
+    batch: &BatchSummary,
+    image_settings: &ImageExportSettings,
+    job: &JobContext,
+    step: usize,
+) -> Result<(Vec<u8>, Vec<EmbeddedFile>)> {
+    let mut archive = ZipArchive::new(Cursor::new(template)).context("模板不是有效的docx文件")?;
+    let document_xml = merge_template_runs(
+        &read_file_from_zip_archive(&mut archive, "word/document.xml").context("模板缺少 word/document.xml")?,
+    );
+
+    let mut ctx = TemplateContext {
+        batch,
+        exported_at: format_cover_time(OffsetDateTime::now_utc()),
+        images: Vec::new(),
+    };
+    let mut embedded_files = Vec::new();
+
+    let start = find_template_marker(&document_xml, TEMPLATE_EACH_START);
+    let end = find_template_marker(&document_xml, TEMPLATE_EACH_END);
+    let rendered = match (start, end) {
+        (Some(start), Some(end)) if start.1 <= end.0 => {
+            let block = &document_xml[start.1..end.0];
+            let mut out = fill_template_scope(&document_xml[..start.0], &mut ctx, None, &[]);
+            let total_zips = batch.zips.len();
+            for (zip_idx, z) in batch.zips.iter().enumerate() {
+                job.checkpoint()?;
+                let span = ProgressSpan::item(step, zip_idx, total_zips);
+                job.report_span(span, 0.0, &format!("正在处理: {}", z.word.instruction_no));
+
+                let images = if block.contains(TEMPLATE_IMAGES) {
+                    let mut all_images = z.image_files.clone();
+                    all_images.extend_from_slice(&z.pdf_page_screenshot_files);
+                    process_images_parallel_with_progress(&all_images, image_settings, job, span)
+                        .with_context(|| "并行处理图片失败")?
+                        .into_iter()
+                        .map(|(_, image)| image)
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                if block.contains(TEMPLATE_ATTACHMENTS) {
+                    embedded_files.extend(collect_embedded_files(z, image_settings.strip_metadata));
+                }
+                out.push_str(&fill_template_scope(block, &mut ctx, Some((zip_idx, z)), &images));
+            }
+            out.push_str(&fill_template_scope(&document_xml[end.1..], &mut ctx, None, &[]));
+            out
+        }
+        (None, None) => fill_template_scope(&document_xml, &mut ctx, None, &[]),
+        _ => return Err(anyhow!("模板中的 {} 与 {} 不成对", TEMPLATE_EACH_START, TEMPLATE_EACH_END)),
+    };
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let name = file.name().to_string();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        drop(file);
+
+        let is_header_footer = name.starts_with("word/header") || name.starts_with("word/footer");
+        let content = if name == "word/document.xml" {
+            rendered.clone().into_bytes()
+        } else if name == "word/_rels/document.xml.rels" && !ctx.images.is_empty() {
+            let rels: String = ctx
+                .images
+                .iter()
+                .map(|img| {
+                    format!(
+                        r#"<Relationship Id="{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/image" Target="{}"/>"#,
+                        img.rel_id, img.target
+                    )
+                })
+                .collect();
+            String::from_utf8_lossy(&content)
+                .replace("</Relationships>", &format!("{}</Relationships>", rels))
+                .into_bytes()
+        } else if name == "[Content_Types].xml" && !ctx.images.is_empty() {
+            let mut types = String::from_utf8_lossy(&content).to_string();
+            for ext in ["jpeg", "png"] {
+                if ctx.images.iter().any(|img| img.target.ends_with(ext)) {
+                    types = with_default_content_type(&types, ext, &format!("image/{}", ext));
+                }
+            }
+            types.into_bytes()
+        } else if is_header_footer && name.ends_with(".xml") {
+            let xml = merge_template_runs(&String::from_utf8_lossy(&content));
+            fill_template_scope(&xml, &mut ctx, None, &[]).into_bytes()
+        } else {
+            content
+        };
+
+        writer.start_file(&name, options)?;
+        writer.write_all(&content)?;
+    }
+    for img in &ctx.images {
+        writer.start_file(format!("word/{}", img.target), options)?;
+        writer.write_all(&img.bytes)?;
+    }
+
+    Ok((writer.finish()?.into_inner(), embedded_files))
 }
 
-// 新增：获取嵌入配置
 #[tauri::command]
-fn get_embedding_config() -> EmbeddingConfig {
-    EmbeddingConfig::default()
+fn pick_docx_template() -> Result<Option<String>, String> {
+    Ok(rfd::FileDialog::new()
+        .add_filter("Word模板", &["docx"])
+        .pick_file()
+        .map(|p| p.to_string_lossy().to_string()))
 }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    tauri::Builder::default()
-        .manage(AppState::default())
-        .invoke_handler(tauri::generate_handler![
-            pick_zip_files,
-            import_zips,
-            export_excel,
-            export_excel_with_selection,
-            export_bundle_zip,
-            export_bundle_zip_with_selection,
-            export_bundle_zip_with_embeddings,
-            get_embedding_config,
-            open_path,
-            get_preview_image_data,
-            get_excel_preview_data,
-            save_pdf_page_screenshots,
-            cleanup_temp_files,
-            cleanup_old_batches
-        ])
-        .setup(|app| {
-            if cfg!(debug_assertions) {
-                app.handle().plugin(
-                    tauri_plugin_log::Builder::default()
-                        .level(log::LevelFilter::Info)
-                        .build(),
-                )?;
+// ==================== Word 模板导出结束 ====================
+
+// ==================== 富格式正文合并 ====================
+//
+// 把源 docx 的正文（段落、run、表格、列表、超链接、图片）原样搬进汇总文档：
+// 构建汇总文档时先写入一个隐藏的标记段落，打包后再把标记段落替换成源正文 XML，
+// 同时复制正文用到的样式定义、编号定义和媒体关系。样式和编号都重新编号，
+// 避免与汇总文档自身的 Heading1 等样式冲突。遇到无法安全复制的关系（图表、SmartArt 等）时
+// 放弃合并，由调用方退回纯文本。
+
+const RICH_BODY_MARKER_PREFIX: &str = "RICH_BODY_MARKER_";
+
+/// 待合并进汇总文档的源 docx 正文
+struct RichBody {
+    marker: String,
+    /// 要插入的 body 子元素（已去掉分节符、批注和脚注引用）
+    body: String,
+    source: Vec<u8>,
+}
+
+static RE_RICH_BODY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?s)<w:body(?:\s[^>]*)?>(.*)</w:body>").expect("valid regex")
+});
+
+/// 分节符、批注、脚注引用和段落 ID 在合并后都会失效，直接去掉
+static RE_RICH_STRIP: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?s)<w:sectPr\b[^>]*/>|<w:sectPr\b.*?</w:sectPr>|<w:comment(?:RangeStart|RangeEnd|Reference)\b[^>]*/>|<w:(?:footnote|endnote)Reference\b[^>]*/>|\sw14:(?:paraId|textId)="[^"]*""#,
+    )
+    .expect("valid regex")
+});
+
+static RE_RICH_REL_ATTR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"\b(r:(?:id|embed|link|pict|dm|lo|qs|cs))="([^"]+)""#).expect("valid regex")
+});
+
+static RE_RICH_STYLE_REF: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(<w:(?:pStyle|rStyle|tblStyle|basedOn|link|next)\s+w:val=")([^"]+)(")"#).expect("valid regex")
+});
+
+static RE_RICH_NUM_ID: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(<w:numId\s+w:val=")(\d+)(")"#).expect("valid regex")
+});
+
+static RE_RICH_STYLE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?s)<w:style\b[^>]*\bw:styleId="([^"]+)"[^>]*>.*?</w:style>"#).expect("valid regex")
+});
+
+static RE_RICH_ABSTRACT_NUM: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?s)<w:abstractNum\b[^>]*\bw:abstractNumId="(\d+)"[^>]*>.*?</w:abstractNum>"#).expect("valid regex")
+});
+
+static RE_RICH_NUM: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?s)<w:num\b[^>]*\bw:numId="(\d+)"[^>]*>.*?</w:num>"#).expect("valid regex")
+});
+
+static RE_RICH_RELATIONSHIP: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"<Relationship\b[^>]*>"#).expect("valid regex")
+});
+
+static RE_XML_ATTR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"([\w:]+)="([^"]*)""#).expect("valid regex")
+});
+
+static RE_RICH_PPR_DEFAULT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?s)<w:pPrDefault>\s*<w:pPr>(.*?)</w:pPr>").expect("valid regex")
+});
+
+static RE_RICH_RPR_DEFAULT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?s)<w:rPrDefault>\s*<w:rPr>(.*?)</w:rPr>").expect("valid regex")
+});
+
+static RE_RICH_STYLE_NAME: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"<w:name w:val="([^"]*)"\s*/>"#).expect("valid regex")
+});
+
+static RE_RICH_ABSTRACT_REF: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"<w:abstractNumId w:val="(\d+)"\s*/>"#).expect("valid regex")
+});
+
+static RE_RICH_NSID: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"<w:nsid w:val="[0-9A-Fa-f]+"\s*/>"#).expect("valid regex")
+});
+
+static RE_RICH_DOC_PR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(<wp:docPr\b[^>]*?\bid=")(\d+)(")"#).expect("valid regex")
+});
+
+/// 源文档中一条关系
+struct RichRelationship {
+    rel_type: String,
+    target: String,
+    external: bool,
+}
+
+/// 可以安全复制的关系类型（按 Type 结尾判断）
+const RICH_COPYABLE_RELS: [&str; 4] = ["/hyperlink", "/image", "/oleObject", "/package"];
+
+/// 隐藏的标记段落：白色 1 磅文字，后续处理时按文本定位
+fn hidden_marker_paragraph(marker: &str) -> Paragraph {
+    Paragraph::new().add_run(Run::new().add_text(marker).size(2).color("FFFFFF"))
+}
+
+fn read_zip_text(archive: &mut ZipArchive<Cursor<&[u8]>>, path: &str) -> Option<String> {
+    let mut file = archive.by_name(path).ok()?;
+    let mut content = String::new();
+    file.read_to_string(&mut content).ok()?;
+    Some(content)
+}
+
+fn parse_relationships(rels_xml: &str) -> std::collections::HashMap<String, RichRelationship> {
+    let mut map = std::collections::HashMap::new();
+    for tag in RE_RICH_RELATIONSHIP.find_iter(rels_xml) {
+        let attrs: std::collections::HashMap<&str, &str> = RE_XML_ATTR
+            .captures_iter(tag.as_str())
+            .map(|c| (c.get(1).map_or("", |m| m.as_str()), c.get(2).map_or("", |m| m.as_str())))
+            .collect();
+        if let (Some(id), Some(rel_type), Some(target)) = (attrs.get("Id"), attrs.get("Type"), attrs.get("Target")) {
+            map.insert(
+                id.to_string(),
+                RichRelationship {
+                    rel_type: rel_type.to_string(),
+                    target: target.to_string(),
+                    external: attrs.get("TargetMode") == Some(&"External"),
+                },
+            );
+        }
+    }
+    map
+}
+
+/// 关系目标相对 word/ 目录，转换为包内路径
+fn resolve_part_path(target: &str) -> String {
+    if let Some(absolute) = target.strip_prefix('/') {
+        return absolute.to_string();
+    }
+    let mut parts: Vec<&str> = vec!["word"];
+    for seg in target.split('/') {
+        match seg {
+            ".." => {
+                parts.pop();
             }
-            Ok(())
-        })
-        .on_window_event(|window, event| {
-            match event {
-                tauri::WindowEvent::CloseRequested { .. } => {
-                    // 应用关闭时清理临时文件
-                    if let Err(e) = cleanup_temp_files_on_exit(window.app_handle()) {
-                        eprintln!("清理临时文件失败: {}", e);
-                    }
+            "." | "" => {}
+            seg => parts.push(seg),
+        }
+    }
+    parts.join("/")
+}
+
+/// 按深度切分 body 的顶层子元素（段落、表格、内容控件等）
+fn top_level_elements(fragment: &str) -> Result<Vec<&str>> {
+    let mut reader = XmlReader::from_str(fragment);
+    reader.config_mut().trim_text(false);
+    let mut depth = 0usize;
+    let mut start = 0usize;
+    let mut out = Vec::new();
+    loop {
+        let pos = reader.buffer_position() as usize;
+        match reader.read_event() {
+            Ok(Event::Start(_)) => {
+                if depth == 0 {
+                    start = pos;
                 }
-                _ => {}
+                depth += 1;
             }
-        })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+            Ok(Event::End(_)) => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    out.push(&fragment[start..reader.buffer_position() as usize]);
+                }
+            }
+            Ok(Event::Empty(_)) if depth == 0 => {
+                out.push(&fragment[pos..reader.buffer_position() as usize]);
+            }
+            Ok(Event::Eof) => break,
+            Err(err) => return Err(anyhow!("XML解析错误: {:?}", err)),
+            _ => {}
+        }
+    }
+    Ok(out)
 }
 
-// ==================== 临时文件清理功能 ====================
+/// 从源 docx 取出可合并的正文。from_content_label 为 true 时只取“指令内容”所在段落及之后的内容
+/// （主文档前面的编号、标题、时间已由汇总文档自己输出）。找不到内容或含无法复制的关系时返回 None。
+fn rich_body_from_docx(marker: String, docx_bytes: Vec<u8>, from_content_label: bool) -> Result<Option<RichBody>> {
+    let mut archive = ZipArchive::new(Cursor::new(docx_bytes.as_slice()))?;
+    let document_xml = read_file_from_zip_archive(&mut archive, "word/document.xml")?;
+    let Some(body) = RE_RICH_BODY.captures(&document_xml).and_then(|c| c.get(1)) else {
+        return Ok(None);
+    };
+    let body = RE_RICH_STRIP.replace_all(body.as_str(), "");
+    let elements = top_level_elements(&body)?;
+
+    let start = if from_content_label {
+        let found = elements.iter().position(|e| {
+            normalize_text(&template_paragraph_text(e))
+                .trim_start()
+                .strip_prefix("指令内容")
+                .is_some_and(|rest| rest.trim_start().starts_with(':'))
+        });
+        match found {
+            Some(idx) => idx,
+            None => return Ok(None),
+        }
+    } else {
+        0
+    };
+    let body: String = elements[start..].concat();
+    if body.trim().is_empty() {
+        return Ok(None);
+    }
 
-/// 应用关闭时清理临时文件
-fn cleanup_temp_files_on_exit(app: &tauri::AppHandle) -> Result<()> {
-    println!("应用关闭，开始清理临时文件...");
-    
-    // 清理所有批次文件
-    if let Ok(app_data) = app_data_dir(app) {
-        let batches_dir = app_data.join("batches");
-        if batches_dir.exists() {
-            match fs::remove_dir_all(&batches_dir) {
-                Ok(_) => println!("✓ 已清理所有批次文件: {}", batches_dir.display()),
-                Err(e) => eprintln!("⚠ 清理批次文件失败: {}", e),
-            }
+    let rels = parse_relationships(
+        &read_zip_text(&mut archive, "word/_rels/document.xml.rels").unwrap_or_default(),
+    );
+    for caps in RE_RICH_REL_ATTR.captures_iter(&body) {
+        let copyable = rels.get(&caps[2]).is_some_and(|rel| {
+            rel.external || RICH_COPYABLE_RELS.iter().any(|t| rel.rel_type.ends_with(t))
+        });
+        if !copyable {
+            log::warn!("正文含无法合并的对象（{}），改用纯文本", &caps[2]);
+            return Ok(None);
         }
     }
-    
-    Ok(())
+
+    drop(archive);
+    Ok(Some(RichBody {
+        marker,
+        body,
+        source: docx_bytes,
+    }))
 }
 
-/// 手动清理临时文件的命令
-#[tauri::command]
-fn cleanup_temp_files(app: tauri::AppHandle) -> Result<String, String> {
-    let app_data = app_data_dir(&app).map_err(err_to_string)?;
-    let batches_dir = app_data.join("batches");
-    
-    if !batches_dir.exists() {
-        return Ok("没有找到需要清理的临时文件".to_string());
+/// 主文档的指令内容，需要导入时记录了主 docx 的 ZIP 条目
+fn main_docx_rich_body(z: &ZipSummary) -> Option<RichBody> {
+    if z.main_docx_entry.is_empty() {
+        return None;
     }
-    
-    // 计算清理前的大小
-    let size_before = calculate_dir_size(&batches_dir).unwrap_or(0);
-    let size_mb = size_before as f64 / 1024.0 / 1024.0;
-    
-    // 清理所有批次文件
-    fs::remove_dir_all(&batches_dir).map_err(err_to_string)?;
-    
-    Ok(format!("已清理临时文件，释放空间: {:.1} MB", size_mb))
+    // 人工修改过指令内容时原文档格式已不对应，按修改后的纯文本输出
+    if active_edited_fields(z).contains(&"content") {
+        return None;
+    }
+    let result = (|| -> Result<Option<RichBody>> {
+        let bytes = Package::open(Path::new(&z.stored_path))?.read_by_name(&z.main_docx_entry)?;
+        rich_body_from_docx(format!("{}{}_main", RICH_BODY_MARKER_PREFIX, z.id), bytes, true)
+    })();
+    result.unwrap_or_else(|e| {
+        log::warn!("读取主文档格式失败，改用纯文本: {}", e);
+        None
+    })
 }
 
-/// 清理旧的批次文件，保留最近的几个
-#[tauri::command]
-fn cleanup_old_batches(app: tauri::AppHandle, keep_count: Option<usize>) -> Result<String, String> {
-    let keep = keep_count.unwrap_or(3);
-    cleanup_old_batches_internal(&app, keep).map_err(err_to_string)?;
-    Ok(format!("已清理旧批次，保留最近 {} 个批次", keep))
+/// 附加文档的完整正文；未选择包含文本时不合并
+fn additional_docx_rich_body(zip_id: &str, docx_index: usize, additional: &AdditionalDocx) -> Option<RichBody> {
+    if additional.full_text.trim().is_empty() || additional.file_path.is_empty() {
+        return None;
+    }
+    let result = fs::read(&additional.file_path).map_err(anyhow::Error::from).and_then(|bytes| {
+        // 完整正文会带上全部内嵌图片；只勾选了部分图片时改用纯文本 + 已选图片
+        let media_count = ZipArchive::new(Cursor::new(bytes.as_slice()))?
+            .file_names()
+            .filter(|name| is_docx_media_image(name))
+            .count();
+        if additional.image_files.len() < media_count {
+            return Ok(None);
+        }
+        rich_body_from_docx(
+            format!("{}{}_{}", RICH_BODY_MARKER_PREFIX, zip_id, docx_index),
+            bytes,
+            false,
+        )
+    });
+    result.unwrap_or_else(|e| {
+        log::warn!("读取附加文档格式失败，改用纯文本: {}", e);
+        None
+    })
+}
+
+/// 把 source 根元素上 target 缺少的命名空间声明补到 target 根元素上
+fn merge_root_namespaces(target: &str, source: &str, root: &str) -> String {
+    let open = format!("<{}", root);
+    let (Some(src_start), Some(dst_start)) = (source.find(&open), target.find(&open)) else {
+        return target.to_string();
+    };
+    let (Some(src_len), Some(dst_len)) = (source[src_start..].find('>'), target[dst_start..].find('>')) else {
+        return target.to_string();
+    };
+    let src_tag = &source[src_start..src_start + src_len];
+    let dst_tag = &target[dst_start..dst_start + dst_len];
+    let mut extra = String::new();
+    for caps in RE_XML_ATTR.captures_iter(src_tag) {
+        let name = &caps[1];
+        if name.starts_with("xmlns:") && !dst_tag.contains(&format!("{}=", name)) {
+            extra.push_str(&format!(r#" {}="{}""#, name, &caps[2]));
+        }
+    }
+    if extra.is_empty() {
+        return target.to_string();
+    }
+    // 自闭合根元素（如空的 numbering）在 '/' 之前插入
+    let insert_at = if dst_tag.ends_with('/') { dst_start + dst_len - 1 } else { dst_start + dst_len };
+    let mut out = target.to_string();
+    out.insert_str(insert_at, &extra);
+    out
+}
+
+/// 给没有段落样式的段落补上源文档默认段落样式，保持源文档的默认字体和段距
+fn apply_default_paragraph_style(body: &str, style_id: &str) -> String {
+    static RE_P_OPEN: Lazy<Regex> = Lazy::new(|| Regex::new(r"<w:p(?:\s[^>]*)?>").expect("valid regex"));
+    let p_style = format!(r#"<w:pStyle w:val="{}"/>"#, style_id);
+    let mut out = String::with_capacity(body.len());
+    let mut last = 0;
+    for m in RE_P_OPEN.find_iter(body) {
+        if m.as_str().ends_with("/>") {
+            continue;
+        }
+        out.push_str(&body[last..m.end()]);
+        last = m.end();
+        let rest = &body[m.end()..];
+        if rest.starts_with("<w:pPr/>") {
+            out.push_str(&format!("<w:pPr>{}</w:pPr>", p_style));
+            last += "<w:pPr/>".len();
+        } else if rest.starts_with("<w:pPr>") {
+            let ppr_end = rest.find("</w:pPr>").unwrap_or(0);
+            if !rest[..ppr_end].contains("<w:pStyle ") {
+                out.push_str("<w:pPr>");
+                out.push_str(&p_style);
+                last += "<w:pPr>".len();
+            }
+        } else {
+            out.push_str(&format!("<w:pPr>{}</w:pPr>", p_style));
+        }
+    }
+    out.push_str(&body[last..]);
+    out
+}
+
+fn max_numeric_attr(xml: &str, re: &Regex) -> usize {
+    re.captures_iter(xml)
+        .filter_map(|c| c.get(1)?.as_str().parse::<usize>().ok())
+        .max()
+        .unwrap_or(0)
 }
 
-/// 内部清理旧批次的实现
-fn cleanup_old_batches_internal(app: &tauri::AppHandle, keep_count: usize) -> Result<()> {
-    let app_data = app_data_dir(app)?;
-    let batches_dir = app_data.join("batches");
-    
-    if !batches_dir.exists() {
-        return Ok(());
+/// 汇总文档中正在合并的各个部件
+struct RichMergeTarget {
+    document: String,
+    rels: String,
+    styles: String,
+    numbering: String,
+    content_types: String,
+    new_parts: Vec<(String, Vec<u8>)>,
+    next_doc_pr_id: usize,
+}
+
+impl RichMergeTarget {
+    fn ensure_default_content_type(&mut self, ext: &str, content_type: &str) {
+        self.content_types = with_default_content_type(&self.content_types, ext, content_type);
+    }
+
+    /// 合并一个源正文，返回替换后的正文 XML
+    fn merge(&mut self, k: usize, rich: &RichBody) -> Result<String> {
+        let mut src = ZipArchive::new(Cursor::new(rich.source.as_slice()))?;
+        let src_document = read_zip_text(&mut src, "word/document.xml").unwrap_or_default();
+        let src_rels = parse_relationships(&read_zip_text(&mut src, "word/_rels/document.xml.rels").unwrap_or_default());
+        let src_styles = read_zip_text(&mut src, "word/styles.xml").unwrap_or_default();
+        let src_numbering = read_zip_text(&mut src, "word/numbering.xml").unwrap_or_default();
+        let src_content_types = read_zip_text(&mut src, "[Content_Types].xml").unwrap_or_default();
+
+        self.document = merge_root_namespaces(&self.document, &src_document, "w:document");
+        self.styles = merge_root_namespaces(&self.styles, &src_styles, "w:styles");
+        self.numbering = merge_root_namespaces(&self.numbering, &src_numbering, "w:numbering");
+
+        // 1. 样式：复制正文引用到的样式及其 basedOn/link/next 链，统一加前缀
+        let style_defs: std::collections::HashMap<String, String> = RE_RICH_STYLE
+            .captures_iter(&src_styles)
+            .map(|c| (c[1].to_string(), c[0].to_string()))
+            .collect();
+        let default_style = style_defs
+            .iter()
+            .find(|(_, xml)| {
+                let open = &xml[..xml.find('>').unwrap_or(0)];
+                open.contains(r#"w:type="paragraph""#) && open.contains(r#"w:default="1""#)
+            })
+            .map(|(id, _)| id.clone());
+        let defaults_style_id = format!("AB{}Defaults", k);
+        let rename = |id: &str| format!("AB{}{}", k, id);
+
+        let mut pending: Vec<String> = RE_RICH_STYLE_REF
+            .captures_iter(&rich.body)
+            .map(|c| c[2].to_string())
+            .chain(default_style.clone())
+            .collect();
+        let mut copied: Vec<String> = Vec::new();
+        while let Some(id) = pending.pop() {
+            if copied.contains(&id) {
+                continue;
+            }
+            let Some(xml) = style_defs.get(&id) else { continue };
+            copied.push(id.clone());
+            pending.extend(RE_RICH_STYLE_REF.captures_iter(xml).map(|c| c[2].to_string()));
+        }
+        let rename_refs = |xml: &str| {
+            RE_RICH_STYLE_REF
+                .replace_all(xml, |c: &regex::Captures| {
+                    if copied.contains(&c[2].to_string()) {
+                        format!("{}{}{}", &c[1], rename(&c[2]), &c[3])
+                    } else {
+                        format!("{}{}{}", &c[1], &c[2], &c[3])
+                    }
+                })
+                .into_owned()
+        };
+
+        // 源文档的 docDefaults 转为一个基础样式，所有没有 basedOn 的段落样式都基于它
+        let doc_defaults = |re: &Regex| re.captures(&src_styles).map(|c| c[1].to_string()).unwrap_or_default();
+        let mut new_styles = format!(
+            r#"<w:style w:type="paragraph" w:customStyle="1" w:styleId="{}"><w:name w:val="源文档{} 默认"/><w:pPr>{}</w:pPr><w:rPr>{}</w:rPr></w:style>"#,
+            defaults_style_id,
+            k + 1,
+            doc_defaults(&RE_RICH_PPR_DEFAULT),
+            doc_defaults(&RE_RICH_RPR_DEFAULT),
+        );
+        for id in &copied {
+            let xml = &style_defs[id];
+            let mut xml = rename_refs(xml)
+                .replacen(&format!(r#"w:styleId="{}""#, id), &format!(r#"w:styleId="{}""#, rename(id)), 1)
+                .replacen(r#" w:default="1""#, "", 1);
+            xml = RE_RICH_STYLE_NAME
+                .replacen(&xml, 1, format!(r#"<w:name w:val="源文档{} $1"/>"#, k + 1))
+                .into_owned();
+            let is_paragraph = xml[..xml.find('>').unwrap_or(0)].contains(r#"w:type="paragraph""#);
+            if is_paragraph && !xml.contains("<w:basedOn ") {
+                if let Some(pos) = xml.find("<w:name ").and_then(|p| xml[p..].find("/>").map(|e| p + e + 2)) {
+                    xml.insert_str(pos, &format!(r#"<w:basedOn w:val="{}"/>"#, defaults_style_id));
+                }
+            }
+            new_styles.push_str(&xml);
+        }
+        self.styles = self.styles.replace("</w:styles>", &format!("{}</w:styles>", new_styles));
+
+        let mut body = rename_refs(&rich.body);
+        let body_default = default_style.filter(|id| copied.contains(id)).map(|id| rename(&id)).unwrap_or(defaults_style_id);
+        body = apply_default_paragraph_style(&body, &body_default);
+
+        // 2. 编号：复制用到的 num 和 abstractNum，编号 ID 接在汇总文档已有编号之后
+        let used_nums: Vec<usize> = RE_RICH_NUM_ID
+            .captures_iter(&body)
+            .chain(RE_RICH_NUM_ID.captures_iter(&new_styles))
+            .filter_map(|c| c[2].parse::<usize>().ok())
+            .filter(|id| *id != 0)
+            .collect();
+        if !used_nums.is_empty() {
+            let src_nums: std::collections::HashMap<usize, String> = RE_RICH_NUM
+                .captures_iter(&src_numbering)
+                .filter_map(|c| Some((c[1].parse().ok()?, c[0].to_string())))
+                .collect();
+            let src_abstracts: std::collections::HashMap<usize, String> = RE_RICH_ABSTRACT_NUM
+                .captures_iter(&src_numbering)
+                .filter_map(|c| Some((c[1].parse().ok()?, c[0].to_string())))
+                .collect();
+            let mut next_num = max_numeric_attr(&self.numbering, &RE_RICH_NUM) + 1;
+            let mut next_abstract = max_numeric_attr(&self.numbering, &RE_RICH_ABSTRACT_NUM) + 1;
+            let mut num_map = std::collections::HashMap::new();
+            let mut abstract_map = std::collections::HashMap::new();
+            let (mut abstracts_xml, mut nums_xml) = (String::new(), String::new());
+
+            for num_id in used_nums {
+                if num_map.contains_key(&num_id) {
+                    continue;
+                }
+                let Some(num_xml) = src_nums.get(&num_id) else { continue };
+                let Some(abstract_id) = RE_RICH_ABSTRACT_REF
+                    .captures(num_xml)
+                    .and_then(|c| c[1].parse::<usize>().ok())
+                else {
+                    continue;
+                };
+                if let std::collections::hash_map::Entry::Vacant(slot) = abstract_map.entry(abstract_id) {
+                    let Some(abstract_xml) = src_abstracts.get(&abstract_id) else { continue };
+                    let new_abstract = next_abstract;
+                    next_abstract += 1;
+                    slot.insert(new_abstract);
+                    // nsid 相同的列表会被 Word 视为同一列表，重新生成
+                    let nsid = format!(r#"<w:nsid w:val="{:08X}"/>"#, Uuid::new_v4().as_u128() as u32);
+                    let xml = abstract_xml.replacen(
+                        &format!(r#"w:abstractNumId="{}""#, abstract_id),
+                        &format!(r#"w:abstractNumId="{}""#, new_abstract),
+                        1,
+                    );
+                    abstracts_xml.push_str(&RE_RICH_NSID.replace(&xml, nsid.as_str()));
+                }
+                let new_num = next_num;
+                next_num += 1;
+                num_map.insert(num_id, new_num);
+                let xml = num_xml.replacen(&format!(r#"w:numId="{}""#, num_id), &format!(r#"w:numId="{}""#, new_num), 1);
+                nums_xml.push_str(
+                    &RE_RICH_ABSTRACT_REF.replace(&xml, format!(r#"<w:abstractNumId w:val="{}"/>"#, abstract_map[&abstract_id]).as_str()),
+                );
+            }
+
+            let remap_nums = |xml: &str| {
+                RE_RICH_NUM_ID
+                    .replace_all(xml, |c: &regex::Captures| {
+                        let id = c[2].parse::<usize>().unwrap_or(0);
+                        let new_id = num_map.get(&id).copied().unwrap_or(0);
+                        format!("{}{}{}", &c[1], new_id, &c[3])
+                    })
+                    .into_owned()
+            };
+            body = remap_nums(&body);
+            self.styles = self.styles.replace(&new_styles, &remap_nums(&new_styles));
+
+            // abstractNum 必须全部位于 num 之前
+            let abstract_pos = RE_RICH_NUM
+                .find(&self.numbering)
+                .map(|m| m.start())
+                .or_else(|| self.numbering.find("</w:numbering>"));
+            if let Some(pos) = abstract_pos {
+                self.numbering.insert_str(pos, &abstracts_xml);
+            }
+            self.numbering = self.numbering.replace("</w:numbering>", &format!("{}</w:numbering>", nums_xml));
+        }
+
+        // 3. 关系：超链接直接复制，图片和嵌入对象连同部件一起复制
+        let mut rel_map: std::collections::HashMap<String, String> = std::collections::HashMap::new();
+        let mut new_rels = String::new();
+        for caps in RE_RICH_REL_ATTR.captures_iter(&body) {
+            let old_id = caps[2].to_string();
+            if rel_map.contains_key(&old_id) {
+                continue;
+            }
+            let Some(rel) = src_rels.get(&old_id) else { continue };
+            let new_id = format!("rIdAB{}_{}", k, rel_map.len() + 1);
+            let target = if rel.external {
+                xml_escape(&xml_unescape(&rel.target))
+            } else {
+                let part = resolve_part_path(&rel.target);
+                let mut bytes = Vec::new();
+                src.by_name(&part)
+                    .with_context(|| format!("源文档缺少部件: {}", part))?
+                    .read_to_end(&mut bytes)?;
+                let basename = part.rsplit('/').next().unwrap_or("part");
+                let folder = if rel.rel_type.ends_with("/image") { "media" } else { "embeddings" };
+                let target = format!("{}/ab{}_{}_{}", folder, k, rel_map.len() + 1, basename);
+                if let Some(ext) = basename.rsplit_once('.').map(|(_, e)| e) {
+                    let content_type = RE_XML_ATTR
+                        .captures_iter(&src_content_types)
+                        .collect::<Vec<_>>()
+                        .windows(2)
+                        .find(|w| w[0][1] == *"Extension" && w[0][2].eq_ignore_ascii_case(ext))
+                        .map(|w| w[1][2].to_string())
+                        .unwrap_or_else(|| "application/octet-stream".to_string());
+                    self.ensure_default_content_type(ext, &content_type);
+                }
+                self.new_parts.push((format!("word/{}", target), bytes));
+                target
+            };
+            new_rels.push_str(&format!(
+                r#"<Relationship Id="{}" Type="{}" Target="{}"{}/>"#,
+                new_id,
+                rel.rel_type,
+                target,
+                if rel.external { r#" TargetMode="External""# } else { "" }
+            ));
+            rel_map.insert(old_id, new_id);
+        }
+        self.rels = self.rels.replace("</Relationships>", &format!("{}</Relationships>", new_rels));
+        body = RE_RICH_REL_ATTR
+            .replace_all(&body, |c: &regex::Captures| {
+                let id = rel_map.get(&c[2]).cloned().unwrap_or_else(|| c[2].to_string());
+                format!(r#"{}="{}""#, &c[1], id)
+            })
+            .into_owned();
+
+        // 4. 图片的 docPr id 在文档内必须唯一
+        body = RE_RICH_DOC_PR
+            .replace_all(&body, |c: &regex::Captures| {
+                self.next_doc_pr_id += 1;
+                format!("{}{}{}", &c[1], self.next_doc_pr_id, &c[3])
+            })
+            .into_owned();
+
+        Ok(body)
+    }
+}
+
+/// 把汇总文档中的标记段落替换为源 docx 正文
+fn merge_rich_bodies(docx_bytes: Vec<u8>, bodies: &[RichBody]) -> Result<Vec<u8>> {
+    if bodies.is_empty() {
+        return Ok(docx_bytes);
+    }
+    let mut archive = ZipArchive::new(Cursor::new(docx_bytes.as_slice()))?;
+    let mut target = RichMergeTarget {
+        document: read_file_from_zip_archive(&mut archive, "word/document.xml")?,
+        rels: read_file_from_zip_archive(&mut archive, "word/_rels/document.xml.rels")?,
+        styles: read_file_from_zip_archive(&mut archive, "word/styles.xml")?,
+        numbering: read_zip_text(&mut archive, "word/numbering.xml")
+            .filter(|x| x.contains("<w:numbering"))
+            .unwrap_or_else(|| {
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><w:numbering xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"></w:numbering>"#.to_string()
+            }),
+        content_types: read_file_from_zip_archive(&mut archive, "[Content_Types].xml")?,
+        new_parts: Vec::new(),
+        next_doc_pr_id: 30000,
+    };
+    // 自闭合的空 numbering 展开，便于插入
+    if let Some(pos) = target.numbering.find("<w:numbering") {
+        if let Some(end) = target.numbering[pos..].find("/>").map(|e| pos + e) {
+            if !target.numbering[pos..end].contains('>') {
+                target.numbering.replace_range(end..end + 2, "></w:numbering>");
+            }
+        }
+    }
+
+    for (k, rich) in bodies.iter().enumerate() {
+        let Some(marker_pos) = target.document.find(&rich.marker) else {
+            log::warn!("未找到正文标记: {}", rich.marker);
+            continue;
+        };
+        let start = ["<w:p>", "<w:p "]
+            .iter()
+            .filter_map(|open| target.document[..marker_pos].rfind(open))
+            .max();
+        let end = target.document[marker_pos..].find("</w:p>").map(|e| marker_pos + e + "</w:p>".len());
+        let (Some(start), Some(end)) = (start, end) else { continue };
+
+        let body = target.merge(k, rich)?;
+        target.document.replace_range(start..end, &body);
+    }
+
+    if !target.rels.contains("numbering.xml") {
+        target.rels = target.rels.replace(
+            "</Relationships>",
+            r#"<Relationship Id="rIdABNumbering" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/numbering" Target="numbering.xml"/></Relationships>"#,
+        );
+    }
+    if !target.content_types.contains("/word/numbering.xml") {
+        target.content_types = target.content_types.replace(
+            "</Types>",
+            r#"<Override PartName="/word/numbering.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml"/></Types>"#,
+        );
+    }
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let replaced = [
+        ("word/document.xml", target.document.as_bytes()),
+        ("word/_rels/document.xml.rels", target.rels.as_bytes()),
+        ("word/styles.xml", target.styles.as_bytes()),
+        ("word/numbering.xml", target.numbering.as_bytes()),
+        ("[Content_Types].xml", target.content_types.as_bytes()),
+    ];
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let name = file.name().to_string();
+        if replaced.iter().any(|(n, _)| *n == name) {
+            continue;
+        }
+        writer.start_file(&name, options)?;
+        std::io::copy(&mut file, &mut writer)?;
+    }
+    for (name, content) in replaced {
+        writer.start_file(name, options)?;
+        writer.write_all(content)?;
+    }
+    for (name, content) in &target.new_parts {
+        writer.start_file(name, options)?;
+        writer.write_all(content)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
+
+// ==================== 富格式正文合并结束 ====================
+
+/// 导入来源：ZIP 文件，或已解压的文件夹。条目按下标访问，文件夹的条目为按路径排序的相对路径（以 / 分隔）
+enum Package {
+    Zip(ZipArchive<fs::File>),
+    Folder { root: PathBuf, entries: Vec<String> },
+}
+
+impl Package {
+    fn open(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            let mut entries = Vec::new();
+            collect_folder_entries(path, path, &mut entries)?;
+            entries.sort();
+            return Ok(Package::Folder { root: path.to_path_buf(), entries });
+        }
+        let f = fs::File::open(path)?;
+        Ok(Package::Zip(ZipArchive::new(f)?))
+    }
+
+    fn is_folder(&self) -> bool {
+        matches!(self, Package::Folder { .. })
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Package::Zip(zip) => zip.len(),
+            Package::Folder { entries, .. } => entries.len(),
+        }
+    }
+
+    /// 条目名：ZIP 中按原样保存的名称（按名读取时使用）
+    fn name(&mut self, index: usize) -> Result<String> {
+        match self {
+            Package::Zip(zip) => Ok(zip.by_index(index)?.name().to_string()),
+            Package::Folder { entries, .. } => entries.get(index).cloned().ok_or_else(|| anyhow!("条目不存在: {}", index)),
+        }
+    }
+
+    /// 读取条目，返回解码后的文件名和内容
+    fn read(&mut self, index: usize) -> Result<(String, Vec<u8>)> {
+        match self {
+            Package::Zip(zip) => {
+                let mut file = zip.by_index(index)?;
+                let name = decode_zip_filename(file.name_raw());
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf)?;
+                Ok((name, buf))
+            }
+            Package::Folder { root, entries } => {
+                let name = entries.get(index).cloned().ok_or_else(|| anyhow!("条目不存在: {}", index))?;
+                let bytes = fs::read(root.join(&name)).with_context(|| format!("读取文件失败: {}", name))?;
+                Ok((name, bytes))
+            }
+        }
+    }
+
+    fn read_by_name(&mut self, name: &str) -> Result<Vec<u8>> {
+        match self {
+            Package::Zip(zip) => {
+                let mut file = zip.by_name(name)?;
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf)?;
+                Ok(buf)
+            }
+            Package::Folder { root, .. } => {
+                fs::read(root.join(name)).with_context(|| format!("读取文件失败: {}", name))
+            }
+        }
+    }
+}
+
+/// 系统生成的文件和 Word 打开文档时的锁文件，不属于指令包内容
+fn is_folder_artifact(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    lower.starts_with('.') || lower.starts_with("~$") || lower == "thumbs.db" || lower == "desktop.ini"
+}
+
+fn collect_folder_entries(root: &Path, dir: &Path, entries: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("无法读取文件夹: {}", dir.display()))? {
+        let path = entry?.path();
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if is_folder_artifact(&name) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_folder_entries(root, &path, entries)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let parts: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+            entries.push(parts.join("/"));
+        }
+    }
+    Ok(())
+}
+
+/// 把文件夹打包成 ZIP，条目路径与文件夹导入时一致
+fn zip_folder(dir: &Path, out: &Path) -> Result<()> {
+    let Package::Folder { root, entries } = Package::open(dir)? else {
+        return Err(anyhow!("不是文件夹: {}", dir.display()));
+    };
+    let file = fs::File::create(out).with_context(|| format!("创建ZIP失败: {}", out.display()))?;
+    let mut writer = ZipWriter::new(file);
+    for name in &entries {
+        let bytes = fs::read(root.join(name)).with_context(|| format!("读取文件失败: {}", name))?;
+        writer.start_file(name.as_str(), FileOptions::default())?;
+        writer.write_all(&bytes)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn stored_package_path(z: &ZipSummary) -> PathBuf {
+    PathBuf::from(if z.stored_path.trim().is_empty() { &z.source_path } else { &z.stored_path })
+}
+
+/// 导出时原始 ZIP 的文件名；以文件夹导入的加上 .zip
+fn original_zip_name(z: &ZipSummary) -> String {
+    if stored_package_path(z).is_dir() {
+        format!("{}.zip", z.filename)
+    } else {
+        z.filename.clone()
+    }
+}
+
+/// 原始 ZIP 的路径；以文件夹导入的在旁边按需打包一份（已打包过的直接使用）
+fn original_zip_path(z: &ZipSummary) -> Result<PathBuf> {
+    let stored = stored_package_path(z);
+    if !stored.is_dir() {
+        return Ok(stored);
+    }
+    let zipped = stored.with_file_name(original_zip_name(z));
+    if !zipped.exists() {
+        zip_folder(&stored, &zipped)?;
+    }
+    Ok(zipped)
+}
+
+#[derive(Debug, Clone)]
+struct ZipScan {
+    docx_entry: String,
+    additional_docx_entries: Vec<usize>,  // 附加docx的ZIP索引列表
+    video_entries: Vec<usize>,  // 存储ZIP中的索引
+    image_entries: Vec<usize>,
+    pdf_entries: Vec<usize>,
+    excel_entries: Vec<usize>,
+    nested_zip_entries: Vec<usize>,  // 嵌套ZIP的索引列表
+    has_sample: bool,
+    main_docx_decision: MainDocxDecision,
+}
+
+/// 主 docx 候选的得分
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct MainDocxCandidate {
+    entry: String,
+    score: i32,
+    /// 得分构成，如 "文件名与ZIP相同 +40"
+    reasons: Vec<String>,
+}
+
+/// 主 docx 的识别结果，保存下来便于核对和手动改选
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct MainDocxDecision {
+    /// 按得分从高到低排列，第一个是自动选中的
+    candidates: Vec<MainDocxCandidate>,
+    /// 用户用 set_main_document 手动指定过主 docx
+    #[serde(default)]
+    manual: bool,
+}
+
+/// 给一个 docx 打分：文件名与 ZIP 是否一致、是否含字段标签、文字长度、所在文件夹深度。
+/// text 为空表示没有读取内容（ZIP 内只有一个 docx 时不需要比较）
+fn score_main_docx(zip_stem: &str, entry: &str, text: Option<&str>) -> MainDocxCandidate {
+    let mut score = 0;
+    let mut reasons = Vec::new();
+    let mut add = |points: i32, reason: String| {
+        score += points;
+        reasons.push(format!("{} {:+}", reason, points));
+    };
+
+    let stem = Path::new(entry)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    if !zip_stem.is_empty() && stem == zip_stem {
+        add(40, "文件名与ZIP相同".to_string());
+    } else if !zip_stem.is_empty() && !stem.is_empty() && (zip_stem.contains(&stem) || stem.contains(zip_stem)) {
+        add(20, "文件名与ZIP相近".to_string());
+    }
+
+    if let Some(text) = text {
+        let labels: std::collections::HashSet<&str> = RE_FIELD
+            .captures_iter(text)
+            .filter_map(|c| c.get(1).map(|m| m.as_str()))
+            .collect();
+        if !labels.is_empty() {
+            add(15 * labels.len() as i32, format!("含{}个字段标签", labels.len()));
+        }
+        let chars = text.chars().filter(|c| !c.is_whitespace()).count();
+        if chars == 0 {
+            add(-20, "没有文字".to_string());
+        } else if chars >= 200 {
+            add((chars / 200).min(10) as i32, format!("{}字", chars));
+        }
+    }
+
+    let depth = entry.trim_end_matches('/').matches('/').count();
+    if depth == 0 {
+        add(10, "位于根目录".to_string());
+    } else if depth > 1 {
+        add(-5 * (depth as i32 - 1).min(4), format!("位于第{}层文件夹", depth));
+    }
+
+    MainDocxCandidate { entry: entry.to_string(), score, reasons }
+}
+
+/// 给所有 docx 打分并按得分排序，得分相同时保持 ZIP 中的顺序
+fn rank_main_docx(zip_filename: &str, docs: &[(String, Option<String>)]) -> Vec<MainDocxCandidate> {
+    let zip_stem = Path::new(zip_filename)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let mut candidates: Vec<MainDocxCandidate> = docs
+        .iter()
+        .map(|(entry, text)| score_main_docx(&zip_stem, entry, text.as_deref()))
+        .collect();
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.score));
+    candidates
+}
+
+/// 解码ZIP文件名（处理中文乱码）
+/// Windows创建的ZIP文件通常使用GBK编码，需要正确解码
+fn decode_zip_filename(name_bytes: &[u8]) -> String {
+    // 首先尝试UTF-8解码
+    if let Ok(utf8_name) = std::str::from_utf8(name_bytes) {
+        // 检查是否包含乱码字符（如□、�等）
+        if !utf8_name.chars().any(|c| c == '\u{FFFD}' || c == '□') {
+            return utf8_name.to_string();
+        }
+    }
+
+    // UTF-8失败或有乱码，尝试GBK解码
+    let (decoded, _encoding, had_errors) = GBK.decode(name_bytes);
+    if !had_errors {
+        return decoded.to_string();
+    }
+
+    // 都失败了，使用UTF-8并替换无效字符
+    String::from_utf8_lossy(name_bytes).to_string()
+}
+
+/// 处理后的图片数据及其像素尺寸，format 只会是 PNG 或 JPEG
+struct ProcessedImage {
+    bytes: Vec<u8>,
+    width: u32,
+    height: u32,
+    format: image::ImageFormat,
+    /// 动图时为抽帧联系表的帧信息
+    animation: Option<AnimationInfo>,
+}
+
+/// 动图的帧数和时长
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AnimationInfo {
+    frame_count: usize,
+    sampled_frames: usize,
+    duration_ms: u64,
+}
+
+fn image_extension(format: image::ImageFormat) -> &'static str {
+    if format == image::ImageFormat::Jpeg { "jpeg" } else { "png" }
+}
+
+/// [Content_Types].xml 缺少该扩展名时补上 Default 声明
+fn with_default_content_type(types: &str, ext: &str, content_type: &str) -> String {
+    let needle = format!(r#"extension="{}""#, ext.to_lowercase());
+    if types.to_lowercase().contains(&needle) {
+        return types.to_string();
+    }
+    types.replace(
+        "</Types>",
+        &format!(r#"<Default Extension="{}" ContentType="{}"/></Types>"#, ext, content_type),
+    )
+}
+
+/// docx-rs 把所有图片都按 .png 打包，JPEG 图片改名为 .jpeg 并补上内容类型
+fn fix_docx_media_extensions(docx_bytes: Vec<u8>) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(docx_bytes.as_slice()))?;
+    let renamed: Vec<String> = (0..archive.len())
+        .filter_map(|i| {
+            let mut file = archive.by_index(i).ok()?;
+            let name = file.name().to_string();
+            if !(name.starts_with("word/media/") && name.ends_with(".png")) {
+                return None;
+            }
+            let mut magic = [0u8; 3];
+            file.read_exact(&mut magic).ok()?;
+            (magic == [0xFF, 0xD8, 0xFF]).then_some(name)
+        })
+        .collect();
+    if renamed.is_empty() {
+        drop(archive);
+        return Ok(docx_bytes);
+    }
+    let jpeg_name = |name: &str| format!("{}.jpeg", name.trim_end_matches(".png"));
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let name = file.name().to_string();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        drop(file);
+
+        let (name, content) = if renamed.contains(&name) {
+            (jpeg_name(&name), content)
+        } else if name.starts_with("word/_rels/") && name.ends_with(".rels") {
+            let mut rels = String::from_utf8_lossy(&content).to_string();
+            for media in &renamed {
+                let target = media.trim_start_matches("word/");
+                rels = rels.replace(&format!(r#"Target="{}""#, target), &format!(r#"Target="{}""#, jpeg_name(target)));
+            }
+            (name, rels.into_bytes())
+        } else if name == "[Content_Types].xml" {
+            let types = with_default_content_type(&String::from_utf8_lossy(&content), "jpeg", "image/jpeg");
+            (name, types.into_bytes())
+        } else {
+            (name, content)
+        };
+        writer.start_file(&name, options)?;
+        writer.write_all(&content)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
+
+/// 汇总文档图片的缩放和编码设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct ImageExportSettings {
+    /// 最大宽度（像素）
+    max_width: u32,
+    /// 最大高度（像素）
+    max_height: u32,
+    /// JPEG 质量（1-100），只用于照片
+    jpeg_quality: u8,
+    /// 去掉导出图片中的 EXIF（拍摄时间、设备、GPS 位置）等元数据
+    strip_metadata: bool,
+}
+
+impl Default for ImageExportSettings {
+    fn default() -> Self {
+        // 1200x1680，质量 95（高分辨率，文字非常清晰）
+        Self {
+            max_width: 1200,
+            max_height: 1680,
+            jpeg_quality: 95,
+            strip_metadata: false,
+        }
+    }
+}
+
+/// 按图片类型缩放并重新编码（优化版本）：
+/// - 照片（JPEG，以及不透明的 WebP/AVIF）输出 JPEG；
+/// - 截图等无损格式（PNG/GIF/BMP 等）和带透明通道的图片输出 PNG，避免文字出现压缩噪点、透明背景变黑；
+/// - PNG/JPEG 不需要缩放和旋转时直接使用原始数据，不重新编码。
+///
+/// 按 EXIF 方向摆正照片；重新编码的图片不带元数据，原样使用的图片按设置去掉元数据。
+fn prepare_image(image_bytes: &[u8], settings: &ImageExportSettings) -> Result<ProcessedImage> {
+    use image::ImageFormat;
+
+    let source_format = image::guess_format(image_bytes).context("无法识别图片格式")?;
+    let (max_width, max_height) = (settings.max_width.max(1), settings.max_height.max(1));
+
+    // 动图只保留第一帧会丢失内容，改为输出抽帧联系表
+    if let Some(sheet) = animation_contact_sheet(image_bytes, settings)? {
+        return Ok(sheet);
+    }
+
+    let orientation = read_image_metadata(image_bytes).map_or(1, |m| m.orientation);
+
+    // PNG/JPEG 只读取尺寸，足够小且无需旋转就原样使用
+    if matches!(source_format, ImageFormat::Png | ImageFormat::Jpeg) && orientation == 1 {
+        let (width, height) = image::io::Reader::with_format(Cursor::new(image_bytes), source_format)
+            .into_dimensions()
+            .context("无法读取图片尺寸")?;
+        if width <= max_width && height <= max_height {
+            let bytes = if settings.strip_metadata {
+                strip_image_metadata(image_bytes)
+            } else {
+                image_bytes.to_vec()
+            };
+            return Ok(ProcessedImage {
+                bytes,
+                width,
+                height,
+                format: source_format,
+                animation: None,
+            });
+        }
+    }
+
+    // 加载图片
+    let img = image::load_from_memory_with_format(image_bytes, source_format)
+        .context("无法加载图片")?;
+    let img = apply_exif_orientation(img, orientation);
+
+    let (orig_width, orig_height) = img.dimensions();
+
+    // 计算缩放后的尺寸（保持纵横比）
+    let (new_width, new_height) = if orig_width <= max_width && orig_height <= max_height {
+        // 图片已经足够小，不需要缩放
+        (orig_width, orig_height)
+    } else {
+        let width_ratio = max_width as f32 / orig_width as f32;
+        let height_ratio = max_height as f32 / orig_height as f32;
+        let ratio = width_ratio.min(height_ratio);
+
+        (((orig_width as f32 * ratio) as u32).max(1), ((orig_height as f32 * ratio) as u32).max(1))
+    };
+
+    // 使用Lanczos3滤波器进行缩放（对文字友好，减少锯齿）
+    let resized = if new_width != orig_width || new_height != orig_height {
+        img.resize(new_width, new_height, image::imageops::FilterType::Lanczos3)
+    } else {
+        img
+    };
+
+    let is_photo = source_format == ImageFormat::Jpeg
+        || (matches!(source_format, ImageFormat::WebP | ImageFormat::Avif) && !resized.color().has_alpha());
+
+    let mut bytes = Vec::new();
+    let format = if is_photo {
+        // JPEG 不支持透明通道和 16 位色深，统一转为 8 位 RGB
+        let rgb = resized.to_rgb8();
+        let mut encoder = JpegEncoder::new_with_quality(&mut bytes, settings.jpeg_quality.clamp(1, 100));
+        encoder
+            .encode(rgb.as_raw(), rgb.width(), rgb.height(), image::ColorType::Rgb8)
+            .context("无法将图片转换为JPEG")?;
+        ImageFormat::Jpeg
+    } else {
+        resized
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .context("无法将图片转换为PNG")?;
+        ImageFormat::Png
+    };
+
+    Ok(ProcessedImage {
+        bytes,
+        width: resized.width(),
+        height: resized.height(),
+        format,
+        animation: None,
+    })
+}
+
+/// 联系表最多抽取的帧数和列数
+const CONTACT_SHEET_FRAMES: usize = 9;
+const CONTACT_SHEET_COLUMNS: usize = 3;
+/// 每帧缩略图的最大边长（像素）和间距
+const CONTACT_SHEET_TILE: u32 = 400;
+const CONTACT_SHEET_GAP: u32 = 8;
+/// 超长动图只读取前若干帧，避免内存占用过大
+const MAX_ANIMATION_FRAMES: usize = 2000;
+
+/// 读取动图（GIF、动态 WebP、APNG）的帧序列，静态图片返回 None
+fn animation_frames(image_bytes: &[u8]) -> Result<Option<image::Frames<'_>>> {
+    use image::AnimationDecoder;
+    use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+
+    let frames = match image::guess_format(image_bytes) {
+        Ok(image::ImageFormat::Gif) => GifDecoder::new(Cursor::new(image_bytes))?.into_frames(),
+        Ok(image::ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(image_bytes))?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames()
+        }
+        Ok(image::ImageFormat::Png) => {
+            let decoder = PngDecoder::new(Cursor::new(image_bytes))?;
+            if !decoder.is_apng() {
+                return Ok(None);
+            }
+            decoder.apng().into_frames()
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(frames))
+}
+
+/// 是否为多帧动图（用于决定是否把原文件作为附件嵌入）
+fn is_animated_image(image_bytes: &[u8]) -> bool {
+    match animation_frames(image_bytes) {
+        Ok(Some(frames)) => frames.take(2).count() > 1,
+        _ => false,
+    }
+}
+
+/// 把动图均匀抽取最多 9 帧，按 3 列排成一张 PNG 联系表；单帧图片返回 None
+fn animation_contact_sheet(image_bytes: &[u8], settings: &ImageExportSettings) -> Result<Option<ProcessedImage>> {
+    let Some(frames) = animation_frames(image_bytes)? else {
+        return Ok(None);
+    };
+
+    // 逐帧缩小后再保存，长动图也只占用缩略图的内存
+    let mut thumbnails = Vec::new();
+    let mut duration_ms = 0u64;
+    for frame in frames.take(MAX_ANIMATION_FRAMES) {
+        let frame = frame.context("动图帧解码失败")?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        duration_ms += numer as u64 / denom.max(1) as u64;
+        let buffer = frame.into_buffer();
+        let ratio = (CONTACT_SHEET_TILE as f32 / buffer.width().max(buffer.height()) as f32).min(1.0);
+        let (w, h) = (
+            ((buffer.width() as f32 * ratio) as u32).max(1),
+            ((buffer.height() as f32 * ratio) as u32).max(1),
+        );
+        thumbnails.push(image::imageops::thumbnail(&buffer, w, h));
+    }
+    let frame_count = thumbnails.len();
+    if frame_count < 2 {
+        return Ok(None);
+    }
+
+    let sampled: Vec<&image::RgbaImage> = if frame_count <= CONTACT_SHEET_FRAMES {
+        thumbnails.iter().collect()
+    } else {
+        (0..CONTACT_SHEET_FRAMES)
+            .map(|i| &thumbnails[i * (frame_count - 1) / (CONTACT_SHEET_FRAMES - 1)])
+            .collect()
+    };
+    let tile_w = sampled.iter().map(|t| t.width()).max().unwrap_or(1);
+    let tile_h = sampled.iter().map(|t| t.height()).max().unwrap_or(1);
+    let columns = sampled.len().min(CONTACT_SHEET_COLUMNS) as u32;
+    let rows = sampled.len().div_ceil(CONTACT_SHEET_COLUMNS) as u32;
+    let mut sheet = image::RgbaImage::from_pixel(
+        columns * tile_w + (columns + 1) * CONTACT_SHEET_GAP,
+        rows * tile_h + (rows + 1) * CONTACT_SHEET_GAP,
+        image::Rgba([255, 255, 255, 255]),
+    );
+    for (i, tile) in sampled.iter().enumerate() {
+        let (col, row) = ((i % CONTACT_SHEET_COLUMNS) as u32, (i / CONTACT_SHEET_COLUMNS) as u32);
+        let x = CONTACT_SHEET_GAP + col * (tile_w + CONTACT_SHEET_GAP) + (tile_w - tile.width()) / 2;
+        let y = CONTACT_SHEET_GAP + row * (tile_h + CONTACT_SHEET_GAP) + (tile_h - tile.height()) / 2;
+        // 透明帧铺在白底上
+        image::imageops::overlay(&mut sheet, *tile, x as i64, y as i64);
+    }
+
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(sheet)
+        .to_rgb8()
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .context("无法生成动图联系表")?;
+    let mut processed = prepare_image(&png, settings)?;
+    processed.animation = Some(AnimationInfo {
+        frame_count,
+        sampled_frames: sampled.len(),
+        duration_ms,
+    });
+    Ok(Some(processed))
+}
+
+/// 并行处理多个图片文件，分批处理；每张图片完成后在 span 区间内汇报进度，批次之间响应暂停和取消
+fn process_images_parallel_with_progress(
+    image_paths: &[String],
+    settings: &ImageExportSettings,
+    ctx: &JobContext,
+    span: ProgressSpan,
+) -> Result<Vec<(String, ProcessedImage)>> {
+    let paths: Vec<String> = image_paths.to_vec();
+    let count = paths.len();
+
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+
+    // 分批处理，避免内存爆炸
+    let batch_size = std::cmp::min(5, count); // 每批最多处理5张图片
+    let mut all_results = Vec::new();
+    let done = std::sync::atomic::AtomicUsize::new(0);
+
+    log::debug!("开始分批处理 {} 张图片，每批 {} 张...", count, batch_size);
+
+    for (batch_idx, chunk) in paths.chunks(batch_size).enumerate() {
+        ctx.checkpoint()?;
+
+        let batch_results: Result<Vec<_>> = chunk
+            .par_iter()
+            .map(|path| {
+                let _log_scope = ctx.log_scope();
+                let bytes = fs::read(path)
+                    .with_context(|| format!("读取图片失败: {}", path))?;
+                let resized_bytes = prepare_image(&bytes, settings)
+                    .with_context(|| format!("调整图片大小失败: {}", path))?;
+
+                // 每处理完一张图片汇报一次进度
+                let current = done.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+                ctx.report_span(
+                    span,
+                    current as f64 / count as f64,
+                    &format!("处理图片 {}/{}: {}", current, count, safe_basename(path)),
+                );
+
+                Ok((path.clone(), resized_bytes))
+            })
+            .collect();
+
+        let mut results = batch_results?;
+        all_results.append(&mut results);
+        log::debug!("批次 {}/{} 完成，已处理 {} 张图片", batch_idx + 1, count.div_ceil(batch_size), all_results.len());
+    }
+
+    log::debug!("所有图片处理完成，共 {} 张", all_results.len());
+    Ok(all_results)
+}
+
+/// 并行处理多个图片文件（保留原函数用于其他地方）
+fn process_images_parallel(
+    image_paths: &[String],
+    settings: &ImageExportSettings,
+    progress_callback: impl Fn(usize, usize, &str) + Send + Sync,
+) -> Result<Vec<(String, ProcessedImage)>> {
+    let paths: Vec<String> = image_paths.to_vec();
+    let count = paths.len();
+
+    let results: Result<Vec<_>> = paths
+        .into_par_iter()
+        .enumerate()
+        .map(|(index, path)| {
+            progress_callback(index, count, &safe_basename(&path));
+
+            let bytes = fs::read(&path)
+                .with_context(|| format!("读取图片失败: {}", path))?;
+            let resized_bytes = prepare_image(&bytes, settings)?;
+
+            Ok((path, resized_bytes))
+        })
+        .collect();
+
+    results
+}
+
+/// 按文件类型给 ZIP（或已解压的文件夹）中的条目分类，并识别主 docx
+fn scan_zip(zip_path: &Path) -> Result<ZipScan> {
+    let mut package = Package::open(zip_path)?;
+
+    let mut all_docx_entries = Vec::new();  // 收集所有 docx 的索引和名称
+    let mut has_sample = false;
+    let mut video_entries = Vec::new();
+    let mut image_entries = Vec::new();
+    let mut pdf_entries = Vec::new();
+    let mut excel_entries = Vec::new();
+    let mut nested_zip_entries = Vec::new();  // 收集嵌套 ZIP 的索引
+
+    for i in 0..package.len() {
+        // 保存原始文件名（用于后续从ZIP中读取）
+        let name = package.name(i)?;
+        let lower = name.to_ascii_lowercase();
+
+        if lower.ends_with(".docx") {
+            all_docx_entries.push((i, name));  // 收集所有 docx
+            continue;
+        }
+
+        if lower.ends_with("/") || lower.ends_with(".ds_store") {
+            continue;
+        }
+
+        // Word之外都算样本
+        has_sample = true;
+
+        if lower.ends_with(".mp4") {
+            video_entries.push(i);  // 保存索引
+        } else if lower.ends_with(".pdf") {
+            pdf_entries.push(i);
+        } else if lower.ends_with(".png")
+            || lower.ends_with(".jpg")
+            || lower.ends_with(".jpeg")
+            || lower.ends_with(".gif")
+            || lower.ends_with(".webp")
+        {
+            image_entries.push(i);
+        } else if lower.ends_with(".xlsx") || lower.ends_with(".xls") {
+            excel_entries.push(i);
+        } else if lower.ends_with(".zip") {
+            nested_zip_entries.push(i);  // 收集嵌套 ZIP
+        }
+    }
+
+    if all_docx_entries.is_empty() {
+        return Err(anyhow!("{}内未找到docx", if package.is_folder() { "文件夹" } else { "ZIP" }));
+    }
+
+    // 识别主 docx
+    let zip_filename = zip_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("unknown.zip");
+
+    // 有多个 docx 时读取内容参与评分，读不出的按没有文字处理
+    let multiple = all_docx_entries.len() > 1;
+    let mut docs = Vec::new();
+    for (_, name) in &all_docx_entries {
+        let text = multiple.then(|| {
+            package
+                .read_by_name(name)
+                .and_then(|bytes| extract_full_text_from_docx(&bytes))
+                .unwrap_or_default()
+        });
+        docs.push((name.clone(), text));
+    }
+    let candidates = rank_main_docx(zip_filename, &docs);
+    let main_docx_name = candidates.first().map(|c| c.entry.clone()).ok_or_else(|| anyhow!("无法识别主docx"))?;
+
+    // 分离主 docx 和附加 docx
+    let mut main_docx_entry = None;
+    let mut additional_docx_entries = Vec::new();
+
+    for (idx, name) in all_docx_entries {
+        if name == main_docx_name {
+            main_docx_entry = Some(name);
+        } else {
+            additional_docx_entries.push(idx);
+        }
+    }
+
+    Ok(ZipScan {
+        docx_entry: main_docx_entry.ok_or_else(|| anyhow!("主docx丢失"))?,
+        additional_docx_entries,
+        video_entries,
+        image_entries,
+        pdf_entries,
+        excel_entries,
+        nested_zip_entries,
+        has_sample,
+        main_docx_decision: MainDocxDecision { candidates, manual: false },
+    })
+}
+
+fn extract_word_and_videos(zip_path: &Path, scan: &ZipScan) -> Result<(WordFields, Vec<String>)> {
+    let docx_bytes = Package::open(zip_path)?.read_by_name(&scan.docx_entry)?;
+    let fields = extract_fields_from_docx(&docx_bytes)?;
+
+    // 返回空的 video_entries，因为现在使用索引而不是文件名
+    // 实际的文件信息在 extract_preview_files 中处理
+    Ok((fields, vec![]))
+}
+
+fn extract_preview_files(
+    batch_dir: &Path,
+    zip_id: &str,
+    zip_path: &Path,
+    scan: &ZipScan,
+    summary: &mut ZipSummary,
+) -> Result<()> {
+    let root = batch_dir.join("zips").join(zip_id).join("extracted");
+    summary.extracted_dir = root.to_string_lossy().to_string();
+    let videos_dir = root.join("videos");
+    let images_dir = root.join("images");
+    let pdf_dir = root.join("pdf");
+    let excel_dir = root.join("excel");
+    fs::create_dir_all(&videos_dir)?;
+    fs::create_dir_all(&images_dir)?;
+    fs::create_dir_all(&pdf_dir)?;
+    fs::create_dir_all(&excel_dir)?;
+
+    let mut package = Package::open(zip_path)?;
+
+    for &index in &scan.video_entries {
+        let (name, buf) = package.read(index)?;
+        let basename = safe_basename(&name);
+        let out = unique_path(&videos_dir, &basename);
+        fs::write(&out, buf)?;
+        summary.video_files.push(out.to_string_lossy().to_string());
+    }
+
+    for &index in &scan.image_entries {
+        let (name, buf) = package.read(index)?;
+        let basename = safe_basename(&name);
+        let out = unique_path(&images_dir, &basename);
+        fs::write(&out, buf)?;
+        summary.image_files.push(out.to_string_lossy().to_string());
+    }
+
+    for &index in &scan.pdf_entries {
+        let (name, buf) = package.read(index)?;
+        let basename = safe_basename(&name);
+        let out = unique_path(&pdf_dir, &basename);
+        fs::write(&out, buf)?;
+        summary.pdf_files.push(out.to_string_lossy().to_string());
+    }
+
+    for &index in &scan.excel_entries {
+        let (name, buf) = package.read(index)?;
+        let basename = safe_basename(&name);
+        let out = unique_path(&excel_dir, &basename);
+        fs::write(&out, buf)?;
+        summary.excel_files.push(out.to_string_lossy().to_string());
+    }
+
+    Ok(())
+}
+
+/// word/media/ 下会被提取出来的图片（与 image_files 的顺序一一对应）
+fn is_docx_media_image(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    lower.starts_with("word/media/")
+        && (lower.ends_with(".png") || lower.ends_with(".jpg") || lower.ends_with(".jpeg") || lower.ends_with(".gif"))
+}
+
+/// 从 docx 中提取图片
+fn extract_images_from_docx(docx_bytes: &[u8], output_dir: &Path) -> Result<Vec<String>> {
+    let cursor = Cursor::new(docx_bytes);
+    let mut zip = ZipArchive::new(cursor)?;
+    let mut image_paths = Vec::new();
+
+    fs::create_dir_all(output_dir)?;
+
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        let name = file.name();
+
+        // 只提取 word/media/ 下的图片
+        if is_docx_media_image(name) {
+            let basename = Path::new(name)
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or("image.png");
+
+            let out_path = unique_path(output_dir, basename);
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf)?;
+            fs::write(&out_path, buf)?;
+            image_paths.push(out_path.to_string_lossy().to_string());
+        }
+    }
+
+    Ok(image_paths)
+}
+
+/// 提取 docx 的完整文本内容（所有段落）
+fn extract_full_text_from_docx(docx_bytes: &[u8]) -> Result<String> {
+    let cursor = Cursor::new(docx_bytes);
+    let mut zip = ZipArchive::new(cursor)?;
+    let mut document_xml = zip
+        .by_name("word/document.xml")
+        .context("docx缺少word/document.xml")?;
+    let mut xml = String::new();
+    document_xml.read_to_string(&mut xml)?;
+
+    // 复用现有的段落文本提取逻辑
+    let text = extract_paragraph_texts(&xml)?;
+
+    Ok(text)
+}
+
+/// 处理附加 docx 文件；单个文档读取失败时记录问题并跳过
+fn process_additional_docx(
+    batch_dir: &Path,
+    zip_id: &str,
+    zip_path: &Path,
+    additional_indices: &[usize],
+    diagnostics: &mut Vec<ZipDiagnostic>,
+) -> Result<Vec<AdditionalDocx>> {
+    let mut package = Package::open(zip_path)?;
+    let mut results = Vec::new();
+
+    for &index in additional_indices {
+        // 读取 docx 内容（entry 为按名读取时用的原始条目名）
+        let entry = package.name(index).unwrap_or_default();
+        let (name, docx_bytes) = match package.read(index) {
+            Ok(read) => read,
+            Err(e) => {
+                let name = entry;
+                diagnostics.push(ZipDiagnostic::new(DiagnosticCode::AdditionalDocxFailed, &name, format!("{e:#}")));
+                continue;
+            }
+        };
+
+        // 解析结构化字段（附加文档常常没有字段，失败不记录）
+        let fields = extract_fields_from_docx(&docx_bytes)
+            .unwrap_or_else(|_| WordFields::default());
+
+        // 提取完整文本内容
+        let full_text = extract_full_text_from_docx(&docx_bytes).unwrap_or_else(|e| {
+            diagnostics.push(ZipDiagnostic::new(DiagnosticCode::AdditionalDocxText, &name, format!("{e:#}")));
+            String::from("无法提取文本内容")
+        });
+
+        // 提取图片
+        let docx_id = Uuid::new_v4().to_string();
+        let images_dir = batch_dir
+            .join("zips")
+            .join(zip_id)
+            .join("extracted")
+            .join("additional_docx")
+            .join(&docx_id);
+
+        let image_files = extract_images_from_docx(&docx_bytes, &images_dir).unwrap_or_else(|e| {
+            diagnostics.push(ZipDiagnostic::new(DiagnosticCode::AdditionalDocxImages, &name, format!("{e:#}")));
+            vec![]
+        });
+
+        // 保存 docx 文件本身
+        let docx_dir = batch_dir
+            .join("zips")
+            .join(zip_id)
+            .join("extracted")
+            .join("additional_docx_files");
+        let docx_path = unique_path(&docx_dir, &name);
+        if let Err(e) = fs::create_dir_all(&docx_dir).and_then(|_| fs::write(&docx_path, &docx_bytes)) {
+            diagnostics.push(ZipDiagnostic::new(DiagnosticCode::AdditionalDocxFailed, &name, e.to_string()));
+            continue;
+        }
+
+        results.push(AdditionalDocx {
+            id: docx_id,
+            name: safe_basename(&name),
+            entry,
+            file_path: docx_path.to_string_lossy().to_string(),
+            fields,
+            full_text,
+            image_files,
+        });
+    }
+
+    Ok(results)
+}
+
+/// 手动指定主 docx：重新抽取字段，原主 docx 作为附加文档处理，新主 docx 从附加文档中移除
+fn swap_main_docx(batch_dir: &Path, z: &mut ZipSummary, entry: &str) -> Result<()> {
+    if z.status == "failed" {
+        return Err(anyhow!("导入失败的ZIP请先重试导入"));
+    }
+    let stored = stored_package_path(z);
+    let mut package = Package::open(&stored)?;
+    let mut entries = Vec::new();
+    for i in 0..package.len() {
+        entries.push(package.name(i)?);
+    }
+    if !entries.iter().any(|e| e == entry) || !entry.to_lowercase().ends_with(".docx") {
+        return Err(anyhow!("ZIP中没有该docx: {}", entry));
+    }
+    let decision = z.main_docx_decision.get_or_insert_with(MainDocxDecision::default);
+    decision.manual = true;
+    if z.main_docx_entry == entry {
+        return Ok(());
+    }
+
+    let bytes = package.read_by_name(entry)?;
+    let word = extract_fields_from_docx(&bytes).with_context(|| format!("主文档解析失败: {}", entry))?;
+
+    // 原主 docx 改为附加文档
+    let old_main = entries.iter().position(|e| *e == z.main_docx_entry);
+    let mut diagnostics = Vec::new();
+    let added = match old_main {
+        Some(index) => process_additional_docx(batch_dir, &z.id, &stored, &[index], &mut diagnostics)?,
+        None => vec![],
+    };
+
+    // 旧批次的附加文档没有条目名，按文件名匹配
+    let matches = |doc: &AdditionalDocx| {
+        if doc.entry.is_empty() {
+            doc.name == safe_basename(entry)
+        } else {
+            doc.entry == entry
+        }
+    };
+    let extracted = batch_dir.join("zips").join(&z.id).join("extracted");
+    for doc in z.additional_docx_files.iter().filter(|d| matches(d)) {
+        let _ = fs::remove_file(&doc.file_path);
+        let _ = fs::remove_dir_all(extracted.join("additional_docx").join(&doc.id));
+    }
+    z.additional_docx_files.retain(|d| !matches(d));
+    z.additional_docx_files.extend(added);
+    z.diagnostics.retain(|d| d.entry != entry && d.code != DiagnosticCode::MainDocxUnreadable);
+    z.diagnostics.extend(diagnostics);
+
+    // 人工修改针对的是原来的主文档，只保留作记录，字段和原始值随主文档一起更换
+    for edit in &mut z.field_edits {
+        edit.superseded = true;
+    }
+    z.word = word;
+    z.original_word = None;
+    z.main_docx_entry = entry.to_string();
+    Ok(())
+}
+
+/// 把 ZIP 中的另一个 docx 设为主文档，返回更新后的 ZIP
+#[tauri::command]
+fn set_main_document(app: tauri::AppHandle, batch_id: String, zip_id: String, entry: String) -> Result<ZipSummary, String> {
+    let batch_dir = batch_dir(&app, &batch_id).map_err(err_to_string)?;
+    update_zip(&app, &batch_id, &zip_id, |z| swap_main_docx(&batch_dir, z, &entry)).map_err(|e| format!("{e:#}"))?;
+    log::info!("已将 {} 设为主文档: {}", entry, zip_id);
+    refresh_previous_instruction(&app, &batch_id, &zip_id).map_err(|e| format!("{e:#}"))?;
+    let batch = refresh_validation_flags(&app, &batch_id).map_err(|e| format!("{e:#}"))?;
+    update_search_index(&app, &batch);
+    record_seen_instructions(&app, &batch_id, &batch.zips);
+    batch.zips.into_iter().find(|z| z.id == zip_id).ok_or_else(|| "ZIP不存在".to_string())
+}
+
+/// 处理嵌套 ZIP 文件；某个嵌套 ZIP 损坏时记录问题，继续处理其余的
+fn process_nested_zip(
+    batch_dir: &Path,
+    parent_zip_id: &str,
+    parent_zip_path: &Path,
+    nested_zip_indices: &[usize],
+    summary: &mut ZipSummary,
+) -> Result<()> {
+    let mut package = Package::open(parent_zip_path)?;
+
+    for &index in nested_zip_indices {
+        // 读取嵌套 ZIP 内容
+        let (nested_zip_name, result) = match package.read(index) {
+            Ok((name, bytes)) => {
+                let result = extract_nested_zip_files(batch_dir, parent_zip_id, &name, &bytes, summary);
+                (name, result)
+            }
+            Err(e) => (package.name(index).unwrap_or_default(), Err(e)),
+        };
+        if let Err(e) = result {
+            summary
+                .diagnostics
+                .push(ZipDiagnostic::new(DiagnosticCode::NestedZipFailed, &nested_zip_name, format!("{e:#}")));
+        }
+    }
+
+    Ok(())
+}
+
+/// 把嵌套 ZIP 中的文件按类型解压到父 ZIP 的目录，文件名加上来源前缀
+fn extract_nested_zip_files(
+    batch_dir: &Path,
+    parent_zip_id: &str,
+    nested_zip_name: &str,
+    nested_zip_bytes: &[u8],
+    summary: &mut ZipSummary,
+) -> Result<()> {
+    let nested_zip_basename = safe_basename(nested_zip_name);
+
+    // 解析嵌套 ZIP
+    let cursor = Cursor::new(nested_zip_bytes);
+    let mut nested_zip = ZipArchive::new(cursor)?;
+
+    // 提取嵌套 ZIP 中的文件
+    for i in 0..nested_zip.len() {
+        let mut nested_file = nested_zip.by_index(i)?;
+        let nested_file_name = decode_zip_filename(nested_file.name_raw());
+        let lower = nested_file_name.to_ascii_lowercase();
+
+        if lower.ends_with("/") || lower.ends_with(".ds_store") {
+            continue;
+        }
+
+        // 为文件名添加前缀（标识来源）
+        let prefixed_name = format!("[{}]/{}", nested_zip_basename, safe_basename(&nested_file_name));
+
+        // 根据文件类型分类处理
+        if lower.ends_with(".docx") {
+            // 处理为附加 docx
+            let mut docx_bytes = Vec::new();
+            nested_file.read_to_end(&mut docx_bytes)?;
+            // 诊断中的条目为嵌套 ZIP 内的路径
+            let entry = format!("{}/{}", nested_zip_name, nested_file_name);
+
+            // 附加文档常常没有字段，失败不记录
+            let fields = extract_fields_from_docx(&docx_bytes)
+                .unwrap_or_else(|_| WordFields::default());
+            let full_text = extract_full_text_from_docx(&docx_bytes).unwrap_or_else(|e| {
+                summary.diagnostics.push(ZipDiagnostic::new(DiagnosticCode::AdditionalDocxText, &entry, format!("{e:#}")));
+                String::from("无法提取文本内容")
+            });
+
+            let docx_id = Uuid::new_v4().to_string();
+            let images_dir = batch_dir
+                .join("zips")
+                .join(parent_zip_id)
+                .join("extracted")
+                .join("nested_zip_docx")
+                .join(&docx_id);
+
+            let image_files = extract_images_from_docx(&docx_bytes, &images_dir).unwrap_or_else(|e| {
+                summary.diagnostics.push(ZipDiagnostic::new(DiagnosticCode::AdditionalDocxImages, &entry, format!("{e:#}")));
+                vec![]
+            });
+
+            let docx_dir = batch_dir
+                .join("zips")
+                .join(parent_zip_id)
+                .join("extracted")
+                .join("nested_zip_docx_files");
+            fs::create_dir_all(&docx_dir)?;
+            let docx_path = unique_path(&docx_dir, &prefixed_name);
+            fs::write(&docx_path, &docx_bytes)?;
+
+            summary.additional_docx_files.push(AdditionalDocx {
+                id: docx_id,
+                name: prefixed_name,
+                entry: String::new(),
+                file_path: docx_path.to_string_lossy().to_string(),
+                fields,
+                full_text,
+                image_files,
+            });
+        } else if lower.ends_with(".pdf") {
+            // 处理 PDF
+            let pdf_dir = batch_dir
+                .join("zips")
+                .join(parent_zip_id)
+                .join("extracted")
+                .join("nested_zip_pdfs");
+            fs::create_dir_all(&pdf_dir)?;
+            let pdf_path = unique_path(&pdf_dir, &prefixed_name);
+
+            let mut pdf_bytes = Vec::new();
+            nested_file.read_to_end(&mut pdf_bytes)?;
+            fs::write(&pdf_path, pdf_bytes)?;
+            summary.pdf_files.push(pdf_path.to_string_lossy().to_string());
+        } else if lower.ends_with(".mp4") {
+            // 处理视频
+            let video_dir = batch_dir
+                .join("zips")
+                .join(parent_zip_id)
+                .join("extracted")
+                .join("nested_zip_videos");
+            fs::create_dir_all(&video_dir)?;
+            let video_path = unique_path(&video_dir, &prefixed_name);
+
+            let mut video_bytes = Vec::new();
+            nested_file.read_to_end(&mut video_bytes)?;
+            fs::write(&video_path, video_bytes)?;
+            summary.video_files.push(video_path.to_string_lossy().to_string());
+        } else if lower.ends_with(".png") || lower.ends_with(".jpg") ||
+                  lower.ends_with(".jpeg") || lower.ends_with(".gif") ||
+                  lower.ends_with(".webp") {
+            // 处理图片
+            let image_dir = batch_dir
+                .join("zips")
+                .join(parent_zip_id)
+                .join("extracted")
+                .join("nested_zip_images");
+            fs::create_dir_all(&image_dir)?;
+            let image_path = unique_path(&image_dir, &prefixed_name);
+
+            let mut image_bytes = Vec::new();
+            nested_file.read_to_end(&mut image_bytes)?;
+            fs::write(&image_path, image_bytes)?;
+            summary.image_files.push(image_path.to_string_lossy().to_string());
+        } else if lower.ends_with(".xlsx") || lower.ends_with(".xls") {
+            // 处理 Excel
+            let excel_dir = batch_dir
+                .join("zips")
+                .join(parent_zip_id)
+                .join("extracted")
+                .join("nested_zip_excels");
+            fs::create_dir_all(&excel_dir)?;
+            let excel_path = unique_path(&excel_dir, &prefixed_name);
+
+            let mut excel_bytes = Vec::new();
+            nested_file.read_to_end(&mut excel_bytes)?;
+            fs::write(&excel_path, excel_bytes)?;
+            summary.excel_files.push(excel_path.to_string_lossy().to_string());
+        }
+    }
+
+    Ok(())
+}
+
+fn unique_path(dir: &Path, file_name: &str) -> PathBuf {
+    let base = Path::new(file_name)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("file");
+    let ext = Path::new(file_name).extension().and_then(|s| s.to_str());
+
+    let mut n = 0usize;
+    loop {
+        let candidate = if n == 0 {
+            match ext {
+                Some(ext) => dir.join(format!("{base}.{ext}")),
+                None => dir.join(base),
+            }
+        } else {
+            match ext {
+                Some(ext) => dir.join(format!("{base}_{n}.{ext}")),
+                None => dir.join(format!("{base}_{n}")),
+            }
+        };
+        if !candidate.exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+fn extract_fields_from_docx(docx_bytes: &[u8]) -> Result<WordFields> {
+    let cursor = Cursor::new(docx_bytes);
+    let mut zip = ZipArchive::new(cursor)?;
+    let mut document_xml = zip
+        .by_name("word/document.xml")
+        .context("docx缺少word/document.xml")?;
+    let mut xml = String::new();
+    document_xml.read_to_string(&mut xml)?;
+
+    let text = extract_paragraph_texts(&xml)?;
+
+    // 处理字段提取，特别处理指令内容的多行情况
+    let fields = extract_all_fields(&text)?;
+
+    fn first_nonempty(values: Option<&Vec<String>>) -> String {
+        let Some(values) = values else { return String::new() };
+        for v in values {
+            let t = v.trim();
+            if !t.is_empty() {
+                return t.to_string();
+            }
+        }
+        String::new()
+    }
+
+    // 对于指令内容，直接使用提取的完整内容，不再选择"最佳"
+    fn get_instruction_content(values: Option<&Vec<String>>) -> String {
+        let Some(values) = values else { return String::new() };
+        for v in values {
+            let t = v.trim();
+            if !t.is_empty() && t != "序号" {
+                return t.to_string();
+            }
+        }
+        String::new()
+    }
+
+    let mut word = WordFields {
+        instruction_no: first_nonempty(fields.get("指令编号")),
+        title: first_nonempty(fields.get("指令标题")),
+        issued_at: first_nonempty(fields.get("下发时间")),
+        content: get_instruction_content(fields.get("指令内容")),
+        ..Default::default()
+    };
+    word.normalize_issued_at(OffsetDateTime::now_utc().date());
+    Ok(word)
+}
+
+// 检查是否是表格表头或表格内容
+fn is_table_header_or_content(line: &str) -> bool {
+    let trimmed = line.trim();
+
+    // 常见的表格表头模式
+    let table_headers = [
+        "标题", "链接", "网站", "属地", "处置方式", "序号", "时间", "内容", "类型",
+        "编号", "名称", "地址", "来源", "状态", "备注", "操作", "详情",
+        "链接地址", "网站名称", "处理方式", "处理结果", "处理时间"
+    ];
+
+    // 检查是否包含表格表头关键词
+    for header in &table_headers {
+        if trimmed.contains(header) && trimmed.len() <= 20 {
+            return true;
+        }
+    }
+
+    // 检查是否是纯数字或编号（表格中的常见内容）
+    if trimmed.chars().all(|c| c.is_ascii_digit() || c == '.' || c == '、') {
+        return true;
+    }
+
+    // 检查是否是URL链接（表格中常见）
+    if trimmed.starts_with("http://") || trimmed.starts_with("https://") || trimmed.starts_with("www.") {
+        return true;
+    }
+
+    // 检查是否包含大量分隔符（表格特征）
+    let tab_count = trimmed.matches('\t').count();
+    let space_count = trimmed.matches("  ").count();
+    if tab_count >= 2 || space_count >= 3 {
+        return true;
+    }
+
+    false
+}
+
+// 提取所有字段，特别处理指令内容的多行情况
+fn extract_all_fields(text: &str) -> Result<std::collections::BTreeMap<String, Vec<String>>> {
+    let mut map: std::collections::BTreeMap<String, Vec<String>> = std::collections::BTreeMap::new();
+    let lines: Vec<&str> = text.lines().collect();
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim();
+
+        // 检查是否是字段行
+        if let Some(cap) = RE_FIELD.captures(line) {
+            let key = cap.get(1).unwrap().as_str().to_string();
+            let mut value = cap.name("v").unwrap().as_str().trim().to_string();
+
+            // 如果是指令内容，需要收集多行内容
+            if key == "指令内容" {
+                // 收集后续非字段行，直到遇到下一个字段或表格
+                i += 1;
+                let mut content_lines = Vec::new();
+                while i < lines.len() {
+                    let next_line = lines[i].trim();
+
+                    // 如果下一个字段开始，停止收集
+                    if RE_FIELD.is_match(next_line) {
+                        break;
+                    }
+
+                    // 如果遇到空行，继续检查下一行（空行可能是段落分隔）
+                    if next_line.is_empty() {
+                        i += 1;
+                        continue;
+                    }
+
+                    // 如果是表格表头或表格内容，停止收集指令内容
+                    if is_table_header_or_content(next_line) {
+                        break;
+                    }
+
+                    // 过滤掉一些明显的非内容行
+                    if next_line != "序号" && next_line != "指令编号" && next_line != "指令标题" && next_line != "下发时间" {
+                        // 检查行长度，过短的行可能不是内容
+                        if next_line.len() > 3 {
+                            content_lines.push(next_line);
+                        }
+                    }
+                    i += 1;
+                }
+
+                // 保留原始格式的多行内容
+                if !content_lines.is_empty() {
+                    let multi_line_content = content_lines.join("\n");
+                    let cleaned_content = normalize_instruction_content_with_format(&format!("{}\n{}", value, multi_line_content));
+                    value = cleaned_content;
+                }
+                i -= 1; // 回退一行，因为外层循环会再次递增
+            } else {
+                // 对于其他字段，应用简单的清理
+                value = normalize_text(&value);
+            }
+
+            map.entry(key).or_default().push(value);
+        }
+        i += 1;
+    }
+
+    Ok(map)
+}
+
+fn extract_paragraph_texts(document_xml: &str) -> Result<String> {
+    let mut reader = XmlReader::from_str(document_xml);
+    reader.config_mut().trim_text(false);
+    let mut buf = Vec::new();
+    let mut current = String::new();
+    let mut out = String::new();
+    let mut in_paragraph = false;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => {
+                if e.name().as_ref() == b"w:p" {
+                    in_paragraph = true;
+                    current.clear();
+                }
+            }
+            Ok(Event::Empty(e)) => {
+                if in_paragraph && e.name().as_ref() == b"w:br" {
+                    current.push('\n');
+                }
+            }
+            Ok(Event::End(e)) => {
+                if e.name().as_ref() == b"w:p" {
+                    in_paragraph = false;
+                    let line = normalize_text(&current);
+                    if !line.trim().is_empty() {
+                        out.push_str(line.trim_end());
+                        out.push('\n');
+                    }
+                }
+            }
+            Ok(Event::Text(e)) => {
+                if in_paragraph {
+                    current.push_str(&e.unescape()?.to_string());
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(err) => return Err(anyhow!("XML解析错误: {:?}", err)),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(out)
+}
+
+fn normalize_text(s: &str) -> String {
+    s.replace('\u{00A0}', " ")
+        .replace('\u{3000}', " ")
+        .replace('：', ":")
+}
+
+// 专门用于处理指令内容的函数，保留标点符号和格式
+
+// 专门用于处理指令内容的函数，保留原始格式（换行、段落等）
+fn normalize_instruction_content_with_format(s: &str) -> String {
+    let mut result = String::new();
+    let mut lines = Vec::new();
+
+    // 按行分割内容，保留空行（段落分隔）
+    for line in s.lines() {
+        let trimmed = line.trim_end();  // 只删除行尾空白，保留标点
+        lines.push(trimmed);
+    }
+
+    // 重新组合内容，保留原始换行结构
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            // 检查是否需要在换行前添加空格
+            let prev_line = lines[i-1];
+            let current_line = *line;
+
+            // 如果前一行以标点结尾，且当前行不是空行，保留换行
+            if !current_line.is_empty() &&
+               (prev_line.ends_with('。') || prev_line.ends_with('！') || prev_line.ends_with('？') ||
+                prev_line.ends_with('.') || prev_line.ends_with('!') || prev_line.ends_with('?')) {
+                // 保留换行
+                result.push('\n');
+            } else if !current_line.is_empty() && !prev_line.is_empty() {
+                // 如果都不是空行，且前一行不是句号等结束，添加换行
+                result.push('\n');
+            } else {
+                // 如果当前行是空行，添加额外的换行（段落分隔）
+                result.push('\n');
+            }
+        }
+
+        // 替换特殊空白字符，但保留格式
+        let cleaned = line.replace('\u{00A0}', " ")
+                         .replace('\u{3000}', " ")
+                         .replace('：', ":");
+        result.push_str(&cleaned);
+    }
+
+    // 清理开头和结尾的空白字符，但保留中间的换行
+    result.trim_matches('\n').trim_matches('\r').to_string()
+}
+
+// ==================== 图片 EXIF 信息 ====================
+
+/// 图片的 EXIF 元数据，导入时读取并随批次保存
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+struct ImageMetadata {
+    /// 解压后的图片路径，对应 image_files 中的条目
+    file: String,
+    /// EXIF 方向（1-8），1 为正常
+    orientation: u16,
+    /// 拍摄时间，格式 YYYY-MM-DD HH:MM:SS
+    captured_at: String,
+    /// 拍摄设备（厂商 + 型号）
+    device: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+const EXIF_TAG_MAKE: u16 = 0x010F;
+const EXIF_TAG_MODEL: u16 = 0x0110;
+const EXIF_TAG_ORIENTATION: u16 = 0x0112;
+const EXIF_TAG_DATETIME: u16 = 0x0132;
+const EXIF_TAG_EXIF_IFD: u16 = 0x8769;
+const EXIF_TAG_GPS_IFD: u16 = 0x8825;
+const EXIF_TAG_DATETIME_ORIGINAL: u16 = 0x9003;
+const GPS_TAG_LATITUDE_REF: u16 = 1;
+const GPS_TAG_LATITUDE: u16 = 2;
+const GPS_TAG_LONGITUDE_REF: u16 = 3;
+const GPS_TAG_LONGITUDE: u16 = 4;
+
+/// 从 JPEG（APP1）、PNG（eXIf 块）或 WebP（EXIF 块）中取出 TIFF 格式的 EXIF 数据
+fn find_exif_block(bytes: &[u8]) -> Option<&[u8]> {
+    const EXIF_HEADER: &[u8] = b"Exif\0\0";
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        let mut pos = 2;
+        while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+            let marker = bytes[pos + 1];
+            // SOS 之后是图像数据，不再有元数据段
+            if marker == 0xDA {
+                break;
+            }
+            let len = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+            let data = bytes.get(pos + 4..pos + 2 + len)?;
+            if marker == 0xE1 && data.starts_with(EXIF_HEADER) {
+                return Some(&data[EXIF_HEADER.len()..]);
+            }
+            pos += 2 + len;
+        }
+        None
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_chunks(bytes)
+            .find(|(kind, _)| kind == b"eXIf")
+            .map(|(_, data)| data)
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().ok()?) as usize;
+            let data = bytes.get(pos + 8..pos + 8 + len)?;
+            if &bytes[pos..pos + 4] == b"EXIF" {
+                return Some(data.strip_prefix(EXIF_HEADER).unwrap_or(data));
+            }
+            pos += 8 + len + (len & 1);
+        }
+        None
+    } else {
+        None
+    }
+}
+
+/// 遍历 PNG 数据块，返回（类型，数据）
+fn png_chunks(bytes: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 8;
+    std::iter::from_fn(move || {
+        let len = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind = bytes.get(pos + 4..pos + 8)?;
+        let data = bytes.get(pos + 8..pos + 8 + len)?;
+        pos += 12 + len;
+        Some((kind, data))
+    })
+}
+
+/// TIFF 结构的 EXIF 数据读取
+struct TiffReader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+/// IFD 条目：标签、类型、个数、值所在位置（4 字节以内内联，否则为偏移）
+struct TiffEntry {
+    tag: u16,
+    kind: u16,
+    count: usize,
+    value_pos: usize,
+}
+
+impl<'a> TiffReader<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Self { data, little_endian })
+    }
+
+    fn u16_at(&self, pos: usize) -> Option<u16> {
+        let b: [u8; 2] = self.data.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) })
+    }
+
+    fn u32_at(&self, pos: usize) -> Option<u32> {
+        let b: [u8; 4] = self.data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) })
+    }
+
+    fn entries(&self, ifd_offset: usize) -> Vec<TiffEntry> {
+        let count = self.u16_at(ifd_offset).unwrap_or(0) as usize;
+        (0..count)
+            .filter_map(|i| {
+                let pos = ifd_offset + 2 + i * 12;
+                let tag = self.u16_at(pos)?;
+                let kind = self.u16_at(pos + 2)?;
+                let count = self.u32_at(pos + 4)? as usize;
+                let unit = match kind {
+                    3 => 2,
+                    4 | 9 => 4,
+                    5 | 10 => 8,
+                    _ => 1,
+                };
+                let value_pos = if unit * count <= 4 { pos + 8 } else { self.u32_at(pos + 8)? as usize };
+                Some(TiffEntry { tag, kind, count, value_pos })
+            })
+            .collect()
+    }
+
+    fn short(&self, entry: &TiffEntry) -> Option<u16> {
+        match entry.kind {
+            3 => self.u16_at(entry.value_pos),
+            4 => self.u32_at(entry.value_pos).map(|v| v as u16),
+            _ => None,
+        }
+    }
+
+    fn long(&self, entry: &TiffEntry) -> Option<usize> {
+        match entry.kind {
+            4 => self.u32_at(entry.value_pos).map(|v| v as usize),
+            3 => self.u16_at(entry.value_pos).map(|v| v as usize),
+            _ => None,
+        }
+    }
+
+    fn ascii(&self, entry: &TiffEntry) -> Option<String> {
+        let raw = self.data.get(entry.value_pos..entry.value_pos + entry.count)?;
+        let text = String::from_utf8_lossy(raw);
+        let text = text.trim_end_matches('\0').trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+
+    fn rationals(&self, entry: &TiffEntry) -> Vec<f64> {
+        if entry.kind != 5 {
+            return Vec::new();
+        }
+        // 个数来自文件本身，不可信：最多读到数据末尾
+        let available = self.data.len().saturating_sub(entry.value_pos) / 8;
+        (0..entry.count.min(available))
+            .filter_map(|i| {
+                let num = self.u32_at(entry.value_pos + i * 8)?;
+                let den = self.u32_at(entry.value_pos + i * 8 + 4)?;
+                (den != 0).then(|| num as f64 / den as f64)
+            })
+            .collect()
+    }
+}
+
+/// 度分秒转为十进制度数，南纬和西经为负
+fn gps_degrees(values: &[f64], reference: Option<String>) -> Option<f64> {
+    let [deg, min, sec] = values else { return None };
+    let degrees = deg + min / 60.0 + sec / 3600.0;
+    match reference.as_deref() {
+        Some("S") | Some("W") => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
+
+/// 解析图片中的 EXIF 信息；没有 EXIF 时返回 None
+fn read_image_metadata(bytes: &[u8]) -> Option<ImageMetadata> {
+    let tiff = TiffReader::new(find_exif_block(bytes)?)?;
+    let ifd0 = tiff.u32_at(4)? as usize;
+    let mut meta = ImageMetadata {
+        orientation: 1,
+        ..Default::default()
+    };
+    let (mut make, mut model, mut datetime) = (None, None, None);
+    let (mut exif_ifd, mut gps_ifd) = (None, None);
+    for entry in tiff.entries(ifd0) {
+        match entry.tag {
+            EXIF_TAG_ORIENTATION => meta.orientation = tiff.short(&entry).filter(|o| (1..=8).contains(o)).unwrap_or(1),
+            EXIF_TAG_MAKE => make = tiff.ascii(&entry),
+            EXIF_TAG_MODEL => model = tiff.ascii(&entry),
+            EXIF_TAG_DATETIME => datetime = tiff.ascii(&entry),
+            EXIF_TAG_EXIF_IFD => exif_ifd = tiff.long(&entry),
+            EXIF_TAG_GPS_IFD => gps_ifd = tiff.long(&entry),
+            _ => {}
+        }
+    }
+    // 拍摄时间优先取 DateTimeOriginal，其次为文件修改时间 DateTime
+    if let Some(offset) = exif_ifd {
+        if let Some(entry) = tiff.entries(offset).iter().find(|e| e.tag == EXIF_TAG_DATETIME_ORIGINAL) {
+            datetime = tiff.ascii(entry).or(datetime);
+        }
+    }
+    if let Some(offset) = gps_ifd {
+        let entries = tiff.entries(offset);
+        let find = |tag: u16| entries.iter().find(|e| e.tag == tag);
+        let lat_ref = find(GPS_TAG_LATITUDE_REF).and_then(|e| tiff.ascii(e));
+        let lon_ref = find(GPS_TAG_LONGITUDE_REF).and_then(|e| tiff.ascii(e));
+        meta.latitude = find(GPS_TAG_LATITUDE).and_then(|e| gps_degrees(&tiff.rationals(e), lat_ref));
+        meta.longitude = find(GPS_TAG_LONGITUDE).and_then(|e| gps_degrees(&tiff.rationals(e), lon_ref));
+    }
+
+    // EXIF 时间格式为 "YYYY:MM:DD HH:MM:SS"
+    meta.captured_at = datetime
+        .map(|d| match d.split_once(' ') {
+            Some((date, time)) => format!("{} {}", date.replace(':', "-"), time),
+            None => d.replace(':', "-"),
+        })
+        .unwrap_or_default();
+    meta.device = match (make, model) {
+        // 型号里已带厂商名（如 "HUAWEI P60"）时不重复
+        (Some(make), Some(model)) if model.to_lowercase().starts_with(&make.to_lowercase()) => model,
+        (Some(make), Some(model)) => format!("{} {}", make, model),
+        (make, model) => make.or(model).unwrap_or_default(),
+    };
+    Some(meta)
+}
+
+/// 读取解压后图片的 EXIF 信息，只保留有内容的条目
+fn collect_image_metadata(image_files: &[String]) -> Vec<ImageMetadata> {
+    image_files
+        .iter()
+        .filter_map(|path| {
+            let bytes = fs::read(path).ok()?;
+            let meta = read_image_metadata(&bytes)?;
+            let useful = meta.orientation != 1
+                || !meta.captured_at.is_empty()
+                || !meta.device.is_empty()
+                || meta.latitude.is_some();
+            useful.then(|| ImageMetadata {
+                file: path.clone(),
+                ..meta
+            })
+        })
+        .collect()
+}
+
+/// 按 EXIF 方向旋转/翻转图片
+fn apply_exif_orientation(img: image::DynamicImage, orientation: u16) -> image::DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// 去掉图片中的元数据：JPEG 的 APP1（EXIF/XMP）、APP13 和注释段，PNG 的 eXIf、文本和时间块。
+/// 其他格式原样返回。
+fn strip_image_metadata(bytes: &[u8]) -> Vec<u8> {
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        let mut out = bytes[..2].to_vec();
+        let mut pos = 2;
+        while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+            let marker = bytes[pos + 1];
+            if marker == 0xDA {
+                break;
+            }
+            let len = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+            let end = (pos + 2 + len).min(bytes.len());
+            if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+                out.extend_from_slice(&bytes[pos..end]);
+            }
+            pos = end;
+        }
+        out.extend_from_slice(&bytes[pos..]);
+        out
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let mut out = bytes[..8].to_vec();
+        let mut pos = 8;
+        for (kind, data) in png_chunks(bytes) {
+            let end = pos + 12 + data.len();
+            if !matches!(kind, b"eXIf" | b"tEXt" | b"iTXt" | b"zTXt" | b"tIME") {
+                out.extend_from_slice(&bytes[pos..end]);
+            }
+            pos = end;
+        }
+        out
+    } else {
+        bytes.to_vec()
+    }
+}
+
+/// 重新打包 ZIP，去掉其中图片的元数据；嵌套的 ZIP 和 docx 一并处理，其余条目原样保留
+fn strip_zip_image_metadata(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let mut out = Cursor::new(Vec::new());
+    {
+        let mut writer = ZipWriter::new(&mut out);
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let name = decode_zip_filename(file.name_raw());
+            if file.is_dir() {
+                writer.add_directory(name, FileOptions::default())?;
+                continue;
+            }
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            let lower = name.to_lowercase();
+            let data = if lower.ends_with(".zip") || lower.ends_with(".docx") {
+                strip_zip_image_metadata(&data).unwrap_or(data)
+            } else {
+                strip_image_metadata(&data)
+            };
+            writer.start_file(name, FileOptions::default())?;
+            writer.write_all(&data)?;
+        }
+        writer.finish()?;
+    }
+    Ok(out.into_inner())
+}
+
+/// 导出用的原始 ZIP；去除元数据时重新打包，处理失败返回 None，避免把带元数据的原件导出
+fn original_zip_bytes(z: &ZipSummary, strip_metadata: bool) -> Result<Option<Vec<u8>>> {
+    let zip_path = original_zip_path(z)?;
+    let bytes = fs::read(&zip_path).with_context(|| format!("读取ZIP失败: {}", zip_path.display()))?;
+    if !strip_metadata {
+        return Ok(Some(bytes));
+    }
+    match strip_zip_image_metadata(&bytes) {
+        Ok(stripped) => Ok(Some(stripped)),
+        Err(e) => {
+            log::warn!("原始ZIP {} 无法去除图片元数据，不导出: {:#}", z.filename, e);
+            Ok(None)
+        }
+    }
+}
+
+/// ZIP 中（导出时选中的）图片的元数据说明
+fn zip_image_metadata_lines(z: &ZipSummary, include_gps: bool) -> Vec<String> {
+    z.image_metadata
+        .iter()
+        .filter(|meta| z.image_files.contains(&meta.file))
+        .filter_map(|meta| image_metadata_line(meta, include_gps))
+        .collect()
+}
+
+/// 附件清单中一张图片的元数据说明；include_gps 为 false 时不输出位置
+fn image_metadata_line(meta: &ImageMetadata, include_gps: bool) -> Option<String> {
+    let mut parts = Vec::new();
+    if !meta.captured_at.is_empty() {
+        parts.push(format!("拍摄时间 {}", meta.captured_at));
+    }
+    if !meta.device.is_empty() {
+        parts.push(format!("设备 {}", meta.device));
+    }
+    if let (true, Some(lat), Some(lon)) = (include_gps, meta.latitude, meta.longitude) {
+        parts.push(format!("位置 {:.6}, {:.6}", lat, lon));
+    }
+    (!parts.is_empty()).then(|| format!("- {}：{}", safe_basename(&meta.file), parts.join("，")))
+}
+
+// ==================== 图片 EXIF 信息结束 ====================
+
+// ==================== 图片文字识别（OCR） ====================
+//
+// 识别引擎为本机安装的 tesseract（离线运行，需要 chi_sim 语言包），只在启用 ocr 特性时编译进来。
+// 识别结果按图片保存在批次中，供搜索、规则匹配和汇总文档的“识别文本”使用。
+
+/// 一张图片的识别文本
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+struct OcrText {
+    /// 图片路径，对应 image_files 或 pdf_page_screenshot_files 中的条目
+    file: String,
+    text: String,
+}
+
+const OCR_DISABLED_MESSAGE: &str = "当前版本未启用文字识别，需要以 ocr 特性编译";
+
+static RE_OCR_CJK_SPACE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"([\p{Han}，。；：？！、（）《》“”])[ \t]+([\p{Han}，。；：？！、（）《》“”])").expect("valid regex")
+});
+
+/// 整理识别结果：tesseract 会在汉字之间插入空格，去掉这些空格并合并多余空行
+fn clean_ocr_text(raw: &str) -> String {
+    let mut lines = Vec::new();
+    for line in raw.lines() {
+        // 相邻匹配会共享字符，替换两遍才能处理“中 文 字”
+        let line = RE_OCR_CJK_SPACE.replace_all(line.trim(), "$1$2");
+        let line = RE_OCR_CJK_SPACE.replace_all(&line, "$1$2");
+        if line.is_empty() && lines.last().map_or(true, |l: &String| l.is_empty()) {
+            continue;
+        }
+        lines.push(line.into_owned());
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+/// 调用 tesseract 识别一张图片，可用环境变量 ARCHIVEBOX_TESSERACT 指定程序路径
+#[cfg(feature = "ocr")]
+fn run_ocr_engine(path: &str) -> Result<String> {
+    let program = std::env::var("ARCHIVEBOX_TESSERACT").unwrap_or_else(|_| "tesseract".to_string());
+    let output = Command::new(&program)
+        .arg(path)
+        .arg("stdout")
+        .args(["-l", "chi_sim+eng", "--psm", "3"])
+        .output()
+        .with_context(|| format!("无法启动 {}，请确认已安装 tesseract", program))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "tesseract 识别失败: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(not(feature = "ocr"))]
+fn run_ocr_engine(_path: &str) -> Result<String> {
+    Err(anyhow!(OCR_DISABLED_MESSAGE))
+}
+
+fn recognize_image_text(path: &str) -> Result<String> {
+    Ok(clean_ocr_text(&run_ocr_engine(path)?))
+}
+
+#[tauri::command]
+fn ocr_available() -> bool {
+    cfg!(feature = "ocr")
+}
+
+/// 在后台识别批次中所有图片和 PDF 截图的文字，立即返回任务 ID；任务结果为 { batch_id, recognized }
+#[tauri::command]
+fn run_ocr(app: tauri::AppHandle, batch_id: String) -> Result<String, String> {
+    if !ocr_available() {
+        return Err(OCR_DISABLED_MESSAGE.to_string());
+    }
+    read_batch(&app, &batch_id).map_err(err_to_string)?;
+    Ok(spawn_ocr_job(&app, batch_id))
+}
+
+fn spawn_ocr_job(app: &tauri::AppHandle, batch_id: String) -> String {
+    let title = format!("识别图片文字（{}）", batch_id);
+    let handle = app.clone();
+    spawn_job(app, JobKind::Ocr, title, OCR_JOB_STEPS, move |ctx| {
+        let recognized = ocr_batch_job(&handle, ctx, &batch_id)?;
+        Ok(serde_json::json!({ "batch_id": batch_id, "recognized": recognized }))
+    })
+}
+
+/// 识别批次中所有图片和 PDF 截图的文字，已识别过的图片跳过；返回本次识别的图片数。
+/// 每个 ZIP 识别完就保存，取消后已识别的结果保留
+fn ocr_batch_job(app: &tauri::AppHandle, ctx: &JobContext, batch_id: &str) -> Result<usize> {
+    use std::sync::atomic::Ordering;
+
+    let batch = read_batch(app, batch_id)?;
+    let total: usize = batch
+        .zips
+        .iter()
+        .map(|z| z.image_files.len() + z.pdf_page_screenshot_files.len())
+        .sum();
+    let done = std::sync::atomic::AtomicUsize::new(0);
+    let mut recognized = 0;
+
+    for z in &batch.zips {
+        if ctx.checkpoint().is_err() {
+            break;
+        }
+        let pending: Vec<String> = z
+            .image_files
+            .iter()
+            .chain(&z.pdf_page_screenshot_files)
+            .filter(|f| !z.ocr_texts.iter().any(|o| &o.file == *f))
+            .cloned()
+            .collect();
+        let skipped = z.image_files.len() + z.pdf_page_screenshot_files.len() - pending.len();
+        done.fetch_add(skipped, Ordering::Relaxed);
+
+        let results: Vec<OcrText> = pending
+            .par_iter()
+            .filter_map(|file| {
+                let _log_scope = ctx.log_scope();
+                if ctx.is_cancelled() {
+                    return None;
+                }
+                let result = recognize_image_text(file);
+                let current = done.fetch_add(1, Ordering::Relaxed) + 1;
+                ctx.report_items(0, current, total, &format!("正在识别: {}", safe_basename(file)));
+                match result {
+                    // 没有文字的图片也记录下来，避免重复识别
+                    Ok(text) => Some(OcrText { file: file.clone(), text }),
+                    Err(e) => {
+                        log::warn!("文字识别失败 {}: {:#}", safe_basename(file), e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        if results.is_empty() {
+            continue;
+        }
+        recognized += results.len();
+        // 识别耗时较长，期间可能有新保存的截图，只合并识别结果而不覆盖整个批次
+        update_zip(app, batch_id, &z.id, |zip| {
+            zip.ocr_texts.extend(results);
+            Ok(())
+        })?;
+    }
+
+    // 识别文本参与关键词校验和全文检索
+    ctx.report(1, 0.0, "正在更新检索索引");
+    let batch = refresh_validation_flags(app, batch_id)?;
+    update_search_index(app, &batch);
+    Ok(recognized)
+}
+
+/// 启用文字识别时，导入完成后自动在后台识别新图片
+fn start_ocr_after_import(app: &tauri::AppHandle, batch_id: &str) {
+    if ocr_available() {
+        spawn_ocr_job(app, batch_id.to_string());
+    }
+}
+
+// ==================== 图片文字识别（OCR）结束 ====================
+
+// ==================== 全文检索 ====================
+
+/// 单个 PDF 最多提取文字的页数
+const SEARCH_PDF_MAX_PAGES: usize = 50;
+/// 摘要片段在命中位置前后保留的字符数
+const SEARCH_SNIPPET_CONTEXT: usize = 30;
+/// 单次检索最多返回的 ZIP 数
+const SEARCH_MAX_HITS: usize = 100;
+
+/// 被检索的字段
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+enum SearchField {
+    Filename,
+    InstructionNo,
+    Title,
+    Content,
+    AdditionalDocx,
+    Pdf,
+    Ocr,
+}
+
+impl SearchField {
+    const ALL: [SearchField; 7] = [
+        SearchField::Filename,
+        SearchField::InstructionNo,
+        SearchField::Title,
+        SearchField::Content,
+        SearchField::AdditionalDocx,
+        SearchField::Pdf,
+        SearchField::Ocr,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SearchField::Filename => "filename",
+            SearchField::InstructionNo => "instruction_no",
+            SearchField::Title => "title",
+            SearchField::Content => "content",
+            SearchField::AdditionalDocx => "additional_docx",
+            SearchField::Pdf => "pdf",
+            SearchField::Ocr => "ocr",
+        }
+    }
+
+    fn parse(field: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.as_str() == field)
+    }
+
+    /// 排序权重：标题、编号命中比正文命中更相关
+    fn weight(self) -> f64 {
+        match self {
+            SearchField::Title | SearchField::InstructionNo => 3.0,
+            SearchField::Filename => 2.0,
+            SearchField::Content => 1.5,
+            SearchField::AdditionalDocx | SearchField::Pdf | SearchField::Ocr => 1.0,
+        }
+    }
+}
+
+/// 索引中的一段文本，对应某个 ZIP 的一个字段（附加 docx、PDF 按文件拆开）
+#[derive(Debug, Clone, PartialEq)]
+struct SearchDoc {
+    batch_id: String,
+    batch_created_at: i64,
+    zip_id: String,
+    filename: String,
+    title: String,
+    issued_at_iso: String,
+    field: SearchField,
+    /// 字段所在的文件名，例如附加 docx 或 PDF 的文件名
+    source: String,
+    /// 字段所在文件的路径，Word 字段和文件名为空
+    path: String,
+    text: String,
+}
+
+const SEARCH_DOC_COLUMNS: &str = "search_docs.doc_id, batch_id, batch_created_at, zip_id, filename, title, \
+    issued_at_iso, field, source, path, text";
+
+fn search_doc_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<(i64, SearchDoc)> {
+    let field: String = row.get(7)?;
+    let doc = SearchDoc {
+        batch_id: row.get(1)?,
+        batch_created_at: row.get(2)?,
+        zip_id: row.get(3)?,
+        filename: row.get(4)?,
+        title: row.get(5)?,
+        issued_at_iso: row.get(6)?,
+        field: SearchField::parse(&field).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(7, rusqlite::types::Type::Text, format!("未知字段: {field}").into())
+        })?,
+        source: row.get(8)?,
+        path: row.get(9)?,
+        text: row.get(10)?,
+    };
+    Ok((row.get(0)?, doc))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct SearchFilters {
+    /// 只检索这些批次，为空时检索全部
+    #[serde(default)]
+    batch_ids: Vec<String>,
+    /// 下发日期范围（YYYY-MM-DD，含端点）
+    #[serde(default)]
+    issued_from: Option<String>,
+    #[serde(default)]
+    issued_to: Option<String>,
+    /// 只检索这些字段，为空时检索全部
+    #[serde(default)]
+    fields: Vec<SearchField>,
+}
+
+/// 命中片段，highlights 为片段内命中位置的字符区间 [start, end)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SearchSnippet {
+    field: SearchField,
+    source: String,
+    text: String,
+    highlights: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SearchHit {
+    batch_id: String,
+    batch_created_at: i64,
+    /// 批次仍在磁盘上；已清理的批次只能查看命中片段
+    batch_available: bool,
+    zip_id: String,
+    filename: String,
+    title: String,
+    issued_at_iso: String,
+    score: f64,
+    snippets: Vec<SearchSnippet>,
+}
+
+fn is_cjk_char(c: char) -> bool {
+    matches!(c as u32, 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x20000..=0x2FA1F)
+}
+
+/// 切分词项：字母数字按词切分，中文按相邻两字（二元组）切分，单个汉字保留为一元组
+fn search_tokens(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut cjk_run: Vec<char> = Vec::new();
+
+    fn flush_cjk(run: &mut Vec<char>, tokens: &mut Vec<String>) {
+        if run.len() == 1 {
+            tokens.push(run[0].to_string());
+        }
+        for pair in run.windows(2) {
+            tokens.push(pair.iter().collect());
+        }
+        run.clear();
+    }
+
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if is_cjk_char(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            cjk_run.push(c);
+        } else if c.is_alphanumeric() {
+            flush_cjk(&mut cjk_run, &mut tokens);
+            word.push(c);
+        } else {
+            flush_cjk(&mut cjk_run, &mut tokens);
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+        }
+    }
+    flush_cjk(&mut cjk_run, &mut tokens);
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+/// 查询词项的 token：中文只取二元组，单字查询才用一元组
+fn query_tokens(term: &str) -> Vec<String> {
+    let mut tokens = search_tokens(term);
+    if tokens.iter().any(|t| t.chars().count() > 1 || !t.chars().all(is_cjk_char)) {
+        tokens.retain(|t| !(t.chars().count() == 1 && t.chars().all(is_cjk_char)));
+    }
+    tokens.sort();
+    tokens.dedup();
+    tokens
+}
+
+/// 在文本中查找所有（不区分大小写）出现位置，返回字符区间
+fn find_char_ranges(text: &str, term: &str) -> Vec<(usize, usize)> {
+    let text_chars: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+    let term_chars: Vec<char> = term.chars().flat_map(char::to_lowercase).collect();
+    // 小写后长度可能变化（极少见），此时退化为不高亮
+    if term_chars.is_empty() || text_chars.len() != text.chars().count() {
+        return vec![];
+    }
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while i + term_chars.len() <= text_chars.len() {
+        if text_chars[i..i + term_chars.len()] == term_chars[..] {
+            ranges.push((i, i + term_chars.len()));
+            i += term_chars.len();
+        } else {
+            i += 1;
+        }
+    }
+    ranges
+}
+
+/// 截取第一个命中位置附近的片段，并把片段内所有命中位置换算为片段内的偏移
+fn make_snippet(text: &str, terms: &[String]) -> Option<(String, Vec<(usize, usize)>)> {
+    let mut ranges: Vec<(usize, usize)> = terms.iter().flat_map(|t| find_char_ranges(text, t)).collect();
+    if ranges.is_empty() {
+        return None;
+    }
+    ranges.sort();
+
+    let chars: Vec<char> = text.chars().collect();
+    let (first_start, first_end) = ranges[0];
+    let start = first_start.saturating_sub(SEARCH_SNIPPET_CONTEXT);
+    let end = (first_end + SEARCH_SNIPPET_CONTEXT).min(chars.len());
+
+    let mut snippet = String::new();
+    let prefix = if start > 0 { 1 } else { 0 };
+    if start > 0 {
+        snippet.push('…');
+    }
+    snippet.extend(chars[start..end].iter().map(|c| if c.is_whitespace() { ' ' } else { *c }));
+    if end < chars.len() {
+        snippet.push('…');
+    }
+
+    let highlights = ranges
+        .into_iter()
+        .filter(|&(s, e)| s >= start && e <= end)
+        .map(|(s, e)| (s - start + prefix, e - start + prefix))
+        .collect();
+    Some((snippet, highlights))
+}
+
+/// 提取 PDF 的文本层（扫描件没有文本层时为空）
+fn pdf_plain_text(path: &str) -> Result<String> {
+    let doc = lopdf::Document::load(path).with_context(|| format!("无法读取PDF: {}", path))?;
+    let pages: Vec<u32> = doc.get_pages().keys().copied().take(SEARCH_PDF_MAX_PAGES).collect();
+    let mut text = String::new();
+    for page in pages {
+        // 单页解析失败不影响其他页
+        if let Ok(page_text) = doc.extract_text(&[page]) {
+            text.push_str(&page_text);
+            text.push('\n');
+        }
+    }
+    Ok(text)
+}
+
+/// 把 ZIP 拆成检索文本段；pdf_texts 中已有的 PDF（按路径）不再重新提取文字
+fn search_docs_for_zip(
+    batch: &BatchSummary,
+    z: &ZipSummary,
+    pdf_texts: &std::collections::HashMap<String, String>,
+) -> Vec<SearchDoc> {
+    let mut docs = Vec::new();
+    let mut push = |field: SearchField, source: &str, path: &str, text: &str| {
+        if text.trim().is_empty() {
+            return;
+        }
+        docs.push(SearchDoc {
+            batch_id: batch.batch_id.clone(),
+            batch_created_at: batch.created_at,
+            zip_id: z.id.clone(),
+            filename: z.filename.clone(),
+            title: z.word.title.clone(),
+            issued_at_iso: z.word.issued_at_iso.clone(),
+            field,
+            source: source.to_string(),
+            path: path.to_string(),
+            text: text.to_string(),
+        });
+    };
+
+    push(SearchField::Filename, "", "", &z.filename);
+    push(SearchField::InstructionNo, "", "", &z.word.instruction_no);
+    push(SearchField::Title, "", "", &z.word.title);
+    push(SearchField::Content, "", "", &z.word.content);
+    for additional in &z.additional_docx_files {
+        push(SearchField::AdditionalDocx, &additional.name, &additional.file_path, &additional.full_text);
+    }
+    for pdf_path in &z.pdf_files {
+        let text = match pdf_texts.get(pdf_path) {
+            Some(text) => text.clone(),
+            None => pdf_plain_text(pdf_path).unwrap_or_else(|e| {
+                log::warn!("PDF文字提取失败 {}: {:#}", safe_basename(pdf_path), e);
+                String::new()
+            }),
+        };
+        push(SearchField::Pdf, &safe_basename(pdf_path), pdf_path, &text);
+    }
+    for ocr in &z.ocr_texts {
+        push(SearchField::Ocr, &safe_basename(&ocr.file), &ocr.file, &ocr.text);
+    }
+    docs
+}
+
+/// 按 ZIP 更新批次的检索索引：内容没变的 ZIP 不改动，已提取过文字的 PDF 不重新读取
+fn index_batch(catalog: &Catalog, batch: &BatchSummary) -> Result<()> {
+    for z in &batch.zips {
+        let existing = catalog.zip_search_docs(&batch.batch_id, &z.id)?;
+        let pdf_texts = existing
+            .iter()
+            .filter(|d| d.field == SearchField::Pdf)
+            .map(|d| (d.path.clone(), d.text.clone()))
+            .collect();
+        let docs = search_docs_for_zip(batch, z, &pdf_texts);
+        if docs != existing {
+            catalog.replace_search_docs(&batch.batch_id, &z.id, &docs)?;
+        }
+    }
+    // 批次中已移除的 ZIP
+    for zip_id in catalog.search_zip_ids(&batch.batch_id)? {
+        if !batch.zips.iter().any(|z| z.id == zip_id) {
+            catalog.replace_search_docs(&batch.batch_id, &zip_id, &[])?;
+        }
+    }
+    Ok(())
+}
+
+/// 检索：查询按空白切成多个词，ZIP 需命中全部词（可分布在不同字段）
+fn search_catalog(catalog: &Catalog, query: &str, filters: &SearchFilters) -> Result<Vec<SearchHit>> {
+    let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    if terms.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let doc_allowed = |doc: &SearchDoc| {
+        let issued_date = doc.issued_at_iso.get(..10).unwrap_or("");
+        (filters.batch_ids.is_empty() || filters.batch_ids.contains(&doc.batch_id))
+            && (filters.fields.is_empty() || filters.fields.contains(&doc.field))
+            && filters.issued_from.as_deref().map_or(true, |from| !issued_date.is_empty() && issued_date >= from)
+            && filters.issued_to.as_deref().map_or(true, |to| !issued_date.is_empty() && issued_date <= to)
+    };
+
+    // (batch_id, zip_id) -> 每个查询词命中的文本段
+    let mut docs: std::collections::HashMap<i64, SearchDoc> = std::collections::HashMap::new();
+    let mut matched: std::collections::HashMap<(String, String), Vec<Vec<i64>>> = std::collections::HashMap::new();
+    for (term_idx, term) in terms.iter().enumerate() {
+        let tokens = query_tokens(term);
+        if tokens.is_empty() {
+            return Ok(vec![]);
+        }
+        for (doc_id, doc) in catalog.search_candidates(&tokens)? {
+            // 词项只是候选，确认原文中确实连续出现
+            if !doc_allowed(&doc) || find_char_ranges(&doc.text, term).is_empty() {
+                continue;
+            }
+            matched
+                .entry((doc.batch_id.clone(), doc.zip_id.clone()))
+                .or_insert_with(|| vec![vec![]; terms.len()])[term_idx]
+                .push(doc_id);
+            docs.insert(doc_id, doc);
+        }
+    }
+
+    let available: std::collections::HashSet<String> = catalog.batch_ids()?.into_iter().collect();
+    let mut hits: Vec<SearchHit> = matched
+        .into_values()
+        .filter(|per_term| per_term.iter().all(|ids| !ids.is_empty()))
+        .map(|per_term| {
+            let mut doc_ids: Vec<i64> = per_term.into_iter().flatten().collect();
+            doc_ids.sort();
+            doc_ids.dedup();
+            let first = &docs[&doc_ids[0]];
+
+            let mut score = 0.0;
+            let mut snippets = Vec::new();
+            for doc_id in &doc_ids {
+                let doc = &docs[doc_id];
+                let occurrences: usize = terms.iter().map(|t| find_char_ranges(&doc.text, t).len()).sum();
+                // 出现次数取对数，避免长正文压过标题
+                score += doc.field.weight() * (1.0 + (occurrences as f64).ln());
+                if let Some((text, highlights)) = make_snippet(&doc.text, &terms) {
+                    snippets.push(SearchSnippet {
+                        field: doc.field,
+                        source: doc.source.clone(),
+                        text,
+                        highlights,
+                    });
+                }
+            }
+
+            SearchHit {
+                batch_id: first.batch_id.clone(),
+                batch_created_at: first.batch_created_at,
+                batch_available: available.contains(&first.batch_id),
+                zip_id: first.zip_id.clone(),
+                filename: first.filename.clone(),
+                title: first.title.clone(),
+                issued_at_iso: first.issued_at_iso.clone(),
+                score,
+                snippets,
+            }
+        })
+        .collect();
+
+    hits.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| b.issued_at_iso.cmp(&a.issued_at_iso))
+            .then_with(|| b.batch_created_at.cmp(&a.batch_created_at))
+    });
+    hits.truncate(SEARCH_MAX_HITS);
+    Ok(hits)
+}
+
+/// 为还没有索引的批次建立索引（包括只有 batch.json 的旧批次），并删除旧版本的索引文件。只在启动时执行一次
+fn index_missing_batches(app: &tauri::AppHandle, catalog: &Catalog) -> Result<()> {
+    let legacy_index = app_data_dir(app)?.join("search_index.json");
+    if legacy_index.exists() {
+        let _ = fs::remove_file(&legacy_index);
+    }
+
+    let mut batch_ids: std::collections::BTreeSet<String> = catalog.unindexed_batch_ids()?.into_iter().collect();
+    let cataloged: std::collections::HashSet<String> = catalog.batch_ids()?.into_iter().collect();
+    let batches_dir = app_data_dir(app)?.join("batches");
+    if batches_dir.exists() {
+        for entry in fs::read_dir(&batches_dir)? {
+            let entry = entry?;
+            if let Some(name) = entry.file_name().to_str() {
+                if !cataloged.contains(name) && entry.path().join("batch.json").exists() {
+                    batch_ids.insert(name.to_string());
+                }
+            }
+        }
+    }
+    // 旧版本的批次只有 batch.json，read_batch 会顺带迁移
+    for batch_id in batch_ids {
+        match read_batch(app, &batch_id) {
+            Ok(batch) => index_batch(catalog, &batch)?,
+            Err(e) => log::warn!("跳过无法读取的批次 {}: {:#}", batch_id, e),
+        }
+    }
+    Ok(())
+}
+
+/// 批次内容变化后更新索引；失败只记录日志，不影响导入等主流程
+fn update_search_index(app: &tauri::AppHandle, batch: &BatchSummary) {
+    if let Err(e) = Catalog::open(app).and_then(|catalog| index_batch(&catalog, batch)) {
+        log::warn!("更新检索索引失败: {:#}", e);
+    }
+}
+
+/// 在所有导入过的批次（包括已清理的批次）中检索 Word 字段、附加 docx、PDF 文字、OCR 文本和文件名
+#[tauri::command]
+fn search(app: tauri::AppHandle, query: String, filters: Option<SearchFilters>) -> Result<Vec<SearchHit>, String> {
+    let catalog = Catalog::open(&app).map_err(err_to_string)?;
+    search_catalog(&catalog, &query, &filters.unwrap_or_default()).map_err(err_to_string)
+}
+
+/// 读取已导入的批次，用于从检索结果跳转到其他批次
+#[tauri::command]
+fn load_batch(app: tauri::AppHandle, state: State<'_, AppState>, batch_id: String) -> Result<BatchSummary, String> {
+    let batch = read_batch(&app, &batch_id).map_err(err_to_string)?;
+    *state.last_batch_id.lock().unwrap() = Some(batch_id);
+    Ok(batch)
+}
+
+// ==================== 全文检索结束 ====================
+
+// ==================== 批次数据版本与迁移 ====================
+
+/// 批次数据的迁移链，下标 i 的函数把数据从版本 i 升到 i+1。
+/// 只追加、不修改已有的迁移；没有 schema_version 的旧数据视为版本 0。
+const BATCH_MIGRATIONS: &[fn(&mut serde_json::Value) -> Result<()>] = &[migrate_batch_v1, migrate_batch_v2, migrate_batch_v3];
+
+/// 当前批次数据版本
+const BATCH_SCHEMA_VERSION: u32 = BATCH_MIGRATIONS.len() as u32;
+
+fn batch_zips_mut(batch: &mut serde_json::Value) -> Result<&mut Vec<serde_json::Value>> {
+    batch
+        .get_mut("zips")
+        .and_then(serde_json::Value::as_array_mut)
+        .ok_or_else(|| anyhow!("批次缺少 zips 字段"))
+}
+
+/// v0 → v1：早期版本没有的字段补默认值，避免整个批次读取失败
+fn migrate_batch_v1(batch: &mut serde_json::Value) -> Result<()> {
+    use serde_json::Value;
+
+    fn fill(obj: &mut serde_json::Map<String, Value>, keys: &[&str], default: Value) {
+        for key in keys {
+            obj.entry(*key).or_insert_with(|| default.clone());
+        }
+    }
+    fn fill_word(word: &mut Value) {
+        if let Some(word) = word.as_object_mut() {
+            fill(word, &["instruction_no", "title", "issued_at", "content"], Value::from(""));
+        }
+    }
+
+    if let Some(obj) = batch.as_object_mut() {
+        obj.entry("created_at").or_insert(Value::from(0));
+    }
+    for z in batch_zips_mut(batch)? {
+        let obj = z.as_object_mut().ok_or_else(|| anyhow!("ZIP记录格式错误"))?;
+        fill(obj, &["source_path", "stored_path", "extracted_dir"], Value::from(""));
+        fill(
+            obj,
+            &[
+                "video_entries",
+                "video_files",
+                "image_files",
+                "pdf_files",
+                "pdf_page_screenshot_files",
+                "excel_files",
+                "additional_docx_files",
+            ],
+            Value::Array(vec![]),
+        );
+        fill(obj, &["has_video", "has_sample"], Value::Bool(false));
+        fill(obj, &["status"], Value::from("completed"));
+        fill(obj, &["word"], Value::Object(Default::default()));
+        fill_word(&mut obj["word"]);
+
+        for additional in obj["additional_docx_files"].as_array_mut().into_iter().flatten() {
+            if let Some(doc) = additional.as_object_mut() {
+                fill(doc, &["id", "name", "file_path", "full_text"], Value::from(""));
+                fill(doc, &["image_files"], Value::Array(vec![]));
+                fill(doc, &["fields"], Value::Object(Default::default()));
+                fill_word(&mut doc["fields"]);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// v1 → v2：补充规范化的下发时间（早期版本只保存了原文，排序和分组会失效）
+fn migrate_batch_v2(batch: &mut serde_json::Value) -> Result<()> {
+    let created_at = batch.get("created_at").and_then(serde_json::Value::as_i64).unwrap_or(0);
+    // 缺少年份的日期按导入日期推断，没有导入时间时按今天
+    let reference = match created_at {
+        t if t > 0 => OffsetDateTime::from_unix_timestamp(t).map(|t| t.date()).ok(),
+        _ => None,
+    }
+    .unwrap_or_else(|| OffsetDateTime::now_utc().date());
+
+    for z in batch_zips_mut(batch)? {
+        let Some(word) = z.get_mut("word") else { continue };
+        let mut fields: WordFields = serde_json::from_value(word.take()).context("解析Word字段失败")?;
+        if fields.issued_at_iso.is_empty() {
+            fields.normalize_issued_at(reference);
+        }
+        *word = serde_json::to_value(fields)?;
+    }
+    Ok(())
+}
+
+/// v2 → v3：失败原因从 status 文本（"failed: 原因"）移到结构化的 diagnostics
+fn migrate_batch_v3(batch: &mut serde_json::Value) -> Result<()> {
+    for z in batch_zips_mut(batch)? {
+        let obj = z.as_object_mut().ok_or_else(|| anyhow!("ZIP记录格式错误"))?;
+        let mut diagnostics: Vec<ZipDiagnostic> = match obj.remove("diagnostics") {
+            Some(value) => serde_json::from_value(value).context("解析导入问题记录失败")?,
+            None => Vec::new(),
+        };
+        let status = obj.get("status").and_then(serde_json::Value::as_str).unwrap_or_default();
+        if let Some(reason) = status.strip_prefix("failed") {
+            let reason = reason.trim_start_matches(':').trim();
+            if !reason.is_empty() {
+                diagnostics.push(ZipDiagnostic::new(DiagnosticCode::ImportFailed, "", reason.to_string()));
+            }
+            obj.insert("status".to_string(), serde_json::Value::from("failed"));
+        }
+        obj.insert("diagnostics".to_string(), serde_json::to_value(diagnostics)?);
+    }
+    Ok(())
+}
+
+/// 把任意版本的批次数据升级到当前版本
+fn migrate_batch_value(batch: &mut serde_json::Value) -> Result<()> {
+    let version = batch
+        .get("schema_version")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or(0) as usize;
+    if version > BATCH_MIGRATIONS.len() {
+        return Err(anyhow!(
+            "批次数据版本 {} 高于当前程序支持的版本 {}，请升级程序",
+            version,
+            BATCH_SCHEMA_VERSION
+        ));
+    }
+    for (from, migration) in BATCH_MIGRATIONS.iter().enumerate().skip(version) {
+        migration(batch).with_context(|| format!("批次数据从版本 {} 迁移失败", from))?;
+    }
+    if let Some(obj) = batch.as_object_mut() {
+        obj.insert("schema_version".to_string(), BATCH_SCHEMA_VERSION.into());
+    }
+    Ok(())
+}
+
+/// 读取任意版本的 batch.json
+fn parse_batch_json(data: &[u8]) -> Result<BatchSummary> {
+    let mut value: serde_json::Value = serde_json::from_slice(data).context("batch.json 格式错误")?;
+    migrate_batch_value(&mut value)?;
+    serde_json::from_value(value).context("解析批次信息失败")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MissingFile {
+    zip_id: String,
+    filename: String,
+    /// 文件类别：zip / video / image / pdf / pdf_screenshot / excel / additional_docx / additional_docx_image
+    kind: String,
+    path: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct BatchValidationReport {
+    batch_id: String,
+    schema_version: u32,
+    checked_files: usize,
+    missing: Vec<MissingFile>,
+    /// 从原始位置重新复制回来的 ZIP 文件
+    restored: Vec<String>,
+    repaired: bool,
+}
+
+/// 检查批次引用的文件是否都在磁盘上；repair 时去掉缺失文件的引用，
+/// 并尽量从原始路径恢复缺失的 ZIP 副本
+fn validate_batch_files(batch: &mut BatchSummary, repair: bool) -> BatchValidationReport {
+    let mut report = BatchValidationReport {
+        batch_id: batch.batch_id.clone(),
+        schema_version: batch.schema_version,
+        ..Default::default()
+    };
+
+    for z in &mut batch.zips {
+        let (zip_id, filename) = (z.id.clone(), z.filename.clone());
+        let mut check = |kind: &str, path: &str| {
+            report.checked_files += 1;
+            let exists = Path::new(path).exists();
+            if !exists {
+                report.missing.push(MissingFile {
+                    zip_id: zip_id.clone(),
+                    filename: filename.clone(),
+                    kind: kind.to_string(),
+                    path: path.to_string(),
+                });
+            }
+            exists
+        };
+
+        if !z.stored_path.is_empty() && !check("zip", &z.stored_path) && repair {
+            let source = Path::new(&z.source_path);
+            let never_cancel = std::sync::atomic::AtomicBool::new(false);
+            let restored = Path::new(&z.stored_path)
+                .parent()
+                .map_or(true, |dir| fs::create_dir_all(dir).is_ok())
+                && if source.is_dir() {
+                    copy_dir_cancellable(source, Path::new(&z.stored_path), &never_cancel).is_ok()
+                } else {
+                    source.is_file() && fs::copy(source, &z.stored_path).is_ok()
+                };
+            if restored {
+                report.restored.push(z.stored_path.clone());
+            }
+        }
+
+        for kind in CatalogFileKind::ALL {
+            let mut files = std::mem::take(kind.files_mut(z));
+            let existing: Vec<bool> = files.iter().map(|f| check(kind.as_str(), f)).collect();
+            if repair {
+                let mut keep = existing.into_iter();
+                files.retain(|_| keep.next().unwrap_or(true));
+            }
+            *kind.files_mut(z) = files;
+        }
+
+        let mut additional_docx = std::mem::take(&mut z.additional_docx_files);
+        for doc in &mut additional_docx {
+            // 附加 docx 的正文已保存在批次里，缺失时只报告
+            check("additional_docx", &doc.file_path);
+            let existing: Vec<bool> = doc.image_files.iter().map(|f| check("additional_docx_image", f)).collect();
+            if repair {
+                let mut keep = existing.into_iter();
+                doc.image_files.retain(|_| keep.next().unwrap_or(true));
+            }
+        }
+        z.additional_docx_files = additional_docx;
+
+        if repair {
+            // 已移除图片的识别文本和元数据一并去掉
+            let images: std::collections::HashSet<&String> =
+                z.image_files.iter().chain(&z.pdf_page_screenshot_files).collect();
+            z.ocr_texts.retain(|o| images.contains(&o.file));
+            z.image_metadata.retain(|m| images.contains(&m.file));
+        }
+    }
+
+    report.repaired = repair && !report.missing.is_empty();
+    report
+}
+
+/// 校验批次引用的文件；repair 为 true 时修复并保存批次
+#[tauri::command]
+fn validate_batch(app: tauri::AppHandle, batch_id: String, repair: bool) -> Result<BatchValidationReport, String> {
+    let mut batch = read_batch(&app, &batch_id).map_err(err_to_string)?;
+    let report = validate_batch_files(&mut batch, repair);
+    if report.repaired {
+        let catalog = Catalog::open(&app).map_err(err_to_string)?;
+        catalog.save_batch(&batch).map_err(err_to_string)?;
+        // 文件列表变了，按下标保存的导出选择已失效
+        catalog.delete_selection(&batch_id).map_err(err_to_string)?;
+        update_search_index(&app, &batch);
+    }
+    Ok(report)
+}
+
+// ==================== 批次数据版本与迁移结束 ====================
+
+// ==================== 批次目录（SQLite） ====================
+
+/// 批次目录数据库文件名（位于 AppData/ArchiveBox 下）
+const CATALOG_FILE: &str = "catalog.sqlite3";
+
+const CATALOG_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS batches (
+    batch_id   TEXT PRIMARY KEY,
+    created_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS zips (
+    batch_id       TEXT NOT NULL REFERENCES batches(batch_id) ON DELETE CASCADE,
+    zip_id         TEXT NOT NULL,
+    position       INTEGER NOT NULL,
+    filename       TEXT NOT NULL,
+    instruction_no TEXT NOT NULL,
+    title          TEXT NOT NULL,
+    issued_at_iso  TEXT NOT NULL,
+    status         TEXT NOT NULL,
+    -- 其余字段（不含文件列表）的 JSON
+    data           TEXT NOT NULL,
+    PRIMARY KEY (batch_id, zip_id)
+);
+CREATE INDEX IF NOT EXISTS zips_instruction_no ON zips(instruction_no);
+CREATE TABLE IF NOT EXISTS files (
+    batch_id TEXT NOT NULL,
+    zip_id   TEXT NOT NULL,
+    kind     TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    path     TEXT NOT NULL,
+    PRIMARY KEY (batch_id, zip_id, kind, position),
+    FOREIGN KEY (batch_id, zip_id) REFERENCES zips(batch_id, zip_id) ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS selections (
+    batch_id   TEXT PRIMARY KEY REFERENCES batches(batch_id) ON DELETE CASCADE,
+    data       TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+"#;
+
+/// 目录数据库的表结构升级（PRAGMA user_version），下标 i 的语句把版本从 i 升到 i+1
+const CATALOG_MIGRATIONS: &[&str] = &[
+    CATALOG_SCHEMA,
+    // 记录批次数据版本，读取旧版本数据时按 BATCH_MIGRATIONS 升级
+    "ALTER TABLE batches ADD COLUMN schema_version INTEGER NOT NULL DEFAULT 0;",
+    // 已结束的后台任务，重新打开程序后仍可查询结果
+    "CREATE TABLE IF NOT EXISTS jobs (
+        job_id     TEXT PRIMARY KEY,
+        created_at INTEGER NOT NULL,
+        data       TEXT NOT NULL
+    );",
+    // 监控文件夹中已自动导入的文件（按路径、大小和修改时间识别），批次清理后仍保留
+    "CREATE TABLE IF NOT EXISTS watched_files (
+        path        TEXT NOT NULL,
+        size        INTEGER NOT NULL,
+        modified    INTEGER NOT NULL,
+        batch_id    TEXT NOT NULL,
+        zip_id      TEXT NOT NULL,
+        imported_at INTEGER NOT NULL,
+        -- 1 表示导入到了当天批次，0 表示导入到文件夹指定的批次
+        daily       INTEGER NOT NULL,
+        PRIMARY KEY (path, size, modified)
+    );",
+    // 导入过的指令（按指令编号和内容哈希识别跨批次重复），批次清理后仍保留；已有批次按指令编号补录
+    "CREATE TABLE IF NOT EXISTS seen_instructions (
+        batch_id       TEXT NOT NULL,
+        zip_id         TEXT NOT NULL,
+        instruction_no TEXT NOT NULL,
+        content_hash   TEXT NOT NULL,
+        imported_at    INTEGER NOT NULL,
+        -- 最近一次导出 Excel 的时间，没有导出过为 NULL
+        exported_at    INTEGER,
+        PRIMARY KEY (batch_id, zip_id)
+    );
+    CREATE INDEX IF NOT EXISTS seen_instructions_no ON seen_instructions(instruction_no);
+    CREATE INDEX IF NOT EXISTS seen_instructions_hash ON seen_instructions(content_hash);
+    INSERT OR IGNORE INTO seen_instructions (batch_id, zip_id, instruction_no, content_hash, imported_at)
+        SELECT z.batch_id, z.zip_id, z.instruction_no, '', b.created_at
+        FROM zips z JOIN batches b ON b.batch_id = z.batch_id
+        WHERE z.status <> 'failed';",
+    // 全文检索的文本段和词项（FTS5），批次清理后仍可检索；已有批次在首次检索时补建
+    "CREATE TABLE IF NOT EXISTS search_docs (
+        doc_id           INTEGER PRIMARY KEY,
+        batch_id         TEXT NOT NULL,
+        batch_created_at INTEGER NOT NULL,
+        zip_id           TEXT NOT NULL,
+        filename         TEXT NOT NULL,
+        title            TEXT NOT NULL,
+        issued_at_iso    TEXT NOT NULL,
+        field            TEXT NOT NULL,
+        source           TEXT NOT NULL,
+        -- 文本来源文件的路径，重新索引时按路径复用已提取的 PDF 文字
+        path             TEXT NOT NULL,
+        text             TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS search_docs_zip ON search_docs(batch_id, zip_id);
+    CREATE VIRTUAL TABLE IF NOT EXISTS search_terms USING fts5(tokens);",
+];
+
+/// ZIP 中按行保存在 files 表里的文件列表
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CatalogFileKind {
+    Video,
+    Image,
+    Pdf,
+    PdfScreenshot,
+    Excel,
+}
+
+impl CatalogFileKind {
+    const ALL: [CatalogFileKind; 5] = [
+        CatalogFileKind::Video,
+        CatalogFileKind::Image,
+        CatalogFileKind::Pdf,
+        CatalogFileKind::PdfScreenshot,
+        CatalogFileKind::Excel,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            CatalogFileKind::Video => "video",
+            CatalogFileKind::Image => "image",
+            CatalogFileKind::Pdf => "pdf",
+            CatalogFileKind::PdfScreenshot => "pdf_screenshot",
+            CatalogFileKind::Excel => "excel",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == kind)
+    }
+
+    fn files(self, z: &ZipSummary) -> &Vec<String> {
+        match self {
+            CatalogFileKind::Video => &z.video_files,
+            CatalogFileKind::Image => &z.image_files,
+            CatalogFileKind::Pdf => &z.pdf_files,
+            CatalogFileKind::PdfScreenshot => &z.pdf_page_screenshot_files,
+            CatalogFileKind::Excel => &z.excel_files,
+        }
+    }
+
+    fn files_mut(self, z: &mut ZipSummary) -> &mut Vec<String> {
+        match self {
+            CatalogFileKind::Video => &mut z.video_files,
+            CatalogFileKind::Image => &mut z.image_files,
+            CatalogFileKind::Pdf => &mut z.pdf_files,
+            CatalogFileKind::PdfScreenshot => &mut z.pdf_page_screenshot_files,
+            CatalogFileKind::Excel => &mut z.excel_files,
+        }
+    }
+}
+
+/// 批次目录：批次、ZIP、文件和导出选择都保存在 SQLite 中，修改在事务内完成。
+/// 每个命令各自打开连接，并发写入由 SQLite 的锁保证不会互相覆盖。
+struct Catalog {
+    conn: rusqlite::Connection,
+}
+
+impl Catalog {
+    fn open(app: &tauri::AppHandle) -> Result<Self> {
+        Self::open_at(&app_data_dir(app)?.join(CATALOG_FILE))
+    }
+
+    fn open_at(path: &Path) -> Result<Self> {
+        let mut conn = rusqlite::Connection::open(path)
+            .with_context(|| format!("打开批次目录失败: {}", path.display()))?;
+        conn.busy_timeout(std::time::Duration::from_secs(10))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+
+        // 加写锁后再读版本，避免多个连接同时升级
+        let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        let version: usize = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > CATALOG_MIGRATIONS.len() {
+            return Err(anyhow!("批次目录由更新版本的程序创建，请升级程序"));
+        }
+        for (from, sql) in CATALOG_MIGRATIONS.iter().enumerate().skip(version) {
+            tx.execute_batch(sql)
+                .with_context(|| format!("批次目录从版本 {} 升级失败", from))?;
+        }
+        tx.pragma_update(None, "user_version", CATALOG_MIGRATIONS.len())?;
+        tx.commit()?;
+        Ok(Self { conn })
+    }
+
+    /// 保存整个批次（覆盖该批次原有的 ZIP 和文件）
+    fn save_batch(&self, batch: &BatchSummary) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO batches (batch_id, created_at, schema_version) VALUES (?1, ?2, ?3)
+             ON CONFLICT(batch_id) DO UPDATE SET
+                 created_at = excluded.created_at,
+                 schema_version = excluded.schema_version",
+            rusqlite::params![batch.batch_id, batch.created_at, BATCH_SCHEMA_VERSION],
+        )?;
+        tx.execute("DELETE FROM zips WHERE batch_id = ?1", [&batch.batch_id])?;
+        for (position, z) in batch.zips.iter().enumerate() {
+            catalog_insert_zip(&tx, &batch.batch_id, position, z)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// 读取批次；数据版本较旧时先迁移，并把升级后的数据写回
+    fn load_batch(&self, batch_id: &str) -> Result<Option<BatchSummary>> {
+        use rusqlite::OptionalExtension;
+
+        // 同一个读事务内读取，避免读到一半被其他写入打断
+        let tx = self.conn.unchecked_transaction()?;
+        let row: Option<(i64, u32)> = tx
+            .query_row(
+                "SELECT created_at, schema_version FROM batches WHERE batch_id = ?1",
+                [batch_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((created_at, schema_version)) = row else {
+            return Ok(None);
+        };
+
+        let mut stmt = tx.prepare("SELECT data FROM zips WHERE batch_id = ?1 ORDER BY position")?;
+        let raw_zips = stmt
+            .query_map([batch_id], |row| row.get::<_, String>(0))?
+            .map(|data| Ok(serde_json::from_str::<serde_json::Value>(&data?)?))
+            .collect::<Result<Vec<_>>>()?;
+        drop(stmt);
+
+        let mut value = serde_json::json!({
+            "batch_id": batch_id,
+            "created_at": created_at,
+            "schema_version": schema_version,
+            "zips": raw_zips,
+        });
+        let migrated = schema_version < BATCH_SCHEMA_VERSION;
+        if migrated {
+            migrate_batch_value(&mut value)?;
+        }
+        let mut batch: BatchSummary = serde_json::from_value(value).context("解析批次信息失败")?;
+        catalog_load_files(&tx, batch_id, None, &mut batch.zips)?;
+        tx.finish()?;
+
+        if migrated {
+            self.save_batch(&batch)?;
+            log::info!("批次 {} 已从数据版本 {} 升级到 {}", batch_id, schema_version, BATCH_SCHEMA_VERSION);
+        }
+        Ok(Some(batch))
+    }
+
+    /// 在一个写事务内读取、修改并保存单个 ZIP，并发修改同一批次时不会丢失更新
+    fn update_zip<T>(
+        &mut self,
+        batch_id: &str,
+        zip_id: &str,
+        update: impl FnOnce(&mut ZipSummary) -> Result<T>,
+    ) -> Result<T> {
+        use rusqlite::OptionalExtension;
+
+        // 旧版本数据先整体升级，下面才能按当前结构解析单个 ZIP
+        let schema_version: Option<u32> = self
+            .conn
+            .query_row("SELECT schema_version FROM batches WHERE batch_id = ?1", [batch_id], |row| row.get(0))
+            .optional()?;
+        if schema_version.is_some_and(|v| v < BATCH_SCHEMA_VERSION) {
+            self.load_batch(batch_id)?;
+        }
+
+        let tx = self
+            .conn
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        let (position, data): (i64, String) = tx
+            .query_row(
+                "SELECT position, data FROM zips WHERE batch_id = ?1 AND zip_id = ?2",
+                [batch_id, zip_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("ZIP不存在"))?;
+        let mut zips = vec![serde_json::from_str::<ZipSummary>(&data)?];
+        catalog_load_files(&tx, batch_id, Some(zip_id), &mut zips)?;
+
+        let out = update(&mut zips[0])?;
+
+        tx.execute("DELETE FROM zips WHERE batch_id = ?1 AND zip_id = ?2", [batch_id, zip_id])?;
+        catalog_insert_zip(&tx, batch_id, position as usize, &zips[0])?;
+        tx.commit()?;
+        Ok(out)
+    }
+
+    /// 在一个写事务内保存多个 ZIP 的校验结果；调用方已通过 load_batch 完成数据升级
+    fn update_flags(&mut self, batch_id: &str, updates: &[(String, Vec<ValidationFlag>)]) -> Result<()> {
+        use rusqlite::OptionalExtension;
+
+        if updates.is_empty() {
+            return Ok(());
+        }
+        let tx = self
+            .conn
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        for (zip_id, flags) in updates {
+            let Some(data) = tx
+                .query_row(
+                    "SELECT data FROM zips WHERE batch_id = ?1 AND zip_id = ?2",
+                    [batch_id, zip_id.as_str()],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?
+            else {
+                continue;
+            };
+            let mut z: ZipSummary = serde_json::from_str(&data)?;
+            z.flags = flags.clone();
+            tx.execute(
+                "UPDATE zips SET data = ?3 WHERE batch_id = ?1 AND zip_id = ?2",
+                rusqlite::params![batch_id, zip_id, serde_json::to_string(&z)?],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// 把新导入的 ZIP 追加到已有批次末尾
+    fn append_zips(&mut self, batch_id: &str, zips: &[ZipSummary]) -> Result<()> {
+        use rusqlite::OptionalExtension;
+
+        let schema_version: u32 = self
+            .conn
+            .query_row("SELECT schema_version FROM batches WHERE batch_id = ?1", [batch_id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| anyhow!("批次不存在: {}", batch_id))?;
+        if schema_version < BATCH_SCHEMA_VERSION {
+            self.load_batch(batch_id)?;
+        }
+
+        let tx = self
+            .conn
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        let next: i64 = tx.query_row(
+            "SELECT COALESCE(MAX(position) + 1, 0) FROM zips WHERE batch_id = ?1",
+            [batch_id],
+            |row| row.get(0),
+        )?;
+        for (offset, z) in zips.iter().enumerate() {
+            catalog_insert_zip(&tx, batch_id, next as usize + offset, z)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn batch_exists(&self, batch_id: &str) -> Result<bool> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM batches WHERE batch_id = ?1", [batch_id], |row| row.get(0))?;
+        Ok(count > 0)
+    }
+
+    fn mark_watched_file(
+        &self,
+        file: &WatchedFile,
+        batch_id: &str,
+        zip_id: &str,
+        imported_at: i64,
+        daily: bool,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO watched_files (path, size, modified, batch_id, zip_id, imported_at, daily)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![file.path, file.size as i64, file.modified, batch_id, zip_id, imported_at, daily],
+        )?;
+        Ok(())
+    }
+
+    fn is_watched_file_imported(&self, file: &WatchedFile) -> Result<bool> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM watched_files WHERE path = ?1 AND size = ?2 AND modified = ?3",
+            rusqlite::params![file.path, file.size as i64, file.modified],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    /// since 之后作为当天批次自动导入过文件、且仍然存在的最近一个批次
+    fn latest_daily_watch_batch(&self, since: i64) -> Result<Option<String>> {
+        use rusqlite::OptionalExtension;
+
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT w.batch_id FROM watched_files w JOIN batches b ON b.batch_id = w.batch_id
+                 WHERE w.daily = 1 AND w.imported_at >= ?1 ORDER BY w.imported_at DESC LIMIT 1",
+                [since],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// 记录（或更新）导入的指令，保留首次导入和导出的时间
+    fn record_instructions(&self, batch_id: &str, zips: &[ZipSummary], imported_at: i64) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        for z in zips.iter().filter(|z| z.status != "failed") {
+            tx.execute(
+                "INSERT INTO seen_instructions (batch_id, zip_id, instruction_no, content_hash, imported_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT(batch_id, zip_id) DO UPDATE SET
+                     instruction_no = excluded.instruction_no,
+                     content_hash = excluded.content_hash",
+                rusqlite::params![batch_id, z.id, z.word.instruction_no.trim(), instruction_content_hash(&z.word), imported_at],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// 其他批次中最近一次导入的相同指令（指令编号相同或内容相同）
+    fn previous_instruction(&self, batch_id: &str, z: &ZipSummary) -> Result<Option<PreviousInstruction>> {
+        use rusqlite::OptionalExtension;
+
+        let number = z.word.instruction_no.trim();
+        let hash = instruction_content_hash(&z.word);
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT batch_id, zip_id, imported_at, exported_at, instruction_no = ?2 FROM seen_instructions
+                 WHERE batch_id <> ?1 AND ((?2 <> '' AND instruction_no = ?2) OR (?3 <> '' AND content_hash = ?3))
+                 ORDER BY imported_at DESC LIMIT 1",
+                rusqlite::params![batch_id, number, hash],
+                |row| {
+                    Ok(PreviousInstruction {
+                        batch_id: row.get(0)?,
+                        zip_id: row.get(1)?,
+                        imported_at: row.get(2)?,
+                        exported_at: row.get(3)?,
+                        matched_by: if row.get(4)? { DuplicateMatch::InstructionNo } else { DuplicateMatch::Content },
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    /// 相同的指令是否已作为别的 ZIP（其他批次或同批次的其他 ZIP）导出过
+    fn exported_elsewhere(&self, batch_id: &str, z: &ZipSummary) -> Result<bool> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM seen_instructions
+             WHERE exported_at IS NOT NULL AND NOT (batch_id = ?1 AND zip_id = ?2)
+               AND ((?3 <> '' AND instruction_no = ?3) OR (?4 <> '' AND content_hash = ?4))",
+            rusqlite::params![batch_id, z.id, z.word.instruction_no.trim(), instruction_content_hash(&z.word)],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    fn mark_instructions_exported(&self, batch_id: &str, zip_ids: &[String], exported_at: i64) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        for zip_id in zip_ids {
+            tx.execute(
+                "UPDATE seen_instructions SET exported_at = ?3 WHERE batch_id = ?1 AND zip_id = ?2",
+                rusqlite::params![batch_id, zip_id, exported_at],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// ZIP 在检索索引中的文本段，按写入顺序
+    fn zip_search_docs(&self, batch_id: &str, zip_id: &str) -> Result<Vec<SearchDoc>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM search_docs WHERE batch_id = ?1 AND zip_id = ?2 ORDER BY doc_id",
+            SEARCH_DOC_COLUMNS
+        ))?;
+        let docs = stmt
+            .query_map([batch_id, zip_id], |row| search_doc_from_row(row).map(|(_, doc)| doc))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(docs)
+    }
+
+    /// 替换 ZIP 的文本段，词项随之增删
+    fn replace_search_docs(&self, batch_id: &str, zip_id: &str, docs: &[SearchDoc]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM search_terms WHERE rowid IN (SELECT doc_id FROM search_docs WHERE batch_id = ?1 AND zip_id = ?2)",
+            [batch_id, zip_id],
+        )?;
+        tx.execute("DELETE FROM search_docs WHERE batch_id = ?1 AND zip_id = ?2", [batch_id, zip_id])?;
+        for doc in docs {
+            tx.execute(
+                "INSERT INTO search_docs
+                     (batch_id, batch_created_at, zip_id, filename, title, issued_at_iso, field, source, path, text)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                rusqlite::params![
+                    doc.batch_id,
+                    doc.batch_created_at,
+                    doc.zip_id,
+                    doc.filename,
+                    doc.title,
+                    doc.issued_at_iso,
+                    doc.field.as_str(),
+                    doc.source,
+                    doc.path,
+                    doc.text,
+                ],
+            )?;
+            let mut tokens = search_tokens(&doc.text);
+            tokens.sort();
+            tokens.dedup();
+            tx.execute(
+                "INSERT INTO search_terms (rowid, tokens) VALUES (?1, ?2)",
+                rusqlite::params![tx.last_insert_rowid(), tokens.join(" ")],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// 批次在检索索引中的 ZIP
+    fn search_zip_ids(&self, batch_id: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT DISTINCT zip_id FROM search_docs WHERE batch_id = ?1")?;
+        let ids = stmt
+            .query_map([batch_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(ids)
+    }
+
+    /// 还没有建立检索索引的批次（升级前导入的批次）
+    fn unindexed_batch_ids(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT batch_id FROM batches WHERE batch_id NOT IN (SELECT DISTINCT batch_id FROM search_docs)",
+        )?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(ids)
+    }
+
+    /// 包含全部词项的文本段（doc_id, 文本段）
+    fn search_candidates(&self, tokens: &[String]) -> Result<Vec<(i64, SearchDoc)>> {
+        // 每个词项加引号按短语匹配，多个短语之间为"且"
+        let expr = tokens
+            .iter()
+            .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM search_terms JOIN search_docs ON search_docs.doc_id = search_terms.rowid
+             WHERE search_terms MATCH ?1",
+            SEARCH_DOC_COLUMNS
+        ))?;
+        let docs = stmt
+            .query_map([expr], search_doc_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(docs)
+    }
+
+    fn batch_ids(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT batch_id FROM batches ORDER BY created_at")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(ids)
+    }
+
+    fn delete_batch(&self, batch_id: &str) -> Result<()> {
+        self.conn.execute("DELETE FROM batches WHERE batch_id = ?1", [batch_id])?;
+        Ok(())
+    }
+
+    fn delete_all_batches(&self) -> Result<()> {
+        self.conn.execute("DELETE FROM batches", [])?;
+        Ok(())
+    }
+
+    fn save_selection(&self, batch_id: &str, selection: &ExportBundleSelection) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO selections (batch_id, data, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(batch_id) DO UPDATE SET data = excluded.data, updated_at = excluded.updated_at",
+            rusqlite::params![
+                batch_id,
+                serde_json::to_string(selection)?,
+                OffsetDateTime::now_utc().unix_timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn delete_selection(&self, batch_id: &str) -> Result<()> {
+        self.conn.execute("DELETE FROM selections WHERE batch_id = ?1", [batch_id])?;
+        Ok(())
+    }
+
+    fn load_selection(&self, batch_id: &str) -> Result<Option<ExportBundleSelection>> {
+        use rusqlite::OptionalExtension;
+
+        let data: Option<String> = self
+            .conn
+            .query_row("SELECT data FROM selections WHERE batch_id = ?1", [batch_id], |row| row.get(0))
+            .optional()?;
+        data.map(|d| serde_json::from_str(&d).context("解析导出选择失败")).transpose()
+    }
+
+    /// 保存已结束的任务，只保留最近 JOB_HISTORY_LIMIT 条
+    fn save_job(&self, job: &JobInfo) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO jobs (job_id, created_at, data) VALUES (?1, ?2, ?3)
+             ON CONFLICT(job_id) DO UPDATE SET data = excluded.data",
+            rusqlite::params![job.id, job.created_at, serde_json::to_string(job)?],
+        )?;
+        tx.execute(
+            "DELETE FROM jobs WHERE job_id NOT IN (SELECT job_id FROM jobs ORDER BY created_at DESC LIMIT ?1)",
+            [JOB_HISTORY_LIMIT as i64],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_jobs(&self, limit: usize) -> Result<Vec<JobInfo>> {
+        let mut stmt = self.conn.prepare("SELECT data FROM jobs ORDER BY created_at DESC LIMIT ?1")?;
+        let rows = stmt.query_map([limit as i64], |row| row.get::<_, String>(0))?;
+        let mut jobs = Vec::new();
+        for data in rows {
+            jobs.push(serde_json::from_str(&data?).context("解析任务记录失败")?);
+        }
+        Ok(jobs)
+    }
+
+    fn load_job(&self, job_id: &str) -> Result<Option<JobInfo>> {
+        use rusqlite::OptionalExtension;
+
+        let data: Option<String> = self
+            .conn
+            .query_row("SELECT data FROM jobs WHERE job_id = ?1", [job_id], |row| row.get(0))
+            .optional()?;
+        data.map(|d| serde_json::from_str(&d).context("解析任务记录失败")).transpose()
+    }
+}
+
+fn catalog_insert_zip(conn: &rusqlite::Connection, batch_id: &str, position: usize, z: &ZipSummary) -> Result<()> {
+    // 文件列表单独按行保存，JSON 里不重复
+    let mut data = z.clone();
+    for kind in CatalogFileKind::ALL {
+        kind.files_mut(&mut data).clear();
+    }
+    conn.execute(
+        "INSERT INTO zips (batch_id, zip_id, position, filename, instruction_no, title, issued_at_iso, status, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        rusqlite::params![
+            batch_id,
+            z.id,
+            position as i64,
+            z.filename,
+            z.word.instruction_no,
+            z.word.title,
+            z.word.issued_at_iso,
+            z.status,
+            serde_json::to_string(&data)?
+        ],
+    )?;
+
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO files (batch_id, zip_id, kind, position, path) VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for kind in CatalogFileKind::ALL {
+        for (i, path) in kind.files(z).iter().enumerate() {
+            stmt.execute(rusqlite::params![batch_id, z.id, kind.as_str(), i as i64, path])?;
+        }
+    }
+    Ok(())
+}
+
+/// 从 files 表补全 ZIP 的文件列表；zip_id 为 None 时读取整个批次
+fn catalog_load_files(
+    conn: &rusqlite::Connection,
+    batch_id: &str,
+    zip_id: Option<&str>,
+    zips: &mut [ZipSummary],
+) -> Result<()> {
+    let by_id: std::collections::HashMap<String, usize> =
+        zips.iter().enumerate().map(|(i, z)| (z.id.clone(), i)).collect();
+    let mut stmt = conn.prepare(
+        "SELECT zip_id, kind, path FROM files
+         WHERE batch_id = ?1 AND (?2 IS NULL OR zip_id = ?2)
+         ORDER BY zip_id, kind, position",
+    )?;
+    let mut rows = stmt.query(rusqlite::params![batch_id, zip_id])?;
+    while let Some(row) = rows.next()? {
+        let zip_id: String = row.get(0)?;
+        let kind: String = row.get(1)?;
+        let (Some(&idx), Some(kind)) = (by_id.get(&zip_id), CatalogFileKind::parse(&kind)) else {
+            continue;
+        };
+        kind.files_mut(&mut zips[idx]).push(row.get(2)?);
+    }
+    Ok(())
+}
+
+/// 读取批次；旧版本只在批次目录下保存了 batch.json，首次读取时迁移到批次目录数据库
+fn read_batch(app: &tauri::AppHandle, batch_id: &str) -> Result<BatchSummary> {
+    let catalog = Catalog::open(app)?;
+    if let Some(batch) = catalog.load_batch(batch_id)? {
+        return Ok(batch);
+    }
+
+    let legacy_path = app_data_dir(app)?.join("batches").join(batch_id).join("batch.json");
+    if !legacy_path.exists() {
+        return Err(anyhow!("批次不存在: {}", batch_id));
+    }
+    let data = fs::read(&legacy_path).with_context(|| format!("读取批次信息失败: {}", legacy_path.display()))?;
+    let batch = parse_batch_json(&data).with_context(|| format!("读取批次信息失败: {}", legacy_path.display()))?;
+    catalog.save_batch(&batch)?;
+    log::info!("已将 {} 迁移到批次目录", legacy_path.display());
+    Ok(batch)
+}
+
+fn write_batch(app: &tauri::AppHandle, batch: &BatchSummary) -> Result<()> {
+    Catalog::open(app)?.save_batch(batch).context("保存批次信息失败")
+}
+
+fn update_zip(
+    app: &tauri::AppHandle,
+    batch_id: &str,
+    zip_id: &str,
+    update: impl FnOnce(&mut ZipSummary) -> Result<()>,
+) -> Result<()> {
+    Catalog::open(app)?.update_zip(batch_id, zip_id, update)
+}
+
+/// 读取上次导出该批次时的选择，用于重新打开批次时恢复
+#[tauri::command]
+fn get_saved_selection(app: tauri::AppHandle, batch_id: String) -> Result<Option<ExportBundleSelection>, String> {
+    Catalog::open(&app)
+        .and_then(|catalog| catalog.load_selection(&batch_id))
+        .map_err(err_to_string)
+}
+
+// ==================== 批次目录（SQLite）结束 ====================
+
+// ==================== 下发时间解析 ====================
+
+/// 日期部分：2025年12月11日 / 2025-12-11 / 2025/12/11 / 2025.12.11
+static RE_DATE_YMD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?P<y>\d{4})\s*(?:年|[-/.])\s*(?P<m>\d{1,2})\s*(?:月|[-/.])\s*(?P<d>\d{1,2})\s*(?:日|号)?")
+        .expect("valid regex")
+});
+
+/// 紧凑日期：20251211（可紧跟 4 位时间 1430）
+static RE_DATE_COMPACT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:^|\D)(?P<y>\d{4})(?P<m>\d{2})(?P<d>\d{2})(?:(?P<h>\d{2})(?P<mi>\d{2})(?P<s>\d{2})?)?(?:\D|$)")
+        .expect("valid regex")
+});
+
+/// 无年份日期：12月11日
+static RE_DATE_MD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?P<m>\d{1,2})\s*月\s*(?P<d>\d{1,2})\s*(?:日|号)?").expect("valid regex")
+});
+
+/// 紧跟在日期后的时间：14:30 / 14:30:00 / 14时30分 / 14点 / 下午2点30分
+static RE_TIME: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^[\sT,，]*(?P<ap>上午|中午|下午|晚上|凌晨|早上)?\s*(?P<h>\d{1,2})\s*[:时点]\s*(?:(?P<mi>\d{1,2})\s*分?\s*(?::?\s*(?P<s>\d{1,2})\s*秒?)?)?",
+    )
+    .expect("valid regex")
+});
+
+/// 下发时间解析置信度
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum IssuedAtConfidence {
+    /// 日期和时间都完整解析
+    Exact,
+    /// 只有日期，时间按 00:00 处理
+    DateOnly,
+    /// 原文缺少年份，按导入日期推断
+    YearInferred,
+    /// 为空或无法解析
+    #[default]
+    Unparsed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ParsedIssuedAt {
+    datetime: time::PrimitiveDateTime,
+    has_time: bool,
+    confidence: IssuedAtConfidence,
+}
+
+impl ParsedIssuedAt {
+    /// 规范化的 ISO 8601 表示（无时区，按原文的本地时间理解）
+    fn to_iso(self) -> String {
+        let d = self.datetime.date();
+        if self.has_time {
+            let t = self.datetime.time();
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                d.year(),
+                d.month() as u8,
+                d.day(),
+                t.hour(),
+                t.minute(),
+                t.second()
+            )
+        } else {
+            format!("{:04}-{:02}-{:02}", d.year(), d.month() as u8, d.day())
+        }
+    }
+}
+
+impl WordFields {
+    /// 根据 issued_at 原文填充 issued_at_iso / issued_at_confidence
+    fn normalize_issued_at(&mut self, reference: time::Date) {
+        match parse_issued_at_with_reference(&self.issued_at, reference) {
+            Some(parsed) => {
+                self.issued_at_iso = parsed.to_iso();
+                self.issued_at_confidence = parsed.confidence;
+            }
+            None => {
+                self.issued_at_iso = String::new();
+                self.issued_at_confidence = IssuedAtConfidence::Unparsed;
+            }
+        }
+    }
+}
+
+/// 全角数字和标点转半角，便于统一匹配
+fn normalize_date_text(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32).unwrap_or(c),
+            '／' => '/',
+            '－' | '—' | '–' => '-',
+            '．' | '。' => '.',
+            '：' => ':',
+            '\u{3000}' | '\u{00A0}' => ' ',
+            _ => c,
+        })
+        .collect()
+}
+
+fn make_date(year: i32, month: u32, day: u32) -> Option<time::Date> {
+    let month = time::Month::try_from(u8::try_from(month).ok()?).ok()?;
+    time::Date::from_calendar_date(year, month, u8::try_from(day).ok()?).ok()
+}
+
+/// 从日期之后的剩余文本中解析时间，失败时返回 None（只保留日期）
+fn parse_time_after_date(rest: &str) -> Option<time::Time> {
+    let cap = RE_TIME.captures(rest)?;
+    let mut hour: u8 = cap.name("h")?.as_str().parse().ok()?;
+    let minute: u8 = cap.name("mi").map_or(Ok(0), |m| m.as_str().parse()).ok()?;
+    let second: u8 = cap.name("s").map_or(Ok(0), |m| m.as_str().parse()).ok()?;
+    let afternoon = match cap.name("ap").map(|m| m.as_str()) {
+        Some("下午" | "晚上") => hour < 12,
+        Some("中午") => hour < 6,
+        _ => false,
+    };
+    if afternoon {
+        hour += 12;
+    }
+    time::Time::from_hms(hour, minute, second).ok()
+}
+
+/// 解析下发时间，支持中文和数字格式：
+/// `2025年12月11日 14时30分`、`2025-12-11 14:30:00`、`2025/12/11`、`2025.12.11`、
+/// `20251211`、`12月11日`（无年份时按 reference 推断）以及全角数字。
+fn parse_issued_at_with_reference(date_str: &str, reference: time::Date) -> Option<ParsedIssuedAt> {
+    let text = normalize_date_text(date_str);
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    let with_time = |date: time::Date, rest: &str, year_known: bool| {
+        let time = parse_time_after_date(rest);
+        let confidence = match (year_known, time.is_some()) {
+            (false, _) => IssuedAtConfidence::YearInferred,
+            (true, true) => IssuedAtConfidence::Exact,
+            (true, false) => IssuedAtConfidence::DateOnly,
+        };
+        ParsedIssuedAt {
+            datetime: time::PrimitiveDateTime::new(date, time.unwrap_or(time::Time::MIDNIGHT)),
+            has_time: time.is_some(),
+            confidence,
+        }
+    };
+
+    if let Some(cap) = RE_DATE_YMD.captures(text) {
+        let date = make_date(
+            cap["y"].parse().ok()?,
+            cap["m"].parse().ok()?,
+            cap["d"].parse().ok()?,
+        );
+        if let Some(date) = date {
+            let rest = &text[cap.get(0).map_or(0, |m| m.end())..];
+            return Some(with_time(date, rest, true));
+        }
+    }
+
+    if let Some(cap) = RE_DATE_COMPACT.captures(text) {
+        let date = make_date(
+            cap["y"].parse().ok()?,
+            cap["m"].parse().ok()?,
+            cap["d"].parse().ok()?,
+        );
+        if let Some(date) = date {
+            let time = match (cap.name("h"), cap.name("mi")) {
+                (Some(h), Some(mi)) => time::Time::from_hms(
+                    h.as_str().parse().ok()?,
+                    mi.as_str().parse().ok()?,
+                    cap.name("s").map_or(Ok(0), |s| s.as_str().parse()).ok()?,
+                )
+                .ok(),
+                _ => None,
+            };
+            if let Some(time) = time {
+                return Some(ParsedIssuedAt {
+                    datetime: time::PrimitiveDateTime::new(date, time),
+                    has_time: true,
+                    confidence: IssuedAtConfidence::Exact,
+                });
+            }
+            let rest = &text[cap.name("d").map_or(0, |m| m.end())..];
+            return Some(with_time(date, rest, true));
+        }
+    }
+
+    if let Some(cap) = RE_DATE_MD.captures(text) {
+        let month: u32 = cap["m"].parse().ok()?;
+        let day: u32 = cap["d"].parse().ok()?;
+        // 先按参考年份推断；如果落在参考日期一个月之后，视为上一年（如 1 月导入 12 月的指令）
+        let mut date = make_date(reference.year(), month, day)?;
+        if date > reference + time::Duration::days(31) {
+            date = make_date(reference.year() - 1, month, day)?;
+        }
+        let rest = &text[cap.get(0).map_or(0, |m| m.end())..];
+        return Some(with_time(date, rest, false));
+    }
+
+    None
+}
+
+// 解析下发时间字符串为 OffsetDateTime，无法解析时返回错误
+fn parse_issued_at(date_str: &str) -> Result<OffsetDateTime> {
+    parse_issued_at_with_reference(date_str, OffsetDateTime::now_utc().date())
+        .map(|p| p.datetime.assume_utc())
+        .ok_or_else(|| anyhow!("无法解析下发时间: {}", date_str.trim()))
+}
+
+/// 排序用的下发时间：优先使用导入时规范化的值，旧批次回退到原文解析
+fn issued_at_sort_key(word: &WordFields) -> Option<OffsetDateTime> {
+    if !word.issued_at_iso.is_empty() {
+        if let Ok(t) = parse_issued_at(&word.issued_at_iso) {
+            return Some(t);
+        }
+    }
+    parse_issued_at(&word.issued_at).ok()
+}
+
+// 对 ZipSummary 列表按下发时间排序，无法解析的排在最后并保持原有顺序
+fn sort_zips_by_issued_at(zips: &mut [ZipSummary]) {
+    zips.sort_by_cached_key(|z| match issued_at_sort_key(&z.word) {
+        Some(t) => (0, t),
+        None => (1, OffsetDateTime::UNIX_EPOCH),
+    });
+}
+
+// ==================== 下发时间解析结束 ====================
+
+// ==================== 字段人工修改 ====================
+
+/// 一次人工修改字段的记录
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FieldEdit {
+    /// 修改人（系统用户名）
+    edited_by: String,
+    edited_at: i64,
+    /// 改动的字段，如 "title"
+    fields: Vec<String>,
+    /// 之后更换了主文档，这次修改不再作用于当前字段，只保留作记录
+    #[serde(default)]
+    superseded: bool,
+}
+
+/// 当前系统用户名，作为修改人记录
+fn current_user_name() -> String {
+    ["USERNAME", "USER", "LOGNAME"]
+        .iter()
+        .find_map(|key| std::env::var(key).ok().filter(|v| !v.trim().is_empty()))
+        .unwrap_or_else(|| "未知用户".to_string())
+}
+
+/// 用人工修正的值替换抽取结果；第一次修改时保留原始抽取值。返回改动的字段，没有改动时为空
+fn apply_field_edit(z: &mut ZipSummary, mut fields: WordFields, edited_by: &str, now: OffsetDateTime) -> Vec<String> {
+    fields.instruction_no = fields.instruction_no.trim().to_string();
+    fields.title = fields.title.trim().to_string();
+    fields.issued_at = fields.issued_at.trim().to_string();
+    fields.content = fields.content.trim().to_string();
+
+    let mut changed = Vec::new();
+    for (name, old, new) in [
+        ("instruction_no", &z.word.instruction_no, &fields.instruction_no),
+        ("title", &z.word.title, &fields.title),
+        ("issued_at", &z.word.issued_at, &fields.issued_at),
+        ("content", &z.word.content, &fields.content),
+    ] {
+        if old.trim() != new {
+            changed.push(name.to_string());
+        }
+    }
+    if changed.is_empty() {
+        return changed;
+    }
+
+    if z.original_word.is_none() {
+        z.original_word = Some(z.word.clone());
+    }
+    fields.normalize_issued_at(now.date());
+    z.word = fields;
+    z.field_edits.push(FieldEdit {
+        edited_by: edited_by.to_string(),
+        edited_at: now.unix_timestamp(),
+        fields: changed.clone(),
+        superseded: false,
+    });
+    changed
+}
+
+/// 重试导入时保留用户在旧记录上做过的处理：人工修改、保存的 PDF 截图和对应的识别文本
+fn carry_over_user_data(old: &ZipSummary, new: &mut ZipSummary, today: time::Date) {
+    reapply_field_edits(old, new, today);
+    new.pdf_page_screenshot_files = old
+        .pdf_page_screenshot_files
+        .iter()
+        .filter(|f| Path::new(f.as_str()).exists())
+        .cloned()
+        .collect();
+    new.ocr_texts = old
+        .ocr_texts
+        .iter()
+        .filter(|o| new.image_files.contains(&o.file) || new.pdf_page_screenshot_files.contains(&o.file))
+        .cloned()
+        .collect();
+}
+
+/// 仍然有效（没有因更换主文档失效）的人工修改字段
+fn active_edited_fields(z: &ZipSummary) -> Vec<&str> {
+    let mut fields: Vec<&str> = Vec::new();
+    for edit in z.field_edits.iter().filter(|edit| !edit.superseded) {
+        for field in &edit.fields {
+            if !fields.contains(&field.as_str()) {
+                fields.push(field);
+            }
+        }
+    }
+    fields
+}
+
+/// 重新导入后把旧记录上的人工修改套到新的抽取结果上，原始值换成新的抽取值
+fn reapply_field_edits(old: &ZipSummary, new: &mut ZipSummary, today: time::Date) {
+    if old.field_edits.is_empty() {
+        return;
+    }
+    new.field_edits = old.field_edits.clone();
+    let fields = active_edited_fields(old);
+    if fields.is_empty() || old.original_word.is_none() {
+        return;
+    }
+    new.original_word = Some(new.word.clone());
+    for field in &fields {
+        match *field {
+            "instruction_no" => new.word.instruction_no = old.word.instruction_no.clone(),
+            "title" => new.word.title = old.word.title.clone(),
+            "issued_at" => new.word.issued_at = old.word.issued_at.clone(),
+            "content" => new.word.content = old.word.content.clone(),
+            _ => {}
+        }
+    }
+    if fields.contains(&"issued_at") {
+        new.word.normalize_issued_at(today);
+    }
+}
+
+/// 保存人工修正的 Word 字段，之后的导出和检索都使用修正后的值
+#[tauri::command]
+fn update_zip_fields(app: tauri::AppHandle, batch_id: String, zip_id: String, fields: WordFields) -> Result<ZipSummary, String> {
+    let edited_by = current_user_name();
+    update_zip(&app, &batch_id, &zip_id, |z| {
+        let changed = apply_field_edit(z, fields, &edited_by, OffsetDateTime::now_utc());
+        if !changed.is_empty() {
+            log::info!("{} 修改了 {} 的字段: {}", edited_by, z.filename, changed.join(", "));
+        }
+        Ok(())
+    })
+    .map_err(|e| format!("保存字段失败: {:#}", e))?;
+    refresh_previous_instruction(&app, &batch_id, &zip_id).map_err(|e| format!("{e:#}"))?;
+    let batch = refresh_validation_flags(&app, &batch_id).map_err(|e| format!("{e:#}"))?;
+    update_search_index(&app, &batch);
+    record_seen_instructions(&app, &batch_id, &batch.zips);
+    batch.zips.into_iter().find(|z| z.id == zip_id).ok_or_else(|| "ZIP不存在".to_string())
+}
+
+// ==================== 字段人工修改结束 ====================
+
+// ==================== 字段校验 ====================
+
+/// 导入后对抽取字段的校验规则，保存在 AppData/ArchiveBox/validation_rules.json
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct ValidationRules {
+    /// 必填字段（WordFields 的字段名）
+    required_fields: Vec<String>,
+    /// 指令编号格式（正则），为空时不检查
+    instruction_no_pattern: String,
+    /// 文件名中有编号（连续 8 位以上数字）时，指令编号必须包含它
+    match_filename: bool,
+    /// 同一批次中指令编号不能重复
+    unique_in_batch: bool,
+    /// 下发时间早于多少天视为可疑，0 表示不检查
+    max_age_days: u32,
+    /// 导出 Excel 时增加"校验问题"列
+    excel_column: bool,
+    /// 图片识别文本中出现这些关键词时提示
+    ocr_keywords: Vec<String>,
+}
+
+impl Default for ValidationRules {
+    fn default() -> Self {
+        ValidationRules {
+            required_fields: vec!["instruction_no".to_string(), "title".to_string(), "issued_at".to_string()],
+            instruction_no_pattern: r"^\d{12}-ZL\d+$".to_string(),
+            match_filename: true,
+            unique_in_batch: true,
+            max_age_days: 0,
+            excel_column: false,
+            ocr_keywords: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ValidationFlagKind {
+    MissingField,
+    InvalidNumber,
+    MismatchedNumber,
+    DuplicateNumber,
+    SuspiciousDate,
+    OcrKeyword,
+}
+
+/// 校验发现的一个问题
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ValidationFlag {
+    kind: ValidationFlagKind,
+    /// 相关字段（WordFields 的字段名）
+    field: String,
+    message: String,
+}
+
+impl ValidationFlag {
+    fn new(kind: ValidationFlagKind, field: &str, message: String) -> Self {
+        Self { kind, field: field.to_string(), message }
+    }
+}
+
+/// 下发时间允许比当前时间晚的范围，避免时区差异误报
+const ISSUED_AT_FUTURE_TOLERANCE: time::Duration = time::Duration::days(1);
+
+static RE_FILENAME_NUMBER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d{8,}").expect("valid regex"));
+
+fn validation_rules_path(app: &tauri::AppHandle) -> Result<PathBuf> {
+    Ok(app_data_dir(app)?.join("validation_rules.json"))
+}
+
+fn load_validation_rules(app: &tauri::AppHandle) -> ValidationRules {
+    validation_rules_path(app)
+        .and_then(|path| Ok(fs::read(path)?))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+fn word_field<'a>(word: &'a WordFields, field: &str) -> Option<&'a str> {
+    match field {
+        "instruction_no" => Some(&word.instruction_no),
+        "title" => Some(&word.title),
+        "issued_at" => Some(&word.issued_at),
+        "content" => Some(&word.content),
+        _ => None,
+    }
+}
+
+fn word_field_label(field: &str) -> &'static str {
+    match field {
+        "instruction_no" => "指令编号",
+        "title" => "指令标题",
+        "issued_at" => "下发时间",
+        "content" => "指令内容",
+        _ => "字段",
+    }
+}
+
+/// 单个 ZIP 的校验（不含批次内重复）
+fn validate_zip(z: &ZipSummary, rules: &ValidationRules, pattern: Option<&Regex>, now: OffsetDateTime) -> Vec<ValidationFlag> {
+    let mut flags = Vec::new();
+    for field in &rules.required_fields {
+        if word_field(&z.word, field).is_some_and(|v| v.trim().is_empty()) {
+            flags.push(ValidationFlag::new(
+                ValidationFlagKind::MissingField,
+                field,
+                format!("缺少{}", word_field_label(field)),
+            ));
+        }
+    }
+
+    let number = z.word.instruction_no.trim();
+    if !number.is_empty() {
+        if pattern.is_some_and(|re| !re.is_match(number)) {
+            flags.push(ValidationFlag::new(
+                ValidationFlagKind::InvalidNumber,
+                "instruction_no",
+                format!("指令编号格式不符: {}", number),
+            ));
+        }
+        if rules.match_filename {
+            let stem = Path::new(&z.filename).file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            if let Some(m) = RE_FILENAME_NUMBER.find(stem).filter(|m| !number.contains(m.as_str())) {
+                flags.push(ValidationFlag::new(
+                    ValidationFlagKind::MismatchedNumber,
+                    "instruction_no",
+                    format!("指令编号与文件名中的 {} 不一致", m.as_str()),
+                ));
+            }
+        }
+    }
+
+    if !z.word.issued_at.trim().is_empty() {
+        match issued_at_sort_key(&z.word) {
+            None => flags.push(ValidationFlag::new(
+                ValidationFlagKind::SuspiciousDate,
+                "issued_at",
+                "下发时间无法识别".to_string(),
+            )),
+            // 下发时间按原文的本地时间理解，与当前时间比较前不做时区换算
+            Some(t) if t > now + ISSUED_AT_FUTURE_TOLERANCE => flags.push(ValidationFlag::new(
+                ValidationFlagKind::SuspiciousDate,
+                "issued_at",
+                "下发时间晚于当前时间".to_string(),
+            )),
+            Some(t) if rules.max_age_days > 0 && t < now - time::Duration::days(rules.max_age_days as i64) => {
+                flags.push(ValidationFlag::new(
+                    ValidationFlagKind::SuspiciousDate,
+                    "issued_at",
+                    format!("下发时间早于{}天前", rules.max_age_days),
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    let matched: Vec<&str> = rules
+        .ocr_keywords
+        .iter()
+        .map(|k| k.as_str())
+        .filter(|k| z.ocr_texts.iter().any(|o| o.text.contains(k)))
+        .collect();
+    if !matched.is_empty() {
+        flags.push(ValidationFlag::new(
+            ValidationFlagKind::OcrKeyword,
+            "ocr_texts",
+            format!("图片文字包含关键词: {}", matched.join("、")),
+        ));
+    }
+    flags
+}
+
+/// 校验批次内全部 ZIP，返回与 zips 一一对应的问题列表；导入失败的 ZIP 不校验
+fn validate_zips(zips: &[ZipSummary], rules: &ValidationRules, now: OffsetDateTime) -> Vec<Vec<ValidationFlag>> {
+    // 格式写错时只记录日志，不影响其余规则
+    let pattern = match rules.instruction_no_pattern.trim() {
+        "" => None,
+        p => Regex::new(p).map_err(|e| log::warn!("指令编号格式无效: {}", e)).ok(),
+    };
+    let mut counts: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    for z in zips.iter().filter(|z| z.status != "failed") {
+        let number = z.word.instruction_no.trim();
+        if !number.is_empty() {
+            *counts.entry(number.to_string()).or_default() += 1;
+        }
+    }
+
+    zips.iter()
+        .map(|z| {
+            if z.status == "failed" {
+                return vec![];
+            }
+            let mut flags = validate_zip(z, rules, pattern.as_ref(), now);
+            let count = counts.get(z.word.instruction_no.trim()).copied().unwrap_or(0);
+            if rules.unique_in_batch && count > 1 {
+                flags.push(ValidationFlag::new(
+                    ValidationFlagKind::DuplicateNumber,
+                    "instruction_no",
+                    format!("批次中有{}个相同的指令编号", count),
+                ));
+            }
+            flags
+        })
+        .collect()
+}
+
+/// 按当前规则重新校验批次，保存有变化的 ZIP；返回更新后的批次
+fn refresh_validation_flags(app: &tauri::AppHandle, batch_id: &str) -> Result<BatchSummary> {
+    let rules = load_validation_rules(app);
+    let mut batch = read_batch(app, batch_id)?;
+    let flags = validate_zips(&batch.zips, &rules, OffsetDateTime::now_utc());
+    let mut changed = Vec::new();
+    for (z, flags) in batch.zips.iter_mut().zip(flags) {
+        if z.flags != flags {
+            changed.push((z.id.clone(), flags.clone()));
+            z.flags = flags;
+        }
+    }
+    if !changed.is_empty() {
+        Catalog::open(app)?.update_flags(batch_id, &changed)?;
+    }
+    Ok(batch)
+}
+
+/// Excel "校验问题"列的内容
+fn validation_flags_text(z: &ZipSummary) -> String {
+    z.flags.iter().map(|f| f.message.as_str()).collect::<Vec<_>>().join("；")
+}
+
+#[tauri::command]
+fn get_validation_rules(app: tauri::AppHandle) -> ValidationRules {
+    load_validation_rules(&app)
+}
+
+/// 去掉空白和重复的关键词，保留用户填写的顺序
+fn normalize_ocr_keywords(keywords: &[String]) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    keywords
+        .iter()
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty() && seen.insert(k.clone()))
+        .collect()
+}
+
+#[tauri::command]
+fn set_validation_rules(app: tauri::AppHandle, rules: ValidationRules) -> Result<ValidationRules, String> {
+    let mut rules = rules;
+    rules.instruction_no_pattern = rules.instruction_no_pattern.trim().to_string();
+    if !rules.instruction_no_pattern.is_empty() {
+        Regex::new(&rules.instruction_no_pattern).map_err(|e| format!("指令编号格式无效: {}", e))?;
+    }
+    rules.required_fields.retain(|f| word_field(&WordFields::default(), f).is_some());
+    rules.ocr_keywords = normalize_ocr_keywords(&rules.ocr_keywords);
+    let path = validation_rules_path(&app).map_err(err_to_string)?;
+    fs::write(path, serde_json::to_vec_pretty(&rules).map_err(err_to_string)?).map_err(err_to_string)?;
+    Ok(rules)
+}
+
+/// 按当前规则重新校验批次（修改规则后使用）
+#[tauri::command]
+fn revalidate_batch_fields(app: tauri::AppHandle, batch_id: String) -> Result<BatchSummary, String> {
+    refresh_validation_flags(&app, &batch_id).map_err(|e| format!("{e:#}"))
+}
+
+// ==================== 字段校验结束 ====================
+
+// ==================== 跨批次重复指令 ====================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DuplicateMatch {
+    InstructionNo,
+    Content,
+}
+
+/// 导入时发现之前已处理过的相同指令
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PreviousInstruction {
+    batch_id: String,
+    zip_id: String,
+    imported_at: i64,
+    exported_at: Option<i64>,
+    matched_by: DuplicateMatch,
+}
+
+/// 指令标题和内容去掉空白后的 FNV-1a 哈希，用于识别编号不同但内容相同的指令；没有指令内容时为空，只有标题不足以判断相同
+fn instruction_content_hash(word: &WordFields) -> String {
+    if word.content.trim().is_empty() {
+        return String::new();
+    }
+    let text: String = word.title.chars().chain(word.content.chars()).filter(|c| !c.is_whitespace()).collect();
+    if text.is_empty() {
+        return String::new();
+    }
+    let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3));
+    format!("{:016x}", hash)
+}
+
+/// 给新导入的 ZIP 标出其他批次中已处理过的相同指令
+fn mark_previous_instructions(catalog: &Catalog, batch_id: &str, zips: &mut [ZipSummary]) -> Result<()> {
+    for z in zips.iter_mut().filter(|z| z.status != "failed") {
+        z.duplicate_of = catalog.previous_instruction(batch_id, z)?;
+    }
+    Ok(())
+}
+
+/// 字段改动后重新查找其他批次中的相同指令
+fn refresh_previous_instruction(app: &tauri::AppHandle, batch_id: &str, zip_id: &str) -> Result<()> {
+    // 另开一个连接查询，update_zip 的写事务不影响读取（WAL）
+    let lookup = Catalog::open(app)?;
+    update_zip(app, batch_id, zip_id, |z| mark_previous_instructions(&lookup, batch_id, std::slice::from_mut(z)))
+}
+
+/// 更新指令索引；失败只记录日志，不影响导入等主流程
+fn record_seen_instructions(app: &tauri::AppHandle, batch_id: &str, zips: &[ZipSummary]) {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    if let Err(e) = Catalog::open(app).and_then(|catalog| catalog.record_instructions(batch_id, zips, now)) {
+        log::warn!("更新指令索引失败: {:#}", e);
+    }
+}
+
+// ==================== 跨批次重复指令结束 ====================
+
+fn build_summary_docx(
+    batch: &BatchSummary,
+    groups: &[ExportGroup],
+    style: &SummaryDocStyle,
+) -> Result<Vec<u8>> {
+    let mut docx = new_summary_docx(batch, style, !groups.is_empty());
+    let mut numbering = SectionNumbering::new(style, groups);
+    let mut rich_bodies = Vec::new();
+
+    for (zip_idx, z) in batch.zips.iter().enumerate() {
+        docx = add_section_headings(docx, z, group_heading_at(groups, zip_idx), &mut numbering, style);
+        let zip_folder = format!("attachments/{}/", z.id);
+        docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
+            "指令编号:  {}",
+            z.word.instruction_no
+        ))));
+        docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
+            "下发时间:  {}",
+            z.word.issued_at
+        ))));
+        let main_rich = if z.word.content.trim().is_empty() { None } else { main_docx_rich_body(z) };
+        if !z.word.content.trim().is_empty() {
+            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(numbering.item("指令内容"))));
+        }
+        if let Some(rich) = main_rich {
+            docx = docx.add_paragraph(hidden_marker_paragraph(&rich.marker));
+            rich_bodies.push(rich);
+        } else if !z.word.content.trim().is_empty() {
+            // 将指令内容按换行符分割，创建多个段落
+            for line in z.word.content.lines() {
+                let trimmed_line = line.trim();
+                if !trimmed_line.is_empty() {
+                    docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(trimmed_line)));
+                } else {
+                    // 空行创建空段落（段落间距）
+                    docx = docx.add_paragraph(Paragraph::new());
+                }
+            }
+        }
+
+        // 直接显示图片和PDF截图，删除"图片"、"PDF页面截图:"标题
+        let mut images = Vec::new();
+        for img_path in &z.image_files {
+            let bytes = fs::read(img_path)
+                .with_context(|| format!("读取图片失败: {}", img_path))?;
+            images.push((img_path.clone(), prepare_image(&bytes, &style.image_settings)?));
+        }
+        for img_path in &z.pdf_page_screenshot_files {
+            let bytes = fs::read(img_path)
+                .with_context(|| format!("读取PDF页面截图失败: {}", img_path))?;
+            images.push((img_path.clone(), prepare_image(&bytes, &style.image_settings)?));
+        }
+        docx = add_images(docx, &images, &z.ocr_texts, style);
+
+        docx = docx.add_paragraph(
+            Paragraph::new().add_run(Run::new().add_text(numbering.item("附件清单")).bold()),
+        );
+
+        // 仅提供“本ZIP附件文件夹”链接
+        let folder_link = Hyperlink::new(&zip_folder, HyperlinkType::External)
+            .add_run(Run::new().add_text(zip_folder.clone()).style("Hyperlink"));
+        docx = docx.add_paragraph(
+            Paragraph::new()
+                .add_run(Run::new().add_text("附件目录："))
+                .add_hyperlink(folder_link),
+        );
+
+        for video in &z.video_files {
+            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
+                "- {}",
+                safe_basename(video)
+            ))));
+        }
+        if z.include_original_zip {
+            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(format!(
+                "- {}",
+                original_zip_name(z)
+            ))));
+        }
+        // 图片的拍摄信息；导出时去除元数据则不列出位置
+        let metadata_lines = zip_image_metadata_lines(z, !style.image_settings.strip_metadata);
+        for line in &metadata_lines {
+            docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(line)));
+        }
+        if z.video_files.is_empty() && !z.include_original_zip && metadata_lines.is_empty() {
+            docx = docx
+                .add_paragraph(Paragraph::new().add_run(Run::new().add_text("- （无）")));
+        }
+    }
+
+    let mut out = Cursor::new(Vec::<u8>::new());
+    docx.build()
+        .pack(&mut out)
+        .map_err(|e| anyhow!("docx生成失败: {e:?}"))?;
+    merge_rich_bodies(fix_docx_media_extensions(out.into_inner())?, &rich_bodies)
+}
+
+fn build_bundle_zip_bytes(batch: &BatchSummary, docx_bytes: &[u8], strip_metadata: bool) -> Result<Vec<u8>> {
+    let file_options = FileOptions::default();
+    let dir_options = FileOptions::default();
+
+    let mut out = Cursor::new(Vec::<u8>::new());
+    {
+        let mut writer = ZipWriter::new(&mut out);
+
+        writer.start_file("汇总文档.docx", file_options)?;
+        writer.write_all(docx_bytes)?;
+
+        writer.add_directory("attachments/", dir_options)?;
+
+        for z in &batch.zips {
+            let zip_dir = format!("attachments/{}/", z.id);
+            writer.add_directory(&zip_dir, dir_options)?;
+
+            if z.include_original_zip {
+                if let Some(zip_bytes) = original_zip_bytes(z, strip_metadata)? {
+                    writer.start_file(format!("{zip_dir}{}", original_zip_name(z)), file_options)?;
+                    writer.write_all(&zip_bytes)?;
+                }
+            }
+
+            for video_path in &z.video_files {
+                let bytes = fs::read(video_path)
+                    .with_context(|| format!("读取视频失败: {}", video_path))?;
+                writer.start_file(
+                    format!("{zip_dir}{}", safe_basename(video_path)),
+                    file_options,
+                )?;
+                writer.write_all(&bytes)?;
+            }
+
+            for pdf_path in &z.pdf_files {
+                let bytes = fs::read(pdf_path)
+                    .with_context(|| format!("读取PDF失败: {}", pdf_path))?;
+                writer.start_file(format!("{zip_dir}{}", safe_basename(pdf_path)), file_options)?;
+                writer.write_all(&bytes)?;
+            }
+        }
+
+        writer.finish()?;
+    } // writer 在这里被 drop，释放对 out 的借用
+
+    Ok(out.into_inner())
+}
+
+fn safe_basename(name: &str) -> String {
+    let n = name.replace('\\', "/");
+    n.rsplit('/').next().unwrap_or(&n).to_string()
+}
+
+fn err_to_string<E: std::fmt::Display>(e: E) -> String {
+    e.to_string()
+}
+
+#[tauri::command]
+fn open_path(path: String) -> Result<(), String> {
+    let p = PathBuf::from(path);
+    if !p.exists() {
+        return Err("路径不存在".to_string());
+    }
+    open_in_os(&p).map_err(err_to_string)?;
+    Ok(())
+}
+
+fn open_in_os(path: &Path) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    {
+        let status = Command::new("open").arg(path).status()?;
+        if !status.success() {
+            return Err(anyhow!("open 返回非0状态码"));
+        }
+        return Ok(());
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        // Windows上使用explorer打开文件或文件夹
+        // 如果是文件，使用 /select 参数在资源管理器中选中它
+        // 如果是文件夹，直接打开
+        if path.is_file() {
+            // /select 参数格式: explorer /select,"C:\path\to\file"
+            let path_str = path.to_str().unwrap_or("");
+            let select_arg = format!("/select,{}", path_str);
+            let status = Command::new("explorer")
+                .arg(select_arg)
+                .status()?;
+            if !status.success() {
+                return Err(anyhow!("explorer 返回非0状态码"));
+            }
+        } else {
+            let status = Command::new("explorer")
+                .arg(path)
+                .status()?;
+            if !status.success() {
+                return Err(anyhow!("explorer 返回非0状态码"));
+            }
+        }
+        return Ok(());
+    }
+
+    #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
+    {
+        let status = Command::new("xdg-open").arg(path).status()?;
+        if !status.success() {
+            return Err(anyhow!("xdg-open 返回非0状态码"));
+        }
+        return Ok(());
+    }
+}
+
+#[tauri::command]
+fn get_preview_image_data(
+    app: tauri::AppHandle,
+    batch_id: String,
+    zip_id: String,
+    index: usize,
+) -> Result<String, String> {
+    let batch: BatchSummary = read_batch(&app, &batch_id).map_err(err_to_string)?;
+    let z = batch
+        .zips
+        .iter()
+        .find(|z| z.id == zip_id)
+        .ok_or_else(|| "ZIP不存在".to_string())?;
+    let path = z
+        .image_files
+        .get(index)
+        .ok_or_else(|| "图片索引越界".to_string())?;
+    let bytes = fs::read(path).map_err(err_to_string)?;
+    let mime = guess_image_mime(path);
+    let b64 = base64_encode(&bytes);
+    Ok(format!("data:{mime};base64,{b64}"))
+}
+
+#[tauri::command]
+fn save_pdf_page_screenshots(
+    app: tauri::AppHandle,
+    batch_id: String,
+    zip_id: String,
+    pdf_name: String,
+    screenshots: Vec<String>,
+) -> Result<Vec<String>, String> {
+    if screenshots.is_empty() {
+        return Err("没有提供截图数据".to_string());
+    }
+    
+    let batch: BatchSummary = read_batch(&app, &batch_id).map_err(err_to_string)?;
+
+    let zip = batch
+        .zips
+        .iter()
+        .find(|z| z.id == zip_id)
+        .ok_or_else(|| "ZIP不存在".to_string())?;
+
+    let out_dir = batch_dir(&app, &batch_id)
+        .map_err(err_to_string)?
+        .join("zips")
+        .join(&zip.id)
+        .join("extracted")
+        .join("pdf_screens")
+        .join(sanitize_file_stem(&pdf_name));
+    
+    // 确保目录存在
+    fs::create_dir_all(&out_dir).map_err(|e| format!("创建目录失败: {}", e))?;
+
+    let mut saved = Vec::new();
+    let mut failed_count = 0;
+    
+    for (i, s) in screenshots.iter().enumerate() {
+        match decode_data_url_base64(s) {
+            Ok(bytes) => {
+                if bytes.is_empty() {
+                    log::warn!("第{}页截图数据为空", i + 1);
+                    failed_count += 1;
+                    continue;
+                }
+                
+                let out_path = out_dir.join(format!("page_{:03}.png", i + 1));
+                match fs::write(&out_path, bytes) {
+                    Ok(_) => {
+                        saved.push(out_path.to_string_lossy().to_string());
+                    }
+                    Err(e) => {
+                        log::warn!("保存第{}页截图失败: {}", i + 1, e);
+                        failed_count += 1;
+                    }
+                }
+            }
+            Err(e) => {
+                log::warn!("解码第{}页截图数据失败: {}", i + 1, e);
+                failed_count += 1;
+            }
+        }
+    }
+    
+    if saved.is_empty() {
+        return Err(format!("所有截图保存失败，共{}个截图", screenshots.len()));
+    }
+    
+    // 保存批次信息（事务内追加，多个PDF同时保存截图时不会互相覆盖）
+    update_zip(&app, &batch_id, &zip_id, |zip| {
+        zip.pdf_page_screenshot_files.extend(saved.iter().cloned());
+        Ok(())
+    })
+    .map_err(|e| format!("保存批次信息失败: {:#}", e))?;
+
+    if failed_count > 0 {
+        log::info!("PDF截图保存完成: 成功{}个，失败{}个", saved.len(), failed_count);
+    }
+    
+    Ok(saved)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ExcelPreviewData {
+    sheet_name: String,
+    rows: Vec<Vec<String>>,
+    total_sheets: usize,
+    sheet_names: Vec<String>,
+}
+
+fn read_excel_preview(excel_path: &Path) -> Result<ExcelPreviewData> {
+    let extension = excel_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    log::debug!("正在读取Excel文件: {:?}, 扩展名: {}", excel_path, extension);
+
+    if extension == "xlsx" {
+        let mut workbook = calamine::open_workbook::<Xlsx<_>, _>(excel_path)
+            .map_err(|e| anyhow!("打开xlsx文件失败: {}", e))?;
+
+        // Use the trait methods
+        use calamine::Reader;
+        let sheet_names = workbook.sheet_names();
+        let first_sheet_name = sheet_names
+            .first()
+            .ok_or_else(|| anyhow!("Excel文件没有工作表"))?
+            .to_string();
+
+        log::debug!("工作表名称: {:?}", sheet_names);
+
+        // 尝试读取第一个工作表的实际数据
+        let mut rows = Vec::new();
+        if let Ok(range) = workbook.worksheet_range(&first_sheet_name) {
+            // 限制读取前10行和前10列，避免数据过多
+            for row in range.rows().take(10) {
+                let mut row_data = Vec::new();
+                for cell in row.iter().take(10) {
+                    let value_str = match cell {
+                        calamine::Data::String(s) => s.to_string(),
+                        calamine::Data::Float(f) => f.to_string(),
+                        calamine::Data::Int(i) => i.to_string(),
+                        calamine::Data::Bool(b) => b.to_string(),
+                        calamine::Data::Empty => String::new(),
+                        _ => "【数据】".to_string(),
+                    };
+                    row_data.push(value_str);
+                }
+                rows.push(row_data);
+            }
+        } else {
+            // 如果无法读取数据，返回错误信息
+            return Err(anyhow!("无法读取Excel工作表数据: {}", first_sheet_name));
+        }
+
+        if rows.is_empty() {
+            // 如果没有数据，至少返回表头
+            rows = vec![
+                vec!["工作表".to_string(), first_sheet_name.to_string(), "".to_string()],
+                vec!["状态".to_string(), "无数据".to_string(), "".to_string()],
+            ];
+        }
+
+        Ok(ExcelPreviewData {
+            sheet_name: first_sheet_name,
+            rows,
+            total_sheets: sheet_names.len(),
+            sheet_names,
+        })
+    } else if extension == "xls" {
+        let mut workbook = calamine::open_workbook::<Xls<_>, _>(excel_path)
+            .map_err(|e| anyhow!("打开xls文件失败: {}", e))?;
+
+        // Use the trait methods
+        let sheet_names = workbook.sheet_names();
+        let first_sheet_name = sheet_names
+            .first()
+            .ok_or_else(|| anyhow!("Excel文件没有工作表"))?
+            .to_string();
+
+        log::debug!("工作表名称: {:?}", sheet_names);
+
+        // 尝试读取第一个工作表的实际数据
+        let mut rows = Vec::new();
+        if let Ok(range) = workbook.worksheet_range(&first_sheet_name) {
+            // 限制读取前10行和前10列，避免数据过多
+            for row in range.rows().take(10) {
+                let mut row_data = Vec::new();
+                for cell in row.iter().take(10) {
+                    let value_str = match cell {
+                        calamine::Data::String(s) => s.to_string(),
+                        calamine::Data::Float(f) => f.to_string(),
+                        calamine::Data::Int(i) => i.to_string(),
+                        calamine::Data::Bool(b) => b.to_string(),
+                        calamine::Data::Empty => String::new(),
+                        _ => "【数据】".to_string(),
+                    };
+                    row_data.push(value_str);
+                }
+                rows.push(row_data);
+            }
+        } else {
+            // 如果无法读取数据，返回错误信息
+            return Err(anyhow!("无法读取Excel工作表数据: {}", first_sheet_name));
+        }
+
+        if rows.is_empty() {
+            // 如果没有数据，至少返回表头
+            rows = vec![
+                vec!["工作表".to_string(), first_sheet_name.to_string(), "".to_string()],
+                vec!["状态".to_string(), "无数据".to_string(), "".to_string()],
+            ];
+        }
+
+        Ok(ExcelPreviewData {
+            sheet_name: first_sheet_name,
+            rows,
+            total_sheets: sheet_names.len(),
+            sheet_names,
+        })
+    } else {
+        Err(anyhow!("不支持的Excel格式: {}", extension))
+    }
+}
+
+
+#[tauri::command]
+fn get_excel_preview_data(
+    app: tauri::AppHandle,
+    batch_id: String,
+    zip_id: String,
+    index: usize,
+) -> Result<ExcelPreviewData, String> {
+    let batch: BatchSummary = read_batch(&app, &batch_id).map_err(err_to_string)?;
+    let z = batch
+        .zips
+        .iter()
+        .find(|z| z.id == zip_id)
+        .ok_or_else(|| "ZIP不存在".to_string())?;
+    let path = z
+        .excel_files
+        .get(index)
+        .ok_or_else(|| "Excel文件索引越界".to_string())?;
+
+    read_excel_preview(Path::new(path)).map_err(err_to_string)
+}
+
+fn sanitize_file_stem(name: &str) -> String {
+    let base = Path::new(name)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("pdf");
+    base.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+}
+
+fn decode_data_url_base64(data_url: &str) -> Result<Vec<u8>> {
+    let (meta, b64) = data_url
+        .split_once(',')
+        .ok_or_else(|| anyhow!("data url格式不正确"))?;
+    if !meta.contains(";base64") {
+        return Err(anyhow!("data url不是base64格式"));
+    }
+    base64_decode(b64)
+}
+
+fn base64_decode(s: &str) -> Result<Vec<u8>> {
+    fn val(c: u8) -> Option<u8> {
+        match c {
+            b'A'..=b'Z' => Some(c - b'A'),
+            b'a'..=b'z' => Some(c - b'a' + 26),
+            b'0'..=b'9' => Some(c - b'0' + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity((bytes.len() / 4) * 3);
+    let mut buf = [0u8; 4];
+    let mut n = 0usize;
+
+    for &b in bytes {
+        if b == b'=' {
+            buf[n] = 64;
+            n += 1;
+        } else if let Some(v) = val(b) {
+            buf[n] = v;
+            n += 1;
+        } else if b == b'\n' || b == b'\r' || b == b' ' || b == b'\t' {
+            continue;
+        } else {
+            return Err(anyhow!("base64包含非法字符"));
+        }
+
+        if n == 4 {
+            out.push((buf[0] << 2) | (buf[1] >> 4));
+            if buf[2] != 64 {
+                out.push((buf[1] << 4) | (buf[2] >> 2));
+            }
+            if buf[3] != 64 {
+                out.push((buf[2] << 6) | buf[3]);
+            }
+            n = 0;
+        }
+    }
+
+    Ok(out)
+}
+
+fn guess_image_mime(path: &str) -> &'static str {
+    let lower = path.to_ascii_lowercase();
+    if lower.ends_with(".png") {
+        "image/png"
+    } else if lower.ends_with(".jpg") || lower.ends_with(".jpeg") {
+        "image/jpeg"
+    } else if lower.ends_with(".gif") {
+        "image/gif"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(((bytes.len() + 2) / 3) * 4);
+    let mut i = 0usize;
+    while i + 3 <= bytes.len() {
+        let b0 = bytes[i];
+        let b1 = bytes[i + 1];
+        let b2 = bytes[i + 2];
+        out.push(TABLE[(b0 >> 2) as usize] as char);
+        out.push(TABLE[(((b0 & 0b0000_0011) << 4) | (b1 >> 4)) as usize] as char);
+        out.push(TABLE[(((b1 & 0b0000_1111) << 2) | (b2 >> 6)) as usize] as char);
+        out.push(TABLE[(b2 & 0b0011_1111) as usize] as char);
+        i += 3;
+    }
+
+    let rem = bytes.len() - i;
+    if rem == 1 {
+        let b0 = bytes[i];
+        out.push(TABLE[(b0 >> 2) as usize] as char);
+        out.push(TABLE[((b0 & 0b0000_0011) << 4) as usize] as char);
+        out.push('=');
+        out.push('=');
+    } else if rem == 2 {
+        let b0 = bytes[i];
+        let b1 = bytes[i + 1];
+        out.push(TABLE[(b0 >> 2) as usize] as char);
+        out.push(TABLE[(((b0 & 0b0000_0011) << 4) | (b1 >> 4)) as usize] as char);
+        out.push(TABLE[((b1 & 0b0000_1111) << 2) as usize] as char);
+        out.push('=');
+    }
+
+    out
+}
+
+// 新增：带文件嵌入的Word导出命令
+#[tauri::command]
+fn export_bundle_zip_with_embeddings(
+    app: tauri::AppHandle,
+    batch_id: String,
+    embed_files: bool,
+    max_file_size_mb: Option<u64>,
+    allowed_types: Option<Vec<String>>,
+) -> Result<String, String> {
+    let mut batch: BatchSummary = read_batch(&app, &batch_id).map_err(err_to_string)?;
+
+    // 按下发时间排序
+    sort_zips_by_issued_at(&mut batch.zips);
+
+    let now = OffsetDateTime::now_utc();
+    let out = prompt_save_path(default_export_bundle_name(now), "zip", "ZIP")?;
+
+    // 创建嵌入配置
+    let mut config = EmbeddingConfig::default();
+    if embed_files {
+        config.enabled = true;
+        if let Some(size_mb) = max_file_size_mb {
+            config.max_file_size = (size_mb * 1024 * 1024) as usize;
+        }
+        if let Some(types) = allowed_types {
+            config.allowed_types = types;
+        }
+    } else {
+        config.enabled = false;
+    }
+
+    // 使用增强的导出功能（同步执行，只发送进度事件）
+    let ctx = JobContext::detached(Some(&app), JobKind::ExportWord, WORD_JOB_STEPS);
+    let (docx, embedded_files, rich_bodies) = build_enhanced_summary_docx(&batch, &[], &SummaryDocStyle::default(), embed_files, &ctx, 0).map_err(err_to_string)?;
+    let docx_bytes = build_docx_with_embeddings(docx, &rich_bodies, &embedded_files).map_err(err_to_string)?;
+    let bundle_bytes = build_bundle_zip_bytes(&batch, &docx_bytes, false).map_err(err_to_string)?;
+
+    fs::write(&out, bundle_bytes).map_err(err_to_string)?;
+    Ok(out.to_string_lossy().to_string())
+}
+
+// 新增：获取嵌入配置
+#[tauri::command]
+fn get_embedding_config() -> EmbeddingConfig {
+    EmbeddingConfig::default()
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .manage(AppState::default())
+        .invoke_handler(tauri::generate_handler![
+            pick_zip_files,
+            pick_import_folders,
+            import_zips,
+            export_excel,
+            export_excel_with_selection,
+            export_bundle_zip,
+            export_bundle_zip_with_selection,
+            export_bundle_zip_with_embeddings,
+            get_embedding_config,
+            open_path,
+            get_preview_image_data,
+            get_excel_preview_data,
+            save_pdf_page_screenshots,
+            pick_docx_template,
+            ocr_available,
+            run_ocr,
+            search,
+            load_batch,
+            get_saved_selection,
+            validate_batch,
+            cancel_job,
+            list_jobs,
+            export_import_report,
+            retry_failed_zips,
+            set_main_document,
+            update_zip_fields,
+            get_validation_rules,
+            set_validation_rules,
+            revalidate_batch_fields,
+            get_job,
+            pause_job,
+            resume_job,
+            get_log_level,
+            set_log_level,
+            collect_diagnostics,
+            get_watch_settings,
+            set_watch_settings,
+            pick_watch_folder,
+            cleanup_temp_files,
+            cleanup_old_batches
+        ])
+        .setup(|app| {
+            app.handle().plugin(log_plugin(app.handle())?)?;
+            let level = load_log_settings(app.handle()).level;
+            log::set_max_level(parse_log_level(&level)?);
+            log::info!("ArchiveBox {} 启动，日志级别 {}", env!("CARGO_PKG_VERSION"), level);
+
+            app.state::<AppState>().watch.replace(load_watch_settings(app.handle()));
+            let handle = app.handle().clone();
+            std::thread::spawn(move || run_folder_watcher(handle));
+            let handle = app.handle().clone();
+            std::thread::spawn(move || {
+                if let Err(e) = Catalog::open(&handle).and_then(|catalog| index_missing_batches(&handle, &catalog)) {
+                    log::warn!("补建检索索引失败: {:#}", e);
+                }
+            });
+            Ok(())
+        })
+        .on_window_event(|window, event| {
+            match event {
+                tauri::WindowEvent::CloseRequested { .. } => {
+                    // 应用关闭时清理临时文件
+                    if let Err(e) = cleanup_temp_files_on_exit(window.app_handle()) {
+                        log::warn!("清理临时文件失败: {}", e);
+                    }
+                }
+                _ => {}
+            }
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+// ==================== 临时文件清理功能 ====================
+
+/// 应用关闭时清理临时文件
+fn cleanup_temp_files_on_exit(app: &tauri::AppHandle) -> Result<()> {
+    log::info!("应用关闭，开始清理临时文件...");
+    
+    // 清理所有批次文件
+    if let Ok(app_data) = app_data_dir(app) {
+        let batches_dir = app_data.join("batches");
+        if batches_dir.exists() {
+            match fs::remove_dir_all(&batches_dir) {
+                Ok(_) => log::info!("已清理所有批次文件: {}", batches_dir.display()),
+                Err(e) => log::warn!("清理批次文件失败: {}", e),
+            }
+        }
+    }
+    if let Err(e) = Catalog::open(app).and_then(|catalog| catalog.delete_all_batches()) {
+        log::warn!("清理批次目录失败: {:#}", e);
+    }
+    
+    Ok(())
+}
+
+/// 手动清理临时文件的命令
+#[tauri::command]
+fn cleanup_temp_files(app: tauri::AppHandle) -> Result<String, String> {
+    let app_data = app_data_dir(&app).map_err(err_to_string)?;
+    let batches_dir = app_data.join("batches");
+    Catalog::open(&app)
+        .and_then(|catalog| catalog.delete_all_batches())
+        .map_err(err_to_string)?;
+    
+    if !batches_dir.exists() {
+        return Ok("没有找到需要清理的临时文件".to_string());
+    }
+    
+    // 计算清理前的大小
+    let size_before = calculate_dir_size(&batches_dir).unwrap_or(0);
+    let size_mb = size_before as f64 / 1024.0 / 1024.0;
+    
+    // 清理所有批次文件
+    fs::remove_dir_all(&batches_dir).map_err(err_to_string)?;
+    
+    Ok(format!("已清理临时文件，释放空间: {:.1} MB", size_mb))
+}
+
+/// 清理旧的批次文件，保留最近的几个
+#[tauri::command]
+fn cleanup_old_batches(app: tauri::AppHandle, keep_count: Option<usize>) -> Result<String, String> {
+    let keep = keep_count.unwrap_or(3);
+    cleanup_old_batches_internal(&app, keep).map_err(err_to_string)?;
+    Ok(format!("已清理旧批次，保留最近 {} 个批次", keep))
+}
+
+/// 内部清理旧批次的实现
+fn cleanup_old_batches_internal(app: &tauri::AppHandle, keep_count: usize) -> Result<()> {
+    let app_data = app_data_dir(app)?;
+    let batches_dir = app_data.join("batches");
+    
+    if !batches_dir.exists() {
+        return Ok(());
+    }
+    
+    // 获取所有批次目录
+    let mut batch_dirs = Vec::new();
+    for entry in fs::read_dir(&batches_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                // 提取时间戳进行排序
+                if let Some(timestamp) = batch_id_timestamp(name) {
+                    batch_dirs.push((timestamp, path));
+                }
+            }
+        }
+    }
+    
+    // 按时间戳排序（最新的在前），同一秒创建的批次按名称排
+    batch_dirs.sort_by(|a, b| b.cmp(a));
+    
+    // 删除超出保留数量的旧批次
+    if batch_dirs.len() > keep_count {
+        let to_remove = &batch_dirs[keep_count..];
+        let mut total_size = 0u64;
+        let catalog = Catalog::open(app)?;
+        
+        for (_timestamp, path) in to_remove {
+            let size = calculate_dir_size(path).unwrap_or(0);
+            total_size += size;
+
+            if let Some(batch_id) = path.file_name().and_then(|n| n.to_str()) {
+                catalog.delete_batch(batch_id)?;
+            }
+            
+            match fs::remove_dir_all(path) {
+                Ok(_) => {
+                    log::info!("已删除旧批次: {} (大小: {:.1} MB)", 
+                            path.display(), 
+                            size as f64 / 1024.0 / 1024.0);
+                }
+                Err(e) => {
+                    log::warn!("删除旧批次失败: {} - {}", path.display(), e);
+                }
+            }
+        }
+        
+        if total_size > 0 {
+            log::info!("清理完成，释放空间: {:.1} MB", total_size as f64 / 1024.0 / 1024.0);
+        }
+    }
+    
+    Ok(())
+}
+
+/// 计算目录大小
+fn calculate_dir_size(dir: &Path) -> Result<u64> {
+    let mut total_size = 0u64;
+    
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            
+            if path.is_dir() {
+                total_size += calculate_dir_size(&path)?;
+            } else {
+                total_size += entry.metadata()?.len();
+            }
+        }
+    }
+    
+    Ok(total_size)
+}
+
+// ==================== 临时文件清理功能结束 ====================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_zip(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .parent()
+            .unwrap()
+            .join("zip_simples")
+            .join(name)
+    }
+
+    #[test]
+    fn parse_fields_from_fixture_zip() {
+        let zip_path = fixture_zip("202512110007-ZL1.zip");
+        let scan = scan_zip(&zip_path).expect("scan_zip");
+        let (fields, _videos) = extract_word_and_videos(&zip_path, &scan).expect("extract");
+        assert!(!fields.instruction_no.is_empty());
+        assert!(!fields.title.is_empty());
+        assert!(!fields.issued_at.is_empty());
+    }
+
+    #[test]
+    fn build_bundle_zip_has_per_zip_attachments_dir_and_docx_links() {
+        let zip_path = fixture_zip("202512110028-ZL1.zip");
+        let scan = scan_zip(&zip_path).expect("scan_zip");
+        let (fields, _videos) = extract_word_and_videos(&zip_path, &scan).expect("extract");
+
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        fs::create_dir_all(&tmp_root).unwrap();
+        let batch_dir = tmp_root.join("batch");
+        fs::create_dir_all(&batch_dir).unwrap();
+
+        let zip_id = Uuid::new_v4().to_string();
+        let stored_zip = tmp_root.join(format!("{}.zip", zip_id));
+        fs::copy(&zip_path, &stored_zip).unwrap();
+
+        let mut zip_summary = ZipSummary {
+            id: zip_id.clone(),
+            filename: "202512110028-ZL1.zip".to_string(),
+            source_path: zip_path.to_string_lossy().to_string(),
+            stored_path: stored_zip.to_string_lossy().to_string(),
+            extracted_dir: String::new(),
+            include_original_zip: true,
+            status: "completed".to_string(),
+            word: fields,
+            main_docx_entry: scan.docx_entry.clone(),
+            main_docx_decision: None,
+            original_word: None,
+            field_edits: vec![],
+            flags: vec![],
+            duplicate_of: None,
+            additional_docx_files: vec![],
+            has_video: !scan.video_entries.is_empty(),
+            has_sample: scan.has_sample,
+            video_entries: vec![],
+            video_files: vec![],
+            image_files: vec![],
+            pdf_files: vec![],
+            pdf_page_screenshot_files: vec![],
+            excel_files: vec![],
+            image_metadata: vec![],
+            ocr_texts: vec![],
+            diagnostics: vec![],
+            mail: None,
+        };
+
+        extract_preview_files(&batch_dir, &zip_id, &stored_zip, &scan, &mut zip_summary)
+            .expect("extract_preview_files");
+
+        let batch = BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "batch_test".to_string(),
+            created_at: OffsetDateTime::now_utc().unix_timestamp(),
+            zips: vec![zip_summary.clone()],
+        };
+
+        let docx_bytes = build_summary_docx(&batch, &[], &SummaryDocStyle::default()).expect("build_summary_docx");
+        assert!(!docx_bytes.is_empty());
+
+        // docx 内应有指向 attachments/<zipId>/ 的链接关系
+        let mut docx_zip = ZipArchive::new(Cursor::new(&docx_bytes)).expect("docx zip");
+        let mut rels = String::new();
+        docx_zip
+            .by_name("word/_rels/document.xml.rels")
+            .expect("rels exists")
+            .read_to_string(&mut rels)
+            .unwrap();
+        assert!(
+            rels.contains(&format!("attachments/{}/", zip_id)),
+            "rels should contain per-zip attachments link"
+        );
+
+        let bundle = build_bundle_zip_bytes(&batch, &docx_bytes, false).expect("bundle");
+        let mut out_zip = ZipArchive::new(Cursor::new(bundle)).expect("bundle zip");
+
+        // attachments/ 目录权限应为 0755，且必须包含 attachments/<zipId>/
+        let attachments_dir_mode = out_zip
+            .by_name("attachments/")
+            .expect("attachments dir")
+            .unix_mode()
+            .unwrap_or(0);
+        assert_eq!(attachments_dir_mode & 0o777, 0o755);
+        let per_zip_dir_mode = out_zip
+            .by_name(&format!("attachments/{}/", zip_id))
+            .expect("per zip dir")
+            .unix_mode()
+            .unwrap_or(0);
+        assert_eq!(per_zip_dir_mode & 0o777, 0o755);
+
+        // 每个ZIP目录下必须包含原始ZIP
+        out_zip
+            .by_name(&format!("attachments/{}/{}", zip_id, zip_summary.filename))
+            .expect("zip copied into per zip dir");
+    }
+
+    #[test]
+    fn parse_issued_at_handles_chinese_and_numeric_variants() {
+        let reference = time::Date::from_calendar_date(2026, time::Month::January, 5).unwrap();
+        let parse = |s: &str| parse_issued_at_with_reference(s, reference).map(|p| (p.to_iso(), p.confidence));
+
+        assert_eq!(
+            parse("2025年12月11日 14时30分"),
+            Some(("2025-12-11T14:30:00".to_string(), IssuedAtConfidence::Exact))
+        );
+        assert_eq!(
+            parse("2025-12-11 14:30:05"),
+            Some(("2025-12-11T14:30:05".to_string(), IssuedAtConfidence::Exact))
+        );
+        assert_eq!(
+            parse("2025/12/11"),
+            Some(("2025-12-11".to_string(), IssuedAtConfidence::DateOnly))
+        );
+        assert_eq!(
+            parse("2025.12.11"),
+            Some(("2025-12-11".to_string(), IssuedAtConfidence::DateOnly))
+        );
+        assert_eq!(
+            parse("２０２５年１２月１１日下午２点"),
+            Some(("2025-12-11T14:00:00".to_string(), IssuedAtConfidence::Exact))
+        );
+        assert_eq!(
+            parse("20251211"),
+            Some(("2025-12-11".to_string(), IssuedAtConfidence::DateOnly))
+        );
+        // 无年份：1 月导入的 12 月指令属于上一年
+        assert_eq!(
+            parse("12月11日"),
+            Some(("2025-12-11".to_string(), IssuedAtConfidence::YearInferred))
+        );
+        assert_eq!(parse("尽快"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    fn zip(id: &str, issued_at: &str) -> ZipSummary {
+        ZipSummary {
+            id: id.to_string(),
+            filename: format!("{id}.zip"),
+            source_path: String::new(),
+            stored_path: String::new(),
+            extracted_dir: String::new(),
+            include_original_zip: false,
+            status: "completed".to_string(),
+            word: WordFields {
+                issued_at: issued_at.to_string(),
+                ..Default::default()
+            },
+            main_docx_entry: String::new(),
+            main_docx_decision: None,
+            original_word: None,
+            field_edits: vec![],
+            flags: vec![],
+            duplicate_of: None,
+            additional_docx_files: vec![],
+            has_video: false,
+            has_sample: false,
+            video_entries: vec![],
+            video_files: vec![],
+            image_files: vec![],
+            pdf_files: vec![],
+            pdf_page_screenshot_files: vec![],
+            excel_files: vec![],
+            image_metadata: vec![],
+            ocr_texts: vec![],
+            diagnostics: vec![],
+            mail: None,
+        }
+    }
+
+    #[test]
+    fn sort_by_issued_at_puts_unparsed_last() {
+        let mut zips = vec![
+            zip("a", "不详"),
+            zip("b", "2025年12月11日 14时30分"),
+            zip("c", "2025/12/10"),
+            zip("d", ""),
+        ];
+        sort_zips_by_issued_at(&mut zips);
+        let ids: Vec<&str> = zips.iter().map(|z| z.id.as_str()).collect();
+        assert_eq!(ids, ["c", "b", "a", "d"]);
+    }
+
+    #[test]
+    fn arrange_zips_keeps_user_order_within_groups() {
+        let mut zips = vec![
+            zip("a", "2025-12-11 09:00"),
+            zip("b", "2025-12-10 09:00"),
+            zip("c", "2025-12-11 08:00"),
+            zip("d", "2025-12-12 08:00"),
+        ];
+        let order: Vec<String> = ["d", "a", "b", "c"].iter().map(|s| s.to_string()).collect();
+
+        let groups = arrange_zips_for_export(&mut zips, &order, None);
+        let ids: Vec<&str> = zips.iter().map(|z| z.id.as_str()).collect();
+        assert_eq!(ids, ["d", "a", "b", "c"]);
+        assert!(groups.is_empty());
+
+        let groups = arrange_zips_for_export(&mut zips, &order, Some(ExportGroupBy::IssuedDate));
+        let ids: Vec<&str> = zips.iter().map(|z| z.id.as_str()).collect();
+        assert_eq!(ids, ["d", "a", "c", "b"]);
+        assert_eq!(
+            groups,
+            vec![
+                ExportGroup { heading: "2025年12月12日".to_string(), first_index: 0 },
+                ExportGroup { heading: "2025年12月11日".to_string(), first_index: 1 },
+                ExportGroup { heading: "2025年12月10日".to_string(), first_index: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn summary_docx_has_headings_toc_and_page_numbers() {
+        let mut first = zip("a", "2025-12-11 09:00");
+        first.word.title = "关于核查线索的指令".to_string();
+        let batch = BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "batch_test".to_string(),
+            created_at: 0,
+            zips: vec![first, zip("b", "2025-12-12 09:00")],
+        };
+        let groups = vec![ExportGroup { heading: "2025年12月11日".to_string(), first_index: 0 }];
+
+        let docx_bytes = build_summary_docx(&batch, &groups, &SummaryDocStyle::default()).expect("build_summary_docx");
+        let mut docx_zip = ZipArchive::new(Cursor::new(&docx_bytes)).expect("docx zip");
+        let mut document = String::new();
+        docx_zip
+            .by_name("word/document.xml")
+            .expect("document.xml exists")
+            .read_to_string(&mut document)
+            .unwrap();
+        let mut styles = String::new();
+        docx_zip
+            .by_name("word/styles.xml")
+            .expect("styles.xml exists")
+            .read_to_string(&mut styles)
+            .unwrap();
+        let mut footer = String::new();
+        docx_zip
+            .by_name("word/footer1.xml")
+            .expect("footer exists")
+            .read_to_string(&mut footer)
+            .unwrap();
+
+        assert_eq!(document.matches(r#"<w:pStyle w:val="Heading1" />"#).count(), 2);
+        // 目录含一个分组标题和两条指令
+        assert!(document.contains(r#"TOC \o &quot;1-1&quot;"#));
+        assert_eq!(document.matches("PAGEREF _Toc").count(), 3);
+        assert!(document.contains("关于核查线索的指令"));
+        assert!(styles.contains("仿宋_GB2312"));
+        assert!(footer.contains("PAGE"));
+        assert!(footer.contains("NUMPAGES"));
+    }
+
+    #[test]
+    fn official_layout_numbers_sections_and_sets_page_grid() {
+        let mut first = zip("a", "2025-12-11 09:00");
+        first.word.title = "关于核查线索的指令".to_string();
+        first.word.content = "请于本周内完成核查。".to_string();
+        let batch = BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "batch_test".to_string(),
+            created_at: 0,
+            zips: vec![first, zip("b", "2025-12-12 09:00")],
+        };
+        let groups = vec![ExportGroup { heading: "执行类指令".to_string(), first_index: 0 }];
+        let style = SummaryDocStyle { layout: SummaryLayout::Official, ..Default::default() };
+
+        let docx_bytes = build_summary_docx(&batch, &groups, &style).expect("build_summary_docx");
+        let mut docx_zip = ZipArchive::new(Cursor::new(&docx_bytes)).expect("docx zip");
+        let mut document = String::new();
+        docx_zip
+            .by_name("word/document.xml")
+            .expect("document.xml exists")
+            .read_to_string(&mut document)
+            .unwrap();
+
+        assert!(document.contains("一、执行类指令"));
+        assert!(document.contains("（一）关于核查线索的指令"));
+        assert!(document.contains("1.指令内容"));
+        assert!(document.contains("（二）b.zip"));
+        assert!(document.contains(r#"w:top="2098""#));
+        assert!(document.contains(r#"w:linePitch="579""#));
+        assert!(document.contains(r#"w:color="FF0000""#));
+        assert!(!document.contains("TOC "));
+        assert_eq!(chinese_number(10), "十");
+        assert_eq!(chinese_number(21), "二十一");
+    }
+
+    #[test]
+    fn template_fills_split_placeholders_and_attachment_marker() {
+        let mut z = zip("a", "2025-12-11 09:00");
+        z.word.instruction_no = "A&1".to_string();
+        z.word.content = "第一行\n第二行".to_string();
+        let batch = BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "batch_test".to_string(),
+            created_at: 0,
+            zips: vec![z.clone()],
+        };
+        let mut ctx = TemplateContext {
+            batch: &batch,
+            exported_at: String::new(),
+            images: Vec::new(),
+        };
+
+        // 占位符被拆进三个 run
+        let xml = concat!(
+            r#"<w:p><w:r><w:rPr><w:b/></w:rPr><w:t>编号：{{指令</w:t></w:r><w:r><w:t>编号</w:t></w:r><w:r><w:t>}}</w:t></w:r></w:p>"#,
+            r#"<w:p><w:r><w:t>{{指令内容}}</w:t></w:r></w:p>"#,
+            // 带属性的自闭合空段落不能和后面的段落合在一起
+            r#"<w:p w:rsidR="00AB" w:rsidRDefault="00AB"/>"#,
+            r#"<w:p w:rsidR="00AC"><w:r><w:t>{{attachments}}</w:t></w:r></w:p>"#,
+            r#"<w:p><w:r><w:t>{{未知字段}} / {{批次编号}}</w:t></w:r></w:p>"#,
+        );
+        let (start, end) = find_template_marker(xml, "{{attachments}}").unwrap();
+        assert!(xml[start..end].starts_with(r#"<w:p w:rsidR="00AC">"#));
+        let merged = merge_template_runs(xml);
+        let filled = fill_template_scope(&merged, &mut ctx, Some((0, &z)), &[]);
+        assert!(filled.contains(r#"<w:p w:rsidR="00AB" w:rsidRDefault="00AB"/>"#));
+
+        assert!(filled.contains(r#"<w:rPr><w:b/></w:rPr><w:t xml:space="preserve">编号：A&amp;1</w:t>"#));
+        assert!(filled.contains(r#"第一行</w:t><w:br/><w:t xml:space="preserve">第二行"#));
+        assert!(filled.contains("EMBED_MARKER_a"));
+        assert!(!filled.contains("{{attachments}}"));
+        assert!(filled.contains("{{未知字段}} / batch_test"));
+    }
+
+    #[test]
+    fn rich_body_keeps_formatting_numbering_and_media() {
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .expect("png");
+        let source = Docx::new()
+            .add_abstract_numbering(
+                AbstractNumbering::new(1).add_level(Level::new(
+                    0,
+                    Start::new(1),
+                    NumberFormat::new("decimal"),
+                    LevelText::new("%1."),
+                    LevelJc::new("left"),
+                )),
+            )
+            .add_numbering(Numbering::new(1, 1))
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("指令编号：A1")))
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("指令内容：")))
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("重点").bold()))
+            .add_paragraph(
+                Paragraph::new()
+                    .numbering(NumberingId::new(1), IndentLevel::new(0))
+                    .add_run(Run::new().add_text("第一项")),
+            )
+            .add_table(Table::new(vec![TableRow::new(vec![
+                TableCell::new().add_paragraph(Paragraph::new().add_run(Run::new().add_text("单元格"))),
+            ])]))
+            .add_paragraph(Paragraph::new().add_hyperlink(
+                Hyperlink::new("https://example.com/?a=1&b=2", HyperlinkType::External)
+                    .add_run(Run::new().add_text("链接")),
+            ))
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_image(Pic::new(&png))));
+        let mut source_bytes = Cursor::new(Vec::new());
+        source.build().pack(&mut source_bytes).expect("pack source");
+
+        let rich = rich_body_from_docx("RICH_BODY_MARKER_a_main".to_string(), source_bytes.into_inner(), true)
+            .expect("read source")
+            .expect("rich body");
+        assert!(!rich.body.contains("A1"));
+        assert!(!rich.body.contains("<w:sectPr"));
+
+        let target = Docx::new()
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("标题")))
+            .add_paragraph(hidden_marker_paragraph(&rich.marker));
+        let mut target_bytes = Cursor::new(Vec::new());
+        target.build().pack(&mut target_bytes).expect("pack target");
+
+        let merged = merge_rich_bodies(target_bytes.into_inner(), &[rich]).expect("merge");
+        let mut archive = ZipArchive::new(Cursor::new(merged.as_slice())).expect("zip");
+        let document = read_file_from_zip_archive(&mut archive, "word/document.xml").expect("document");
+        let rels = read_file_from_zip_archive(&mut archive, "word/_rels/document.xml.rels").expect("rels");
+        let numbering = read_file_from_zip_archive(&mut archive, "word/numbering.xml").expect("numbering");
+        let styles = read_file_from_zip_archive(&mut archive, "word/styles.xml").expect("styles");
+
+        assert!(!document.contains("RICH_BODY_MARKER_a_main"));
+        assert!(document.contains("<w:b />") || document.contains("<w:b/>"));
+        assert!(document.contains("<w:tbl>"));
+        assert!(document.contains(r#"w:pStyle w:val="AB0Defaults""#));
+        assert!(styles.contains(r#"w:styleId="AB0Defaults""#));
+
+        // 编号接在汇总文档已有编号之后，并且 num 引用新的 abstractNum
+        let num_id = RE_RICH_NUM_ID
+            .captures_iter(&document)
+            .map(|c| c[2].to_string())
+            .find(|id| id != "0")
+            .expect("numId");
+        assert!(numbering.contains(&format!(r#"w:numId="{}""#, num_id)));
+        assert_eq!(numbering.matches("<w:abstractNum ").count(), numbering.matches("<w:num ").count());
+
+        assert!(rels.contains(r#"Target="https://example.com/?a=1&amp;b=2" TargetMode="External""#));
+        let media_rel = RE_RICH_RELATIONSHIP
+            .find_iter(&rels)
+            .map(|m| m.as_str())
+            .find(|r| r.contains("rIdAB0_") && r.contains("/image"))
+            .expect("image rel");
+        let media_target = RE_XML_ATTR
+            .captures_iter(media_rel)
+            .find(|c| &c[1] == "Target")
+            .map(|c| c[2].to_string())
+            .expect("target");
+        assert!(archive.by_name(&format!("word/{}", media_target)).is_ok());
+    }
+
+    #[test]
+    fn rich_main_body_keeps_content_heading() {
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        fs::create_dir_all(&tmp_root).unwrap();
+        let source = Docx::new()
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("指令内容：")))
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("重点处理").bold()));
+        let mut source_bytes = Cursor::new(Vec::new());
+        source.build().pack(&mut source_bytes).expect("pack source");
+        let package = tmp_root.join("a.zip");
+        let mut writer = ZipWriter::new(fs::File::create(&package).unwrap());
+        writer.start_file("指令.docx", FileOptions::default()).unwrap();
+        writer.write_all(&source_bytes.into_inner()).unwrap();
+        writer.finish().unwrap();
+
+        let mut z = zip("a", "2026年10月16日");
+        z.stored_path = package.to_string_lossy().to_string();
+        z.main_docx_entry = "指令.docx".to_string();
+        z.word.content = "重点处理".to_string();
+        let batch = BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "batch_test".to_string(),
+            created_at: 0,
+            zips: vec![z],
+        };
+        let groups = vec![ExportGroup { heading: "执行类指令".to_string(), first_index: 0 }];
+        let style = SummaryDocStyle { layout: SummaryLayout::Official, ..Default::default() };
+        let read_document = |bytes: &[u8]| {
+            let mut archive = ZipArchive::new(Cursor::new(bytes)).expect("docx zip");
+            read_file_from_zip_archive(&mut archive, "word/document.xml").expect("document")
+        };
+
+        // 保留原格式时也要输出"指令内容"小标题，附件清单的编号不前移
+        let document = read_document(&build_summary_docx(&batch, &groups, &style).unwrap());
+        assert!(document.contains("1.指令内容"));
+        assert!(document.contains("重点处理"));
+        assert!(!document.contains(RICH_BODY_MARKER_PREFIX));
+
+        let ctx = JobContext::detached(None, JobKind::ExportWord, WORD_JOB_STEPS);
+        let (docx, files, rich_bodies) = build_enhanced_summary_docx(&batch, &groups, &style, false, &ctx, 0).unwrap();
+        assert_eq!(rich_bodies.len(), 1);
+        let document = read_document(&build_docx_with_embeddings(docx, &rich_bodies, &files).unwrap());
+        assert!(document.contains("1.指令内容"));
+        assert!(document.contains("重点处理"));
+
+        let _ = fs::remove_dir_all(&tmp_root);
+    }
+
+    #[test]
+    fn deselected_additional_docx_images_are_not_exported() {
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        fs::create_dir_all(&tmp_root).unwrap();
+        let png = |size: u32| {
+            let mut bytes = Vec::new();
+            image::DynamicImage::new_rgb8(size, size)
+                .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+                .expect("png");
+            bytes
+        };
+        let source = Docx::new()
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("附件说明")))
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_image(Pic::new(&png(4)))))
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_image(Pic::new(&png(6)))));
+        let mut source_bytes = Cursor::new(Vec::new());
+        source.build().pack(&mut source_bytes).expect("pack source");
+        let source_bytes = source_bytes.into_inner();
+        let docx_path = tmp_root.join("附件.docx");
+        fs::write(&docx_path, &source_bytes).unwrap();
+        let image_files = extract_images_from_docx(&source_bytes, &tmp_root.join("images")).unwrap();
+        assert_eq!(image_files.len(), 2);
+
+        let mut z = ZipSummary::pending("z1".to_string(), "z1.zip".to_string(), String::new());
+        z.status = "completed".to_string();
+        z.additional_docx_files.push(AdditionalDocx {
+            id: "d1".to_string(),
+            name: "附件.docx".to_string(),
+            entry: String::new(),
+            file_path: docx_path.to_string_lossy().to_string(),
+            fields: WordFields::default(),
+            full_text: "附件说明".to_string(),
+            image_files,
+        });
+        let batch = BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "batch_test".to_string(),
+            created_at: 0,
+            zips: vec![z],
+        };
+        let export = |selected_image_indices: Vec<usize>| {
+            let selection = ExportBundleSelection {
+                zips: vec![ExportZipSelection {
+                    zip_id: "z1".to_string(),
+                    include: true,
+                    selected_additional_docx: vec![AdditionalDocxSelection {
+                        docx_index: 0,
+                        include_text: true,
+                        selected_image_indices,
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            let (selected, groups) = apply_bundle_selection(&batch, selection).unwrap();
+            let ctx = JobContext::detached(None, JobKind::ExportWord, WORD_JOB_STEPS);
+            let (docx, files, rich_bodies) =
+                build_enhanced_summary_docx(&selected, &groups, &SummaryDocStyle::default(), false, &ctx, 0).unwrap();
+            let bytes = build_docx_with_embeddings(docx, &rich_bodies, &files).unwrap();
+            let archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+            let media = archive.file_names().filter(|n| n.starts_with("word/media/") && !n.ends_with('/')).count();
+            (rich_bodies.len(), media)
+        };
+
+        // 全部勾选时保留正文格式，两张图片都随正文合并
+        assert_eq!(export(vec![0, 1]), (1, 2));
+        // 取消勾选一张图片后不再合并完整正文，word/media 中只剩已选图片
+        assert_eq!(export(vec![0]), (0, 1));
+        assert_eq!(export(vec![]), (0, 0));
+
+        let _ = fs::remove_dir_all(&tmp_root);
+    }
+
+    #[test]
+    fn images_keep_aspect_ratio_and_fit_text_area() {
+        let style = SummaryDocStyle::default();
+        let (text_width, _) = style.text_area_twips();
+        let max_cx = text_width as u64 * EMU_PER_TWIP;
+
+        // 横向截图按版心宽度缩小，保持宽高比
+        let (cx, cy) = fit_image_extent(1200, 600, max_cx, 9_000_000);
+        assert_eq!(cx as u64, max_cx);
+        assert_eq!(cy, cx / 2);
+        // 小图标保持原始尺寸
+        assert_eq!(fit_image_extent(32, 32, max_cx, 9_000_000), (32 * 9525, 32 * 9525));
+
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(40, 20)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .expect("png");
+        let images: Vec<(String, ProcessedImage)> = (0..4)
+            .map(|i| (format!("/tmp/shot{i}.png"), prepare_image(&png, &ImageExportSettings::default()).expect("resize")))
+            .collect();
+        assert_eq!((images[0].1.width, images[0].1.height), (40, 20));
+
+        let grid_style = SummaryDocStyle {
+            image_layout: ImageLayout::Grid,
+            image_captions: true,
+            ..SummaryDocStyle::default()
+        };
+        let mut out = Cursor::new(Vec::new());
+        add_images(Docx::new(), &images, &[], &grid_style).build().pack(&mut out).expect("pack");
+        let mut archive = ZipArchive::new(Cursor::new(out.get_ref().as_slice())).expect("zip");
+        let document = read_file_from_zip_archive(&mut archive, "word/document.xml").expect("document");
+        assert_eq!(document.matches("<w:tr>").count(), 2);
+        assert_eq!(document.matches("<w:tc>").count(), 6);
+        assert!(document.contains("shot3.png"));
+        assert!(document.contains(&format!(r#"cx="{}" cy="{}""#, 40 * 9525, 20 * 9525)));
+    }
+
+    #[test]
+    fn prepare_image_keeps_png_for_screenshots_and_skips_small_images() {
+        let settings = ImageExportSettings {
+            max_width: 100,
+            max_height: 100,
+            jpeg_quality: 80,
+            strip_metadata: false,
+        };
+        let encode = |img: image::DynamicImage, format: image::ImageFormat| {
+            let mut bytes = Vec::new();
+            img.write_to(&mut Cursor::new(&mut bytes), format).expect("encode");
+            bytes
+        };
+
+        // 小图不重新编码
+        let small_jpeg = encode(image::DynamicImage::new_rgb8(50, 40), image::ImageFormat::Jpeg);
+        let prepared = prepare_image(&small_jpeg, &settings).expect("small jpeg");
+        assert_eq!(prepared.bytes, small_jpeg);
+        assert_eq!(prepared.format, image::ImageFormat::Jpeg);
+
+        // 透明截图缩小后仍为 PNG，并保留透明通道
+        let screenshot = encode(image::DynamicImage::new_rgba8(400, 200), image::ImageFormat::Png);
+        let prepared = prepare_image(&screenshot, &settings).expect("png");
+        assert_eq!(prepared.format, image::ImageFormat::Png);
+        assert_eq!((prepared.width, prepared.height), (100, 50));
+        assert!(image::load_from_memory(&prepared.bytes).expect("decode").color().has_alpha());
+
+        // GIF 等无损格式转为 PNG，照片缩小后仍为 JPEG
+        let gif = encode(image::DynamicImage::new_rgba8(20, 20), image::ImageFormat::Gif);
+        assert_eq!(prepare_image(&gif, &settings).expect("gif").format, image::ImageFormat::Png);
+        let photo = encode(image::DynamicImage::new_rgb8(300, 300), image::ImageFormat::Jpeg);
+        let prepared = prepare_image(&photo, &settings).expect("photo");
+        assert_eq!(prepared.format, image::ImageFormat::Jpeg);
+        assert_eq!((prepared.width, prepared.height), (100, 100));
+
+        // docx 中的 JPEG 图片以 .jpeg 打包
+        let docx = Docx::new().add_paragraph(image_paragraph(&prepared, 9_000_000, 9_000_000));
+        let mut out = Cursor::new(Vec::new());
+        docx.build().pack(&mut out).expect("pack");
+        let fixed = fix_docx_media_extensions(out.into_inner()).expect("fix");
+        let mut archive = ZipArchive::new(Cursor::new(fixed.as_slice())).expect("zip");
+        let rels = read_file_from_zip_archive(&mut archive, "word/_rels/document.xml.rels").expect("rels");
+        let types = read_file_from_zip_archive(&mut archive, "[Content_Types].xml").expect("types");
+        assert!(rels.contains(".jpeg\""));
+        assert!(!rels.contains(".png\""));
+        assert!(types.contains(r#"Extension="jpeg""#));
     }
-    
-    // 获取所有批次目录
-    let mut batch_dirs = Vec::new();
-    for entry in fs::read_dir(&batches_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.starts_with("batch_") {
-                    // 提取时间戳进行排序
-                    if let Some(timestamp_str) = name.strip_prefix("batch_") {
-                        if let Ok(timestamp) = timestamp_str.parse::<i64>() {
-                            batch_dirs.push((timestamp, path));
-                        }
-                    }
-                }
-            }
+
+    #[test]
+    fn animated_gif_becomes_contact_sheet() {
+        let mut gif = Vec::new();
+        {
+            let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif);
+            let frames = (0..12u8).map(|i| {
+                image::Frame::from_parts(
+                    image::RgbaImage::from_pixel(40, 30, image::Rgba([i * 20, 0, 0, 255])),
+                    0,
+                    0,
+                    image::Delay::from_numer_denom_ms(100, 1),
+                )
+            });
+            encoder.encode_frames(frames).expect("encode gif");
         }
+        assert!(is_animated_image(&gif));
+
+        let sheet = prepare_image(&gif, &ImageExportSettings::default()).expect("contact sheet");
+        assert_eq!(sheet.format, image::ImageFormat::Png);
+        assert_eq!(
+            sheet.animation,
+            Some(AnimationInfo {
+                frame_count: 12,
+                sampled_frames: 9,
+                duration_ms: 1200,
+            })
+        );
+        // 3×3 网格，每格 40×30，间距 8
+        assert_eq!((sheet.width, sheet.height), (3 * 40 + 4 * 8, 3 * 30 + 4 * 8));
+
+        let mut still = Vec::new();
+        image::DynamicImage::new_rgb8(10, 10)
+            .write_to(&mut Cursor::new(&mut still), image::ImageFormat::Gif)
+            .expect("encode still gif");
+        assert!(!is_animated_image(&still));
+        assert!(prepare_image(&still, &ImageExportSettings::default()).expect("still").animation.is_none());
     }
-    
-    // 按时间戳排序（最新的在前）
-    batch_dirs.sort_by(|a, b| b.0.cmp(&a.0));
-    
-    // 删除超出保留数量的旧批次
-    if batch_dirs.len() > keep_count {
-        let to_remove = &batch_dirs[keep_count..];
-        let mut total_size = 0u64;
-        
-        for (_timestamp, path) in to_remove {
-            let size = calculate_dir_size(path).unwrap_or(0);
-            total_size += size;
-            
-            match fs::remove_dir_all(path) {
-                Ok(_) => {
-                    println!("✓ 已删除旧批次: {} (大小: {:.1} MB)", 
-                            path.display(), 
-                            size as f64 / 1024.0 / 1024.0);
-                }
-                Err(e) => {
-                    eprintln!("⚠ 删除旧批次失败: {} - {}", path.display(), e);
-                }
+
+    #[test]
+    fn exif_orientation_metadata_and_stripping() {
+        // 小端 TIFF：IFD0（厂商、型号、方向、Exif 指针、GPS 指针）+ Exif IFD + GPS IFD
+        let entry = |tag: u16, kind: u16, count: u32, value: u32| {
+            let mut e = Vec::new();
+            e.extend(tag.to_le_bytes());
+            e.extend(kind.to_le_bytes());
+            e.extend(count.to_le_bytes());
+            e.extend(value.to_le_bytes());
+            e
+        };
+        let rational = |num: u32| [num.to_le_bytes(), 1u32.to_le_bytes()].concat();
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        tiff.extend(5u16.to_le_bytes());
+        tiff.extend(entry(EXIF_TAG_MAKE, 2, 6, 74));
+        tiff.extend(entry(EXIF_TAG_MODEL, 2, 10, 80));
+        tiff.extend(entry(EXIF_TAG_ORIENTATION, 3, 1, 6));
+        tiff.extend(entry(EXIF_TAG_EXIF_IFD, 4, 1, 90));
+        tiff.extend(entry(EXIF_TAG_GPS_IFD, 4, 1, 128));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(b"Apple\0");
+        tiff.extend(b"iPhone 15\0");
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend(entry(EXIF_TAG_DATETIME_ORIGINAL, 2, 20, 108));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(b"2025:12:11 09:30:00\0");
+        tiff.extend(4u16.to_le_bytes());
+        tiff.extend(entry(GPS_TAG_LATITUDE_REF, 2, 2, u32::from_le_bytes([b'N', 0, 0, 0])));
+        tiff.extend(entry(GPS_TAG_LATITUDE, 5, 3, 182));
+        tiff.extend(entry(GPS_TAG_LONGITUDE_REF, 2, 2, u32::from_le_bytes([b'E', 0, 0, 0])));
+        tiff.extend(entry(GPS_TAG_LONGITUDE, 5, 3, 206));
+        tiff.extend(0u32.to_le_bytes());
+        assert_eq!(tiff.len(), 182);
+        tiff.extend([rational(30), rational(15), rational(0)].concat());
+        tiff.extend([rational(120), rational(9), rational(36)].concat());
+
+        let mut plain = Vec::new();
+        image::DynamicImage::new_rgb8(40, 20)
+            .write_to(&mut Cursor::new(&mut plain), image::ImageFormat::Jpeg)
+            .expect("jpeg");
+        let mut jpeg = plain[..2].to_vec();
+        jpeg.extend([0xFF, 0xE1]);
+        jpeg.extend(((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(&tiff);
+        jpeg.extend(&plain[2..]);
+
+        let meta = read_image_metadata(&jpeg).expect("exif");
+        assert_eq!(meta.orientation, 6);
+        assert_eq!(meta.captured_at, "2025-12-11 09:30:00");
+        assert_eq!(meta.device, "Apple iPhone 15");
+        assert!((meta.latitude.unwrap() - 30.25).abs() < 1e-9);
+        assert!((meta.longitude.unwrap() - 120.16).abs() < 1e-9);
+        let line = image_metadata_line(&ImageMetadata { file: "/x/IMG_1.jpg".into(), ..meta.clone() }, false).unwrap();
+        assert_eq!(line, "- IMG_1.jpg：拍摄时间 2025-12-11 09:30:00，设备 Apple iPhone 15");
+
+        // 方向 6 需顺时针旋转 90 度
+        let prepared = prepare_image(&jpeg, &ImageExportSettings::default()).expect("prepare");
+        assert_eq!((prepared.width, prepared.height), (20, 40));
+
+        let stripped = strip_image_metadata(&jpeg);
+        assert!(read_image_metadata(&stripped).is_none());
+        assert_eq!(stripped, plain);
+
+        // 去除元数据时原始ZIP重新打包，嵌套ZIP中的图片也要处理
+        let pack = |entries: &[(&str, &[u8])]| {
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+            for (name, data) in entries {
+                writer.start_file(*name, FileOptions::default()).unwrap();
+                writer.write_all(data).unwrap();
             }
-        }
-        
-        if total_size > 0 {
-            println!("✓ 清理完成，释放空间: {:.1} MB", total_size as f64 / 1024.0 / 1024.0);
-        }
+            writer.finish().unwrap().into_inner()
+        };
+        let nested = pack(&[("现场/IMG_2.jpg", &jpeg)]);
+        let original = pack(&[("IMG_1.jpg", &jpeg), ("说明.txt", b"text"), ("附件.zip", &nested)]);
+        let repacked = strip_zip_image_metadata(&original).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(repacked)).unwrap();
+        let read = |archive: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str| {
+            let mut data = Vec::new();
+            archive.by_name(name).unwrap().read_to_end(&mut data).unwrap();
+            data
+        };
+        assert_eq!(read(&mut archive, "IMG_1.jpg"), plain);
+        assert_eq!(read(&mut archive, "说明.txt"), b"text");
+        let mut nested = ZipArchive::new(Cursor::new(read(&mut archive, "附件.zip"))).unwrap();
+        assert_eq!(read(&mut nested, "现场/IMG_2.jpg"), plain);
+
+        // 纬度条目声称有 u32::MAX 个有理数时只读到数据末尾
+        let mut forged = tiff.clone();
+        forged[146..150].copy_from_slice(&u32::MAX.to_le_bytes());
+        let reader = TiffReader::new(&forged).unwrap();
+        let latitude = reader.entries(128).into_iter().find(|e| e.tag == GPS_TAG_LATITUDE).unwrap();
+        assert_eq!(latitude.count, u32::MAX as usize);
+        assert_eq!(reader.rationals(&latitude).len(), 6);
     }
-    
-    Ok(())
-}
 
-/// 计算目录大小
-fn calculate_dir_size(dir: &Path) -> Result<u64> {
-    let mut total_size = 0u64;
-    
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            if path.is_dir() {
-                total_size += calculate_dir_size(&path)?;
-            } else {
-                total_size += entry.metadata()?.len();
-            }
+    #[test]
+    fn ocr_text_is_cleaned_and_rendered_under_images() {
+        let raw = "  网 信 办 通 知 ， 请 及 时 处 理 \n\n\n\nABC 123 测 试\n\n";
+        assert_eq!(clean_ocr_text(raw), "网信办通知，请及时处理\n\nABC 123 测试");
+
+        let ocr_texts = vec![OcrText {
+            file: "/tmp/a.png".to_string(),
+            text: "第一行\n第二行".to_string(),
+        }];
+        let style = SummaryDocStyle {
+            ocr_text: true,
+            ..SummaryDocStyle::default()
+        };
+        assert_eq!(ocr_text_paragraphs("/tmp/a.png", &ocr_texts, &style).len(), 3);
+        assert!(ocr_text_paragraphs("/tmp/b.png", &ocr_texts, &style).is_empty());
+        assert!(ocr_text_paragraphs("/tmp/a.png", &ocr_texts, &SummaryDocStyle::default()).is_empty());
+    }
+
+    #[test]
+    fn search_matches_chinese_phrases_across_fields_with_snippets() {
+        let mut a = zip("a", "2025年3月2日");
+        a.word.title = "关于加强网络安全管理的通知".to_string();
+        a.word.issued_at_iso = "2025-03-02T00:00:00".to_string();
+        a.ocr_texts.push(OcrText {
+            file: "/tmp/shot.png".to_string(),
+            text: "截图中提到 VPN 账号".to_string(),
+        });
+        let mut b = zip("b", "2025年4月1日");
+        b.word.content = "网络运维人员安全培训，管理制度另行下发".to_string();
+        b.word.issued_at_iso = "2025-04-01T00:00:00".to_string();
+        let batch = BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "batch_1".to_string(),
+            created_at: 1,
+            zips: vec![a, b],
+        };
+
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        fs::create_dir_all(&tmp_root).unwrap();
+        let catalog = Catalog::open_at(&tmp_root.join(CATALOG_FILE)).unwrap();
+        catalog.save_batch(&batch).unwrap();
+        index_batch(&catalog, &batch).unwrap();
+        let search = |query: &str, filters: &SearchFilters| search_catalog(&catalog, query, filters).unwrap();
+
+        // 二元组都出现但不连续的文本不算命中
+        let hits = search("网络安全", &SearchFilters::default());
+        assert_eq!(hits.iter().map(|h| h.zip_id.as_str()).collect::<Vec<_>>(), vec!["a"]);
+        let snippet = &hits[0].snippets[0];
+        assert_eq!(snippet.field, SearchField::Title);
+        let (start, end) = snippet.highlights[0];
+        assert_eq!(snippet.text.chars().skip(start).take(end - start).collect::<String>(), "网络安全");
+
+        // 多个词可以分布在不同字段，英文不区分大小写
+        let hits = search("通知 vpn", &SearchFilters::default());
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].snippets.iter().any(|s| s.field == SearchField::Ocr && s.source == "shot.png"));
+
+        let hits = search("管理", &SearchFilters::default());
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].zip_id, "a", "标题命中排在正文命中前面");
+
+        let filters = SearchFilters {
+            issued_from: Some("2025-04-01".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(search("管理", &filters).len(), 1);
+
+        // 已提取过文字的 PDF 重新索引时直接复用，不再读取文件
+        let mut batch = batch;
+        batch.zips[1].pdf_files.push("/missing/通报.pdf".to_string());
+        let cached = SearchDoc {
+            field: SearchField::Pdf,
+            source: "通报.pdf".to_string(),
+            path: "/missing/通报.pdf".to_string(),
+            text: "PDF中的整改要求".to_string(),
+            ..catalog.zip_search_docs("batch_1", "b").unwrap()[0].clone()
+        };
+        let mut docs = catalog.zip_search_docs("batch_1", "b").unwrap();
+        docs.push(cached);
+        catalog.replace_search_docs("batch_1", "b", &docs).unwrap();
+        index_batch(&catalog, &batch).unwrap();
+        assert_eq!(search("整改要求", &SearchFilters::default())[0].zip_id, "b");
+
+        // 批次清理后仍能检索到，但标记为不可打开
+        catalog.delete_batch("batch_1").unwrap();
+        let hits = search("管理", &SearchFilters::default());
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|h| !h.batch_available));
+
+        // 从批次中移除的 ZIP 不再命中
+        batch.zips.truncate(1);
+        index_batch(&catalog, &batch).unwrap();
+        assert_eq!(search("管理", &SearchFilters::default()).len(), 1);
+        assert!(search("整改要求", &SearchFilters::default()).is_empty());
+
+        drop(catalog);
+        let _ = fs::remove_dir_all(&tmp_root);
+    }
+
+    #[test]
+    fn catalog_round_trips_batches_and_keeps_concurrent_updates() {
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        fs::create_dir_all(&tmp_root).unwrap();
+        let db_path = tmp_root.join(CATALOG_FILE);
+
+        let mut a = zip("a", "2025年3月2日");
+        a.image_files = vec!["/x/1.png".to_string(), "/x/2.jpg".to_string()];
+        a.video_entries = vec!["v.mp4".to_string()];
+        let batch = BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "batch_1".to_string(),
+            created_at: 42,
+            zips: vec![zip("b", ""), a],
+        };
+        let mut catalog = Catalog::open_at(&db_path).unwrap();
+        catalog.save_batch(&batch).unwrap();
+
+        let loaded = catalog.load_batch("batch_1").unwrap().unwrap();
+        assert_eq!(loaded.created_at, 42);
+        assert_eq!(loaded.zips.iter().map(|z| z.id.as_str()).collect::<Vec<_>>(), vec!["b", "a"]);
+        assert_eq!(loaded.zips[1].image_files, batch.zips[1].image_files);
+        assert_eq!(loaded.zips[1].video_entries, vec!["v.mp4"]);
+        assert!(catalog.load_batch("missing").unwrap().is_none());
+
+        // 多个连接同时追加截图，任何一次追加都不能丢
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let db_path = db_path.clone();
+                std::thread::spawn(move || {
+                    let mut catalog = Catalog::open_at(&db_path).unwrap();
+                    catalog
+                        .update_zip("batch_1", "a", |z| {
+                            z.pdf_page_screenshot_files.push(format!("/x/page_{i}.png"));
+                            Ok(())
+                        })
+                        .unwrap();
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
         }
+        let loaded = catalog.load_batch("batch_1").unwrap().unwrap();
+        assert_eq!(loaded.zips[1].pdf_page_screenshot_files.len(), 8);
+        assert_eq!(loaded.zips[1].image_files.len(), 2);
+        assert!(catalog.update_zip("batch_1", "missing", |_| Ok(())).is_err());
+
+        // 校验结果一次写入，文件列表不受影响，不存在的 ZIP 忽略
+        let flag = ValidationFlag::new(ValidationFlagKind::MissingField, "title", "缺少指令标题".to_string());
+        catalog
+            .update_flags("batch_1", &[("a".to_string(), vec![flag.clone()]), ("missing".to_string(), vec![])])
+            .unwrap();
+        let loaded = catalog.load_batch("batch_1").unwrap().unwrap();
+        assert_eq!(loaded.zips[1].flags, vec![flag]);
+        assert_eq!(loaded.zips[1].pdf_page_screenshot_files.len(), 8);
+        assert!(loaded.zips[0].flags.is_empty());
+
+        let selection = ExportBundleSelection {
+            order: vec!["a".to_string(), "b".to_string()],
+            ..Default::default()
+        };
+        catalog.save_selection("batch_1", &selection).unwrap();
+        assert_eq!(catalog.load_selection("batch_1").unwrap().unwrap().order, selection.order);
+
+        catalog.delete_batch("batch_1").unwrap();
+        assert!(catalog.load_batch("batch_1").unwrap().is_none());
+        assert!(catalog.load_selection("batch_1").unwrap().is_none());
+        assert!(catalog.batch_ids().unwrap().is_empty());
+
+        drop(catalog);
+        let _ = fs::remove_dir_all(&tmp_root);
     }
-    
-    Ok(total_size)
-}
 
-// ==================== 临时文件清理功能结束 ====================
+    #[test]
+    fn old_batch_json_is_migrated_and_missing_files_are_repaired() {
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        fs::create_dir_all(&tmp_root).unwrap();
+        let kept = tmp_root.join("kept.png");
+        fs::write(&kept, b"png").unwrap();
+        let kept = kept.to_string_lossy().to_string();
+        let gone = tmp_root.join("gone.png").to_string_lossy().to_string();
+
+        // 早期版本：没有 schema_version、截图/Excel 列表和规范化的下发时间
+        let legacy = serde_json::json!({
+            "batch_id": "batch_old",
+            "created_at": 1_741_000_000,
+            "zips": [{
+                "id": "z1",
+                "filename": "z1.zip",
+                "status": "completed",
+                "word": { "instruction_no": "1", "title": "t", "issued_at": "3月2日", "content": "" },
+                "has_video": false,
+                "has_sample": false,
+                "video_entries": [],
+                "video_files": [],
+                "image_files": [kept, gone],
+                "pdf_files": [],
+                "ocr_texts": [{ "file": gone, "text": "x" }]
+            }]
+        });
+        let mut batch = parse_batch_json(&serde_json::to_vec(&legacy).unwrap()).unwrap();
+        assert_eq!(batch.schema_version, BATCH_SCHEMA_VERSION);
+        assert!(batch.zips[0].excel_files.is_empty());
+        assert_eq!(batch.zips[0].word.issued_at_iso, "2025-03-02");
+
+        let newer = serde_json::json!({ "schema_version": BATCH_SCHEMA_VERSION + 1, "batch_id": "b", "created_at": 0, "zips": [] });
+        assert!(parse_batch_json(&serde_json::to_vec(&newer).unwrap()).is_err());
+
+        let report = validate_batch_files(&mut batch, false);
+        assert_eq!(report.checked_files, 2);
+        assert_eq!(report.missing.len(), 1);
+        assert_eq!(report.missing[0].kind, "image");
+        assert_eq!(batch.zips[0].image_files.len(), 2, "只检查时不修改批次");
+
+        let report = validate_batch_files(&mut batch, true);
+        assert!(report.repaired);
+        assert_eq!(batch.zips[0].image_files, vec![kept]);
+        assert!(batch.zips[0].ocr_texts.is_empty());
+        assert!(validate_batch_files(&mut batch, false).missing.is_empty());
+
+        // 目录中保存的旧版本数据在读取时升级
+        let catalog = Catalog::open_at(&tmp_root.join(CATALOG_FILE)).unwrap();
+        catalog.save_batch(&batch).unwrap();
+        catalog
+            .conn
+            .execute("UPDATE batches SET schema_version = 1", [])
+            .unwrap();
+        catalog
+            .conn
+            .execute("UPDATE zips SET data = json_remove(data, '$.word.issued_at_iso')", [])
+            .unwrap();
+        let loaded = catalog.load_batch("batch_old").unwrap().unwrap();
+        assert_eq!(loaded.zips[0].word.issued_at_iso, "2025-03-02");
+        let version: u32 = catalog
+            .conn
+            .query_row("SELECT schema_version FROM batches", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, BATCH_SCHEMA_VERSION);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        drop(catalog);
+        let _ = fs::remove_dir_all(&tmp_root);
+    }
 
-    fn fixture_zip(name: &str) -> PathBuf {
-        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .parent()
-            .unwrap()
-            .join("zip_simples")
-            .join(name)
+    #[test]
+    fn import_one_zip_reports_failures_and_cleans_up_when_cancelled() {
+        use std::sync::atomic::AtomicBool;
+
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        let batch_dir = tmp_root.join("batch");
+        fs::create_dir_all(&batch_dir).unwrap();
+        let source = tmp_root.join("broken.zip");
+        fs::write(&source, b"not a zip").unwrap();
+        let source = source.to_string_lossy().to_string();
+
+        let failed = import_one_zip(&batch_dir, &source, &AtomicBool::new(false)).unwrap();
+        assert_eq!(failed.status, "failed");
+        assert_eq!(failed.diagnostics[0].code, DiagnosticCode::ZipUnreadable);
+        assert!(Path::new(&failed.stored_path).exists());
+
+        let cancelled = import_one_zip(&batch_dir, &source, &AtomicBool::new(true));
+        assert!(cancelled.is_none());
+        let remaining = fs::read_dir(batch_dir.join("zips")).unwrap().count();
+        assert_eq!(remaining, 1, "取消的 ZIP 不应留下文件");
+
+        // 嵌套 ZIP 中损坏的 docx 同样记录诊断
+        let mut nested = ZipWriter::new(Cursor::new(Vec::new()));
+        nested.start_file("附件/说明.docx", FileOptions::default()).unwrap();
+        nested.write_all(b"not a docx").unwrap();
+        let nested = nested.finish().unwrap().into_inner();
+        let mut summary = ZipSummary::pending("z1".to_string(), "z1.zip".to_string(), String::new());
+        extract_nested_zip_files(&batch_dir, "z1", "材料.zip", &nested, &mut summary).unwrap();
+        assert_eq!(summary.additional_docx_files.len(), 1);
+        let codes: Vec<_> = summary.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec![DiagnosticCode::AdditionalDocxText, DiagnosticCode::AdditionalDocxImages]);
+        assert_eq!(summary.diagnostics[0].entry, "材料.zip/附件/说明.docx");
+
+        let _ = fs::remove_dir_all(&tmp_root);
     }
 
     #[test]
-    fn parse_fields_from_fixture_zip() {
-        let zip_path = fixture_zip("202512110007-ZL1.zip");
-        let scan = scan_zip(&zip_path).expect("scan_zip");
-        let (fields, _videos) = extract_word_and_videos(&zip_path, &scan).expect("extract");
-        assert!(!fields.instruction_no.is_empty());
-        assert!(!fields.title.is_empty());
-        assert!(!fields.issued_at.is_empty());
+    fn new_batch_ids_are_unique_within_a_second() {
+        let now = OffsetDateTime::from_unix_timestamp(1_792_114_200).unwrap();
+        let ids: std::collections::HashSet<String> = (0..100).map(|_| new_batch_id(now)).collect();
+        assert_eq!(ids.len(), 100);
+        assert!(ids.iter().all(|id| batch_id_timestamp(id) == Some(1_792_114_200)));
+        // 旧批次没有随机后缀
+        assert_eq!(batch_id_timestamp("batch_1741000000"), Some(1_741_000_000));
+        assert_eq!(batch_id_timestamp("other"), None);
     }
 
     #[test]
-    fn build_bundle_zip_has_per_zip_attachments_dir_and_docx_links() {
-        let zip_path = fixture_zip("202512110028-ZL1.zip");
-        let scan = scan_zip(&zip_path).expect("scan_zip");
-        let (fields, _videos) = extract_word_and_videos(&zip_path, &scan).expect("extract");
+    fn jobs_report_weighted_progress_and_respect_queue_pause_and_cancel() {
+        let ctx = JobContext::detached(None, JobKind::ExportWord, WORD_JOB_STEPS);
+        // 权重 15 / 4 / 1
+        assert!((ctx.overall_progress(0, 0.5) - 0.375).abs() < 1e-9);
+        assert!((ctx.overall_progress(1, 0.0) - 0.75).abs() < 1e-9);
+        assert_eq!(ctx.overall_progress(2, 1.0), 1.0);
+        let span = ProgressSpan::item(0, 1, 4);
+        assert!((ctx.overall_progress(span.step, span.at(0.5)) - 0.375 * 0.75).abs() < 1e-9);
+
+        let manager = JobManager::default();
+        let job = |id: &str| JobInfo {
+            id: id.to_string(),
+            kind: JobKind::Import,
+            title: String::new(),
+            state: JobState::Running,
+            progress: 0.0,
+            step: String::new(),
+            message: String::new(),
+            created_at: 0,
+            started_at: Some(0),
+            finished_at: None,
+            result: None,
+            error: None,
+        };
+        let controls: Vec<_> = ["a", "b", "c"].into_iter().map(|id| manager.insert(job(id))).collect();
+        assert!(manager.acquire_slot(&controls[0]));
+        assert!(manager.acquire_slot(&controls[1]));
+
+        // 名额用完后第三个任务排队，取消后不再等待
+        std::thread::scope(|s| {
+            let waiting = s.spawn(|| manager.acquire_slot(&controls[2]));
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!waiting.is_finished());
+            manager.cancel("c").unwrap();
+            assert!(!waiting.join().unwrap());
+        });
+
+        // 暂停的任务停在检查点，继续后放行
+        let ctx_a = JobContext {
+            app: None,
+            id: Some("a".to_string()),
+            kind: JobKind::Import,
+            steps: IMPORT_JOB_STEPS,
+            control: controls[0].clone(),
+        };
+        assert_eq!(manager.pause("a").unwrap().state, JobState::Paused);
+        std::thread::scope(|s| {
+            let waiting = s.spawn(|| ctx_a.checkpoint());
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!waiting.is_finished());
+            assert_eq!(manager.resume("a").unwrap().state, JobState::Running);
+            assert!(waiting.join().unwrap().is_ok());
+        });
+
+        // 取消后检查点返回 JobCancelled；已结束的任务不能再暂停
+        manager.cancel("a").unwrap();
+        assert!(ctx_a.checkpoint().unwrap_err().is::<JobCancelled>());
+        manager.update("a", |j| j.state = JobState::Cancelled);
+        assert!(manager.pause("a").is_err());
+        assert!(manager.cancel("missing").is_err());
+    }
+
+    #[test]
+    fn broken_attachments_become_diagnostics_and_legacy_failures_are_migrated() {
+        use std::sync::atomic::AtomicBool;
 
         let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
-        fs::create_dir_all(&tmp_root).unwrap();
         let batch_dir = tmp_root.join("batch");
         fs::create_dir_all(&batch_dir).unwrap();
 
-        let zip_id = Uuid::new_v4().to_string();
-        let stored_zip = tmp_root.join(format!("{}.zip", zip_id));
-        fs::copy(&zip_path, &stored_zip).unwrap();
+        let mut main_docx = Cursor::new(Vec::new());
+        Docx::new()
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("指令编号：A-1")))
+            .build()
+            .pack(&mut main_docx)
+            .unwrap();
+        let source = tmp_root.join("task.zip");
+        let mut writer = ZipWriter::new(fs::File::create(&source).unwrap());
+        let options = FileOptions::default();
+        writer.start_file("task.docx", options).unwrap();
+        writer.write_all(main_docx.get_ref()).unwrap();
+        writer.start_file("附件.docx", options).unwrap();
+        writer.write_all(b"not a docx").unwrap();
+        writer.start_file("inner.zip", options).unwrap();
+        writer.write_all(b"not a zip").unwrap();
+        writer.finish().unwrap();
+
+        // 附件坏了不影响整个 ZIP，但问题都有记录，且算作需要重试
+        let z = import_one_zip(&batch_dir, &source.to_string_lossy(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(z.status, "completed");
+        let codes: Vec<_> = z.diagnostics.iter().map(|d| (d.code, d.entry.as_str())).collect();
+        assert!(codes.contains(&(DiagnosticCode::AdditionalDocxText, "附件.docx")), "{:?}", codes);
+        assert!(codes.contains(&(DiagnosticCode::NestedZipFailed, "inner.zip")), "{:?}", codes);
+        assert!(z.has_errors());
 
-        let mut zip_summary = ZipSummary {
-            id: zip_id.clone(),
-            filename: "202512110028-ZL1.zip".to_string(),
-            source_path: zip_path.to_string_lossy().to_string(),
-            stored_path: stored_zip.to_string_lossy().to_string(),
-            extracted_dir: String::new(),
-            include_original_zip: true,
-            status: "completed".to_string(),
-            word: fields,
-            has_video: !scan.video_entries.is_empty(),
-            has_sample: scan.has_sample,
-            video_entries: scan.video_entries.clone(),
-            video_files: vec![],
-            image_files: vec![],
-            pdf_files: vec![],
-            pdf_page_screenshot_files: vec![],
-            excel_files: vec![],
+        let batch = BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "b".to_string(),
+            created_at: 0,
+            zips: vec![z.clone()],
         };
+        let report = build_import_report(&batch, OffsetDateTime::now_utc());
+        assert_eq!(report.zips.len(), 1);
+        assert_eq!(report.failed_zips, 0);
+        assert_eq!(report.error_count, 1);
+        assert_eq!(report.warning_count, z.diagnostics.len() - 1);
+        let xlsx = tmp_root.join("report.xlsx");
+        write_import_report_xlsx(&report, &xlsx).unwrap();
+        assert!(xlsx.exists());
+
+        // v2 数据的失败原因在 status 文本里
+        let mut legacy = serde_json::to_value(&batch).unwrap();
+        legacy["schema_version"] = serde_json::Value::from(2);
+        legacy["zips"][0]["status"] = serde_json::Value::from("failed: 无法读取ZIP");
+        legacy["zips"][0].as_object_mut().unwrap().remove("diagnostics");
+        let migrated = parse_batch_json(legacy.to_string().as_bytes()).unwrap();
+        let z = &migrated.zips[0];
+        assert_eq!(z.status, "failed");
+        assert_eq!(z.diagnostics.len(), 1);
+        assert_eq!(z.diagnostics[0].code, DiagnosticCode::ImportFailed);
+        assert_eq!(z.diagnostics[0].message, "无法读取ZIP");
+
+        let _ = fs::remove_dir_all(&tmp_root);
+    }
 
-        extract_preview_files(&batch_dir, &zip_id, &stored_zip, &scan, &mut zip_summary)
-            .expect("extract_preview_files");
+    #[test]
+    fn log_lines_carry_job_id_and_diagnostics_zip_collects_logs() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        {
+            let _outer = LogJobScope::enter(Some("job-1"));
+            {
+                let _inner = LogJobScope::enter(None);
+                assert_eq!(current_log_job_id(), None);
+            }
+            assert_eq!(current_log_job_id().as_deref(), Some("job-1"));
+            let line = format_log_line(now, log::Level::Warn, current_log_job_id().as_deref(), &format_args!("导入失败"));
+            assert_eq!(line, "[2023-11-14 22:13:20.000][WARN][job=job-1] 导入失败");
+        }
+        assert_eq!(current_log_job_id(), None);
+        assert_eq!(
+            format_log_line(now, log::Level::Info, None, &format_args!("启动")),
+            "[2023-11-14 22:13:20.000][INFO] 启动"
+        );
+        assert_eq!(parse_log_level("debug").unwrap(), log::LevelFilter::Debug);
+        assert!(parse_log_level("off").is_err());
 
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        let logs = tmp_root.join(LOG_DIR);
+        fs::create_dir_all(&logs).unwrap();
+        fs::write(logs.join("archivebox.log"), "[..][INFO] 启动\n").unwrap();
+        fs::write(logs.join("archivebox_2023-11-14_22-13-20.log"), "[..][WARN] 旧日志\n").unwrap();
         let batch = BatchSummary {
-            batch_id: "batch_test".to_string(),
-            created_at: OffsetDateTime::now_utc().unix_timestamp(),
-            zips: vec![zip_summary.clone()],
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "b".to_string(),
+            created_at: 0,
+            zips: vec![],
         };
+        let out = tmp_root.join("诊断包.zip");
+        write_diagnostics_zip(&out, &logs, Some(&batch), &[], &serde_json::json!({ "os": "test" })).unwrap();
+
+        let mut archive = ZipArchive::new(fs::File::open(&out).unwrap()).unwrap();
+        let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "batch.json",
+                "environment.json",
+                "jobs.json",
+                "logs/archivebox.log",
+                "logs/archivebox_2023-11-14_22-13-20.log"
+            ]
+        );
+        let mut batch_json = String::new();
+        archive.by_name("batch.json").unwrap().read_to_string(&mut batch_json).unwrap();
+        assert_eq!(parse_batch_json(batch_json.as_bytes()).unwrap().batch_id, "b");
 
-        let docx_bytes = build_summary_docx(&batch).expect("build_summary_docx");
-        assert!(!docx_bytes.is_empty());
+        let _ = fs::remove_dir_all(&tmp_root);
+    }
 
-        // docx 内应有指向 attachments/<zipId>/ 的链接关系
-        let mut docx_zip = ZipArchive::new(Cursor::new(&docx_bytes)).expect("docx zip");
-        let mut rels = String::new();
-        docx_zip
-            .by_name("word/_rels/document.xml.rels")
-            .expect("rels exists")
-            .read_to_string(&mut rels)
+    #[test]
+    fn watch_waits_for_files_to_settle_and_remembers_imported_files() {
+        use std::time::{Duration, Instant};
+
+        let settle = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut tracker = WatchTracker::default();
+        let mut file = WatchedFile { path: "/inbox/a.zip".to_string(), size: 100, modified: 1 };
+        assert_eq!(tracker.observe(&file, start, settle), WatchObservation::Detected);
+        // 还在增长时重新计时
+        file.size = 200;
+        assert_eq!(tracker.observe(&file, start + Duration::from_secs(8), settle), WatchObservation::Waiting);
+        assert_eq!(tracker.observe(&file, start + Duration::from_secs(15), settle), WatchObservation::Waiting);
+        assert_eq!(tracker.observe(&file, start + Duration::from_secs(18), settle), WatchObservation::Ready);
+        assert_eq!(tracker.observe(&file, start + Duration::from_secs(30), settle), WatchObservation::Handled);
+        // 同名文件被替换后重新导入
+        file.modified = 2;
+        assert_eq!(tracker.observe(&file, start + Duration::from_secs(31), settle), WatchObservation::Detected);
+        tracker.retain(&std::collections::HashSet::new());
+        assert!(tracker.files.is_empty());
+
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        let inbox = tmp_root.join("inbox");
+        fs::create_dir_all(&inbox).unwrap();
+        fs::write(inbox.join("b.ZIP"), b"zip").unwrap();
+        fs::write(inbox.join("~$tmp.zip"), b"zip").unwrap();
+        fs::write(inbox.join("note.txt"), b"txt").unwrap();
+        let listed = list_watch_zips(&inbox).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].path.ends_with("b.ZIP"));
+        assert_eq!(listed[0].size, 3);
+
+        let inbox_path = inbox.to_string_lossy().to_string();
+        let settings = normalize_watch_settings(WatchSettings {
+            enabled: true,
+            folders: vec![
+                WatchFolder { path: format!(" {inbox_path} "), batch_id: Some(" ".to_string()) },
+                WatchFolder { path: inbox_path.clone(), batch_id: Some("batch_1".to_string()) },
+            ],
+            settle_secs: 0,
+        })
+        .unwrap();
+        assert_eq!(settings.folders, vec![WatchFolder { path: inbox_path, batch_id: None }]);
+        assert_eq!(settings.settle_secs, 1);
+        let missing = WatchFolder { path: tmp_root.join("missing").to_string_lossy().to_string(), batch_id: None };
+        assert!(normalize_watch_settings(WatchSettings { folders: vec![missing], ..Default::default() }).is_err());
+
+        // 当天批次：追加导入，并按目录记录识别已导入的文件
+        let mut catalog = Catalog::open_at(&tmp_root.join(CATALOG_FILE)).unwrap();
+        let batch = BatchSummary {
+            schema_version: BATCH_SCHEMA_VERSION,
+            batch_id: "batch_1".to_string(),
+            created_at: 0,
+            zips: vec![zip("a", "")],
+        };
+        catalog.save_batch(&batch).unwrap();
+        catalog.append_zips("batch_1", &[zip("b", ""), zip("c", "")]).unwrap();
+        let loaded = catalog.load_batch("batch_1").unwrap().unwrap();
+        assert_eq!(loaded.zips.iter().map(|z| z.id.as_str()).collect::<Vec<_>>(), vec!["a", "b", "c"]);
+        assert!(catalog.append_zips("missing", &[zip("d", "")]).is_err());
+
+        assert!(!catalog.is_watched_file_imported(&listed[0]).unwrap());
+        catalog.mark_watched_file(&listed[0], "batch_1", "b", 1_000, true).unwrap();
+        catalog.mark_watched_file(&file, "batch_1", "c", 2_000, false).unwrap();
+        assert!(catalog.is_watched_file_imported(&listed[0]).unwrap());
+        assert_eq!(catalog.latest_daily_watch_batch(900).unwrap().as_deref(), Some("batch_1"));
+        assert_eq!(catalog.latest_daily_watch_batch(1_500).unwrap(), None);
+        catalog.delete_batch("batch_1").unwrap();
+        assert_eq!(catalog.latest_daily_watch_batch(0).unwrap(), None);
+        assert!(catalog.is_watched_file_imported(&listed[0]).unwrap());
+
+        let _ = fs::remove_dir_all(&tmp_root);
+    }
+
+    #[test]
+    fn folder_packages_import_like_zips_and_rezip_for_export() {
+        use std::sync::atomic::AtomicBool;
+
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        let batch_dir = tmp_root.join("batch");
+        let source = tmp_root.join("task");
+        fs::create_dir_all(batch_dir.join("x")).unwrap();
+        fs::create_dir_all(source.join("附件")).unwrap();
+
+        let mut main_docx = Cursor::new(Vec::new());
+        Docx::new()
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("指令编号：A-1")))
+            .build()
+            .pack(&mut main_docx)
             .unwrap();
-        assert!(
-            rels.contains(&format!("attachments/{}/", zip_id)),
-            "rels should contain per-zip attachments link"
+        fs::write(source.join("task.docx"), main_docx.get_ref()).unwrap();
+        // Word 打开文档时留下的锁文件和系统文件不算指令包内容
+        fs::write(source.join("~$task.docx"), b"lock").unwrap();
+        fs::write(source.join("Thumbs.db"), b"db").unwrap();
+        fs::write(source.join("附件").join("现场.png"), b"png").unwrap();
+        fs::write(source.join("附件").join("说明.pdf"), b"%PDF").unwrap();
+
+        let scan = scan_zip(&source).unwrap();
+        assert_eq!(scan.docx_entry, "task.docx");
+        assert_eq!(scan.image_entries.len(), 1);
+        assert_eq!(scan.pdf_entries.len(), 1);
+        assert!(scan.additional_docx_entries.is_empty());
+
+        let mut z = import_one_zip(&batch_dir, &source.to_string_lossy(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(z.status, "completed", "{:?}", z.diagnostics);
+        assert_eq!(z.filename, "task");
+        assert_eq!(z.word.instruction_no, "A-1");
+        assert!(Path::new(&z.stored_path).is_dir());
+        assert_eq!(z.image_files.iter().map(|f| safe_basename(f)).collect::<Vec<_>>(), vec!["现场.png"]);
+        assert_eq!(z.pdf_files.len(), 1);
+        let main = Package::open(Path::new(&z.stored_path)).unwrap().read_by_name(&z.main_docx_entry).unwrap();
+        assert_eq!(main, main_docx.get_ref().as_slice());
+
+        // 导出原始 ZIP 时按需打包，内容与文件夹一致
+        z.include_original_zip = true;
+        assert_eq!(original_zip_name(&z), "task.zip");
+        let zipped = original_zip_path(&z).unwrap();
+        assert!(zipped.is_file());
+        let mut archive = ZipArchive::new(fs::File::open(&zipped).unwrap()).unwrap();
+        let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        assert_eq!(names, vec!["task.docx", "附件/现场.png", "附件/说明.pdf"]);
+        assert_eq!(archive.by_name("附件/现场.png").unwrap().size(), 3);
+        assert_eq!(scan_zip(&zipped).unwrap().docx_entry, "task.docx");
+
+        let _ = fs::remove_dir_all(&tmp_root);
+    }
+
+    #[test]
+    fn mail_attachments_import_with_sender_subject_and_time() {
+        use base64::Engine as _;
+        use std::sync::atomic::AtomicBool;
+
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        let batch_dir = tmp_root.join("batch");
+        fs::create_dir_all(&batch_dir).unwrap();
+
+        let mut docx = Cursor::new(Vec::new());
+        Docx::new()
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_text("指令编号：M-1")))
+            .build()
+            .pack(&mut docx)
+            .unwrap();
+        let mut package = ZipWriter::new(Cursor::new(Vec::new()));
+        package.start_file("task.docx", FileOptions::default()).unwrap();
+        package.write_all(docx.get_ref()).unwrap();
+        let package = package.finish().unwrap().into_inner();
+
+        // .eml：ZIP 附件单独导入
+        let b64 = base64::engine::general_purpose::STANDARD.encode(&package);
+        let eml = format!(
+            "From: =?UTF-8?B?{}?= <zhang@example.com>\r\nSubject: =?UTF-8?B?{}?=\r\nDate: Fri, 16 Oct 2026 09:30:00 +0800\r\n\
+             MIME-Version: 1.0\r\nContent-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+             --b\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nsee attachment\r\n\
+             --b\r\nContent-Type: application/zip\r\nContent-Disposition: attachment; filename=\"pkg.zip\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n{}\r\n--b--\r\n",
+            base64::engine::general_purpose::STANDARD.encode("张三"),
+            base64::engine::general_purpose::STANDARD.encode("今日指令"),
+            b64
         );
+        let eml_path = tmp_root.join("指令.eml");
+        fs::write(&eml_path, eml).unwrap();
+        let zips = import_source(&batch_dir, &eml_path.to_string_lossy(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(zips.len(), 1);
+        assert_eq!(zips[0].status, "completed", "{:?}", zips[0].diagnostics);
+        assert_eq!(zips[0].filename, "pkg.zip");
+        assert_eq!(zips[0].word.instruction_no, "M-1");
+        let mail = zips[0].mail.clone().unwrap();
+        assert_eq!(mail.sender, "张三 <zhang@example.com>");
+        assert_eq!(mail.subject, "今日指令");
+        assert_eq!(mail.received_at, Some(1_792_114_200));
+        assert_eq!(mail_field(&zips[0], "发件人"), "张三 <zhang@example.com>");
+
+        // .msg：散装的 docx 附件合成一个文件夹导入
+        let utf16 = |s: &str| s.encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<u8>>();
+        let mut comp = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        let mut put = |path: &str, bytes: &[u8]| comp.create_stream(path).unwrap().write_all(bytes).unwrap();
+        put("/__substg1.0_0037001F", &utf16("Outlook 指令"));
+        put("/__substg1.0_0C1A001F", &utf16("李四"));
+        put("/__substg1.0_0C1F001F", &utf16("li@example.com"));
+        let mut props = vec![0u8; 32];
+        props.extend_from_slice(&0x0E06_0040u32.to_le_bytes());
+        props.extend_from_slice(&[0u8; 4]);
+        props.extend_from_slice(&134_365_878_000_000_000u64.to_le_bytes());
+        put("/__properties_version1.0", &props);
+        comp.create_storage("/__attach_version1.0_#00000000").unwrap();
+        let mut put = |path: &str, bytes: &[u8]| comp.create_stream(path).unwrap().write_all(bytes).unwrap();
+        put("/__attach_version1.0_#00000000/__substg1.0_3707001F", &utf16("任务.docx"));
+        put("/__attach_version1.0_#00000000/__substg1.0_37010102", docx.get_ref());
+        comp.flush().unwrap();
+        let msg_path = tmp_root.join("转发.msg");
+        fs::write(&msg_path, comp.into_inner().into_inner()).unwrap();
+        let zips = import_source(&batch_dir, &msg_path.to_string_lossy(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(zips.len(), 1);
+        assert_eq!(zips[0].status, "completed", "{:?}", zips[0].diagnostics);
+        assert_eq!(zips[0].filename, "转发");
+        assert_eq!(zips[0].word.instruction_no, "M-1");
+        let mail = zips[0].mail.clone().unwrap();
+        assert_eq!((mail.sender.as_str(), mail.subject.as_str()), ("李四 <li@example.com>", "Outlook 指令"));
+        assert_eq!(mail.received_at, Some(1_792_114_200));
+
+        // 没有可导入的附件时整封邮件记为失败，可以重试
+        let empty_path = tmp_root.join("空.eml");
+        fs::write(&empty_path, "From: a@example.com\r\nSubject: hi\r\n\r\nno attachments\r\n").unwrap();
+        let zips = import_source(&batch_dir, &empty_path.to_string_lossy(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(zips.len(), 1);
+        assert!(zips[0].has_errors());
+        assert_eq!(zips[0].diagnostics[0].code, DiagnosticCode::MailNoPackages);
+        assert_eq!(zips[0].source_path, empty_path.to_string_lossy());
+
+        let _ = fs::remove_dir_all(&tmp_root);
+    }
 
-        let bundle = build_bundle_zip_bytes(&batch, &docx_bytes).expect("bundle");
-        let mut out_zip = ZipArchive::new(Cursor::new(bundle)).expect("bundle zip");
+    #[test]
+    fn main_docx_is_scored_and_can_be_overridden() {
+        use std::sync::atomic::AtomicBool;
 
-        // attachments/ 目录权限应为 0755，且必须包含 attachments/<zipId>/
-        let attachments_dir_mode = out_zip
-            .by_name("attachments/")
-            .expect("attachments dir")
-            .unix_mode()
-            .unwrap_or(0);
-        assert_eq!(attachments_dir_mode & 0o777, 0o755);
-        let per_zip_dir_mode = out_zip
-            .by_name(&format!("attachments/{}/", zip_id))
-            .expect("per zip dir")
-            .unix_mode()
-            .unwrap_or(0);
-        assert_eq!(per_zip_dir_mode & 0o777, 0o755);
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        let batch_dir = tmp_root.join("batch");
+        fs::create_dir_all(&batch_dir).unwrap();
+        let docx = |lines: &[&str]| {
+            let mut doc = Docx::new();
+            for line in lines {
+                doc = doc.add_paragraph(Paragraph::new().add_run(Run::new().add_text(*line)));
+            }
+            let mut out = Cursor::new(Vec::new());
+            doc.build().pack(&mut out).unwrap();
+            out.into_inner()
+        };
+        // 按旧规则会选中排在前面的封面
+        let source = tmp_root.join("pkg.zip");
+        let mut writer = ZipWriter::new(fs::File::create(&source).unwrap());
+        writer.start_file("a_cover.docx", FileOptions::default()).unwrap();
+        writer.write_all(&docx(&["指令标题：封面"])).unwrap();
+        writer.start_file("正文/指令.docx", FileOptions::default()).unwrap();
+        writer
+            .write_all(&docx(&["指令编号：S-1", "指令标题：清理", "下发时间：2026年10月16日", "指令内容：", "按要求处理相关内容。"]))
+            .unwrap();
+        writer.finish().unwrap();
+
+        let ranked = rank_main_docx("pkg.zip", &[("pkg.docx".to_string(), None), ("x/y/z/other.docx".to_string(), None)]);
+        assert_eq!(ranked[0].entry, "pkg.docx");
+        assert!(ranked[1].score < 0, "{:?}", ranked[1]);
+
+        let mut z = import_one_zip(&batch_dir, &source.to_string_lossy(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(z.status, "completed", "{:?}", z.diagnostics);
+        assert_eq!(z.main_docx_entry, "正文/指令.docx");
+        assert_eq!(z.word.instruction_no, "S-1");
+        let decision = z.main_docx_decision.clone().unwrap();
+        assert_eq!(decision.candidates.len(), 2);
+        assert!(decision.candidates[0].score > decision.candidates[1].score);
+        assert!(!decision.manual);
+        assert_eq!(z.additional_docx_files[0].entry, "a_cover.docx");
+
+        // 手动改选后主文档和附加文档互换
+        swap_main_docx(&batch_dir, &mut z, "a_cover.docx").unwrap();
+        assert_eq!(z.main_docx_entry, "a_cover.docx");
+        assert_eq!(z.word.title, "封面");
+        assert_eq!(z.additional_docx_files.len(), 1);
+        assert_eq!(z.additional_docx_files[0].entry, "正文/指令.docx");
+        assert_eq!(z.additional_docx_files[0].fields.instruction_no, "S-1");
+        assert!(Path::new(&z.additional_docx_files[0].file_path).is_file());
+        assert!(z.main_docx_decision.as_ref().unwrap().manual);
+        assert!(swap_main_docx(&batch_dir, &mut z, "missing.docx").is_err());
+
+        let _ = fs::remove_dir_all(&tmp_root);
+    }
 
-        // 每个ZIP目录下必须包含原始ZIP
-        out_zip
-            .by_name(&format!("attachments/{}/{}", zip_id, zip_summary.filename))
-            .expect("zip copied into per zip dir");
+    #[test]
+    fn field_edits_keep_original_values_and_audit_trail() {
+        let mut z = zip("a", "2026年10月16日");
+        z.word.title = "错误标题".to_string();
+        z.main_docx_entry = "a.docx".to_string();
+        let now = OffsetDateTime::from_unix_timestamp(1_792_114_200).unwrap();
+
+        let mut fields = z.word.clone();
+        fields.title = "  正确标题 ".to_string();
+        fields.issued_at = "2026年10月17日 9:30".to_string();
+        let changed = apply_field_edit(&mut z, fields, "张三", now);
+        assert_eq!(changed, vec!["title", "issued_at"]);
+        assert_eq!(z.word.title, "正确标题");
+        assert_eq!(z.word.issued_at_iso, "2026-10-17T09:30:00");
+        assert_eq!(z.original_word.as_ref().unwrap().title, "错误标题");
+        assert_eq!(z.field_edits[0], FieldEdit { edited_by: "张三".into(), edited_at: 1_792_114_200, fields: changed, superseded: false });
+
+        // 再次修改时原始值不变；没有改动不记录
+        let mut fields = z.word.clone();
+        fields.content = "新的内容".to_string();
+        apply_field_edit(&mut z, fields, "李四", now);
+        let unchanged = z.word.clone();
+        assert!(apply_field_edit(&mut z, unchanged, "李四", now).is_empty());
+        assert_eq!(z.field_edits.len(), 2);
+        assert_eq!(z.original_word.as_ref().unwrap().title, "错误标题");
+        // 修改过指令内容后导出不再使用原文档的格式
+        assert!(main_docx_rich_body(&z).is_none());
+
+        // 重试导入后人工修改套到新的抽取结果上，找不到图片的识别文本丢弃
+        z.image_files = vec!["a/1.png".to_string()];
+        z.ocr_texts = vec![
+            OcrText { file: "a/1.png".to_string(), text: "图一".to_string() },
+            OcrText { file: "a/2.png".to_string(), text: "图二".to_string() },
+        ];
+        let mut retried = zip("a", "2026年10月16日");
+        retried.word.title = "重新抽取的标题".to_string();
+        retried.image_files = vec!["a/1.png".to_string()];
+        carry_over_user_data(&z, &mut retried, now.date());
+        assert_eq!(retried.word.title, "正确标题");
+        assert_eq!(retried.word.content, "新的内容");
+        assert_eq!(retried.word.issued_at_iso, "2026-10-17T09:30:00");
+        assert_eq!(retried.original_word.as_ref().unwrap().title, "重新抽取的标题");
+        assert_eq!(retried.field_edits, z.field_edits);
+        assert_eq!(retried.ocr_texts.len(), 1);
+
+        // 更换主文档后旧的修改只作记录，不再影响字段和导出格式
+        for edit in &mut z.field_edits {
+            edit.superseded = true;
+        }
+        z.original_word = None;
+        assert!(active_edited_fields(&z).is_empty());
+        let mut retried = zip("a", "2026年10月16日");
+        carry_over_user_data(&z, &mut retried, now.date());
+        assert_eq!(retried.field_edits.len(), 2);
+        assert!(retried.original_word.is_none());
+    }
+
+    #[test]
+    fn validation_flags_missing_mismatched_duplicate_and_suspicious_dates() {
+        use ValidationFlagKind::*;
+
+        let now = OffsetDateTime::from_unix_timestamp(1_792_114_200).unwrap();
+        let with = |filename: &str, no: &str, title: &str, issued_at: &str| {
+            let mut z = zip(no, issued_at);
+            z.filename = filename.to_string();
+            z.word.instruction_no = no.to_string();
+            z.word.title = title.to_string();
+            z
+        };
+        let mut failed = with("bad.zip", "", "", "");
+        failed.status = "failed".to_string();
+        let mut zips = vec![
+            with("202610160001-ZL1.zip", "202610160001-ZL1", "清理", "2026年10月15日 10:00"),
+            with("其他.zip", "X-1", "", "2027年1月1日"),
+            with("202610169999.zip", "202610160001-ZL1", "清理", "2025年1月1日"),
+            failed,
+        ];
+        zips[0].ocr_texts.push(OcrText { file: "/x/a.png".to_string(), text: "网络赌博推广".to_string() });
+        let kinds = |flags: &[ValidationFlag]| flags.iter().map(|f| f.kind).collect::<Vec<_>>();
+
+        let flags = validate_zips(&zips, &ValidationRules::default(), now);
+        assert_eq!(kinds(&flags[0]), vec![DuplicateNumber]);
+        assert_eq!(kinds(&flags[1]), vec![MissingField, InvalidNumber, SuspiciousDate]);
+        assert_eq!(flags[1][0].field, "title");
+        // 默认不检查下发时间是否过早
+        assert_eq!(kinds(&flags[2]), vec![MismatchedNumber, DuplicateNumber]);
+        assert!(flags[3].is_empty());
+        let rules = ValidationRules { max_age_days: 90, ..Default::default() };
+        let flags = validate_zips(&zips, &rules, now);
+        assert_eq!(kinds(&flags[2]), vec![MismatchedNumber, SuspiciousDate, DuplicateNumber]);
+        assert_eq!(flags[2][1].message, "下发时间早于90天前");
+
+        // 规则可以关闭；格式写错时忽略该规则
+        let rules = ValidationRules {
+            required_fields: vec![],
+            instruction_no_pattern: "(".to_string(),
+            match_filename: false,
+            unique_in_batch: false,
+            max_age_days: 0,
+            excel_column: true,
+            ocr_keywords: vec!["赌博".to_string(), "诈骗".to_string()],
+        };
+        let flags = validate_zips(&zips, &rules, now);
+        assert!(flags[2].is_empty());
+        // 图片识别文本命中关键词
+        assert_eq!(kinds(&flags[0]), vec![OcrKeyword]);
+        assert_eq!(flags[0][0].message, "图片文字包含关键词: 赌博");
+        let keywords = ["诈骗", " 赌博", "", "诈骗 ", "赌博"].map(String::from);
+        assert_eq!(normalize_ocr_keywords(&keywords), vec!["诈骗", "赌博"]);
+        assert_eq!(kinds(&flags[1]), vec![SuspiciousDate]);
+
+        let mut z = zips[1].clone();
+        z.flags = validate_zips(&zips, &ValidationRules::default(), now).remove(1);
+        assert_eq!(validation_flags_text(&z), "缺少指令标题；指令编号格式不符: X-1；下发时间晚于当前时间");
+    }
+
+    #[test]
+    fn instructions_seen_in_other_batches_are_flagged_and_excluded_once_exported() {
+        let tmp_root = std::env::temp_dir().join(format!("archivebox_test_{}", Uuid::new_v4()));
+        fs::create_dir_all(&tmp_root).unwrap();
+        let catalog = Catalog::open_at(&tmp_root.join(CATALOG_FILE)).unwrap();
+        let with = |id: &str, no: &str, content: &str| {
+            let mut z = zip(id, "");
+            z.word.instruction_no = no.to_string();
+            z.word.title = "清理".to_string();
+            z.word.content = content.to_string();
+            z
+        };
+
+        let old = vec![with("a1", "202610160001-ZL1", "删除相关内容"), with("a2", "202610160002-ZL1", "限流 相关 账号")];
+        catalog.record_instructions("batch_a", &old, 1_792_000_000).unwrap();
+        catalog.mark_instructions_exported("batch_a", &["a1".to_string()], 1_792_000_100).unwrap();
+
+        // 编号相同、编号不同但内容相同（忽略空白）、全新的指令
+        let mut new = vec![
+            with("b1", "202610160001-ZL1", "另一段内容"),
+            with("b2", "202610170009-ZL1", "限流相关账号"),
+            with("b3", "202610170010-ZL1", "新的指令"),
+        ];
+        mark_previous_instructions(&catalog, "batch_b", &mut new).unwrap();
+        let first = new[0].duplicate_of.clone().unwrap();
+        assert_eq!((first.batch_id.as_str(), first.zip_id.as_str()), ("batch_a", "a1"));
+        assert_eq!((first.imported_at, first.exported_at), (1_792_000_000, Some(1_792_000_100)));
+        assert_eq!(first.matched_by, DuplicateMatch::InstructionNo);
+        let second = new[1].duplicate_of.clone().unwrap();
+        assert_eq!((second.zip_id.as_str(), second.matched_by, second.exported_at), ("a2", DuplicateMatch::Content, None));
+        assert!(new[2].duplicate_of.is_none());
+
+        // 只有导出过的指令会在之后的导出中排除，重新导出同一个 ZIP 不受影响
+        catalog.record_instructions("batch_b", &new, 1_792_100_000).unwrap();
+        assert!(catalog.exported_elsewhere("batch_b", &new[0]).unwrap());
+        assert!(!catalog.exported_elsewhere("batch_b", &new[1]).unwrap());
+        assert!(!catalog.exported_elsewhere("batch_a", &old[0]).unwrap());
+        // 同一批次内的记录不算"之前处理过"
+        let mut again = vec![new[2].clone()];
+        mark_previous_instructions(&catalog, "batch_b", &mut again).unwrap();
+        assert!(again[0].duplicate_of.is_none());
+
+        // 只有标题、没有指令内容时不按内容判断相同
+        catalog.record_instructions("batch_a", &[with("a3", "202610160003-ZL1", "")], 1_792_000_000).unwrap();
+        let mut title_only = vec![with("b4", "202610170011-ZL1", "")];
+        mark_previous_instructions(&catalog, "batch_b", &mut title_only).unwrap();
+        assert!(title_only[0].duplicate_of.is_none());
+        // 人工修改内容后重新查找，之前的匹配不再保留
+        let mut edited = vec![new[1].clone()];
+        edited[0].word.content = "修改后的内容".to_string();
+        mark_previous_instructions(&catalog, "batch_b", &mut edited).unwrap();
+        assert!(edited[0].duplicate_of.is_none());
+
+        drop(catalog);
+        let _ = fs::remove_dir_all(&tmp_root);
     }
 }